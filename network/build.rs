@@ -0,0 +1,182 @@
+use sha2::{ Digest, Sha256, };
+use std::path::Path;
+use std::process::Command;
+
+/// Files that define the network protocol. If the wire layout they describe changes, the protocol checksum changes.
+const PROTOCOL_FILES: &[&str] = &[
+	"src/encryption.rs",
+	"src/handshake.rs",
+	"src/network_stream.rs",
+	"src/payload/acknowledge_mask.rs",
+	"src/payload/disconnect_reason.rs",
+	"src/payload/fragment.rs",
+	"src/payload/ntp_packet.rs",
+	"src/payload/packet.rs",
+	"src/payload/payload.rs",
+	"src/payload/snapshot.rs",
+	"src/reliable/message.rs",
+];
+
+/// Traits whose implementations describe how data is laid out on the wire.
+const ENCODING_TRAITS: &[&str] = &[
+	"Decode<",
+	"Encode<",
+	"TryInto<",
+	"U8ReadStream<",
+	"U8ReadStringSafeStream<",
+	"U8WriteStream<",
+];
+
+/// Constants that limit what the protocol accepts, defined in files that otherwise do not define the protocol. Only
+/// the lines declaring these constants affect the protocol checksum.
+const PROTOCOL_CONSTANTS: &[(&str, &[&str])] = &[
+	("src/lib.rs", &["MAX_PACKET_SIZE"]),
+	("src/ntp/mod.rs", &["MAX_NTP_PACKET_SIZE", "NTP_MAGIC_NUMBER"]),
+	("src/simulation.rs", &["MAX_INPUT_SIZE"]),
+];
+
+fn main() {
+	// hash the protocol definition
+	let mut hasher = Sha256::new();
+	for file in PROTOCOL_FILES {
+		for line in protocol_lines(file, is_wire_item) {
+			hasher.update(line.as_bytes());
+			hasher.update(b"\n");
+		}
+	}
+
+	for (file, constants) in PROTOCOL_CONSTANTS {
+		let lines = protocol_lines(file, |_| true);
+		for constant in constants.iter() {
+			let line = lines.iter()
+				.find(|line| line.trim_start_matches("pub ").starts_with(&format!("const {}:", constant)))
+				.unwrap_or_else(|| panic!("Could not find protocol constant {} in {}", constant, file));
+
+			hasher.update(line.as_bytes());
+			hasher.update(b"\n");
+		}
+	}
+
+	let checksum = hasher.finalize()[0..16].iter().map(|byte| format!("{:#04x}", byte)).collect::<Vec<String>>();
+	let out_directory = std::env::var("OUT_DIR").expect("Could not get output directory");
+	std::fs::write(Path::new(&out_directory).join("protocol_checksum.rs"), format!("[{}]", checksum.join(", ")))
+		.expect("Could not write protocol checksum");
+
+	// figure out the version from git
+	if let Some(git_directory) = git(&["rev-parse", "--git-dir"]) {
+		let git_directory = Path::new(&git_directory);
+		println!("cargo:rerun-if-changed={}", git_directory.join("HEAD").display());
+		println!("cargo:rerun-if-changed={}", git_directory.join("refs").display());
+	}
+
+	let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"])
+		.filter(|branch| branch.len() >= 3)
+		.map(|branch| branch.chars().take(32).collect::<String>())
+		.unwrap_or(String::from("unknown"));
+
+	// major-minor releases are tagged as `v<major>.<minor>`. the revision is the amount of commits since the last release
+	let release = git(&["describe", "--tags", "--abbrev=0", "--match", "v*"]);
+	let (major, minor) = release.as_ref()
+		.and_then(|tag| {
+			let (major, minor) = tag.trim_start_matches('v').split_once('.')?;
+			Some((major.parse::<u16>().ok()?, minor.parse::<u16>().ok()?))
+		})
+		.unwrap_or((0, 0));
+
+	let revision_range = release.map_or(String::from("HEAD"), |tag| format!("{}..HEAD", tag));
+	let revision = git(&["rev-list", "--count", &revision_range])
+		.and_then(|count| count.parse::<u16>().ok())
+		.unwrap_or(0);
+
+	let version = format!(
+		"const VERSION_BRANCH: &str = {:?};\n\
+		const VERSION_MAJOR: u16 = {};\n\
+		const VERSION_MINOR: u16 = {};\n\
+		const VERSION_REVISION: u16 = {};\n",
+		branch,
+		major,
+		minor,
+		revision
+	);
+
+	std::fs::write(Path::new(&out_directory).join("version.rs"), version).expect("Could not write version");
+}
+
+/// Read the top-level items of a protocol file that pass the filter. Comments, whitespace, and empty lines are
+/// stripped.
+fn protocol_lines(file: &str, filter: fn(&[String]) -> bool) -> Vec<String> {
+	println!("cargo:rerun-if-changed={}", file);
+
+	let contents = std::fs::read_to_string(file).expect("Could not read protocol file");
+	let mut lines = Vec::new();
+	let mut item = Vec::new();
+	let mut depth = 0;
+	for line in contents.lines() {
+		let line = line.split("//").next().unwrap().trim();
+		if line.is_empty() {
+			continue;
+		}
+
+		// collect lines until the item's braces are balanced, or until it ends if it does not have a body
+		depth += line.matches('{').count() as i64 - line.matches('}').count() as i64;
+		item.push(String::from(line));
+		if depth > 0 || !(line.contains('}') || line.ends_with(';')) {
+			continue;
+		}
+
+		if filter(&item) {
+			lines.append(&mut item);
+		}
+
+		item.clear();
+	}
+
+	lines
+}
+
+/// Whether or not a top-level item describes the wire layout: public constants, free functions, enums with explicit
+/// discriminants, and implementations of the encoding traits. Everything else, like connection logic, private limits
+/// and tests, does not affect the protocol checksum.
+fn is_wire_item(item: &[String]) -> bool {
+	if item.iter().any(|line| line == "#[cfg(test)]") {
+		return false;
+	}
+
+	let Some(header) = item.iter().find(|line| !line.starts_with("#[")) else {
+		return false;
+	};
+
+	// private constants are limits that only affect how we handle data, not how it is laid out
+	let public = header.starts_with("pub");
+	let header = header.trim_start_matches("pub(crate) ").trim_start_matches("pub ");
+	if header.starts_with("const ") {
+		return public;
+	}
+
+	if header.starts_with("fn ") {
+		return true;
+	}
+
+	if header.starts_with("enum ") {
+		return item.iter().any(|line| line.contains('='));
+	}
+
+	// the implementation's header runs until its body starts
+	if header.starts_with("impl") {
+		let item = item.join(" ");
+		let header = item.split('{').next().unwrap();
+		return ENCODING_TRAITS.iter().any(|name| header.contains(name));
+	}
+
+	false
+}
+
+/// Run a git command, returning its trimmed output if it succeeded.
+fn git(arguments: &[&str]) -> Option<String> {
+	let output = Command::new("git").args(arguments).output().ok()?;
+	if !output.status.success() {
+		return None;
+	}
+
+	Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
@@ -0,0 +1,106 @@
+use socket2::{ Domain, Protocol, Socket, Type, };
+use std::net::{ IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket, };
+
+/// Convert an IPv4-mapped IPv6 address into the IPv4 address it maps. Dual-stack sockets report IPv4 remotes using
+/// mapped addresses, so every address we receive is normalized to make sure a remote is always identified by the same
+/// address, regardless of the kind of socket it talked to.
+pub fn normalize_address(address: SocketAddr) -> SocketAddr {
+	match address {
+		SocketAddr::V6(v6_address) => match v6_address.ip().to_ipv4_mapped() {
+			Some(v4_address) => SocketAddr::new(IpAddr::V4(v4_address), v6_address.port()),
+			None => address,
+		},
+		SocketAddr::V4(_) => address,
+	}
+}
+
+/// Convert a normalized address into one that a socket bound to `local_address` can send to. IPv6 sockets can only
+/// send to IPv4 remotes using mapped addresses.
+pub(crate) fn to_socket_family(address: SocketAddr, local_address: &SocketAddr) -> SocketAddr {
+	match (address, local_address) {
+		(SocketAddr::V4(v4_address), SocketAddr::V6(_)) => {
+			SocketAddr::new(IpAddr::V6(v4_address.ip().to_ipv6_mapped()), v4_address.port())
+		},
+		_ => address,
+	}
+}
+
+/// Convert an IP address into an IPv6 address, mapping IPv4 addresses. Used by the blacklist so it only has to deal with
+/// one kind of address.
+pub(crate) fn to_ipv6(address: IpAddr) -> Ipv6Addr {
+	match address {
+		IpAddr::V4(address) => address.to_ipv6_mapped(),
+		IpAddr::V6(address) => address,
+	}
+}
+
+/// Bind a UDP socket to the first address that works. IPv6 sockets are bound as dual-stack sockets, so binding to `[::]`
+/// accepts IPv4 remotes as well, regardless of the operating system's default.
+pub(crate) fn bind_udp<T: ToSocketAddrs>(address: T) -> Result<UdpSocket, std::io::Error> {
+	let mut last_error = None;
+	for address in address.to_socket_addrs()? {
+		let result = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))
+			.and_then(|socket| {
+				if address.is_ipv6() {
+					socket.set_only_v6(false)?;
+				}
+
+				socket.bind(&address.into())?;
+				Ok(socket)
+			});
+
+		match result {
+			Ok(socket) => return Ok(socket.into()),
+			Err(error) => last_error = Some(error),
+		}
+	}
+
+	Err(last_error.unwrap_or_else(|| {
+		std::io::Error::new(std::io::ErrorKind::InvalidInput, "could not resolve to any addresses")
+	}))
+}
+
+/// Test address normalization.
+#[cfg(test)]
+mod tests {
+	use std::net::{ Ipv6Addr, SocketAddr, };
+
+	use super::{ bind_udp, normalize_address, to_ipv6, to_socket_family, };
+
+	/// Ensure that mapped addresses are normalized into IPv4 addresses, and mapped back for IPv6 sockets.
+	#[test]
+	fn normalize() {
+		let mapped: SocketAddr = "[::ffff:10.0.0.1]:5000".parse().unwrap();
+		let v4: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+		let v6: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+
+		assert_eq!(normalize_address(mapped), v4);
+		assert_eq!(normalize_address(v4), v4);
+		assert_eq!(normalize_address(v6), v6);
+
+		let v6_socket: SocketAddr = "[::]:0".parse().unwrap();
+		let v4_socket: SocketAddr = "0.0.0.0:0".parse().unwrap();
+		assert_eq!(to_socket_family(v4, &v6_socket), mapped);
+		assert_eq!(to_socket_family(v4, &v4_socket), v4);
+		assert_eq!(to_socket_family(v6, &v6_socket), v6);
+
+		assert_eq!(to_ipv6(v4.ip()), "::ffff:10.0.0.1".parse::<Ipv6Addr>().unwrap());
+	}
+
+	/// Ensure that sockets bound to `[::]` receive data sent over IPv4.
+	#[test]
+	fn dual_stack() {
+		let Ok(server) = bind_udp("[::]:0") else {
+			return; // IPv6 is not available
+		};
+
+		let port = server.local_addr().expect("Could not get local address").port();
+		let client = bind_udp("127.0.0.1:0").expect("Could not bind IPv4 socket");
+		client.send_to(&[1, 2, 3], ("127.0.0.1", port)).expect("Could not send data");
+
+		let mut buffer = [0; 3];
+		let (_, source) = server.recv_from(&mut buffer).expect("Could not receive data");
+		assert_eq!(buffer, [1, 2, 3]);
+		assert_eq!(normalize_address(source), client.local_addr().expect("Could not get local address"));
+	}
+}
@@ -22,7 +22,12 @@ async fn main() {
 					panic!("{:?}", error);
 				}
 			}
-			std::thread::sleep(std::time::Duration::from_millis(33));
+
+			while let Some(event) = server.poll_event() {
+				println!("{:?}", event);
+			}
+
+			std::thread::sleep(server.get_config().get_tick_duration());
 		}
 	} else {
 		let mut client = Client::new("[::]:0").unwrap();
@@ -50,7 +55,11 @@ async fn main() {
 				}
 			}
 
-			std::thread::sleep(std::time::Duration::from_millis(33));
+			while let Some(event) = client.poll_event() {
+				println!("{:?}", event);
+			}
+
+			std::thread::sleep(client.get_config().get_tick_duration());
 		}
 	}
 }
@@ -1,30 +1,70 @@
 use rand::Rng;
-use std::net::{ ToSocketAddrs, UdpSocket, };
-use std::time::{ Instant, SystemTime, UNIX_EPOCH, };
+use std::collections::VecDeque;
+use std::net::{ SocketAddr, ToSocketAddrs, };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH, };
 use streams::{ ReadStream, WriteStream, };
 
+use crate::address::normalize_address;
+use crate::congestion::{ CongestionController, MAX_PACKETS_PER_TICK, };
+use crate::connection_statistics::ConnectionStatistics;
+use crate::encryption::{ KeyExchange, Role, Session, };
 use crate::error::NetworkStreamError;
-use crate::handshake::{ Handshake, Version, };
-use crate::log::{ Log, LogLevel, };
+use crate::event::{ Channel, ClientEvent, };
+use crate::handshake::{
+	Challenge,
+	Handshake,
+	Rejection,
+	Version,
+	CHALLENGE_MAGIC_NUMBER,
+	HANDSHAKE_PADDED_SIZE,
+	PROTOCOL_CHECKSUM,
+	REJECTION_MAGIC_NUMBER,
+};
+use crate::link_conditioner::LinkConditioner;
+use crate::log::{ Log, LogFields, LogLevel, };
 use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
-use crate::ntp::{NtpServerError, NtpServer};
-use crate::payload::{ AcknowledgeMask, DisconnectionReason, Packet, SubPayload, };
+use crate::ntp::{ NetworkClock, NtpServer, NtpServerError, };
+use crate::payload::{
+	AcknowledgeMask,
+	DisconnectionReason,
+	Fragmenter,
+	Packet,
+	Priority,
+	Reassembler,
+	SubPayload,
+	MAX_UNRELIABLE_MESSAGE_SIZE,
+};
+use crate::reliable::{ IncomingStream, ReliableChannel, StreamError, StreamId, };
+use crate::replication::{ Component, EntityId, Interpolate, };
+use crate::replication::receiver::SnapshotReceiver;
+use crate::simulation::{ tick_at, PendingInputs, MAX_INPUT_SIZE, };
+use crate::transport::{ Transport, UdpTransport, };
 use crate::MAX_PACKET_SIZE;
 
+use super::ClientConfig;
+
+/// How long we wait for the server to reply to our handshake before we send it again, in case it was dropped.
+const HANDSHAKE_RESEND_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(Debug)]
 pub enum ClientError {
 	/// Emitted if we were disconnected by the server. Fatal.
 	Disconnected(DisconnectionReason),
 	/// Received an invalid handshake. We likely talked to a random UDP server. Fatal.
 	Handshake,
+	/// Emitted if an unreliable message is bigger than `MAX_UNRELIABLE_MESSAGE_SIZE`, or if an input is bigger than
+	/// `MAX_INPUT_SIZE`. Non-fatal.
+	MessageTooBig,
 	/// Emitted if we encountered a problem with network streams.
 	NetworkStreamError(NetworkStreamError),
 	/// Wrapper for an error from the client NTP implementation
 	NtpError(NtpServerError),
 	/// Emitted if a received packet is too big to be an eggine packet. Non-fatal.
 	PacketTooBig,
-	/// Emitted if we encountered an OS error during a socket operation.
+	/// Emitted if we encountered an OS error during a transport operation.
 	Socket(std::io::ErrorKind),
+	/// Emitted if we misused a stream, or if the server went over the limits on the streams it can open. Non-fatal.
+	Stream(StreamError),
 }
 
 impl ClientError {
@@ -33,10 +73,12 @@ impl ClientError {
 		match self {
 			ClientError::Disconnected(_) => true,
 			ClientError::Handshake => true,
+			ClientError::MessageTooBig => false,
 			ClientError::NetworkStreamError(_) => false,
 			ClientError::NtpError(error) => error.is_fatal(),
 			ClientError::PacketTooBig => false,
 			ClientError::Socket(_) => true,
+			ClientError::Stream(_) => false,
 		}
 	}
 }
@@ -53,6 +95,12 @@ impl From<NtpServerError> for ClientError {
 	}
 }
 
+impl From<StreamError> for ClientError {
+	fn from(error: StreamError) -> Self {
+		ClientError::Stream(error)
+	}
+}
+
 impl From<std::io::Error> for ClientError {
 	fn from(error: std::io::Error) -> Self {
 		ClientError::Socket(error.kind())
@@ -65,15 +113,37 @@ impl From<std::io::Error> for ClientError {
 pub struct Client {
 	/// Acknowledge mask for this client.
 	acknowledge_mask: AcknowledgeMask,
+	/// Whether or not the server sent us data that we have not acknowledged yet.
+	acknowledge_pending: bool,
+	/// Estimates the server's time.
+	clock: NetworkClock,
+	/// Timings used by the client.
+	config: ClientConfig,
+	/// Decides how many bytes of unreliable data we send to the server each tick.
+	congestion: CongestionController,
 	/// True if the server accepted our handshake and we're in a state where we are ready to exchange packets.
 	connection_initialized: bool,
+	/// Events game code has not handled yet.
+	events: VecDeque<ClientEvent>,
+	/// Keeps packets sent to the server within `MAX_PACKET_SIZE`.
+	fragmenter: Fragmenter,
 	/// The handshake we send to the server upon connection initialization.
 	handshake: Handshake,
+	/// When we started connecting to the server. `None` if we are not waiting for the server to accept our handshake.
+	handshake_start_time: Option<Instant>,
+	/// Our half of the key exchange, consumed once the server sends us its handshake.
+	key_exchange: Option<KeyExchange>,
 	/// The highest sequence number that the server said it had acknowledged. This is initialized as `None`, since the
 	/// server starts off having acknowledged nothing.
 	highest_acknowledge_received: Option<u32>,
 	/// The last time we received data from the server.
 	last_activity: Instant,
+	/// The last time we sent our handshake to the server. `None` if we are not waiting for the server to accept it.
+	last_handshake_time: Option<Instant>,
+	/// The last time we sent NTP requests to the server.
+	last_ntp_sync: Instant,
+	/// The last time we sent a packet to the server.
+	last_send_time: Instant,
 	/// The last sequence we received from the server.
 	last_sequence_received: Option<u32>,
 	log: Log,
@@ -84,80 +154,182 @@ pub struct Client {
 	ntp_server: Option<NtpServer>,
 	/// We place all outgoing data into this packet.
 	outgoing_packet: Packet,
+	/// The inputs the server has not acknowledged yet.
+	pending_inputs: PendingInputs,
+	/// Reassembles fragmented sub-payloads sent by the server.
+	reassembler: Reassembler,
+	/// Why the server rejected our handshake. Rejections are not authenticated, so the reason is only reported once the
+	/// handshake times out.
+	rejection: Option<DisconnectionReason>,
 	/// The buffer we write into when we receive data.
 	receive_buffer: [u8; MAX_PACKET_SIZE + 1],
+	/// Holds back received data to simulate network conditions.
+	receive_conditioner: Option<LinkConditioner<Vec<u8>>>,
+	/// Reliable messages exchanged with the server.
+	reliable_channel: ReliableChannel,
 	/// The stream we import data into when we receive data.
 	receive_stream: NetworkReadStream,
+	/// Holds back sent data to simulate network conditions.
+	send_conditioner: Option<LinkConditioner<Vec<u8>>>,
 	/// The stream we use to export data so we can sent it to a client.
 	send_stream: NetworkWriteStream,
 	/// The client-side sequence number. Client -> server packets will be identified using this sequence number.
 	sequence: u32,
-	/// The socket the client is connected to the server on.
-	socket: UdpSocket,
+	/// The address of the server we are connected to. Data sent from other addresses is ignored.
+	server_address: Option<SocketAddr>,
+	/// The version of the eggine the server is running, known once the server replies to our handshake.
+	server_version: Option<Version>,
+	/// Encrypts and authenticates the packets exchanged with the server. Created once the server accepts our handshake.
+	session: Option<Session>,
+	/// Reassembles the snapshots of the entities the server replicates to us.
+	snapshot_receiver: SnapshotReceiver,
+	/// Round-trip time, packet loss and bandwidth of the connection.
+	statistics: ConnectionStatistics,
+	/// The transport the client talks to the server over.
+	transport: Box<dyn Transport>,
 }
 
 impl Client {
 	/// Initialize the a client socket bound to the specified address.
 	pub fn new<T: ToSocketAddrs>(address: T) -> Result<Self, ClientError> {
-		let socket = UdpSocket::bind(address)?;
-		socket.set_nonblocking(true)?;
+		Client::with_config(address, ClientConfig::default())
+	}
+
+	/// Initialize the a client socket bound to the specified address, using the specified timings.
+	pub fn with_config<T: ToSocketAddrs>(address: T, config: ClientConfig) -> Result<Self, ClientError> {
+		Client::with_transport(Box::new(UdpTransport::bind(address)?), config)
+	}
 
+	/// Initialize a client that talks over the specified transport, using the specified timings.
+	pub fn with_transport(transport: Box<dyn Transport>, config: ClientConfig) -> Result<Self, ClientError> {
 		let ntp_id_server = rand::thread_rng().gen::<u32>();
+		let key_exchange = KeyExchange::new();
+		let receive_conditioner = config.receive_conditions.clone().map(LinkConditioner::new);
+		let send_conditioner = config.send_conditions.clone().map(LinkConditioner::new);
+		let log = Log::new(config.log_level, config.log_capacity);
 
 		Ok(Client {
 			acknowledge_mask: AcknowledgeMask::default(),
+			acknowledge_pending: false,
+			clock: NetworkClock::default(),
+			config,
+			congestion: CongestionController::default(),
 			connection_initialized: false,
+			events: VecDeque::new(),
+			fragmenter: Fragmenter::default(),
 			handshake: Handshake {
-				checksum: [0; 16],
+				checksum: PROTOCOL_CHECKSUM,
+				cookie: None,
 				ntp_id: ntp_id_server,
+				public_key: key_exchange.get_public_key(),
 				sequences: (0, 0),
-				version: Version {
-					branch: String::from("master"),
-					major: 0,
-					minor: 0,
-					revision: 0,
-				}
+				version: Version::current(),
 			},
+			handshake_start_time: None,
 			highest_acknowledge_received: None,
+			key_exchange: Some(key_exchange),
 			last_activity: Instant::now(),
+			last_handshake_time: None,
+			last_ntp_sync: Instant::now(),
+			last_send_time: Instant::now(),
 			last_sequence_received: None,
-			log: Log::default(),
+			log,
 			ntp_id_client: 0,
 			ntp_id_server,
 			ntp_server: None,
 			outgoing_packet: Packet::new(0, 0),
+			pending_inputs: PendingInputs::default(),
+			reassembler: Reassembler::default(),
 			// create the receive buffer. if we ever receive a packet that is greater than `MAX_PACKET_SIZE`, then the recv
 			// function call will say that we have read `MAX_PACKET_SIZE + 1` bytes. the extra read byte allows us to check
 			// if a packet is too big to decode, while also allowing us to use all the packet bytes within the range
 			// `0..MAX_PACKET_SIZE`.
 			receive_buffer: [0; MAX_PACKET_SIZE + 1],
+			receive_conditioner,
+			rejection: None,
+			reliable_channel: ReliableChannel::default(),
 			receive_stream: NetworkReadStream::new(),
+			send_conditioner,
 			send_stream: NetworkWriteStream::new(),
 			sequence: 0,
-			socket,
+			server_address: None,
+			server_version: None,
+			session: None,
+			snapshot_receiver: SnapshotReceiver::default(),
+			statistics: ConnectionStatistics::default(),
+			transport,
 		})
 	}
 
 	/// Perform all necessary network functions for this tick. This includes receiving data, sending data, and figuring
 	/// out our time-to-live.
 	pub async fn tick(&mut self) -> Result<(), ClientError> {
-		// send the packet we worked on constructing to the server, then reset it
-		if self.outgoing_packet.get_sub_payloads().len() > 0 && self.is_connection_valid() {
-			self.sequence += 1;
+		// send the packet we worked on constructing to the server, then reset it. queued unreliable data that does not fit
+		// into the first packet is sent while the budget allows it
+		let now = Instant::now();
+		let mut budget = self.congestion.get_budget();
+
+		// resend the newest inputs the server has not acknowledged, in case earlier packets were dropped. inputs for ticks
+		// the server already simulated are too late to be accepted
+		if self.is_connection_valid() {
+			if let Some(tick) = self.get_simulation_tick() {
+				self.pending_inputs.discard_before(tick);
+			}
+
+			for (tick, input) in self.pending_inputs.get_newest() {
+				self.outgoing_packet.add_sub_payload(SubPayload::Input(*tick, input.clone()));
+			}
+		}
+
+		for packet_index in 0..MAX_PACKETS_PER_TICK {
+			let has_data = if packet_index == 0 {
+				!self.outgoing_packet.get_sub_payloads().is_empty()
+					|| self.reliable_channel.needs_packet(now)
+					|| self.fragmenter.has_queued()
+					|| self.acknowledge_pending
+					|| now - self.last_send_time >= self.config.keepalive_interval
+			} else {
+				budget > 0 && self.fragmenter.has_queued()
+			};
+
+			if !has_data || !self.is_connection_valid() {
+				break;
+			}
+
+			self.sequence = self.sequence.wrapping_add(1);
+			self.reliable_channel.attach(&mut self.outgoing_packet, self.sequence, now);
+			let used = self.fragmenter.fragment(&mut self.outgoing_packet, budget, now)?;
+			budget = budget.saturating_sub(used);
 			self.outgoing_packet.prepare(
 				self.acknowledge_mask,
 				self.sequence,
 				self.last_sequence_received.unwrap_or(0)
 			);
+			Session::prepare(&mut self.outgoing_packet);
 
 			self.send_stream.encode(&self.outgoing_packet)?;
 
-			let bytes = self.send_stream.export()?;
+			let mut bytes = self.send_stream.export()?;
+			self.session.as_mut().unwrap().seal(&mut bytes)?;
+			self.acknowledge_pending = false;
+			self.last_send_time = now;
+			self.statistics.record_sent(self.sequence, bytes.len(), now);
 			self.send_bytes(&bytes)?;
 
 			self.outgoing_packet.next();
 		}
 
+		self.congestion.update(&self.statistics, self.fragmenter.has_queued(), now);
+
+		// the handshake or the server's reply to it may have been dropped
+		if !self.connection_initialized
+			&& self.last_handshake_time.is_some_and(|time| now - time >= HANDSHAKE_RESEND_INTERVAL)
+		{
+			self.send_handshake()?;
+		}
+
+		self.flush_send_conditioner()?;
+
 		// receive packets from the server
 		loop {
 			match self.recv() {
@@ -172,11 +344,35 @@ impl Client {
 			}
 		}
 
-		// process NTP packets from the server
+		// the server never accepted our handshake. if it rejected us, report why
+		let now = Instant::now();
+		if self.handshake_start_time.is_some_and(|time| now - time > self.config.timeout) {
+			self.log.print(LogLevel::Warning, String::from("server did not accept our handshake in time"), 0);
+			self.handshake_start_time = None;
+			self.last_handshake_time = None;
+
+			let reason = self.rejection.take().unwrap_or(DisconnectionReason::Timeout);
+			self.events.push_back(ClientEvent::Disconnected(reason.clone()));
+			return Err(ClientError::Disconnected(reason));
+		}
+
+		// the server stopped talking to us
+		if self.is_connection_valid() && now - self.last_activity > self.config.timeout {
+			self.log.print(LogLevel::Warning, String::from("server timed out"), 0);
+			self.connection_initialized = false;
+			self.events.push_back(ClientEvent::Disconnected(DisconnectionReason::Timeout));
+			return Err(ClientError::Disconnected(DisconnectionReason::Timeout));
+		}
+
+		// synchronize our time with the server
 		if self.is_connection_valid() && self.ntp_server.is_some() {
 			let ntp_server = self.ntp_server.as_mut().unwrap();
-			ntp_server.sync_time(None).await?;
-			ntp_server.process_all().await?;
+			if now - self.last_ntp_sync >= self.config.ntp_sync_interval {
+				self.last_ntp_sync = now;
+				ntp_server.sync_time(None)?;
+			}
+
+			self.send_ntp_packets()?;
 		}
 
 		Ok(())
@@ -185,21 +381,24 @@ impl Client {
 	/// Initializes a connection with the specified server. Done by sending a handshake, and receiving a sequence ID pair
 	/// used for exchanging packets.
 	pub async fn initialize_connection<T: ToSocketAddrs>(&mut self, address: T) -> Result<(), ClientError> {
-		self.socket.connect(address)?;
-
-		self.log.print(LogLevel::Info, format!("establishing connection to {:?}...", self.socket.peer_addr().unwrap()), 0);
-		self.send_stream.encode(&self.handshake)?;
-		let bytes = self.send_stream.export()?;
-
-		self.send_bytes(&bytes)?;
+		// IPv4 sockets cannot talk to IPv6 servers, but dual-stack sockets can talk to both
+		let local_address = self.transport.local_addr()?;
+		let Some(address) = address.to_socket_addrs()?
+			.map(normalize_address)
+			.find(|address| local_address.is_ipv6() || address.is_ipv4())
+		else {
+			return Err(ClientError::Socket(std::io::ErrorKind::AddrNotAvailable));
+		};
 
-		let mut bind_address = self.socket.local_addr().unwrap();
-		bind_address.set_port(bind_address.port() + 1);
+		self.server_address = Some(address);
 
-		let mut host_address = self.socket.peer_addr().unwrap();
-		host_address.set_port(host_address.port() + 1);
+		let fields = LogFields::default().peer(address);
+		self.log.print_with(LogLevel::Info, String::from("establishing connection..."), 0, fields);
+		self.handshake_start_time = Some(Instant::now());
+		self.rejection = None;
+		self.send_handshake()?;
 
-		self.ntp_server = Some(NtpServer::new(bind_address, Some((host_address, self.ntp_id_server))).await?);
+		self.ntp_server = Some(NtpServer::new(Some((address, self.ntp_id_server))));
 
 		Ok(())
 	}
@@ -208,6 +407,113 @@ impl Client {
 		self.connection_initialized
 	}
 
+	/// The version of the eggine the server is running. Set once the server accepts or rejects our handshake, so it can
+	/// be displayed if the server rejected us with `DisconnectionReason::VersionMismatch`.
+	pub fn get_server_version(&self) -> Option<&Version> {
+		self.server_version.as_ref()
+	}
+
+	/// Get the clock that estimates the server's time. Used to timestamp data and schedule events in server time.
+	pub fn get_clock(&self) -> &NetworkClock {
+		&self.clock
+	}
+
+	/// Get the timings used by the client.
+	pub fn get_config(&self) -> &ClientConfig {
+		&self.config
+	}
+
+	/// Get the client's log.
+	pub fn get_log(&self) -> &Log {
+		&self.log
+	}
+
+	/// Get the client's log, so its level, capacity and sinks can be changed.
+	pub fn get_log_mut(&mut self) -> &mut Log {
+		&mut self.log
+	}
+
+	/// Get the address the client is bound to.
+	pub fn get_local_address(&self) -> Result<SocketAddr, ClientError> {
+		Ok(self.transport.local_addr()?)
+	}
+
+	/// Get the amount of unreliable bytes sent to the server each tick. Adapts to the packet loss and round-trip time of
+	/// the connection.
+	pub fn get_send_budget(&self) -> usize {
+		self.congestion.get_budget()
+	}
+
+	/// Get the round-trip time, packet loss and bandwidth of the connection.
+	pub fn get_statistics(&self) -> &ConnectionStatistics {
+		&self.statistics
+	}
+
+	/// Get the next event game code has not handled yet. Should be drained after every tick.
+	pub fn poll_event(&mut self) -> Option<ClientEvent> {
+		self.events.pop_front()
+	}
+
+	/// Get the simulation tick the server's time currently falls into. `None` until the clock is synchronized.
+	pub fn get_simulation_tick(&self) -> Option<u64> {
+		Some(tick_at(self.clock.get_server_time()?, self.config.get_simulation_tick_duration()))
+	}
+
+	/// Get the tick an input sampled now should be sent for, so it reaches the server before the server simulates the
+	/// tick. Leads the server by half the round-trip time, plus some slack for jitter. `None` until the clock is
+	/// synchronized.
+	pub fn get_input_tick(&self) -> Option<u64> {
+		let lead = self.statistics.get_rtt().unwrap_or_default() / 2 + self.statistics.get_jitter() * 2;
+		let time = self.clock.get_server_time()? + lead.as_micros() as i128;
+		Some(tick_at(time, self.config.get_simulation_tick_duration()) + 1)
+	}
+
+	/// Queue an input for the server to simulate on the specified tick. Inputs are resent until the server acknowledges
+	/// them or `MAX_INPUTS_PER_PACKET` newer inputs are queued, so an input is lost if every packet carrying it is
+	/// dropped. Inputs for ticks that already have an input, or that the server already simulated, are ignored.
+	pub fn send_input(&mut self, tick: u64, input: Vec<u8>) -> Result<(), ClientError> {
+		if input.len() > MAX_INPUT_SIZE {
+			return Err(ClientError::MessageTooBig);
+		}
+
+		self.pending_inputs.push(tick, input);
+		Ok(())
+	}
+
+	/// Get the tick of the last input the server said it simulated.
+	pub fn get_last_processed_input_tick(&self) -> Option<u64> {
+		self.pending_inputs.get_last_processed_tick()
+	}
+
+	/// Get the tick of the newest snapshot of the replicated entities.
+	pub fn get_snapshot_tick(&self) -> Option<u64> {
+		self.snapshot_receiver.get_tick()
+	}
+
+	/// Get the entities in the newest snapshot.
+	pub fn get_entities(&self) -> Vec<EntityId> {
+		self.snapshot_receiver.get_entities()
+	}
+
+	/// Get an entity's component from the newest snapshot. `None` if the entity does not have the component, or if the
+	/// component could not be decoded.
+	pub fn get_component<C: Component>(&self, entity: EntityId) -> Option<C> {
+		self.snapshot_receiver.get_component(entity)
+	}
+
+	/// Get the tick entities should be displayed at. Lags behind the server by `ClientConfig::interpolation_delay`, so
+	/// there usually are snapshots on both sides of the tick to interpolate between. `None` until the clock is
+	/// synchronized.
+	pub fn get_interpolation_tick(&self) -> Option<f64> {
+		let time = self.clock.get_server_time()? - self.config.interpolation_delay.as_micros() as i128;
+		Some(time as f64 / self.config.get_simulation_tick_duration().as_micros() as f64)
+	}
+
+	/// Get an entity's component at the interpolation tick, blended between the snapshots surrounding the tick.
+	pub fn get_interpolated<C: Component + Interpolate>(&self, entity: EntityId) -> Option<C> {
+		self.snapshot_receiver.get_interpolated(entity, self.get_interpolation_tick()?)
+	}
+
 	/// Ping the server.
 	pub fn ping(&mut self) -> Result<(), ClientError> {
 		if !self.is_connection_valid() {
@@ -215,37 +521,128 @@ impl Client {
 		}
 
 		self.outgoing_packet.add_sub_payload(SubPayload::Ping(
-			SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+			SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
 		));
 
 		Ok(())
 	}
 
-	/// Attempt to receive data from the socket.
+	/// Queue a message to be reliably sent to the server. Reliable messages are delivered in order, exactly once.
+	pub fn send_reliable(&mut self, message: Vec<u8>) {
+		self.reliable_channel.send(message);
+	}
+
+	/// Queue a message to be sent to the server. Unreliable messages are not resent if they are dropped, and may arrive
+	/// out of order. Messages too big to fit into a packet are fragmented, and are lost if any of their fragments are
+	/// dropped.
+	pub fn send_unreliable(&mut self, message: Vec<u8>) -> Result<(), ClientError> {
+		if message.len() > MAX_UNRELIABLE_MESSAGE_SIZE {
+			return Err(ClientError::MessageTooBig);
+		}
+
+		self.outgoing_packet.add_sub_payload(SubPayload::Unreliable(message));
+		Ok(())
+	}
+
+	/// Queue a message to be sent to the server with the specified priority. Low priority messages are sent after
+	/// everything else once the send budget runs out, and are discarded if they wait for too long.
+	pub fn send_unreliable_with_priority(&mut self, message: Vec<u8>, priority: Priority) -> Result<(), ClientError> {
+		if message.len() > MAX_UNRELIABLE_MESSAGE_SIZE {
+			return Err(ClientError::MessageTooBig);
+		}
+
+		self.fragmenter.push(SubPayload::Unreliable(message), priority, Instant::now())?;
+		Ok(())
+	}
+
+	/// Open a named stream on the server.
+	pub fn open_stream(&mut self, name: &str) -> Result<StreamId, ClientError> {
+		Ok(self.reliable_channel.open_stream(name)?)
+	}
+
+	/// Write data into a stream we opened. The data is fragmented across as many packets as it needs.
+	pub fn write_stream(&mut self, stream_id: StreamId, data: &[u8]) -> Result<(), ClientError> {
+		Ok(self.reliable_channel.write_stream(stream_id, data)?)
+	}
+
+	/// Tell the server we are done writing into a stream.
+	pub fn finish_stream(&mut self, stream_id: StreamId) -> Result<(), ClientError> {
+		Ok(self.reliable_channel.finish_stream(stream_id)?)
+	}
+
+	/// Get a stream the server opened.
+	pub fn get_incoming_stream(&mut self, stream_id: StreamId) -> Option<&mut IncomingStream> {
+		self.reliable_channel.get_incoming_stream(stream_id)
+	}
+
+	/// Remove a stream the server finished writing into, along with its reassembled data.
+	pub fn take_finished_stream(&mut self) -> Option<(StreamId, IncomingStream)> {
+		self.reliable_channel.take_finished_stream()
+	}
+
+	/// Attempt to receive data from the transport.
 	fn recv(&mut self) -> Result<(), ClientError> {
-		let read_bytes = match self.socket.recv(&mut self.receive_buffer) {
-			Ok(a) => a,
-			Err(error) => {
-				return Err(ClientError::Socket(error.kind()));
-			},
-		};
+		let read_bytes = self.receive_from_transport()?;
+		let recv_time = NtpServer::get_micros(); // taken before decoding so NTP timing information stays accurate
 
 		// make sure what we just read is not too big to be an eggine packet
 		if read_bytes > MAX_PACKET_SIZE {
-			self.log.print(LogLevel::Error, format!("received too big of a packet"), 0);
+			self.log.print(LogLevel::Error, String::from("received too big of a packet"), 0);
 			return Err(ClientError::PacketTooBig);
 		}
 
-		self.last_activity = Instant::now();
-
-		// import raw bytes into the receive stream
 		// TODO optimize this
 		let mut buffer: Vec<u8> = Vec::new();
 		buffer.extend(&self.receive_buffer[0..read_bytes]);
+
+		// drop packets that were not sent by the server
+		if let Some(session) = self.session.as_mut() {
+			if let Err(error) = session.open(&mut buffer) {
+				self.log.print(LogLevel::Error, format!("could not authenticate packet for {:?}", error), 0);
+				return Err(error.into());
+			}
+		}
+
+		self.last_activity = Instant::now();
+
+		// import raw bytes into the receive stream
 		self.receive_stream.import(buffer)?;
 
 		// if the connection has not been initialized yet, we need to check if the server sent us back a handshake
 		if !self.connection_initialized {
+			// the server wants us to prove we can receive data at our address, so send our handshake again with its cookie
+			if self.receive_buffer[0..read_bytes].starts_with(CHALLENGE_MAGIC_NUMBER) {
+				// we already answered a challenge, so this one was duplicated or sent in reply to a resent handshake
+				if self.handshake.cookie.is_some() {
+					return Ok(());
+				}
+
+				let challenge = self.receive_stream.decode::<Challenge>()?.0;
+				self.handshake.cookie = Some(challenge.cookie);
+				return self.send_handshake();
+			}
+
+			// the server does not accept our handshake. it only rejects handshakes that carry its cookie, so rejections sent
+			// before we answered a challenge are spoofed. later ones may be spoofed too, so we keep resending our handshake
+			// until it times out in case the server accepts it after all
+			if self.receive_buffer[0..read_bytes].starts_with(REJECTION_MAGIC_NUMBER) {
+				if self.handshake.cookie.is_none() {
+					self.log.print(LogLevel::Warning, String::from("ignoring rejection sent before challenge"), 0);
+					return Ok(());
+				}
+
+				let rejection = self.receive_stream.decode::<Rejection>()?.0;
+				self.log.print(
+					LogLevel::Error,
+					format!("server running {} rejected us with reason {:?}", rejection.version, rejection.reason),
+					0
+				);
+
+				self.rejection = Some(rejection.reason);
+				self.server_version = Some(rejection.version);
+				return Ok(());
+			}
+
 			// check handshake
 			let handshake = self.receive_stream.decode::<Handshake>()?.0;
 			if !self.handshake.is_compatible(&handshake) {
@@ -255,20 +652,37 @@ impl Client {
 				return Err(ClientError::Handshake);
 			}
 
+			// derive the keys used to encrypt the connection
+			let key_exchange = self.key_exchange.take().ok_or(ClientError::Handshake)?;
+			match key_exchange.finish(handshake.public_key, Role::Client) {
+				Ok(session) => self.session = Some(session),
+				Err(error) => {
+					self.log.print(LogLevel::Error, format!("could not exchange keys for {:?}", error), 1);
+					return Err(ClientError::Handshake);
+				},
+			}
+
+			self.server_version = Some(handshake.version.clone());
+
 			// set our sequence numbers
 			self.last_sequence_received = Some(handshake.sequences.0);
 			self.sequence = handshake.sequences.1;
+			self.highest_acknowledge_received = Some(handshake.sequences.1);
 			self.ntp_id_client = handshake.ntp_id;
-			self.ntp_server.as_mut().unwrap().associate_host_id(self.ntp_id_server, self.ntp_id_client);
+			let address = self.server_address.unwrap();
+			self.ntp_server.as_mut().unwrap().associate_host_id(address, self.ntp_id_server, self.ntp_id_client);
 
-			self.log.print(LogLevel::Info, format!("connection established"), 0);
+			self.log.print(LogLevel::Info, String::from("connection established"), 0);
 			self.connection_initialized = true;
+			self.handshake_start_time = None;
+			self.rejection = None;
+			self.events.push_back(ClientEvent::Connected);
 
 			return Ok(());
 		}
 
 		// figure out what to do with the packet we just got
-		let packet = self.receive_stream.decode::<Packet>()?.0;
+		let mut packet = self.receive_stream.decode::<Packet>()?.0;
 
 		let result = packet.handle_sequences(
 			self.highest_acknowledge_received,
@@ -280,37 +694,190 @@ impl Client {
 		self.last_sequence_received = Some(result.remote_sequence);
 		self.highest_acknowledge_received = Some(result.new_highest_acknowledged_sequence);
 
-		for sub_payload in packet.get_sub_payloads() {
+		let now = Instant::now();
+		self.statistics.record_received(read_bytes, result.out_of_order, now);
+
+		for sequence in result.acknowledged_sequences {
+			self.reliable_channel.handle_acknowledged(sequence);
+			self.statistics.record_acknowledged(sequence, now);
+		}
+
+		for sequence in result.dropped_sequences {
+			self.log.print_with(LogLevel::Trace, String::from("packet dropped"), 0, LogFields::default().sequence(sequence));
+			self.reliable_channel.handle_dropped(sequence);
+			self.statistics.record_dropped(sequence);
+			self.congestion.handle_dropped(sequence, self.sequence, now);
+		}
+
+		// reassembled sub-payloads are handled along with the rest of the packet
+		let mut sub_payloads = VecDeque::from(packet.take_sub_payloads());
+
+		// acknowledge packets that carry data quickly, so the server can measure round-trip time and packet loss
+		self.acknowledge_pending |= !sub_payloads.is_empty();
+		while let Some(sub_payload) = sub_payloads.pop_front() {
 			match sub_payload {
 				SubPayload::Disconnect(reason) => {
 					self.log.print(LogLevel::Info, format!("server told us to disconnect with reason {:?}", reason), 0);
-					return Err(ClientError::Disconnected(*reason));
+
+					// stop sending packets. copies of the disconnect are rejected as replays by the session
+					self.connection_initialized = false;
+					self.events.push_back(ClientEvent::Disconnected(reason.clone()));
+					return Err(ClientError::Disconnected(reason));
+				},
+				SubPayload::Fragment(fragment) => {
+					if let Some(sub_payload) = self.reassembler.handle_fragment(fragment, now)? {
+						sub_payloads.push_back(sub_payload);
+					}
+				},
+				SubPayload::Input(..) => {},
+				SubPayload::InputAck(tick) => self.pending_inputs.acknowledge(tick),
+				SubPayload::Ntp(bytes) => {
+					let Some(ntp_server) = self.ntp_server.as_mut() else {
+						continue;
+					};
+
+					let source = self.server_address.unwrap();
+					if let Err(error) = ntp_server.process(source, &bytes, recv_time) {
+						let fields = LogFields::default().peer(source);
+						self.log.print_with(LogLevel::Warning, format!("could not process NTP packet for {:?}", error), 0, fields);
+					} else if let Some(statistics) = ntp_server.get_statistics(self.ntp_id_server) {
+						self.clock.update(statistics, recv_time);
+					}
 				},
 				SubPayload::Ping(time) => {
-					self.log.print(LogLevel::Info, format!("got ping with time {}", time), 0);
+					self.log.print(LogLevel::Trace, format!("got ping with time {}", time), 0);
 
-					// send a pong to the server
-					self.outgoing_packet.add_sub_payload(SubPayload::Pong(
-						SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
-					));
+					// send a pong to the server, echoing the ping's time so it can measure round-trip time
+					self.outgoing_packet.add_sub_payload(SubPayload::Pong(time));
 				},
 				SubPayload::Pong(time) => {
-					self.log.print(LogLevel::Info, format!("got pong with time {}", time), 0);
-				}
+					self.statistics.record_pong(time);
+					self.log.print(
+						LogLevel::Trace, format!("got pong. round-trip duration is {:?}", self.statistics.get_rtt()), 0
+					);
+				},
+				SubPayload::Reliable(id, message) => {
+					let result = self.reliable_channel.handle_message(id, &message);
+					while let Some(message) = self.reliable_channel.receive() {
+						self.events.push_back(ClientEvent::Message(Channel::Reliable, message));
+					}
+
+					if let Err(error) = result {
+						self.log.print(LogLevel::Error, format!("reset stream for {:?}", error), 0);
+						return Err(error.into());
+					}
+				},
+				SubPayload::Snapshot(chunk) => self.snapshot_receiver.handle_chunk(chunk),
+				SubPayload::Unreliable(message) => {
+					self.events.push_back(ClientEvent::Message(Channel::Unreliable, message));
+				},
 			}
 		}
 
-		Ok(())
+		// reply to NTP requests right away, since the replies contain the time they were sent at
+		self.send_ntp_packets()
+	}
+
+	/// Send our handshake to the server, padded to `HANDSHAKE_PADDED_SIZE` so the server's reply is never bigger than it.
+	fn send_handshake(&mut self) -> Result<(), ClientError> {
+		self.send_stream.encode(&self.handshake)?;
+
+		let mut bytes = self.send_stream.export()?;
+		if bytes.len() < HANDSHAKE_PADDED_SIZE {
+			bytes.resize(HANDSHAKE_PADDED_SIZE, 0);
+		}
+
+		self.last_handshake_time = Some(Instant::now());
+		self.send_bytes(&bytes)
 	}
 
 	/// Send a byte vector to the server.
-	fn send_bytes(&mut self, bytes: &Vec<u8>) -> Result<(), ClientError> {
-		// TODO check if the amount of bytes sent in the socket matches the size of the exported vector
-		match self.socket.send(&bytes) {
+	fn send_bytes(&mut self, bytes: &[u8]) -> Result<(), ClientError> {
+		// TODO check if the amount of bytes sent in the transport matches the size of the exported vector
+		if let Some(conditioner) = self.send_conditioner.as_mut() {
+			conditioner.push(bytes.to_vec(), Instant::now());
+			return Ok(());
+		}
+
+		let address = self.server_address.ok_or(ClientError::Socket(std::io::ErrorKind::NotConnected))?;
+		match self.transport.send_to(bytes, address) {
 			Ok(_) => Ok(()),
 			Err(error) => {
 				Err(ClientError::Socket(error.kind()))
 			},
 		}
 	}
+
+	/// Send the packets the NTP server queued up. Each NTP packet is sealed in its own packet and sent right away, so
+	/// the time it was sent at is not thrown off by waiting for the next tick.
+	fn send_ntp_packets(&mut self) -> Result<(), ClientError> {
+		while let Some((_, bytes)) = self.ntp_server.as_mut().and_then(|ntp_server| ntp_server.poll_packet()) {
+			// NTP packets are only sent once the session is established
+			let Some(session) = self.session.as_mut() else {
+				continue;
+			};
+
+			self.sequence = self.sequence.wrapping_add(1);
+			let mut packet = Packet::new(self.sequence, 0);
+			packet.add_sub_payload(SubPayload::Ntp(bytes));
+			packet.prepare(self.acknowledge_mask, self.sequence, self.last_sequence_received.unwrap_or(0));
+			Session::prepare(&mut packet);
+
+			self.send_stream.encode(&packet)?;
+
+			let mut bytes = self.send_stream.export()?;
+			session.seal(&mut bytes)?;
+
+			let now = Instant::now();
+			self.last_send_time = now;
+			self.statistics.record_sent(self.sequence, bytes.len(), now);
+			self.send_bytes(&bytes)?;
+		}
+
+		Ok(())
+	}
+
+	/// Send the data held back by simulated network conditions that is ready to be delivered.
+	fn flush_send_conditioner(&mut self) -> Result<(), ClientError> {
+		if let Some(conditioner) = self.send_conditioner.as_mut() {
+			let address = self.server_address.ok_or(ClientError::Socket(std::io::ErrorKind::NotConnected))?;
+			let now = Instant::now();
+			while let Some(bytes) = conditioner.pop(now) {
+				self.transport.send_to(&bytes, address)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Receive data sent by the server from the transport into the receive buffer. If we are simulating network
+	/// conditions, received data is held back until it is ready to be delivered.
+	fn receive_from_transport(&mut self) -> Result<usize, ClientError> {
+		let now = Instant::now();
+		loop {
+			let (read_bytes, source) = match self.transport.recv_from(&mut self.receive_buffer) {
+				Ok(result) => result,
+				Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => break,
+				Err(error) => return Err(ClientError::Socket(error.kind())),
+			};
+
+			// the transport is not connected to the server, so anyone can send data to it
+			if Some(source) != self.server_address {
+				continue;
+			}
+
+			let Some(conditioner) = self.receive_conditioner.as_mut() else {
+				return Ok(read_bytes);
+			};
+
+			conditioner.push(self.receive_buffer[0..read_bytes].to_vec(), now);
+		}
+
+		let Some(bytes) = self.receive_conditioner.as_mut().and_then(|conditioner| conditioner.pop(now)) else {
+			return Err(ClientError::Socket(std::io::ErrorKind::WouldBlock));
+		};
+
+		self.receive_buffer[0..bytes.len()].copy_from_slice(&bytes);
+		Ok(bytes.len())
+	}
 }
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use crate::link_conditioner::LinkConditions;
+use crate::log::{ LogLevel, DEFAULT_LOG_CAPACITY, };
+
+/// Timings used by a client.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+	/// How far behind the server's time replicated entities are displayed, so there usually is a snapshot after the
+	/// displayed time to interpolate toward.
+	pub interpolation_delay: Duration,
+	/// If we have not sent the server anything for this long, we send it an empty packet so it knows we are still here.
+	pub keepalive_interval: Duration,
+	/// The amount of log records the client keeps around.
+	pub log_capacity: usize,
+	/// Log records below this level are discarded.
+	pub log_level: LogLevel,
+	/// How often we send NTP requests to the server.
+	pub ntp_sync_interval: Duration,
+	/// Simulated network conditions applied to packets we receive. Only meant for testing.
+	pub receive_conditions: Option<LinkConditions>,
+	/// Simulated network conditions applied to packets we send. Only meant for testing.
+	pub send_conditions: Option<LinkConditions>,
+	/// The amount of simulation ticks per second. Has to match the server's simulation tick rate.
+	pub simulation_tick_rate: u32,
+	/// The amount of times `Client::tick` is expected to be called per second.
+	pub tick_rate: u32,
+	/// How long the server can go without sending us anything, or without accepting our handshake, before we consider
+	/// ourselves disconnected.
+	pub timeout: Duration,
+}
+
+impl Default for ClientConfig {
+	fn default() -> Self {
+		ClientConfig {
+			interpolation_delay: Duration::from_millis(100),
+			keepalive_interval: Duration::from_secs(1),
+			log_capacity: DEFAULT_LOG_CAPACITY,
+			log_level: LogLevel::Info,
+			ntp_sync_interval: Duration::from_secs(1),
+			receive_conditions: None,
+			send_conditions: None,
+			simulation_tick_rate: 60,
+			tick_rate: 30,
+			timeout: Duration::from_secs(30),
+		}
+	}
+}
+
+impl ClientConfig {
+	/// The amount of time between ticks.
+	pub fn get_tick_duration(&self) -> Duration {
+		Duration::from_secs(1) / self.tick_rate.max(1)
+	}
+
+	/// The amount of time between simulation ticks.
+	pub fn get_simulation_tick_duration(&self) -> Duration {
+		Duration::from_secs(1) / self.simulation_tick_rate.max(1)
+	}
+}
@@ -1 +1,4 @@
 pub mod client;
+pub mod config;
+
+pub use config::ClientConfig;
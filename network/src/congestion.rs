@@ -0,0 +1,323 @@
+use std::time::{ Duration, Instant, };
+
+use crate::connection_statistics::ConnectionStatistics;
+use crate::payload::packet::is_newer_sequence;
+use crate::MAX_PACKET_SIZE;
+
+/// The maximum amount of packets we send to a remote per tick. Keeps the amount of packets the remote receives between
+/// two of its acknowledgements within the 128 packets that fit into an acknowledge mask.
+pub(crate) const MAX_PACKETS_PER_TICK: usize = 16;
+
+/// The least amount of unreliable bytes we send per tick.
+const MIN_BUDGET: usize = MAX_PACKET_SIZE;
+
+/// The amount of unreliable bytes we send per tick before we know anything about the connection.
+const INITIAL_BUDGET: usize = 4 * MAX_PACKET_SIZE;
+
+/// The most amount of unreliable bytes we send per tick.
+const MAX_BUDGET: usize = MAX_PACKETS_PER_TICK * MAX_PACKET_SIZE;
+
+/// How much the budget grows every round-trip that we use all of it without any packets getting dropped.
+const BUDGET_INCREASE: usize = MAX_PACKET_SIZE;
+
+/// How much the smoothed round-trip time can grow past twice the lowest round-trip time we measured before we assume
+/// that packets are queueing up somewhere along the route.
+const DELAY_TOLERANCE: Duration = Duration::from_millis(25);
+
+/// Decides how many bytes of unreliable data are sent to a remote each tick. The budget is halved when packets are
+/// dropped or when round-trip time grows, and slowly grows back while the connection keeps up.
+#[derive(Debug)]
+pub struct CongestionController {
+	/// The amount of unreliable bytes we send per tick.
+	budget: usize,
+	/// The last time we shrunk the budget.
+	last_decrease: Instant,
+	/// The last time we grew the budget.
+	last_increase: Instant,
+	/// The lowest smoothed round-trip time we measured.
+	min_rtt: Option<Duration>,
+	/// The newest sequence we had sent when we last shrunk the budget because of a dropped packet. Packets sent before
+	/// it were sent at the old rate, so dropping them does not shrink the budget again.
+	recovery_sequence: Option<u32>,
+}
+
+impl Default for CongestionController {
+	fn default() -> Self {
+		CongestionController {
+			budget: INITIAL_BUDGET,
+			last_decrease: Instant::now(),
+			last_increase: Instant::now(),
+			min_rtt: None,
+			recovery_sequence: None,
+		}
+	}
+}
+
+impl CongestionController {
+	/// The amount of unreliable bytes we send per tick.
+	pub fn get_budget(&self) -> usize {
+		self.budget
+	}
+
+	/// Handle a packet the remote did not receive. `sequence` is the newest sequence we sent.
+	pub(crate) fn handle_dropped(&mut self, dropped_sequence: u32, sequence: u32, now: Instant) {
+		if self.recovery_sequence.is_some_and(|recovery| !is_newer_sequence(dropped_sequence, recovery)) {
+			return;
+		}
+
+		self.recovery_sequence = Some(sequence);
+		self.decrease(now);
+	}
+
+	/// Adjust the budget after a tick. `limited` is whether or not the budget kept us from sending everything we had
+	/// queued, since there is no point in growing a budget we are not using.
+	pub(crate) fn update(&mut self, statistics: &ConnectionStatistics, limited: bool, now: Instant) {
+		let Some(rtt) = statistics.get_rtt() else {
+			return;
+		};
+
+		let min_rtt = self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt));
+		self.min_rtt = Some(min_rtt);
+
+		// queues along the route fill up before they start dropping packets, which shows up as round-trip time growing
+		if rtt > min_rtt * 2 + DELAY_TOLERANCE {
+			if now - self.last_decrease >= rtt {
+				self.decrease(now);
+			}
+
+			return;
+		}
+
+		if limited && now - self.last_increase.max(self.last_decrease) >= rtt {
+			self.budget = (self.budget + BUDGET_INCREASE).min(MAX_BUDGET);
+			self.last_increase = now;
+		}
+	}
+
+	fn decrease(&mut self, now: Instant) {
+		self.budget = (self.budget / 2).max(MIN_BUDGET);
+		self.last_decrease = now;
+	}
+}
+
+/// Test congestion control.
+#[cfg(test)]
+mod tests {
+	use rand::{ Rng, SeedableRng, };
+	use rand::rngs::StdRng;
+	use std::collections::VecDeque;
+	use std::net::{ SocketAddr, UdpSocket, };
+	use std::time::{ Duration, Instant, };
+
+	use crate::{ Channel, Client, ClientEvent, ClientId, Priority, Server, ServerEvent, };
+	use crate::connection_statistics::ConnectionStatistics;
+
+	use super::{ CongestionController, BUDGET_INCREASE, INITIAL_BUDGET, };
+
+	/// Forwards packets between a client and a server, dropping and delaying them along the way.
+	struct LossyProxy {
+		/// The address of the client, known once it sends us its first packet.
+		client_address: Option<SocketAddr>,
+		/// The socket the client talks to.
+		client_socket: UdpSocket,
+		delay: Duration,
+		/// The chance that a packet is dropped.
+		loss: f64,
+		/// Packets waiting to be delivered, along with the time they are delivered at and whether or not they are headed
+		/// to the server.
+		queue: VecDeque<(Instant, Vec<u8>, bool)>,
+		rng: StdRng,
+		/// The socket we talk to the server with.
+		server_socket: UdpSocket,
+	}
+
+	impl LossyProxy {
+		fn new(address: &str, server_address: &str) -> Self {
+			let client_socket = UdpSocket::bind(address).expect("Could not bind proxy socket");
+			client_socket.set_nonblocking(true).expect("Could not make proxy socket non-blocking");
+
+			let server_socket = UdpSocket::bind("127.0.0.1:0").expect("Could not bind proxy socket");
+			server_socket.set_nonblocking(true).expect("Could not make proxy socket non-blocking");
+			server_socket.connect(server_address).expect("Could not connect proxy socket");
+
+			LossyProxy {
+				client_address: None,
+				client_socket,
+				delay: Duration::from_millis(20),
+				loss: 0.0,
+				queue: VecDeque::new(),
+				rng: StdRng::seed_from_u64(0),
+				server_socket,
+			}
+		}
+
+		fn forward(&mut self, now: Instant) {
+			let mut buffer = [0; 2048];
+			while let Ok((length, source)) = self.client_socket.recv_from(&mut buffer) {
+				self.client_address = Some(source);
+				if !self.rng.gen_bool(self.loss) {
+					self.queue.push_back((now + self.delay, buffer[0..length].to_vec(), true));
+				}
+			}
+
+			while let Ok(length) = self.server_socket.recv(&mut buffer) {
+				if !self.rng.gen_bool(self.loss) {
+					self.queue.push_back((now + self.delay, buffer[0..length].to_vec(), false));
+				}
+			}
+
+			while self.queue.front().is_some_and(|(deliver_at, _, _)| *deliver_at <= now) {
+				let (_, bytes, to_server) = self.queue.pop_front().unwrap();
+				if to_server {
+					let _ = self.server_socket.send(&bytes);
+				} else if let Some(client_address) = self.client_address {
+					let _ = self.client_socket.send_to(&bytes, client_address);
+				}
+			}
+		}
+	}
+
+	/// A server and a client talking through a `LossyProxy`.
+	struct Harness {
+		client: Client,
+		client_id: Option<ClientId>,
+		proxy: LossyProxy,
+		/// Reliable messages the client received.
+		reliable_messages: Vec<Vec<u8>>,
+		server: Server,
+	}
+
+	impl Harness {
+		/// Tick the server, client and proxies for the specified duration, sending the client low priority state every
+		/// tick once it is connected. Returns the amount of state messages the client received.
+		async fn run(&mut self, duration: Duration) -> usize {
+			let mut received = 0;
+			let start = Instant::now();
+			while Instant::now() - start < duration {
+				if let Some(client_id) = self.client_id {
+					for _ in 0..16 {
+						self.server.send_unreliable_with_priority(client_id, vec![0; 1000], Priority::Low)
+							.expect("Could not send state");
+					}
+				}
+
+				if let Err(error) = self.server.tick().await {
+					assert!(!error.is_fatal(), "{:?}", error);
+				}
+
+				self.forward();
+
+				if let Err(error) = self.client.tick().await {
+					assert!(!error.is_fatal(), "{:?}", error);
+				}
+
+				self.forward();
+
+				while let Some(event) = self.server.poll_event() {
+					if let ServerEvent::Connected(id) = event {
+						self.client_id = Some(id);
+					}
+				}
+
+				while let Some(event) = self.client.poll_event() {
+					match event {
+						ClientEvent::Message(Channel::Reliable, message) => self.reliable_messages.push(message),
+						ClientEvent::Message(Channel::Unreliable, _) => received += 1,
+						_ => {},
+					}
+				}
+
+				tokio::time::sleep(Duration::from_millis(10)).await;
+			}
+
+			received
+		}
+
+		fn forward(&mut self) {
+			self.proxy.forward(Instant::now());
+		}
+	}
+
+	// statistics for a connection whose round-trip time is `rtt`
+	fn statistics_with_rtt(rtt: Duration, now: Instant) -> ConnectionStatistics {
+		let mut statistics = ConnectionStatistics::default();
+		statistics.record_sent(0, 100, now - rtt);
+		statistics.record_acknowledged(0, now);
+		statistics
+	}
+
+	/// Ensure that dropped packets only shrink the budget once per round of packets.
+	#[test]
+	fn loss() {
+		let now = Instant::now();
+		let mut controller = CongestionController::default();
+		controller.handle_dropped(5, 10, now);
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET / 2);
+
+		// sent before the budget shrunk
+		controller.handle_dropped(8, 12, now);
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET / 2);
+
+		controller.handle_dropped(11, 20, now);
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET / 4);
+	}
+
+	/// Ensure that the budget grows once per round-trip, but only while we use all of it.
+	#[test]
+	fn growth() {
+		let now = Instant::now();
+		let statistics = statistics_with_rtt(Duration::from_millis(50), now);
+		let mut controller = CongestionController::default();
+		controller.update(&statistics, true, now + Duration::from_millis(100));
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET + BUDGET_INCREASE);
+
+		controller.update(&statistics, true, now + Duration::from_millis(120));
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET + BUDGET_INCREASE);
+
+		controller.update(&statistics, false, now + Duration::from_millis(300));
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET + BUDGET_INCREASE);
+	}
+
+	/// Ensure that the budget shrinks when round-trip time grows.
+	#[test]
+	fn delay() {
+		let now = Instant::now();
+		let mut controller = CongestionController::default();
+		controller.update(&statistics_with_rtt(Duration::from_millis(50), now), false, now);
+		controller.update(&statistics_with_rtt(Duration::from_millis(200), now), true, now + Duration::from_secs(1));
+		assert_eq!(controller.get_budget(), INITIAL_BUDGET / 2);
+	}
+
+	/// Ensure that the budget adapts to a lossy connection, and recovers once the connection stops dropping packets.
+	#[tokio::test]
+	async fn lossy_proxy() {
+		let mut client = Client::new("127.0.0.1:32202").expect("Could not start client");
+		let server = Server::new("127.0.0.1:32200").await.expect("Could not start server");
+		let proxy = LossyProxy::new("127.0.0.1:32201", "127.0.0.1:32200");
+		client.initialize_connection("127.0.0.1:32201").await.expect("Could not connect to proxy");
+
+		let mut harness = Harness {
+			client,
+			client_id: None,
+			proxy,
+			reliable_messages: Vec::new(),
+			server,
+		};
+
+		harness.run(Duration::from_millis(500)).await;
+		let id = harness.client_id.expect("Client did not connect");
+		harness.server.send_reliable(id, vec![1, 2, 3]).expect("Could not send message");
+
+		harness.proxy.loss = 0.2;
+		let received = harness.run(Duration::from_secs(2)).await;
+		let lossy_budget = harness.server.get_send_budget(id).expect("Could not get send budget");
+		assert!(received > 0);
+		assert!(lossy_budget < INITIAL_BUDGET);
+		assert!(harness.server.get_statistics(id).expect("Could not get statistics").get_packet_loss() > 0.0);
+
+		harness.proxy.loss = 0.0;
+		harness.run(Duration::from_secs(3)).await;
+		assert!(harness.server.get_send_budget(id).expect("Could not get send budget") > lossy_budget);
+		assert_eq!(harness.reliable_messages, vec![vec![1, 2, 3]]);
+	}
+}
@@ -0,0 +1,239 @@
+use std::collections::{ HashMap, VecDeque, };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH, };
+
+/// The window that per-second rates are measured over.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// The amount of acknowledged/dropped packets that packet loss is measured over.
+const LOSS_WINDOW_SIZE: usize = 256;
+
+/// The amount of packets we remember the send time of. Packets that are never acknowledged or dropped are forgotten
+/// once we have sent this many packets after them.
+const MAX_SEND_TIMES: usize = 1024;
+
+/// Counts how much of something happened over the last `RATE_WINDOW`.
+#[derive(Debug, Default)]
+struct RateCounter {
+	samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateCounter {
+	fn add(&mut self, amount: u64, now: Instant) {
+		self.prune(now);
+		self.samples.push_back((now, amount));
+	}
+
+	fn get_rate(&self, now: Instant) -> u64 {
+		self.samples.iter()
+			.filter(|(time, _)| now.saturating_duration_since(*time) < RATE_WINDOW)
+			.map(|(_, amount)| amount)
+			.sum()
+	}
+
+	fn prune(&mut self, now: Instant) {
+		while let Some((time, _)) = self.samples.front() {
+			if now.saturating_duration_since(*time) < RATE_WINDOW {
+				break;
+			}
+
+			self.samples.pop_front();
+		}
+	}
+}
+
+/// Measures the quality of a connection. Round-trip time is sampled from pings and packet acknowledgements, and is
+/// smoothed the same way TCP smooths it.
+#[derive(Debug, Default)]
+pub struct ConnectionStatistics {
+	bytes_received: RateCounter,
+	bytes_sent: RateCounter,
+	/// Mean deviation of round-trip time samples from the smoothed round-trip time.
+	jitter: Duration,
+	/// Whether or not each of the last `LOSS_WINDOW_SIZE` packets we sent was dropped.
+	loss_samples: VecDeque<bool>,
+	/// The amount of packets we received that were older than a packet we already received.
+	out_of_order: u64,
+	packets_received: RateCounter,
+	packets_sent: RateCounter,
+	/// Smoothed round-trip time.
+	rtt: Option<Duration>,
+	/// The time we sent each packet that has not been acknowledged or dropped yet.
+	send_times: HashMap<u32, Instant>,
+	/// The order we sent the packets in `send_times` in, so we know which ones to forget first.
+	send_order: VecDeque<u32>,
+}
+
+impl ConnectionStatistics {
+	/// The smoothed round-trip time. `None` until we receive our first acknowledgement or pong.
+	pub fn get_rtt(&self) -> Option<Duration> {
+		self.rtt
+	}
+
+	/// How much round-trip time samples vary.
+	pub fn get_jitter(&self) -> Duration {
+		self.jitter
+	}
+
+	/// The fraction of recently sent packets that were dropped, between 0 and 1.
+	pub fn get_packet_loss(&self) -> f64 {
+		if self.loss_samples.is_empty() {
+			return 0.0;
+		}
+
+		self.loss_samples.iter().filter(|dropped| **dropped).count() as f64 / self.loss_samples.len() as f64
+	}
+
+	pub fn get_bytes_sent_per_second(&self) -> u64 {
+		self.bytes_sent.get_rate(Instant::now())
+	}
+
+	pub fn get_bytes_received_per_second(&self) -> u64 {
+		self.bytes_received.get_rate(Instant::now())
+	}
+
+	pub fn get_packets_sent_per_second(&self) -> u64 {
+		self.packets_sent.get_rate(Instant::now())
+	}
+
+	pub fn get_packets_received_per_second(&self) -> u64 {
+		self.packets_received.get_rate(Instant::now())
+	}
+
+	/// The amount of packets received that were older than a packet we already received.
+	pub fn get_out_of_order_count(&self) -> u64 {
+		self.out_of_order
+	}
+
+	/// Record a packet we sent.
+	pub(crate) fn record_sent(&mut self, sequence: u32, bytes: usize, now: Instant) {
+		self.bytes_sent.add(bytes as u64, now);
+		self.packets_sent.add(1, now);
+
+		self.send_times.insert(sequence, now);
+		self.send_order.push_back(sequence);
+		while self.send_order.len() > MAX_SEND_TIMES {
+			if let Some(sequence) = self.send_order.pop_front() {
+				self.send_times.remove(&sequence);
+			}
+		}
+	}
+
+	/// Record a packet we received.
+	pub(crate) fn record_received(&mut self, bytes: usize, out_of_order: bool, now: Instant) {
+		self.bytes_received.add(bytes as u64, now);
+		self.packets_received.add(1, now);
+
+		if out_of_order {
+			self.out_of_order += 1;
+		}
+	}
+
+	/// Record that the remote acknowledged one of our packets.
+	pub(crate) fn record_acknowledged(&mut self, sequence: u32, now: Instant) {
+		self.record_loss(false);
+		if let Some(send_time) = self.send_times.remove(&sequence) {
+			self.record_rtt(now.saturating_duration_since(send_time));
+		}
+	}
+
+	/// Record that the remote did not receive one of our packets.
+	pub(crate) fn record_dropped(&mut self, sequence: u32) {
+		self.record_loss(true);
+		self.send_times.remove(&sequence);
+	}
+
+	/// Record a pong for a ping we sent at `ping_time`, in microseconds since the unix epoch.
+	pub(crate) fn record_pong(&mut self, ping_time: u64) {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+		self.record_rtt(Duration::from_micros(now.saturating_sub(ping_time)));
+	}
+
+	fn record_loss(&mut self, dropped: bool) {
+		if self.loss_samples.len() >= LOSS_WINDOW_SIZE {
+			self.loss_samples.pop_front();
+		}
+
+		self.loss_samples.push_back(dropped);
+	}
+
+	fn record_rtt(&mut self, sample: Duration) {
+		match self.rtt {
+			Some(rtt) => {
+				let deviation = sample.abs_diff(rtt);
+				self.jitter = self.jitter.mul_f64(0.75) + deviation.mul_f64(0.25);
+				self.rtt = Some(rtt.mul_f64(0.875) + sample.mul_f64(0.125));
+			},
+			None => {
+				self.jitter = sample / 2;
+				self.rtt = Some(sample);
+			},
+		}
+	}
+}
+
+/// Test connection statistics.
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, Instant, };
+
+	use super::{ ConnectionStatistics, RateCounter, LOSS_WINDOW_SIZE, };
+
+	/// Ensure that rates only count what happened within the last second.
+	#[test]
+	fn rates() {
+		let start = Instant::now();
+		let mut counter = RateCounter::default();
+		counter.add(100, start);
+		counter.add(50, start + Duration::from_millis(500));
+		assert_eq!(counter.get_rate(start + Duration::from_millis(900)), 150);
+		assert_eq!(counter.get_rate(start + Duration::from_millis(1200)), 50);
+
+		counter.add(25, start + Duration::from_millis(2000));
+		assert_eq!(counter.samples.len(), 1);
+		assert_eq!(counter.get_rate(start + Duration::from_millis(2000)), 25);
+	}
+
+	/// Ensure that round-trip time is measured from acknowledgements, and smoothed.
+	#[test]
+	fn rtt() {
+		let start = Instant::now();
+		let mut statistics = ConnectionStatistics::default();
+		assert_eq!(statistics.get_rtt(), None);
+
+		statistics.record_sent(10, 100, start);
+		statistics.record_sent(11, 100, start);
+		statistics.record_acknowledged(10, start + Duration::from_millis(100));
+		assert_eq!(statistics.get_rtt(), Some(Duration::from_millis(100)));
+
+		// a single slow packet only moves the smoothed round-trip time a little
+		statistics.record_acknowledged(11, start + Duration::from_millis(900));
+		let rtt = statistics.get_rtt().expect("Could not get round-trip time");
+		assert!(rtt > Duration::from_millis(195) && rtt < Duration::from_millis(205));
+		assert!(statistics.get_jitter() > Duration::from_millis(50));
+
+		// packets are only measured once
+		statistics.record_acknowledged(11, start + Duration::from_millis(5000));
+		assert_eq!(statistics.get_rtt(), Some(rtt));
+	}
+
+	/// Ensure that packet loss is measured over the most recent packets.
+	#[test]
+	fn packet_loss() {
+		let mut statistics = ConnectionStatistics::default();
+		assert_eq!(statistics.get_packet_loss(), 0.0);
+
+		for sequence in 0..100 {
+			if sequence % 4 == 0 {
+				statistics.record_dropped(sequence);
+			} else {
+				statistics.record_acknowledged(sequence, Instant::now());
+			}
+		}
+		assert_eq!(statistics.get_packet_loss(), 0.25);
+
+		for sequence in 0..LOSS_WINDOW_SIZE as u32 {
+			statistics.record_acknowledged(sequence, Instant::now());
+		}
+		assert_eq!(statistics.get_packet_loss(), 0.0);
+	}
+}
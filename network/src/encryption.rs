@@ -0,0 +1,314 @@
+use chacha20poly1305::{ AeadInPlace, ChaCha20Poly1305, Key, KeyInit, Nonce, Tag, };
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{ EphemeralSecret, PublicKey, };
+
+use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::payload::{ Packet, PacketExtension, };
+use crate::payload::packet::{ is_newer_sequence, PACKET_HEADER_SIZE, };
+
+/// The size of an X25519 public key.
+pub const PUBLIC_KEY_SIZE: usize = 32;
+
+/// The size of the authentication tag appended to encrypted packets.
+pub const TAG_SIZE: usize = 16;
+
+/// The amount of sequence numbers behind the highest sequence number we received that we still accept packets for.
+const REPLAY_WINDOW_SIZE: u32 = 128;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EncryptionError {
+	/// Emitted if a packet's authentication tag did not match its contents.
+	Authentication,
+	/// Emitted if the key exchange resulted in a shared secret that does not depend on our key, which happens if the
+	/// remote sent us a low order public key.
+	InvalidPublicKey,
+	/// Emitted if we received a packet without the `PacketExtension::Encrypted` extension.
+	NotEncrypted,
+	/// Emitted if we received a packet with a sequence number we already received, or one that is too old to tell.
+	Replayed,
+	/// Emitted if the packet is too short to contain a header and an authentication tag.
+	Truncated,
+}
+
+impl NetworkStreamErrorTrait for EncryptionError {
+	fn as_any(&self) -> &dyn std::any::Any {
+		self
+	}
+}
+
+/// Which end of the connection we are. Each direction of a connection is encrypted with a different key, so packets
+/// sent by one end cannot be reflected back at it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Role {
+	Client,
+	Server,
+}
+
+/// Our half of an X25519 key exchange. The secret is only used for one connection, and is consumed once the remote's
+/// public key is known.
+pub(crate) struct KeyExchange {
+	public_key: PublicKey,
+	secret: EphemeralSecret,
+}
+
+impl std::fmt::Debug for KeyExchange {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("KeyExchange").field("public_key", &self.public_key).finish_non_exhaustive()
+	}
+}
+
+impl KeyExchange {
+	pub(crate) fn new() -> Self {
+		let secret = EphemeralSecret::random_from_rng(OsRng);
+		KeyExchange {
+			public_key: PublicKey::from(&secret),
+			secret,
+		}
+	}
+
+	/// The public key we send to the remote.
+	pub(crate) fn get_public_key(&self) -> [u8; PUBLIC_KEY_SIZE] {
+		self.public_key.to_bytes()
+	}
+
+	/// Combine our secret with the remote's public key, and derive the keys used to encrypt each direction of the
+	/// connection.
+	pub(crate) fn finish(self, remote_public_key: [u8; PUBLIC_KEY_SIZE], role: Role)
+		-> Result<Session, NetworkStreamError>
+	{
+		let shared_secret = self.secret.diffie_hellman(&PublicKey::from(remote_public_key));
+		if !shared_secret.was_contributory() {
+			return Err(Box::new(EncryptionError::InvalidPublicKey));
+		}
+
+		let hkdf = Hkdf::<Sha256>::new(Some(b"eggine"), shared_secret.as_bytes());
+		let mut client_key = [0; 32];
+		let mut server_key = [0; 32];
+		hkdf.expand(b"client to server", &mut client_key).unwrap();
+		hkdf.expand(b"server to client", &mut server_key).unwrap();
+
+		let (send_key, receive_key) = match role {
+			Role::Client => (client_key, server_key),
+			Role::Server => (server_key, client_key),
+		};
+
+		Ok(Session {
+			highest_received: None,
+			receive_cipher: ChaCha20Poly1305::new(Key::from_slice(&receive_key)),
+			received_mask: 0,
+			send_cipher: ChaCha20Poly1305::new(Key::from_slice(&send_key)),
+		})
+	}
+}
+
+/// Encrypts and authenticates the packets exchanged over a connection. Packet headers are sent in plaintext so the
+/// sequence number can be used as the nonce, but are authenticated along with the encrypted payload. Sequence numbers
+/// are never reused on a connection, so each nonce is only ever used once per key.
+pub(crate) struct Session {
+	/// The highest sequence number we received an authentic packet for.
+	highest_received: Option<u32>,
+	receive_cipher: ChaCha20Poly1305,
+	/// Bit `n` is set if we received the sequence number `highest_received - n`.
+	received_mask: u128,
+	send_cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for Session {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Session").field("highest_received", &self.highest_received).finish_non_exhaustive()
+	}
+}
+
+impl Session {
+	/// Enable the encryption extension on a packet before it is encoded.
+	pub(crate) fn prepare(packet: &mut Packet) {
+		packet.add_extension(PacketExtension::Encrypted);
+	}
+
+	/// Encrypt the payload of an encoded packet in place, and append its authentication tag.
+	pub(crate) fn seal(&mut self, bytes: &mut Vec<u8>) -> Result<(), NetworkStreamError> {
+		let Some((sequence, _)) = Packet::peek_header(bytes) else {
+			return Err(Box::new(EncryptionError::Truncated));
+		};
+
+		let (header, payload) = bytes.split_at_mut(PACKET_HEADER_SIZE);
+		let Ok(tag) = self.send_cipher.encrypt_in_place_detached(&nonce(sequence), header, payload) else {
+			return Err(Box::new(EncryptionError::Authentication));
+		};
+
+		bytes.extend_from_slice(&tag);
+		Ok(())
+	}
+
+	/// Authenticate and decrypt an encoded packet in place. Packets that fail authentication, are not encrypted, or were
+	/// already received are rejected.
+	pub(crate) fn open(&mut self, bytes: &mut Vec<u8>) -> Result<(), NetworkStreamError> {
+		if bytes.len() < PACKET_HEADER_SIZE + TAG_SIZE {
+			return Err(Box::new(EncryptionError::Truncated));
+		}
+
+		let Some((sequence, extensions)) = Packet::peek_header(bytes) else {
+			return Err(Box::new(EncryptionError::Truncated));
+		};
+
+		if !extensions.contains(&PacketExtension::Encrypted) {
+			return Err(Box::new(EncryptionError::NotEncrypted));
+		}
+
+		if self.is_replayed(sequence) {
+			return Err(Box::new(EncryptionError::Replayed));
+		}
+
+		let tag = Tag::clone_from_slice(&bytes[bytes.len() - TAG_SIZE..]);
+		bytes.truncate(bytes.len() - TAG_SIZE);
+
+		let (header, payload) = bytes.split_at_mut(PACKET_HEADER_SIZE);
+		if self.receive_cipher.decrypt_in_place_detached(&nonce(sequence), header, payload, &tag).is_err() {
+			return Err(Box::new(EncryptionError::Authentication));
+		}
+
+		// only remember sequence numbers once we know the packet is authentic, so forged packets cannot block real ones
+		self.mark_received(sequence);
+
+		Ok(())
+	}
+
+	fn is_replayed(&self, sequence: u32) -> bool {
+		let Some(highest_received) = self.highest_received else {
+			return false;
+		};
+
+		if is_newer_sequence(sequence, highest_received) {
+			return false;
+		}
+
+		let age = highest_received.wrapping_sub(sequence);
+		age >= REPLAY_WINDOW_SIZE || self.received_mask & (1 << age) != 0
+	}
+
+	fn mark_received(&mut self, sequence: u32) {
+		match self.highest_received {
+			Some(highest_received) if !is_newer_sequence(sequence, highest_received) => {
+				self.received_mask |= 1 << highest_received.wrapping_sub(sequence);
+			},
+			Some(highest_received) => {
+				let shift = sequence.wrapping_sub(highest_received);
+				self.received_mask = if shift >= REPLAY_WINDOW_SIZE { 0 } else { self.received_mask << shift };
+				self.received_mask |= 1;
+				self.highest_received = Some(sequence);
+			},
+			None => {
+				self.received_mask = 1;
+				self.highest_received = Some(sequence);
+			},
+		}
+	}
+}
+
+/// Build a nonce out of a packet's sequence number.
+fn nonce(sequence: u32) -> Nonce {
+	let mut nonce = [0; 12];
+	nonce[0..4].copy_from_slice(&sequence.to_le_bytes());
+	*Nonce::from_slice(&nonce)
+}
+
+/// Test packet encryption.
+#[cfg(test)]
+mod tests {
+	use streams::{ ReadStream, WriteStream, };
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+	use crate::payload::{ Packet, SubPayload, };
+
+	use super::{ EncryptionError, KeyExchange, Role, Session, };
+
+	fn sessions() -> (Session, Session) {
+		let client = KeyExchange::new();
+		let server = KeyExchange::new();
+
+		let client_public_key = client.get_public_key();
+		let server_public_key = server.get_public_key();
+
+		(
+			client.finish(server_public_key, Role::Client).expect("Could not finish client key exchange"),
+			server.finish(client_public_key, Role::Server).expect("Could not finish server key exchange"),
+		)
+	}
+
+	fn encode(session: &mut Session, sequence: u32) -> Vec<u8> {
+		let mut packet = Packet::new(0, 0);
+		packet.add_sub_payload(SubPayload::Ping(sequence as u64));
+		packet.prepare(Default::default(), sequence, 0);
+		Session::prepare(&mut packet);
+
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(&packet).expect("Could not encode packet");
+
+		let mut bytes = stream.export().expect("Could not export packet");
+		session.seal(&mut bytes).expect("Could not seal packet");
+		bytes
+	}
+
+	fn decode(bytes: Vec<u8>) -> Packet {
+		let mut stream = NetworkReadStream::new();
+		stream.import(bytes).expect("Could not import packet");
+		stream.decode::<Packet>().expect("Could not decode packet").0
+	}
+
+	fn error(result: Result<(), crate::error::NetworkStreamError>) -> EncryptionError {
+		*result.unwrap_err().as_any().downcast_ref::<EncryptionError>().expect("Could not downcast `EncryptionError`")
+	}
+
+	/// Ensure that sealed packets are opened into the original packet by the remote.
+	#[test]
+	fn round_trip() {
+		let (mut client, mut server) = sessions();
+
+		let mut bytes = encode(&mut client, 5);
+		server.open(&mut bytes).expect("Could not open packet");
+		assert!(matches!(decode(bytes).get_sub_payloads()[..], [SubPayload::Ping(5)]));
+
+		let mut bytes = encode(&mut server, 10);
+		client.open(&mut bytes).expect("Could not open packet");
+		assert!(matches!(decode(bytes).get_sub_payloads()[..], [SubPayload::Ping(10)]));
+	}
+
+	/// Ensure that tampered packets, including packets with a tampered header, fail authentication.
+	#[test]
+	fn tampered() {
+		let (mut client, mut server) = sessions();
+
+		let mut bytes = encode(&mut client, 5);
+		let last = bytes.len() - 1;
+		bytes[last] ^= 1;
+		assert_eq!(error(server.open(&mut bytes)), EncryptionError::Authentication);
+
+		// change the highest acknowledged sequence in the plaintext header
+		let mut bytes = encode(&mut client, 6);
+		bytes[4] ^= 1;
+		assert_eq!(error(server.open(&mut bytes)), EncryptionError::Authentication);
+
+		// packets sealed for the other direction are rejected
+		let mut bytes = encode(&mut client, 7);
+		assert_eq!(error(client.open(&mut bytes)), EncryptionError::Authentication);
+	}
+
+	/// Ensure that packets are only accepted once.
+	#[test]
+	fn replayed() {
+		let (mut client, mut server) = sessions();
+
+		let first = encode(&mut client, 5);
+		let second = encode(&mut client, 6);
+
+		server.open(&mut second.clone()).expect("Could not open packet");
+		server.open(&mut first.clone()).expect("Could not open out of order packet");
+		assert_eq!(error(server.open(&mut first.clone())), EncryptionError::Replayed);
+		assert_eq!(error(server.open(&mut second.clone())), EncryptionError::Replayed);
+
+		server.open(&mut encode(&mut client, 500)).expect("Could not open packet");
+		assert_eq!(error(server.open(&mut encode(&mut client, 7))), EncryptionError::Replayed);
+	}
+}
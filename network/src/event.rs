@@ -0,0 +1,45 @@
+use crate::payload::DisconnectionReason;
+
+/// Identifies a client connected to a server. IDs are never reused while the server is running, so game code can keep
+/// an ID around after the client disconnects without it ever referring to a different client.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClientId(pub(crate) u64);
+
+impl std::fmt::Display for ClientId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "client #{}", self.0)
+	}
+}
+
+/// The channel a message was received on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+	/// Delivered in order, exactly once.
+	Reliable,
+	/// May be dropped or arrive out of order.
+	Unreliable,
+}
+
+/// Something that happened on a server, queued up for game code to handle. Events for a client are queued in the order
+/// they happened in, so a client's messages always come after its `Connected` event and before its `Disconnected`
+/// event.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ServerEvent {
+	/// A client completed its handshake.
+	Connected(ClientId),
+	/// A client disconnected, was disconnected by the server, or timed out. The client's ID is no longer valid.
+	Disconnected(ClientId, DisconnectionReason),
+	/// A client sent us a message.
+	Message(ClientId, Channel, Vec<u8>),
+}
+
+/// Something that happened on a client, queued up for game code to handle.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ClientEvent {
+	/// The server accepted our handshake.
+	Connected,
+	/// The server disconnected us. Also emitted without a `Connected` event if the server rejected our handshake.
+	Disconnected(DisconnectionReason),
+	/// The server sent us a message.
+	Message(Channel, Vec<u8>),
+}
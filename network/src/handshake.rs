@@ -3,10 +3,13 @@ use std::fmt::Debug;
 use streams::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
 use streams::u8_io::{ U8ReadStringSafeStream, U8WriteStream, U8ReadStream, };
 
+use crate::encryption::PUBLIC_KEY_SIZE;
 use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::payload::DisconnectionReason;
 
 #[derive(Debug, Clone, Copy)]
 pub enum HandshakeError {
+	InvalidCookieFlag,
 	InvalidMagicNumber,
 }
 
@@ -16,6 +19,26 @@ impl NetworkStreamErrorTrait for HandshakeError {
 	}
 }
 
+/// Client handshakes are padded to this size so the server never replies with more data than it received, which keeps
+/// the server from being used to amplify reflection attacks.
+pub const HANDSHAKE_PADDED_SIZE: usize = 256;
+
+/// The size of a cookie's message authentication code.
+pub const COOKIE_MAC_SIZE: usize = 32;
+
+/// Magic number that starts a `Challenge`.
+pub(crate) const CHALLENGE_MAGIC_NUMBER: &[u8; 6] = b"EGGCHL";
+
+/// Magic number that starts a `Rejection`.
+pub(crate) const REJECTION_MAGIC_NUMBER: &[u8; 6] = b"EGGREJ";
+
+/// Checksum of the network protocol definition, computed by the build script. Clients and servers with different
+/// checksums cannot talk to each other.
+pub const PROTOCOL_CHECKSUM: [u8; 16] = include!(concat!(env!("OUT_DIR"), "/protocol_checksum.rs"));
+
+// `VERSION_BRANCH`, `VERSION_MAJOR`, `VERSION_MINOR` and `VERSION_REVISION`, determined from git by the build script
+include!(concat!(env!("OUT_DIR"), "/version.rs"));
+
 /// The eggine's version. The version satisfies this regex: v([0-9]+).([0-9]+).([a-zA-Z_][a-zA-Z_0-9]+)#([0-9]+).
 /// 1st group: Major version. Intended for public consumption, and thus incremented arbitrarily.
 /// 2nd group: Minor version. Intended for public consumption, and thus incremented arbitrarily.
@@ -26,7 +49,7 @@ impl NetworkStreamErrorTrait for HandshakeError {
 /// The versioning system is designed to include a human-readable canonical representation of a commit in the eggine's
 /// game engine repository. This is useful for keeping track of exactly which commit any distributed version of the
 /// eggine is running, helping speed up bug fixing.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Version {
 	pub branch: String,
 	pub major: u16,
@@ -34,6 +57,24 @@ pub struct Version {
 	pub revision: u16,
 }
 
+impl Version {
+	/// The version this build of the eggine was built from, determined from git by the build script.
+	pub fn current() -> Self {
+		Version {
+			branch: String::from(VERSION_BRANCH),
+			major: VERSION_MAJOR,
+			minor: VERSION_MINOR,
+			revision: VERSION_REVISION,
+		}
+	}
+}
+
+impl std::fmt::Display for Version {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "v{}.{}.{}#{}", self.major, self.minor, self.branch, self.revision)
+	}
+}
+
 impl<T> Encode<u8, T, NetworkStreamError> for Version
 where
 	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
@@ -70,14 +111,140 @@ T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError> + U8Rea
 	}
 }
 
+/// Token the server gives to a client so the client can prove it can receive data at the address it is sending from.
+/// The server does not store cookies, it recomputes the cookie's message authentication code when the client echoes it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cookie {
+	/// Unix time in seconds after which the server no longer accepts the cookie.
+	pub expires: u64,
+	/// Message authentication code over the cookie's expiry time and the client's address and public key.
+	pub mac: [u8; COOKIE_MAC_SIZE],
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for Cookie
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		stream.write_u64(self.expires)?;
+		for byte in self.mac {
+			stream.write_u8(byte)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for Cookie
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		let (expires, mut position) = stream.read_u64()?;
+
+		let mut mac = [0; COOKIE_MAC_SIZE];
+		for byte in mac.iter_mut() {
+			(*byte, position) = stream.read_u8()?;
+		}
+
+		Ok((
+			Cookie {
+				expires,
+				mac,
+			},
+			position
+		))
+	}
+}
+
+/// Sent by the server in response to a handshake that does not carry a valid cookie. The client has to send its
+/// handshake again with the challenge's cookie before the server allocates any state for it.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Challenge {
+	pub cookie: Cookie,
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for Challenge
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		for byte in CHALLENGE_MAGIC_NUMBER {
+			stream.write_u8(*byte)?;
+		}
+
+		stream.encode(&self.cookie)
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for Challenge
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		for byte in CHALLENGE_MAGIC_NUMBER {
+			if stream.read_u8()?.0 != *byte {
+				return Err(Box::new(HandshakeError::InvalidMagicNumber));
+			}
+		}
+
+		let (cookie, position) = stream.decode::<Cookie>()?;
+		Ok((Challenge { cookie, }, position))
+	}
+}
+
+/// Sent by the server in response to a handshake it will not accept. Tells the client why, and which version the server
+/// is running so the client can display it. Rejections are not authenticated, so anyone who knows the client's address
+/// can forge one. Clients only display them, and keep trying to connect until their handshake times out.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Rejection {
+	pub reason: DisconnectionReason,
+	pub version: Version,
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for Rejection
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		for byte in REJECTION_MAGIC_NUMBER {
+			stream.write_u8(*byte)?;
+		}
+
+		stream.encode(&self.reason)?;
+		stream.encode(&self.version)
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for Rejection
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError> + U8ReadStringSafeStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		for byte in REJECTION_MAGIC_NUMBER {
+			if stream.read_u8()?.0 != *byte {
+				return Err(Box::new(HandshakeError::InvalidMagicNumber));
+			}
+		}
+
+		let (reason, _) = stream.decode::<DisconnectionReason>()?;
+		let (version, position) = stream.decode::<Version>()?;
+		Ok((Rejection { reason, version, }, position))
+	}
+}
+
 /// Used to verify a client connection on the server.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Handshake {
 	/// Checksum of the network API. If the checksum between a client and server do not match, then they would be unable
 	/// to communicate with each other.
 	pub checksum: [u8; 16],
+	/// The cookie the server challenged the client with. Only sent by the client.
+	pub cookie: Option<Cookie>,
 	/// The identifier that can uniquely identify clients in NTP packets.
 	pub ntp_id: u32,
+	/// Public key used to derive the keys that encrypt the connection.
+	pub public_key: [u8; PUBLIC_KEY_SIZE],
 	/// Used to instantiate packet sequence numbers between the client and server. The server initializes all sequence
 	/// numbers.
 	pub sequences: (u32, u32),
@@ -86,7 +253,8 @@ pub struct Handshake {
 }
 
 impl Handshake {
-	/// Tests if the two handshakes are compatible.
+	/// Tests if the two handshakes are compatible. Handshakes are compatible if they were built with the same network
+	/// protocol, even if they were built from different versions of the eggine.
 	pub fn is_compatible(&self, other: &Handshake) -> bool {
 		self.checksum == other.checksum
 	}
@@ -117,6 +285,19 @@ where
 			stream.write_u8(byte)?;
 		}
 
+		// write public key
+		for byte in self.public_key {
+			stream.write_u8(byte)?;
+		}
+
+		// write cookie
+		if let Some(cookie) = &self.cookie {
+			stream.write_u8(1)?;
+			stream.encode(cookie)?;
+		} else {
+			stream.write_u8(0)?;
+		}
+
 		stream.encode(&self.version)?;
 
 		Ok(())
@@ -151,12 +332,27 @@ where
 			checksum[i] = stream.read_u8()?.0;
 		}
 
+		// read the public key
+		let mut public_key = [0; PUBLIC_KEY_SIZE];
+		for byte in public_key.iter_mut() {
+			*byte = stream.read_u8()?.0;
+		}
+
+		// read the cookie
+		let cookie = match stream.read_u8()?.0 {
+			0 => None,
+			1 => Some(stream.decode::<Cookie>()?.0),
+			_ => return Err(Box::new(HandshakeError::InvalidCookieFlag)),
+		};
+
 		let (version, position) = stream.decode::<Version>()?;
 
 		Ok((
 			Handshake {
 				checksum,
+				cookie,
 				ntp_id,
+				public_key,
 				sequences: (sequence1, sequence2),
 				version,
 			},
@@ -164,3 +360,81 @@ where
 		))
 	}
 }
+
+/// Test handshake encoding.
+#[cfg(test)]
+mod tests {
+	use streams::{ ReadStream, WriteStream, };
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+
+	use crate::payload::DisconnectionReason;
+
+	use super::{ Challenge, Cookie, Handshake, Rejection, Version, HANDSHAKE_PADDED_SIZE, };
+
+	fn handshake(cookie: Option<Cookie>) -> Handshake {
+		Handshake {
+			checksum: [1; 16],
+			cookie,
+			ntp_id: 5,
+			public_key: [2; 32],
+			sequences: (500, 1000),
+			version: Version {
+				branch: "b".repeat(32),
+				major: 1,
+				minor: 2,
+				revision: 3,
+			},
+		}
+	}
+
+	/// Ensure that handshakes, challenges, and rejections are decoded into the same objects.
+	#[test]
+	fn round_trip() {
+		let cookie = Cookie {
+			expires: 1000,
+			mac: [3; 32],
+		};
+
+		let mut write_stream = NetworkWriteStream::new();
+		write_stream.encode(&handshake(Some(cookie.clone()))).expect("Could not encode handshake");
+		write_stream.encode(&Challenge { cookie: cookie.clone(), }).expect("Could not encode challenge");
+		write_stream.encode(&Rejection {
+			reason: DisconnectionReason::VersionMismatch,
+			version: Version::current(),
+		}).expect("Could not encode rejection");
+
+		let mut read_stream = NetworkReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export stream")).expect("Could not import stream");
+		assert_eq!(
+			read_stream.decode::<Handshake>().expect("Could not decode handshake").0, handshake(Some(cookie.clone()))
+		);
+		assert_eq!(read_stream.decode::<Challenge>().expect("Could not decode challenge").0, Challenge { cookie, });
+		assert_eq!(read_stream.decode::<Rejection>().expect("Could not decode rejection").0, Rejection {
+			reason: DisconnectionReason::VersionMismatch,
+			version: Version::current(),
+		});
+	}
+
+	/// Ensure that the server's replies are never bigger than a padded client handshake.
+	#[test]
+	fn padding() {
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(&handshake(None)).expect("Could not encode handshake");
+		assert!(stream.export().expect("Could not export stream").len() <= HANDSHAKE_PADDED_SIZE);
+
+		stream.encode(&Challenge {
+			cookie: Cookie {
+				expires: 1000,
+				mac: [3; 32],
+			},
+		}).expect("Could not encode challenge");
+		assert!(stream.export().expect("Could not export stream").len() <= HANDSHAKE_PADDED_SIZE);
+
+		stream.encode(&Rejection {
+			reason: DisconnectionReason::VersionMismatch,
+			version: handshake(None).version,
+		}).expect("Could not encode rejection");
+		assert!(stream.export().expect("Could not export stream").len() <= HANDSHAKE_PADDED_SIZE);
+	}
+}
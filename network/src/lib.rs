@@ -1,11 +1,24 @@
+pub mod address;
 pub mod client;
+pub mod congestion;
+pub mod connection_statistics;
+pub mod encryption;
 pub mod error;
+pub mod event;
 pub mod handshake;
+pub mod link_conditioner;
 pub mod log;
 pub mod network_stream;
 pub mod ntp;
 pub mod payload;
+pub mod reliable;
+pub mod replication;
 pub mod server;
+pub mod simulation;
+pub mod transport;
+
+#[cfg(test)]
+mod test_harness;
 
 /// Maximum size of a normal packet. Has to stay below 1500 bytes because MTUs are low on the internet. Set to 1400
 /// since headers count towards the 1500 byte limit. Others seem to pick a number closer to 1450 bytes, but I'm picking
@@ -14,4 +27,14 @@ pub mod server;
 const MAX_PACKET_SIZE: usize = 1400;
 
 pub use client::client::Client;
+pub use client::ClientConfig;
+pub use connection_statistics::ConnectionStatistics;
+pub use event::{ Channel, ClientEvent, ClientId, ServerEvent, };
+pub use link_conditioner::LinkConditions;
+pub use log::{ Log, LogFields, LogLevel, LogRecord, LogSink, };
+pub use ntp::NetworkClock;
+pub use payload::Priority;
+pub use replication::{ Component, EntityId, Interest, Interpolate, ReplicatedWorld, VisibilityGroup, };
 pub use server::server::Server;
+pub use server::ServerConfig;
+pub use transport::{ MemoryNetwork, MemoryTransport, Transport, UdpTransport, };
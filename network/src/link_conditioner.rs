@@ -0,0 +1,147 @@
+use rand::{ Rng, SeedableRng, };
+use rand::rngs::StdRng;
+use std::collections::BTreeMap;
+use std::time::{ Duration, Instant, };
+
+/// How much longer than the rest of the packets a reordered packet is held back for, at minimum.
+const MIN_REORDER_DELAY: Duration = Duration::from_millis(10);
+
+/// Simulated network conditions, used to test how the server and client deal with bad connections.
+#[derive(Clone, Debug, Default)]
+pub struct LinkConditions {
+	/// The chance that a packet is delivered twice, between 0 and 1.
+	pub duplication: f64,
+	/// The most amount of extra latency added to a packet. Each packet gets a random amount of extra latency.
+	pub jitter: Duration,
+	/// How long packets take to be delivered.
+	pub latency: Duration,
+	/// The chance that a packet is dropped, between 0 and 1.
+	pub loss: f64,
+	/// The chance that a packet is held back long enough for the packets after it to be delivered first, between 0 and
+	/// 1.
+	pub reordering: f64,
+	/// Seeds the random number generator that decides what happens to each packet, so tests are repeatable.
+	pub seed: u64,
+}
+
+/// Applies `LinkConditions` to packets going in one direction. Packets are pushed into the conditioner when they would
+/// have been sent/received, and are popped once they would have been delivered.
+#[derive(Debug)]
+pub(crate) struct LinkConditioner<T: Clone> {
+	conditions: LinkConditions,
+	/// Used to keep packets that are delivered at the same time in order.
+	next_id: u64,
+	/// Packets waiting to be delivered, sorted by the time they are delivered at.
+	queue: BTreeMap<(Instant, u64), T>,
+	rng: StdRng,
+}
+
+impl<T: Clone> LinkConditioner<T> {
+	pub(crate) fn new(conditions: LinkConditions) -> Self {
+		let rng = StdRng::seed_from_u64(conditions.seed);
+		LinkConditioner {
+			conditions,
+			next_id: 0,
+			queue: BTreeMap::new(),
+			rng,
+		}
+	}
+
+	/// Queue a packet for delivery, unless it is dropped.
+	pub(crate) fn push(&mut self, packet: T, now: Instant) {
+		if self.rng.gen_bool(self.conditions.loss.clamp(0.0, 1.0)) {
+			return;
+		}
+
+		if self.rng.gen_bool(self.conditions.duplication.clamp(0.0, 1.0)) {
+			self.queue_packet(packet.clone(), now);
+		}
+
+		self.queue_packet(packet, now);
+	}
+
+	/// Get the next packet that is ready to be delivered.
+	pub(crate) fn pop(&mut self, now: Instant) -> Option<T> {
+		let entry = self.queue.first_entry()?;
+		if entry.key().0 > now {
+			return None;
+		}
+
+		Some(entry.remove())
+	}
+
+	fn queue_packet(&mut self, packet: T, now: Instant) {
+		let mut delay = self.conditions.latency + self.conditions.jitter.mul_f64(self.rng.gen::<f64>());
+		if self.rng.gen_bool(self.conditions.reordering.clamp(0.0, 1.0)) {
+			delay += (self.conditions.latency + self.conditions.jitter).max(MIN_REORDER_DELAY);
+		}
+
+		self.queue.insert((now + delay, self.next_id), packet);
+		self.next_id += 1;
+	}
+}
+
+/// Test the link conditioner.
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, Instant, };
+
+	use super::{ LinkConditioner, LinkConditions, };
+
+	// push packets 0 through `count` into a conditioner, and return the order they are delivered in
+	fn deliver(conditions: LinkConditions, count: u32) -> Vec<u32> {
+		let now = Instant::now();
+		let mut conditioner = LinkConditioner::new(conditions);
+		for packet in 0..count {
+			conditioner.push(packet, now + Duration::from_millis(packet as u64));
+		}
+
+		assert_eq!(conditioner.pop(now), None);
+
+		let mut delivered = Vec::new();
+		while let Some(packet) = conditioner.pop(now + Duration::from_secs(10)) {
+			delivered.push(packet);
+		}
+
+		delivered
+	}
+
+	/// Ensure that packets are delivered in order after the latency when there are no other conditions.
+	#[test]
+	fn latency() {
+		let now = Instant::now();
+		let mut conditioner = LinkConditioner::new(LinkConditions {
+			latency: Duration::from_millis(50),
+			..LinkConditions::default()
+		});
+
+		conditioner.push(1, now);
+		conditioner.push(2, now);
+		assert_eq!(conditioner.pop(now + Duration::from_millis(49)), None);
+		assert_eq!(conditioner.pop(now + Duration::from_millis(50)), Some(1));
+		assert_eq!(conditioner.pop(now + Duration::from_millis(50)), Some(2));
+		assert_eq!(conditioner.pop(now + Duration::from_millis(50)), None);
+	}
+
+	/// Ensure that packets are dropped, duplicated and reordered, and that the same seed gives the same results.
+	#[test]
+	fn conditions() {
+		let conditions = LinkConditions {
+			duplication: 0.1,
+			latency: Duration::from_millis(20),
+			loss: 0.1,
+			reordering: 0.1,
+			..LinkConditions::default()
+		};
+
+		let delivered = deliver(conditions.clone(), 1000);
+		assert_eq!(delivered, deliver(conditions, 1000));
+
+		let mut unique = delivered.clone();
+		unique.sort();
+		unique.dedup();
+		assert!(unique.len() < 1000);
+		assert!(unique.len() < delivered.len());
+		assert!(delivered.windows(2).any(|packets| packets[0] > packets[1]));
+	}
+}
@@ -1,32 +1,304 @@
-#[derive(Debug)]
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{ SystemTime, UNIX_EPOCH, };
+
+use crate::event::ClientId;
+
+use super::{ LogSink, StderrSink, };
+
+/// The amount of records a log keeps around by default.
+pub const DEFAULT_LOG_CAPACITY: usize = 1024;
+
+/// How severe a log record is. Levels are ordered from least to most severe, so a log set to a level discards records
+/// below it.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum LogLevel {
+	/// Per-packet activity, like pings and pongs.
+	Trace,
+	/// Activity that is only useful while debugging.
+	Debug,
+	/// Connections, disconnections and other changes in state.
+	Info,
+	/// Something went wrong, but it was expected to happen every once in a while.
+	Warning,
+	/// A peer misbehaved and was punished for it.
 	Blacklist,
+	/// Something went wrong.
 	Error,
-	Info,
 }
 
-/// Used to log network activity.
-#[derive(Debug, Default)]
+impl std::fmt::Display for LogLevel {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let name = match self {
+			LogLevel::Trace => "TRACE",
+			LogLevel::Debug => "DEBUG",
+			LogLevel::Info => "INFO",
+			LogLevel::Warning => "WARN",
+			LogLevel::Blacklist => "BAN",
+			LogLevel::Error => "ERROR",
+		};
+
+		f.pad(name)
+	}
+}
+
+/// Structured information attached to a log record, so records can be filtered by the connection they are about.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LogFields {
+	/// The client the record is about.
+	pub client_id: Option<ClientId>,
+	/// The address of the peer the record is about.
+	pub peer: Option<SocketAddr>,
+	/// The sequence of the packet the record is about.
+	pub sequence: Option<u32>,
+}
+
+impl LogFields {
+	/// Attach a client.
+	pub fn client(mut self, client_id: ClientId) -> Self {
+		self.client_id = Some(client_id);
+		self
+	}
+
+	/// Attach a peer address.
+	pub fn peer(mut self, peer: SocketAddr) -> Self {
+		self.peer = Some(peer);
+		self
+	}
+
+	/// Attach a packet sequence.
+	pub fn sequence(mut self, sequence: u32) -> Self {
+		self.sequence = Some(sequence);
+		self
+	}
+}
+
+/// A message in the log.
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+	pub fields: LogFields,
+	/// How deep the message is nested under the message before it.
+	pub indent: u8,
+	pub level: LogLevel,
+	pub message: String,
+	/// When the message was logged.
+	pub time: SystemTime,
+}
+
+impl std::fmt::Display for LogRecord {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let time = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+		write!(f, "{}.{:03} {:<5} ", time.as_secs(), time.subsec_millis(), self.level)?;
+
+		for _ in 0..self.indent {
+			write!(f, "  ")?;
+		}
+
+		write!(f, "{}", self.message)?;
+
+		if let Some(client_id) = self.fields.client_id {
+			write!(f, " client={}", client_id.0)?;
+		}
+
+		if let Some(peer) = self.fields.peer {
+			write!(f, " peer={}", peer)?;
+		}
+
+		if let Some(sequence) = self.fields.sequence {
+			write!(f, " sequence={}", sequence)?;
+		}
+
+		Ok(())
+	}
+}
+
+/// Used to log network activity. Records below the log's level are discarded, the rest are handed to the log's sinks
+/// and kept around until the log is full, at which point the oldest records are discarded.
+#[derive(Debug)]
 pub struct Log {
-	messages: Vec<String>,
+	/// The amount of records we keep around.
+	capacity: usize,
+	/// Records below this level are discarded.
+	level: LogLevel,
+	records: VecDeque<LogRecord>,
+	/// Where records are written to as they are logged.
+	sinks: Vec<Box<dyn LogSink>>,
+}
+
+impl Default for Log {
+	fn default() -> Self {
+		Log::new(LogLevel::Info, DEFAULT_LOG_CAPACITY)
+	}
 }
 
 impl Log {
+	/// Create a log that discards records below the specified level, keeps up to `capacity` records around, and writes
+	/// records to stderr.
+	pub fn new(level: LogLevel, capacity: usize) -> Self {
+		Log {
+			capacity,
+			level,
+			records: VecDeque::new(),
+			sinks: vec![Box::new(StderrSink)],
+		}
+	}
+
 	/// Add a message to the log.
-	pub fn print(&mut self, log_level: LogLevel, message: String, indent: u8) {
-		let log_level_char = match log_level {
-			LogLevel::Blacklist => '@',
-			LogLevel::Error => '!',
-			LogLevel::Info => '.',
+	pub fn print(&mut self, level: LogLevel, message: String, indent: u8) {
+		self.print_with(level, message, indent, LogFields::default());
+	}
+
+	/// Add a message to the log, along with structured information about what the message is about.
+	pub fn print_with(&mut self, level: LogLevel, message: String, indent: u8, fields: LogFields) {
+		if !self.is_enabled(level) {
+			return;
+		}
+
+		let record = LogRecord {
+			fields,
+			indent,
+			level,
+			message,
+			time: SystemTime::now(),
 		};
 
-		let mut indent_string = String::new();
-		for _ in 0..indent {
-			indent_string += "  ";
+		for sink in self.sinks.iter_mut() {
+			sink.write(&record);
+		}
+
+		if self.capacity == 0 {
+			return;
+		}
+
+		if self.records.len() >= self.capacity {
+			self.records.pop_front();
+		}
+
+		self.records.push_back(record);
+	}
+
+	/// Whether or not records of the specified level are logged.
+	pub fn is_enabled(&self, level: LogLevel) -> bool {
+		level >= self.level
+	}
+
+	/// The level records have to be at or above to be logged.
+	pub fn get_level(&self) -> LogLevel {
+		self.level
+	}
+
+	/// Set the level records have to be at or above to be logged.
+	pub fn set_level(&mut self, level: LogLevel) {
+		self.level = level;
+	}
+
+	/// Set the amount of records we keep around. If there are more records than that, the oldest are discarded.
+	pub fn set_capacity(&mut self, capacity: usize) {
+		self.capacity = capacity;
+		while self.records.len() > capacity {
+			self.records.pop_front();
+		}
+	}
+
+	/// Iterate over the records we kept around, from oldest to newest.
+	pub fn get_records(&self) -> impl Iterator<Item = &LogRecord> {
+		self.records.iter()
+	}
+
+	/// Discard the records we kept around.
+	pub fn clear(&mut self) {
+		self.records.clear();
+	}
+
+	/// Write records to the specified sink as they are logged, in addition to the sinks we already have.
+	pub fn add_sink(&mut self, sink: Box<dyn LogSink>) {
+		self.sinks.push(sink);
+	}
+
+	/// Stop writing records to any sink. Records are still kept around.
+	pub fn clear_sinks(&mut self) {
+		self.sinks.clear();
+	}
+}
+
+/// Test log filtering and capacity.
+#[cfg(test)]
+mod tests {
+	use std::net::{ Ipv6Addr, SocketAddr, };
+	use std::sync::{ Arc, Mutex, };
+
+	use crate::event::ClientId;
+	use crate::log::{ LogFields, LogRecord, LogSink, };
+
+	use super::{ Log, LogLevel, };
+
+	#[derive(Debug, Default)]
+	struct TestSink(Arc<Mutex<Vec<String>>>);
+
+	impl LogSink for TestSink {
+		fn write(&mut self, record: &LogRecord) {
+			self.0.lock().unwrap().push(record.message.clone());
 		}
+	}
+
+	fn quiet_log(level: LogLevel, capacity: usize) -> Log {
+		let mut log = Log::new(level, capacity);
+		log.clear_sinks();
+		log
+	}
+
+	/// Ensure that the log discards its oldest records once it is full.
+	#[test]
+	fn capacity() {
+		let mut log = quiet_log(LogLevel::Info, 3);
+		for i in 0..5 {
+			log.print(LogLevel::Info, format!("{}", i), 0);
+		}
+
+		let messages = log.get_records().map(|record| record.message.as_str()).collect::<Vec<&str>>();
+		assert_eq!(messages, vec!["2", "3", "4"]);
+
+		log.set_capacity(1);
+		assert_eq!(log.get_records().count(), 1);
+
+		log.set_capacity(0);
+		log.print(LogLevel::Info, String::from("5"), 0);
+		assert_eq!(log.get_records().count(), 0);
+	}
+
+	/// Ensure that records below the log's level are neither kept nor written to sinks.
+	#[test]
+	fn level_filter() {
+		let messages = Arc::new(Mutex::new(Vec::new()));
+		let mut log = quiet_log(LogLevel::Warning, 16);
+		log.add_sink(Box::new(TestSink(messages.clone())));
+
+		log.print(LogLevel::Trace, String::from("trace"), 0);
+		log.print(LogLevel::Info, String::from("info"), 0);
+		log.print(LogLevel::Blacklist, String::from("blacklist"), 0);
+		log.print(LogLevel::Error, String::from("error"), 0);
+
+		assert_eq!(*messages.lock().unwrap(), vec!["blacklist", "error"]);
+		assert_eq!(log.get_records().count(), 2);
+
+		log.set_level(LogLevel::Trace);
+		log.print(LogLevel::Trace, String::from("trace"), 0);
+		assert_eq!(log.get_records().count(), 3);
+	}
+
+	/// Ensure that structured fields are kept with the record and written out.
+	#[test]
+	fn fields() {
+		let peer = SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 1234);
+		let mut log = quiet_log(LogLevel::Info, 16);
+		log.print_with(
+			LogLevel::Error, String::from("bad packet"), 1, LogFields::default().client(ClientId(7)).peer(peer).sequence(9)
+		);
 
-		let message = format!("{}{} {}", indent_string, log_level_char, message);
-		println!("{}", message);
-		self.messages.push(message);
+		let record = log.get_records().next().unwrap();
+		assert_eq!(record.fields.client_id, Some(ClientId(7)));
+		assert_eq!(record.fields.peer, Some(peer));
+		assert_eq!(record.fields.sequence, Some(9));
+		assert!(record.to_string().ends_with("ERROR   bad packet client=7 peer=[::1]:1234 sequence=9"));
 	}
 }
@@ -1,4 +1,13 @@
 pub mod log;
+pub mod sink;
 
+pub use log::DEFAULT_LOG_CAPACITY;
 pub use log::Log;
+pub use log::LogFields;
 pub use log::LogLevel;
+pub use log::LogRecord;
+pub use sink::FileSink;
+pub use sink::LogSink;
+pub use sink::StderrSink;
+#[cfg(feature = "tracing")]
+pub use sink::TracingSink;
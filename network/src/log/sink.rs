@@ -0,0 +1,105 @@
+use std::fs::{ File, OpenOptions, };
+use std::io::{ LineWriter, Write, };
+use std::path::Path;
+
+use super::LogRecord;
+
+/// Somewhere log records are written to as they are logged.
+pub trait LogSink: std::fmt::Debug + Send {
+	/// Write a record. Sinks cannot fail, since there is nowhere to report the failure to.
+	fn write(&mut self, record: &LogRecord);
+}
+
+/// Writes records to stderr, one per line.
+#[derive(Debug, Default)]
+pub struct StderrSink;
+
+impl LogSink for StderrSink {
+	fn write(&mut self, record: &LogRecord) {
+		eprintln!("{}", record);
+	}
+}
+
+/// Appends records to a file, one per line.
+#[derive(Debug)]
+pub struct FileSink {
+	writer: LineWriter<File>,
+}
+
+impl FileSink {
+	/// Open the file at the specified path, creating it if it does not exist.
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+		let file = OpenOptions::new().append(true).create(true).open(path)?;
+		Ok(FileSink {
+			writer: LineWriter::new(file),
+		})
+	}
+}
+
+impl LogSink for FileSink {
+	fn write(&mut self, record: &LogRecord) {
+		let _ = writeln!(self.writer, "{}", record);
+	}
+}
+
+/// Forwards records to the `tracing` ecosystem as events, with the record's fields attached. Blacklist records are
+/// emitted as warnings.
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default)]
+pub struct TracingSink;
+
+#[cfg(feature = "tracing")]
+impl LogSink for TracingSink {
+	fn write(&mut self, record: &LogRecord) {
+		use super::LogLevel;
+
+		let client_id = record.fields.client_id.map(|client_id| client_id.0);
+		let peer = record.fields.peer.map(|peer| peer.to_string());
+		let sequence = record.fields.sequence;
+		let message = &record.message;
+
+		macro_rules! event {
+			($level:expr) => {
+				tracing::event!(target: "network", $level, client_id, peer, sequence, "{}", message)
+			};
+		}
+
+		match record.level {
+			LogLevel::Trace => event!(tracing::Level::TRACE),
+			LogLevel::Debug => event!(tracing::Level::DEBUG),
+			LogLevel::Info => event!(tracing::Level::INFO),
+			LogLevel::Warning | LogLevel::Blacklist => event!(tracing::Level::WARN),
+			LogLevel::Error => event!(tracing::Level::ERROR),
+		}
+	}
+}
+
+/// Test the file sink.
+#[cfg(test)]
+mod tests {
+	use crate::log::{ Log, LogFields, LogLevel, };
+
+	use super::FileSink;
+
+	/// Ensure that records are appended to the file as they are logged.
+	#[test]
+	fn file() {
+		let path = std::env::temp_dir().join(format!("network-log-test-{}.log", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+
+		let mut log = Log::new(LogLevel::Info, 0);
+		log.clear_sinks();
+		log.add_sink(Box::new(FileSink::open(&path).unwrap()));
+		log.print(LogLevel::Info, String::from("first"), 0);
+		log.print_with(LogLevel::Error, String::from("second"), 0, LogFields::default().sequence(3));
+		log.print(LogLevel::Debug, String::from("filtered"), 0);
+
+		let contents = std::fs::read_to_string(&path).unwrap();
+		let lines = contents.lines().collect::<Vec<&str>>();
+		assert_eq!(lines.len(), 2);
+		assert!(lines[0].ends_with("first"));
+		assert!(lines[1].ends_with("second sequence=3"));
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -22,6 +22,18 @@ impl NetworkStreamErrorTrait for NetworkStreamError {
 	}
 }
 
+#[derive(Debug, Eq, PartialEq)]
+pub enum NetworkReadStreamError {
+	/// Emitted if a read would go past the end of the imported data.
+	UnexpectedEnd,
+}
+
+impl NetworkStreamErrorTrait for NetworkReadStreamError {
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
 impl NetworkStreamErrorTrait for ReadStringSafeError {
 	fn as_any(&self) -> &dyn Any {
 		self
@@ -40,16 +52,27 @@ impl NetworkStreamErrorTrait for ReadStringSafeError {
 /// will be created in read/write pairs on both the client and server, and will have an interface reminiscent of other
 /// stream APIs in Rust (see stuff like tokio::sync::mpsc, C++ IO streams, etc).
 #[derive(Debug)]
-pub(crate) struct NetworkWriteStream {
+pub struct NetworkWriteStream {
 	buffer: Vec<u8>,
 }
 
 impl NetworkWriteStream {
-	pub(crate) fn new() -> Self {
+	pub fn new() -> Self {
 		NetworkWriteStream {
 			buffer: Vec::new(),
 		}
 	}
+
+	/// The amount of bytes written into the stream since the last export.
+	pub(crate) fn len(&self) -> usize {
+		self.buffer.len()
+	}
+}
+
+impl Default for NetworkWriteStream {
+	fn default() -> Self {
+		NetworkWriteStream::new()
+	}
 }
 
 impl U8WriteStream<NetworkStreamError> for NetworkWriteStream {
@@ -119,13 +142,13 @@ impl WriteStream<u8, NetworkStreamError> for NetworkWriteStream {
 /// chunks. The implementation of `NetworkReadStream` reflects this, and gives encode/decode implementations to
 /// determine when the end of a discrete chunk is reached via the `Endable` trait.
 #[derive(Debug)]
-pub(crate) struct NetworkReadStream {
+pub struct NetworkReadStream {
 	buffer: Vec<u8>,
 	position: StreamPosition,
 }
 
 impl NetworkReadStream {
-	pub(crate) fn new() -> Self {
+	pub fn new() -> Self {
 		NetworkReadStream {
 			buffer: Vec::new(),
 			position: 0,
@@ -133,50 +156,86 @@ impl NetworkReadStream {
 	}
 }
 
+impl Default for NetworkReadStream {
+	fn default() -> Self {
+		NetworkReadStream::new()
+	}
+}
+
+impl NetworkReadStream {
+	/// Make sure there are enough bytes left in the buffer to read the specified amount of bytes. Remotes can send us
+	/// truncated packets, which should not be able to make us read past the end of the buffer.
+	fn check_remaining(&self, length: usize) -> Result<(), NetworkStreamError> {
+		if (self.position as usize).saturating_add(length) > self.buffer.len() {
+			Err(Box::new(NetworkReadStreamError::UnexpectedEnd))
+		} else {
+			Ok(())
+		}
+	}
+}
+
 impl U8ReadStream<NetworkStreamError> for NetworkReadStream {
 	fn read_u8(&mut self) -> Result<(u8, StreamPosition), NetworkStreamError> {
+		self.check_remaining(1)?;
 		let (byte, delta) = read_u8(&self.buffer[(self.position as usize)..]);
 		self.position += delta;
 		Ok((byte, self.position))
 	}
 
 	fn read_char(&mut self) -> Result<(char, StreamPosition), NetworkStreamError> {
+		self.check_remaining(1)?;
 		let (character, delta) = read_char(&self.buffer[(self.position as usize)..]);
 		self.position += delta;
 		Ok((character, self.position))
 	}
 
 	fn read_u16(&mut self) -> Result<(u16, StreamPosition), NetworkStreamError> {
+		self.check_remaining(2)?;
 		let (number, delta) = read_u16(&self.buffer[(self.position as usize)..]);
 		self.position += delta;
 		Ok((number, self.position))
 	}
 
 	fn read_u32(&mut self) -> Result<(u32, StreamPosition), NetworkStreamError> {
+		self.check_remaining(4)?;
 		let (number, delta) = read_u32(&self.buffer[(self.position as usize)..]);
 		self.position += delta;
 		Ok((number, self.position))
 	}
 
 	fn read_u64(&mut self) -> Result<(u64, StreamPosition), NetworkStreamError> {
+		self.check_remaining(8)?;
 		let (number, delta) = read_u64(&self.buffer[(self.position as usize)..]);
 		self.position += delta;
 		Ok((number, self.position))
 	}
 
 	fn read_vlq(&mut self) -> Result<(u64, StreamPosition), NetworkStreamError> {
-		let (number, delta) = read_vlq(&self.buffer[(self.position as usize)..]);
+		// the integer is between 2 and 8 bytes long, so only check for the bytes we are about to read
+		let remaining = self.buffer.len().saturating_sub(self.position as usize);
+		let (number, delta) = if remaining >= 8 {
+			read_vlq(&self.buffer[(self.position as usize)..])
+		} else {
+			let mut padded = self.buffer[(self.position as usize)..].to_vec();
+			padded.resize(8, 0);
+
+			let (number, delta) = read_vlq(&padded);
+			if delta as usize > remaining {
+				return Err(Box::new(NetworkReadStreamError::UnexpectedEnd));
+			}
+
+			(number, delta)
+		};
+
 		self.position += delta;
 		Ok((number, self.position))
 	}
 
 	fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), NetworkStreamError> {
-		let mut output = Vec::new();
-		for _ in 0..length {
-			output.push(self.read_u8()?.0);
-		}
-
-		self.position += length as u64;
+		self.check_remaining(length)?;
+		let start = self.position as usize;
+		let output = self.buffer[start..start + length].to_vec();
+		self.position += length as StreamPosition;
 
 		Ok((output, self.position))
 	}
@@ -186,6 +245,14 @@ impl U8ReadStringSafeStream<NetworkStreamError> for NetworkReadStream {
 	fn read_string_safe(&mut self, minimum_length: u64, maximum_length: u64)
 		-> Result<(String, StreamPosition), NetworkStreamError>
 	{
+		// make sure the entire string is in the buffer before we read it
+		let start = self.position;
+		let (length, _) = self.read_vlq()?;
+		if length <= maximum_length {
+			self.check_remaining(length as usize)?;
+		}
+		self.position = start;
+
 		match read_string_safe(&self.buffer[(self.position as usize)..], minimum_length, maximum_length) {
 			Ok((string, delta)) => {
 				self.position += delta;
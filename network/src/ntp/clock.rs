@@ -0,0 +1,126 @@
+use super::{ NtpServer, NtpStatistics, };
+
+/// How fast the clock moves toward a new offset, in microseconds of adjustment per microsecond of time passed. Slow
+/// enough that the server time never runs backwards while slewing.
+const MAX_SLEW_RATE: f64 = 0.05;
+
+/// If the offset changes by more than this amount of microseconds, the clock jumps to the new offset instead of slewing
+/// toward it, since slewing would take too long.
+const STEP_THRESHOLD: i128 = 250_000;
+
+/// Estimates the server's time using the NTP measurements the client takes. All times are in microseconds since the
+/// UNIX epoch, like the times NTP packets carry.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetworkClock {
+	/// The local time the offset was last updated at.
+	last_update: i128,
+	/// The offset between our time and the server's time as of `last_update`.
+	offset: i128,
+	/// The maximum error of the measured offset.
+	synchronization_distance: Option<f64>,
+	/// The offset we measured last, which `offset` slews toward. `None` until we measured the offset once.
+	target_offset: Option<i128>,
+}
+
+impl NetworkClock {
+	/// Get the estimated time on the server. `None` until we measured the offset between our time and the server's.
+	pub fn get_server_time(&self) -> Option<i128> {
+		self.server_time_at(NtpServer::get_micros())
+	}
+
+	/// Get the offset between our time and the server's time that the clock currently applies.
+	pub fn get_offset(&self) -> Option<i128> {
+		self.offset_at(NtpServer::get_micros())
+	}
+
+	/// Get the maximum error of the estimated server time, based on the NTP synchronization distance. Includes the part
+	/// of the offset the clock has yet to slew toward. The smaller the distance, the more confident the estimate is.
+	pub fn get_synchronization_distance(&self) -> Option<f64> {
+		self.synchronization_distance_at(NtpServer::get_micros())
+	}
+
+	/// Update the offset the clock slews toward using new NTP statistics.
+	pub(crate) fn update(&mut self, statistics: &NtpStatistics, local_time: i128) {
+		let Some(best) = statistics.best() else {
+			return;
+		};
+
+		let target_offset = best.time_offset();
+		match self.offset_at(local_time) {
+			Some(offset) if (target_offset - offset).abs() <= STEP_THRESHOLD => self.offset = offset,
+			_ => self.offset = target_offset,
+		}
+
+		self.last_update = local_time;
+		self.synchronization_distance = statistics.synchronization_distance();
+		self.target_offset = Some(target_offset);
+	}
+
+	fn offset_at(&self, local_time: i128) -> Option<i128> {
+		let target_offset = self.target_offset?;
+		let max_slew = ((local_time - self.last_update).max(0) as f64 * MAX_SLEW_RATE) as i128;
+		Some(self.offset + (target_offset - self.offset).clamp(-max_slew, max_slew))
+	}
+
+	fn server_time_at(&self, local_time: i128) -> Option<i128> {
+		Some(local_time + self.offset_at(local_time)?)
+	}
+
+	fn synchronization_distance_at(&self, local_time: i128) -> Option<f64> {
+		let remaining_slew = (self.target_offset? - self.offset_at(local_time)?).abs();
+		Some(self.synchronization_distance? + remaining_slew as f64)
+	}
+}
+
+/// Test the network clock.
+#[cfg(test)]
+mod tests {
+	use crate::ntp::{ NtpServer, NtpStatistics, Times, };
+
+	use super::NetworkClock;
+
+	// statistics holding one measurement of `offset`, with a round-trip time of 10ms
+	fn statistics_with_offset(offset: i128, local_time: i128) -> NtpStatistics {
+		let mut statistics = NtpStatistics::new(300, 0);
+		let server_time = local_time - 5_000 + offset;
+		statistics.add_time(Times::new(local_time, local_time - 10_000, 0, server_time, server_time));
+		statistics
+	}
+
+	/// Ensure that the clock jumps to the first offset it measures, and to offsets that are too far off to slew toward.
+	#[test]
+	fn step() {
+		let now = NtpServer::get_micros();
+		let mut clock = NetworkClock::default();
+		assert_eq!(clock.server_time_at(now), None);
+
+		clock.update(&statistics_with_offset(1_000_000, now), now);
+		assert_eq!(clock.server_time_at(now), Some(now + 1_000_000));
+		assert!(clock.synchronization_distance_at(now).is_some_and(|distance| distance >= 5_000.0));
+
+		clock.update(&statistics_with_offset(-1_000_000, now + 1_000), now + 1_000);
+		assert_eq!(clock.offset_at(now + 1_000), Some(-1_000_000));
+	}
+
+	/// Ensure that the clock slews toward new offsets without ever running backwards.
+	#[test]
+	fn slew() {
+		let now = NtpServer::get_micros();
+		let mut clock = NetworkClock::default();
+		clock.update(&statistics_with_offset(100_000, now), now);
+		clock.update(&statistics_with_offset(0, now), now);
+
+		assert_eq!(clock.offset_at(now), Some(100_000));
+		assert_eq!(clock.offset_at(now + 1_000_000), Some(50_000));
+		assert_eq!(clock.offset_at(now + 3_000_000), Some(0));
+		let distance = clock.synchronization_distance_at(now).unwrap();
+		assert!(distance > clock.synchronization_distance_at(now + 3_000_000).unwrap());
+
+		let mut last_time = clock.server_time_at(now).unwrap();
+		for local_time in (now..now + 3_000_000).step_by(100_000) {
+			let time = clock.server_time_at(local_time).unwrap();
+			assert!(time >= last_time);
+			last_time = time;
+		}
+	}
+}
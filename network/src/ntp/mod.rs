@@ -1,7 +1,9 @@
+pub mod clock;
 pub mod server;
 pub mod times;
 pub mod ntp_statistics;
 
+pub use clock::NetworkClock;
 pub use server::NtpServer;
 pub use server::NtpServerError;
 pub use times::Times;
@@ -1,15 +1,12 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, VecDeque, };
 use std::net::SocketAddr;
-use std::sync::Arc;
 use std::time::{ Instant, SystemTime, UNIX_EPOCH, };
 use streams::{ ReadStream, WriteStream, };
-use tokio::sync::mpsc;
-use tokio::net::{ ToSocketAddrs, UdpSocket, };
 
 use crate::error::NetworkStreamError;
-use crate::log::{ Log, LogLevel, };
 use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
 use crate::payload::{ NtpPacketHeader, NtpRequestPacket, NtpResponsePacket, };
+use crate::payload::ntp_packet::encode_ntp_response;
 
 use super::{ Times, NtpStatistics, MAX_NTP_PACKET_SIZE, NTP_MAGIC_NUMBER, };
 
@@ -19,20 +16,20 @@ pub enum NtpServerError {
 	InvalidMagicNumber(SocketAddr),
 	/// Emitted if the client sent us a packet type that we do not recognize
 	InvalidPacketType(SocketAddr),
-	/// Emitted if we encountered a problem with the tokio mpsc UDP socket communication.
-	MpscError(mpsc::error::TryRecvError),
 	/// Emitted if we encountered a problem with network streams.
 	NetworkStreamError(NetworkStreamError),
 	/// Emitted if we could not match a address to an id.
 	NoId(SocketAddr),
 	/// Emitted if we could not match an ID to a host ID.
 	NoHostId(u32),
+	/// Emitted if we receive a response to a request we did not send. Non-fatal.
+	NoRequest(u32),
 	/// Emitted if we receive data from a non-whitelisted IP. Non-fatal.
 	NotWhitelisted(u32),
 	/// Emitted if a received packet is too big to be an eggine packet. Non-fatal.
 	PacketTooBig(SocketAddr),
-	/// Emitted if we encountered an OS error during a socket operation.
-	Socket(tokio::io::ErrorKind),
+	/// Emitted if a packet's ID is associated with a different address than the one it was received from. Non-fatal.
+	WrongAddress(SocketAddr),
 }
 
 impl NtpServerError {
@@ -41,19 +38,13 @@ impl NtpServerError {
 		match *self {
 			NtpServerError::InvalidPacketType(_) => false,
 			NtpServerError::InvalidMagicNumber(_) => false,
-			NtpServerError::MpscError(error) => {
-				if let mpsc::error::TryRecvError::Disconnected = error {
-					return true;
-				} else {
-					return false;
-				}
-			},
 			NtpServerError::NoId(_) => false,
 			NtpServerError::NoHostId(_) => false,
+			NtpServerError::NoRequest(_) => false,
 			NtpServerError::NetworkStreamError(_) => false,
 			NtpServerError::NotWhitelisted(_) => false,
 			NtpServerError::PacketTooBig(_) => false,
-			NtpServerError::Socket(_) => true,
+			NtpServerError::WrongAddress(_) => false,
 		}
 	}
 }
@@ -64,20 +55,9 @@ impl From<NetworkStreamError> for NtpServerError {
 	}
 }
 
-impl From<mpsc::error::TryRecvError> for NtpServerError {
-	fn from(error: mpsc::error::TryRecvError) -> Self {
-		NtpServerError::MpscError(error)
-	}
-}
-
-impl From<std::io::Error> for NtpServerError {
-	fn from(error: std::io::Error) -> Self {
-		NtpServerError::Socket(error.kind())
-	}
-}
-
-type Message = (SocketAddr, [u8; MAX_NTP_PACKET_SIZE + 1], usize, i128);
-
+/// Exchanges timing information with peers. NTP packets are carried inside the owner's encrypted packets as
+/// `SubPayload::Ntp`: the owner passes the NTP server the NTP packets it receives, and sends the packets it outputs.
+/// Bad packets are reported to the owner as errors, which it logs.
 #[derive(Debug)]
 pub struct NtpServer {
 	/// Used to translate a socket address to the ID that they've been assigned.
@@ -86,7 +66,8 @@ pub struct NtpServer {
 	host_address: Option<SocketAddr>,
 	/// Used to translate between client IDs and the host ID they expect to find in packets we send to them.
 	id_to_host_id: HashMap<u32, u32>,
-	log: Log,
+	/// Packets waiting to be sent by the owner of the NTP server, along with the address they are sent to.
+	outgoing_packets: VecDeque<(SocketAddr, Vec<u8>)>,
 	/// Used to distinguish between packets and timing statistics.
 	packet_indexes: HashMap<u32, u8>,
 	/// Amount of time it takes to read system time, in nanoseconds.
@@ -97,20 +78,15 @@ pub struct NtpServer {
 	send_stream: NetworkWriteStream,
 	/// The last time we sent a packet to the NTP server.
 	send_times: HashMap<u32, [i128; 256]>,
-	socket: Arc<UdpSocket>,
 	/// Used for determining the best system time correction.
 	statistics: HashMap<u32, NtpStatistics>,
-	rx: mpsc::Receiver<Message>,
 }
 
 impl NtpServer {
-	pub async fn new<T: ToSocketAddrs>(address: T, host_address: Option<(T, u32)>) -> Result<Self, NtpServerError> {
-		let socket = UdpSocket::bind(address).await?;
-
+	pub fn new(host_address: Option<(SocketAddr, u32)>) -> Self {
 		let mut address_to_id = HashMap::new();
 		if let Some((host_address, id)) = host_address {
-			socket.connect(host_address).await?;
-			address_to_id.insert(socket.peer_addr()?, id);
+			address_to_id.insert(host_address, id);
 		}
 
 		// benchmark precision
@@ -125,44 +101,22 @@ impl NtpServer {
 			total += (Instant::now() - start).as_nanos();
 		}
 
-		// set up the read thread
-		let (tx, rx) = mpsc::channel::<Message>(100);
-		let socket = Arc::new(socket);
-		let receive_socket = socket.clone();
-		tokio::spawn(async move {
-			loop {
-				let mut receive_buffer = [0; MAX_NTP_PACKET_SIZE + 1];
-				let (read_bytes, address) = receive_socket.recv_from(&mut receive_buffer).await.expect("Could not receive");
-				let recv_time = Self::get_micros();
-
-				tx.send((address, receive_buffer, read_bytes, recv_time)).await.expect("Could not send");
-			}
-		});
-
-		let host_address = if let Ok(address) = socket.peer_addr() {
-			Some(address)
-		} else {
-			None
-		};
-
-		Ok(NtpServer {
+		NtpServer {
 			address_to_id,
-			host_address,
+			host_address: host_address.map(|(address, _)| address),
 			id_to_host_id: HashMap::new(),
-			log: Log::default(),
+			outgoing_packets: VecDeque::new(),
 			packet_indexes: HashMap::new(),
 			precision: (total / BENCHMARK_TIMES) as u64,
 			receive_stream: NetworkReadStream::new(),
 			send_stream: NetworkWriteStream::new(),
 			send_times: HashMap::new(),
 			statistics: HashMap::new(),
-			socket,
-			rx,
-		})
+		}
 	}
 
 	/// Send a time synchronization request to the server.
-	pub async fn sync_time(&mut self, address: Option<SocketAddr>) -> Result<(), NtpServerError> {
+	pub fn sync_time(&mut self, address: Option<SocketAddr>) -> Result<(), NtpServerError> {
 		// determine the address
 		let address = if let Some(address) = address {
 			address
@@ -200,15 +154,8 @@ impl NtpServer {
 
 		self.send_stream.encode(&packet)?;
 
-		// wait for socket to become writable
-		self.socket.writable().await?;
-
 		let time = Self::get_micros();
-		let result = if let Err(error) = self.socket.send_to(&self.send_stream.export()?, address).await {
-			Err(error.into())
-		} else {
-			Ok(())
-		};
+		self.outgoing_packets.push_back((address, self.send_stream.export()?));
 
 		if !self.send_times.contains_key(id) {
 			self.send_times.insert(*id, [0; 256]);
@@ -217,29 +164,19 @@ impl NtpServer {
 		let array = self.send_times.get_mut(id).unwrap();
 		array[packet_index as usize] = time;
 
-		return result;
+		Ok(())
 	}
 
-	/// Consume all messages that the tokio UDP socket sent us.
-	pub async fn process_all(&mut self) -> Result<(), NtpServerError> {
-		loop {
-			match self.rx.try_recv() {
-				Ok(message) => {
-					if let Err(error) = self.process(message).await {
-						println!("{:?}", error);
-						if error.is_fatal() {
-							return Err(error);
-						}
-					}
-				},
-				Err(mpsc::error::TryRecvError::Empty) => return Ok(()),
-				Err(error) => return Err(error.into()),
-			}
-		}
+	/// Get the next packet that needs to be sent, along with the address it is sent to. Should be drained right after
+	/// calling `sync_time` or `process`, since the packets contain the time they were sent at.
+	pub fn poll_packet(&mut self) -> Option<(SocketAddr, Vec<u8>)> {
+		self.outgoing_packets.pop_front()
 	}
 
-	/// Associates a client ID to a host ID.
-	pub fn associate_host_id(&mut self, id: u32, host_id: u32) {
+	/// Associates a peer's address and client ID to a host ID. Packets with the client ID are only accepted from that
+	/// address.
+	pub fn associate_host_id(&mut self, address: SocketAddr, id: u32, host_id: u32) {
+		self.address_to_id.insert(address, id);
 		self.id_to_host_id.insert(id, host_id);
 	}
 
@@ -257,25 +194,18 @@ impl NtpServer {
 		self.statistics.get(&id)
 	}
 
-	/// Filter bad packets, then read the packet header and figure out what to do with the packet.
-	async fn process(&mut self, message: Message) -> Result<(), NtpServerError> {
-		let (source, receive_buffer, read_bytes, recv_time) = message;
-
+	/// Filter bad packets, then read the packet header and figure out what to do with the packet. `recv_time` is the
+	/// time the packet was received at, in microseconds.
+	pub fn process(&mut self, source: SocketAddr, buffer: &[u8], recv_time: i128) -> Result<(), NtpServerError> {
 		// make sure what we just read is not too big to be an eggine packet
-		if read_bytes > MAX_NTP_PACKET_SIZE {
-			self.log.print(LogLevel::Error, format!("received too big of a packet from {:?}", source), 0);
+		if buffer.len() > MAX_NTP_PACKET_SIZE {
 			return Err(NtpServerError::PacketTooBig(source));
 		}
 
-		// TODO optimize this
-		let mut buffer: Vec<u8> = Vec::new();
-		buffer.extend(&receive_buffer[0..read_bytes]);
-
-		self.receive_stream.import(buffer)?;
+		self.receive_stream.import(buffer.to_vec())?;
 
 		let packet_header = self.receive_stream.decode::<NtpPacketHeader>()?.0;
 		if packet_header.magic_number != NTP_MAGIC_NUMBER {
-			self.log.print(LogLevel::Error, format!("received invalid magic number from {:?}", source), 0);
 			return Err(NtpServerError::InvalidMagicNumber(source));
 		}
 
@@ -284,22 +214,22 @@ impl NtpServer {
 			return Err(NtpServerError::NotWhitelisted(packet_header.id));
 		}
 
-		self.address_to_id.insert(source, packet_header.id);
+		// peers can only use the ID that was associated with their address
+		if self.address_to_id.get(&source) != Some(&packet_header.id) {
+			return Err(NtpServerError::WrongAddress(source));
+		}
 
 		match packet_header.packet_type {
-			0 => self.process_request(source, packet_header.id, recv_time).await?,
+			0 => self.process_request(source, packet_header.id, recv_time)?,
 			1 => self.process_response(packet_header.id, recv_time)?,
-			_ => {
-				self.log.print(LogLevel::Error, format!("received invalid packet type from {:?}", source), 0);
-				return Err(NtpServerError::InvalidPacketType(source));
-			}
+			_ => return Err(NtpServerError::InvalidPacketType(source)),
 		}
 
 		Ok(())
 	}
 
-	/// Process a NTP timing information request. Send the timing information back to the socket.
-	async fn process_request(&mut self, source: SocketAddr, id: u32, recv_time: i128) -> Result<(), NtpServerError> {
+	/// Process a NTP timing information request. Queue the timing information to be sent back.
+	fn process_request(&mut self, source: SocketAddr, id: u32, recv_time: i128) -> Result<(), NtpServerError> {
 		let client_packet = self.receive_stream.decode::<NtpRequestPacket>()?.0;
 
 		let Some(host_id) = self.id_to_host_id.get(&id) else {
@@ -307,93 +237,29 @@ impl NtpServer {
 		};
 
 		// send the server time back
-		let mut buffer: [u8; MAX_NTP_PACKET_SIZE] = [0; MAX_NTP_PACKET_SIZE];
-
-		// write the NTP packet header
-		buffer[0] = 9;
-		buffer[1] = 0;
-		buffer[2] = 'E' as u8;
-		buffer[3] = 'G' as u8;
-		buffer[4] = 'G' as u8;
-		buffer[5] = 'I' as u8;
-		buffer[6] = 'N' as u8;
-		buffer[7] = 'E' as u8;
-		buffer[8] = 'N' as u8;
-		buffer[9] = 'T' as u8;
-		buffer[10] = 'P' as u8;
-		buffer[11] = (host_id & 0xFF) as u8;
-		buffer[12] = ((host_id >> 8) & 0xFF) as u8;
-		buffer[13] = ((host_id >> 16) & 0xFF) as u8;
-		buffer[14] = ((host_id >> 24) & 0xFF) as u8;
-		buffer[15] = 1;
-
-		buffer[16] = client_packet.index;
-
-		// we need to send the times as quick as possible since the longer we take, the more inaccurate the `send_time` is
-		// going to be. sends two 128 bit integers for receive time and send time.
-		buffer[17] = (recv_time & 0xFF) as u8;
-		buffer[18] = ((recv_time >> 8) & 0xFF) as u8;
-		buffer[19] = ((recv_time >> 16) & 0xFF) as u8;
-		buffer[20] = ((recv_time >> 24) & 0xFF) as u8;
-		buffer[21] = ((recv_time >> 32) & 0xFF) as u8;
-		buffer[22] = ((recv_time >> 40) & 0xFF) as u8;
-		buffer[23] = ((recv_time >> 48) & 0xFF) as u8;
-		buffer[24] = ((recv_time >> 56) & 0xFF) as u8;
-		buffer[25] = ((recv_time >> 64) & 0xFF) as u8;
-		buffer[26] = ((recv_time >> 72) & 0xFF) as u8;
-		buffer[27] = ((recv_time >> 80) & 0xFF) as u8;
-		buffer[28] = ((recv_time >> 88) & 0xFF) as u8;
-		buffer[29] = ((recv_time >> 96) & 0xFF) as u8;
-		buffer[30] = ((recv_time >> 104) & 0xFF) as u8;
-		buffer[31] = ((recv_time >> 112) & 0xFF) as u8;
-		buffer[32] = ((recv_time >> 120) & 0xFF) as u8;
-
-		// send precision
-		buffer[33] = (self.precision & 0xFF) as u8;
-		buffer[34] = ((self.precision >> 8) & 0xFF) as u8;
-		buffer[35] = ((self.precision >> 16) & 0xFF) as u8;
-		buffer[36] = ((self.precision >> 24) & 0xFF) as u8;
-		buffer[37] = ((self.precision >> 32) & 0xFF) as u8;
-		buffer[38] = ((self.precision >> 40) & 0xFF) as u8;
-		buffer[39] = ((self.precision >> 48) & 0xFF) as u8;
-		buffer[40] = ((self.precision >> 56) & 0xFF) as u8;
-
-		let send_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as i128;
-
-		// send time encoding
-		buffer[41] = (send_time & 0xFF) as u8;
-		buffer[42] = ((send_time >> 8) & 0xFF) as u8;
-		buffer[43] = ((send_time >> 16) & 0xFF) as u8;
-		buffer[44] = ((send_time >> 24) & 0xFF) as u8;
-		buffer[45] = ((send_time >> 32) & 0xFF) as u8;
-		buffer[46] = ((send_time >> 40) & 0xFF) as u8;
-		buffer[47] = ((send_time >> 48) & 0xFF) as u8;
-		buffer[48] = ((send_time >> 56) & 0xFF) as u8;
-		buffer[49] = ((send_time >> 64) & 0xFF) as u8;
-		buffer[50] = ((send_time >> 72) & 0xFF) as u8;
-		buffer[51] = ((send_time >> 80) & 0xFF) as u8;
-		buffer[52] = ((send_time >> 88) & 0xFF) as u8;
-		buffer[53] = ((send_time >> 96) & 0xFF) as u8;
-		buffer[54] = ((send_time >> 104) & 0xFF) as u8;
-		buffer[55] = ((send_time >> 112) & 0xFF) as u8;
-		buffer[56] = ((send_time >> 120) & 0xFF) as u8;
-
-		// TODO check if the amount of bytes sent in the socket matches the size of the exported vector
-		self.socket.send_to(&buffer, source).await?;
+		let buffer = encode_ntp_response(*host_id, client_packet.index, recv_time, self.precision);
+		self.outgoing_packets.push_back((source, buffer.to_vec()));
 
 		Ok(())
 	}
 
 	/// Process the response sent. Update internal state using the timing information received.
 	fn process_response(&mut self, id: u32, recv_time: i128) -> Result<(), NtpServerError> {
+		// figure out what to do with the packet we just got
+		let packet = self.receive_stream.decode::<NtpResponsePacket>()?.0;
+
+		// the send time is cleared once it is used, so a response is only accepted once per request
+		let Some(send_time) = self.send_times.get_mut(&id)
+			.map(|send_times| std::mem::take(&mut send_times[packet.packet_index as usize]))
+			.filter(|send_time| *send_time != 0)
+		else {
+			return Err(NtpServerError::NoRequest(id));
+		};
+
 		if !self.statistics.contains_key(&id) {
 			self.statistics.insert(id, NtpStatistics::new(300, self.precision));
 		}
 
-		// figure out what to do with the packet we just got
-		let packet = self.receive_stream.decode::<NtpResponsePacket>()?.0;
-
-		let send_time = self.send_times.get_mut(&id).unwrap()[packet.packet_index as usize];
 		let statistics = self.statistics.get_mut(&id).unwrap();
 		statistics.add_time(
 			Times::new(
@@ -425,7 +291,76 @@ impl NtpServer {
 	}
 
 	/// Get time in microseconds.
-	fn get_micros() -> i128 {
+	pub(crate) fn get_micros() -> i128 {
 		SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as i128
 	}
 }
+
+/// Test the NTP server.
+#[cfg(test)]
+mod tests {
+	use std::net::SocketAddr;
+
+	use super::{ NtpServer, NtpServerError, };
+
+	/// Ensure that a request and its response produce timing information.
+	#[test]
+	fn exchange() {
+		let client_address: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+		let server_address: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+
+		let mut client = NtpServer::new(Some((server_address, 1)));
+		let mut server = NtpServer::new(None);
+		client.associate_host_id(server_address, 1, 2);
+		server.associate_host_id(client_address, 2, 1);
+
+		client.sync_time(None).expect("Could not sync time");
+		let (address, request) = client.poll_packet().expect("Could not get request");
+		assert_eq!(address, server_address);
+
+		server.process(client_address, &request, NtpServer::get_micros()).expect("Could not process request");
+		let (address, response) = server.poll_packet().expect("Could not get response");
+		assert_eq!(address, client_address);
+
+		client.process(server_address, &response, NtpServer::get_micros()).expect("Could not process response");
+		assert!(client.get_statistics(1).and_then(|statistics| statistics.best()).is_some());
+	}
+
+	/// Ensure that responses to requests we did not send, and packets sent from the wrong address, are rejected.
+	#[test]
+	fn forged() {
+		let client_address: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+		let server_address: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+		let attacker_address: SocketAddr = "10.0.0.3:1000".parse().unwrap();
+
+		let mut client = NtpServer::new(Some((server_address, 1)));
+		let mut server = NtpServer::new(None);
+		client.associate_host_id(server_address, 1, 2);
+		server.associate_host_id(client_address, 2, 1);
+
+		// the server answers a request the client made, but the client never sent it
+		let mut other_client = NtpServer::new(Some((server_address, 1)));
+		other_client.associate_host_id(server_address, 1, 2);
+		other_client.sync_time(None).expect("Could not sync time");
+		let (_, request) = other_client.poll_packet().expect("Could not get request");
+
+		assert!(matches!(
+			server.process(attacker_address, &request, NtpServer::get_micros()),
+			Err(NtpServerError::WrongAddress(_))
+		));
+
+		server.process(client_address, &request, NtpServer::get_micros()).expect("Could not process request");
+		let (_, response) = server.poll_packet().expect("Could not get response");
+		assert!(matches!(
+			client.process(server_address, &response, NtpServer::get_micros()),
+			Err(NtpServerError::NoRequest(1))
+		));
+
+		// responses are only accepted once
+		other_client.process(server_address, &response, NtpServer::get_micros()).expect("Could not process response");
+		assert!(matches!(
+			other_client.process(server_address, &response, NtpServer::get_micros()),
+			Err(NtpServerError::NoRequest(1))
+		));
+	}
+}
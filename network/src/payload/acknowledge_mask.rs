@@ -4,7 +4,7 @@ use streams::u8_io::{ U8ReadStream, U8ReadStringSafeStream, U8WriteStream, };
 
 use crate::error::NetworkStreamError;
 
-const ACKNOWLEDGE_MASK_SIZE: usize = 2;
+pub(crate) const ACKNOWLEDGE_MASK_SIZE: usize = 2;
 
 #[derive(Clone, Copy, Default)]
 pub struct AcknowledgeMask {
@@ -18,6 +18,12 @@ impl AcknowledgeMask {
 			return;
 		}
 
+		// every bit gets shifted out of the mask
+		if amount as usize >= ACKNOWLEDGE_MASK_SIZE * 64 {
+			self.mask = [0; ACKNOWLEDGE_MASK_SIZE];
+			return;
+		}
+
 		let capped_amount = if amount >= 63 {
 			63
 		} else {
@@ -42,6 +48,13 @@ impl AcknowledgeMask {
 		self.mask[0] |= 1;
 	}
 
+	/// Sets a bit to 1. Bits outside of the mask are ignored.
+	pub fn set(&mut self, bit: u32) {
+		if (bit as usize) < ACKNOWLEDGE_MASK_SIZE * 64 {
+			self.mask[(bit / 64) as usize] |= 1 << (bit % 64);
+		}
+	}
+
 	/// Tests a bit.
 	pub fn test(&self, bit: u32) -> Option<bool> {
 		if bit as usize >= ACKNOWLEDGE_MASK_SIZE * 64 {
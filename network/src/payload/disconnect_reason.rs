@@ -1,10 +1,13 @@
 use std::fmt::Debug;
 
 use streams::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
-use streams::u8_io::{ U8ReadStream, U8WriteStream, };
+use streams::u8_io::{ U8ReadStream, U8ReadStringSafeStream, U8WriteStream, };
 
 use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
 
+/// The maximum length of the message sent with `DisconnectionReason::Kicked`.
+pub const MAX_KICK_MESSAGE_LENGTH: usize = 255;
+
 #[derive(Debug, Clone, Copy)]
 pub enum DisconnectionReasonError {
 	InvalidDisconnectionReason,
@@ -16,7 +19,7 @@ impl NetworkStreamErrorTrait for DisconnectionReasonError {
 	}
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum DisconnectionReason {
 	/// A client must send a `SubPayload::Disconnect` to the server with the `Requested` variant to be gracefully
 	/// disconnected from the server.
@@ -25,6 +28,38 @@ pub enum DisconnectionReason {
 	/// timed out may not even be connected to the internet anymore, it is not expected that the client will receive the
 	/// message.
 	Timeout,
+	/// The server rejected the client's handshake because the client's network protocol does not match the server's.
+	VersionMismatch,
+	/// The server kicked the client. The message is displayed to the client, and is at most `MAX_KICK_MESSAGE_LENGTH`
+	/// bytes long.
+	Kicked(String),
+	/// The server banned the client's IP address.
+	Banned,
+	/// The server is shutting down.
+	ServerShutdown,
+	/// The server has no room for more clients.
+	ServerFull,
+	/// The remote sent data that does not follow the network protocol.
+	ProtocolError,
+	/// The server has no room for more clients from the client's IP address.
+	TooManyConnections,
+}
+
+impl std::fmt::Display for DisconnectionReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			DisconnectionReason::Requested => write!(f, "Disconnected"),
+			DisconnectionReason::Timeout => write!(f, "Connection timed out"),
+			DisconnectionReason::VersionMismatch => write!(f, "The server is running an incompatible version"),
+			DisconnectionReason::Kicked(message) if message.is_empty() => write!(f, "Kicked by the server"),
+			DisconnectionReason::Kicked(message) => write!(f, "Kicked by the server: {}", message),
+			DisconnectionReason::Banned => write!(f, "Banned from the server"),
+			DisconnectionReason::ServerShutdown => write!(f, "The server shut down"),
+			DisconnectionReason::ServerFull => write!(f, "The server is full"),
+			DisconnectionReason::ProtocolError => write!(f, "Received invalid data"),
+			DisconnectionReason::TooManyConnections => write!(f, "Too many connections from your address"),
+		}
+	}
 }
 
 impl<T> Encode<u8, T, NetworkStreamError> for DisconnectionReason
@@ -32,9 +67,19 @@ where
 	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
 {
 	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
-		match *self {
+		match self {
 			DisconnectionReason::Requested => stream.write_u8(1)?,
 			DisconnectionReason::Timeout => stream.write_u8(2)?,
+			DisconnectionReason::VersionMismatch => stream.write_u8(3)?,
+			DisconnectionReason::Kicked(message) => {
+				stream.write_u8(4)?;
+				stream.write_string(message)?;
+			},
+			DisconnectionReason::Banned => stream.write_u8(5)?,
+			DisconnectionReason::ServerShutdown => stream.write_u8(6)?,
+			DisconnectionReason::ServerFull => stream.write_u8(7)?,
+			DisconnectionReason::ProtocolError => stream.write_u8(8)?,
+			DisconnectionReason::TooManyConnections => stream.write_u8(9)?,
 		};
 		Ok(())
 	}
@@ -42,15 +87,77 @@ where
 
 impl<T> Decode<u8, T, NetworkStreamError> for DisconnectionReason
 where
-	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError> + U8ReadStringSafeStream<NetworkStreamError>
 {
 	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
 		let (byte, position) = stream.read_u8()?;
 		let reason = match byte {
 			1 => DisconnectionReason::Requested,
 			2 => DisconnectionReason::Timeout,
+			3 => DisconnectionReason::VersionMismatch,
+			4 => {
+				let (message, position) = stream.read_string_safe(0, MAX_KICK_MESSAGE_LENGTH as u64)?;
+				return Ok((DisconnectionReason::Kicked(message), position));
+			},
+			5 => DisconnectionReason::Banned,
+			6 => DisconnectionReason::ServerShutdown,
+			7 => DisconnectionReason::ServerFull,
+			8 => DisconnectionReason::ProtocolError,
+			9 => DisconnectionReason::TooManyConnections,
 			_ => return Err(Box::new(DisconnectionReasonError::InvalidDisconnectionReason))
 		};
 		Ok((reason, position))
 	}
 }
+
+/// Test disconnection reason encoding.
+#[cfg(test)]
+mod tests {
+	use streams::{ ReadStream, WriteStream, };
+	use streams::u8_io::U8WriteStream;
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+
+	use super::{ DisconnectionReason, MAX_KICK_MESSAGE_LENGTH, };
+
+	/// Ensure that every disconnection reason is decoded into the same reason.
+	#[test]
+	fn round_trip() {
+		let reasons = [
+			DisconnectionReason::Requested,
+			DisconnectionReason::Timeout,
+			DisconnectionReason::VersionMismatch,
+			DisconnectionReason::Kicked(String::new()),
+			DisconnectionReason::Kicked(String::from("stop that")),
+			DisconnectionReason::Banned,
+			DisconnectionReason::ServerShutdown,
+			DisconnectionReason::ServerFull,
+			DisconnectionReason::ProtocolError,
+			DisconnectionReason::TooManyConnections,
+		];
+
+		for reason in reasons {
+			let mut stream = NetworkReadStream::new();
+			stream.import(stream_bytes(&reason)).expect("Could not import reason");
+			assert_eq!(stream.decode::<DisconnectionReason>().expect("Could not decode reason").0, reason);
+		}
+	}
+
+	/// Ensure that kick messages longer than `MAX_KICK_MESSAGE_LENGTH` are rejected.
+	#[test]
+	fn long_kick_message() {
+		let mut stream = NetworkWriteStream::new();
+		stream.write_u8(4).expect("Could not write reason");
+		stream.write_string(&"a".repeat(MAX_KICK_MESSAGE_LENGTH + 1)).expect("Could not write message");
+
+		let mut read_stream = NetworkReadStream::new();
+		read_stream.import(stream.export().expect("Could not export reason")).expect("Could not import reason");
+		assert!(read_stream.decode::<DisconnectionReason>().is_err());
+	}
+
+	fn stream_bytes(reason: &DisconnectionReason) -> Vec<u8> {
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(reason).expect("Could not encode reason");
+		stream.export().expect("Could not export reason")
+	}
+}
@@ -0,0 +1,581 @@
+use std::collections::{ BTreeMap, VecDeque, };
+use std::time::{ Duration, Instant, };
+use streams::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
+use streams::u8_io::{ U8ReadStream, U8WriteStream, };
+
+use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+use crate::MAX_PACKET_SIZE;
+use crate::encryption::TAG_SIZE;
+
+use super::{ Packet, SubPayload, };
+use super::packet::PACKET_HEADER_SIZE;
+use super::payload::read_payload_vector;
+
+/// The maximum amount of sub-payload bytes stored in a fragment.
+pub const MAX_FRAGMENT_SIZE: usize = 1024;
+
+/// The maximum amount of fragments a sub-payload can be split into, which caps sub-payloads at 1MiB.
+pub const MAX_FRAGMENT_COUNT: u16 = 1024;
+
+/// The maximum amount of bytes we buffer for incomplete fragmented sub-payloads per connection. If we go over the cap,
+/// the oldest incomplete sub-payloads are discarded.
+const MAX_REASSEMBLY_BYTES: usize = 4 * 1024 * 1024;
+
+/// The maximum amount of incomplete fragmented sub-payloads we buffer per connection. If we go over the cap, the oldest
+/// incomplete sub-payloads are discarded.
+const MAX_FRAGMENT_GROUPS: usize = 64;
+
+/// How long we wait for all the fragments of a sub-payload to arrive before we discard it.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum size of an encoded packet before it is encrypted.
+const MAX_UNENCRYPTED_PACKET_SIZE: usize = MAX_PACKET_SIZE - TAG_SIZE;
+
+/// Sub-payloads whose encoding is bigger than this are fragmented.
+const MAX_UNFRAGMENTED_SIZE: usize = MAX_FRAGMENT_SIZE;
+
+/// How long low priority sub-payloads wait for room in a packet before we discard them.
+const LOW_PRIORITY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How important an unreliable message is. When the send budget runs out, low priority messages are sent after
+/// everything else, and are discarded if they wait for too long.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+	Normal,
+	Low,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum FragmentError {
+	/// Emitted if a fragment's count does not match the count of the other fragments in its group.
+	InconsistentCount,
+	/// Emitted if a fragment has an invalid index or count, or if a fragment of a multi-fragment group is empty.
+	InvalidFragment,
+	/// Emitted if a reassembled sub-payload is itself a fragment.
+	NestedFragment,
+}
+
+impl NetworkStreamErrorTrait for FragmentError {
+	fn as_any(&self) -> &dyn std::any::Any {
+		self
+	}
+}
+
+/// A piece of a sub-payload that was too big to fit into a single packet. Fragments are not resent if they are dropped,
+/// so if any fragment in a group does not arrive the sub-payload is lost. Sub-payloads that need to arrive should be
+/// sent using a `ReliableChannel`, which keeps its messages small enough to not need fragmentation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Fragment {
+	/// The amount of fragments the sub-payload was split into.
+	pub count: u16,
+	/// The fragment's piece of the encoded sub-payload.
+	pub data: Vec<u8>,
+	/// Identifies the sub-payload the fragment belongs to.
+	pub group: u32,
+	/// The fragment's position in the encoded sub-payload.
+	pub index: u16,
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for Fragment
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		stream.write_u32(self.group)?;
+		stream.write_u16(self.index)?;
+		stream.write_u16(self.count)?;
+		stream.write_vlq(self.data.len() as u64)?;
+		stream.write_vector(&self.data)
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for Fragment
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		let (group, _) = stream.read_u32()?;
+		let (index, _) = stream.read_u16()?;
+		let (count, _) = stream.read_u16()?;
+		let (data, position) = read_payload_vector(stream)?;
+
+		// fragmented sub-payloads are split into non-empty pieces, so empty fragments only waste memory
+		if count == 0
+			|| count > MAX_FRAGMENT_COUNT
+			|| index >= count
+			|| data.len() > MAX_FRAGMENT_SIZE
+			|| (data.is_empty() && count > 1)
+		{
+			return Err(Box::new(FragmentError::InvalidFragment));
+		}
+
+		Ok((
+			Fragment {
+				count,
+				data,
+				group,
+				index,
+			},
+			position
+		))
+	}
+}
+
+/// Keeps outgoing packets within `MAX_PACKET_SIZE`. Sub-payloads that are too big are split into fragments, and
+/// sub-payloads that do not fit into the current packet are deferred to the next one.
+#[derive(Debug, Default)]
+pub struct Fragmenter {
+	/// Low priority sub-payloads and fragments waiting for room in a packet, along with the time they were queued.
+	low_priority_queue: VecDeque<(SubPayload, usize, Instant)>,
+	/// The group ID we assign to the next fragmented sub-payload.
+	next_group: u32,
+	/// Sub-payloads and fragments waiting for room in a packet.
+	queue: VecDeque<(SubPayload, usize)>,
+	/// Stream used to measure the size of sub-payloads.
+	stream: NetworkWriteStream,
+}
+
+impl Fragmenter {
+	/// Whether or not there are sub-payloads waiting to be sent.
+	pub fn has_queued(&self) -> bool {
+		!self.queue.is_empty() || !self.low_priority_queue.is_empty()
+	}
+
+	/// Queue a sub-payload to be sent in a later packet, fragmenting it if it is too big.
+	pub(crate) fn push(&mut self, sub_payload: SubPayload, priority: Priority, now: Instant)
+		-> Result<(), NetworkStreamError>
+	{
+		let queue = |fragmenter: &mut Fragmenter, sub_payload: SubPayload, length: usize| match priority {
+			Priority::Normal => fragmenter.queue.push_back((sub_payload, length)),
+			Priority::Low => fragmenter.low_priority_queue.push_back((sub_payload, length, now)),
+		};
+
+		let length = self.measure(&sub_payload)?;
+		if length <= MAX_UNFRAGMENTED_SIZE {
+			queue(self, sub_payload, length);
+			return Ok(());
+		}
+
+		// split the encoded sub-payload into fragments
+		let bytes = self.stream.export()?;
+		let group = self.next_group;
+		self.next_group = self.next_group.wrapping_add(1);
+
+		let count = bytes.len().div_ceil(MAX_FRAGMENT_SIZE);
+		if count > MAX_FRAGMENT_COUNT as usize {
+			return Err(Box::new(FragmentError::InvalidFragment));
+		}
+
+		for (index, data) in bytes.chunks(MAX_FRAGMENT_SIZE).enumerate() {
+			let fragment = SubPayload::Fragment(Fragment {
+				count: count as u16,
+				data: data.to_vec(),
+				group,
+				index: index as u16,
+			});
+
+			let length = self.measure(&fragment)?;
+			queue(self, fragment, length);
+		}
+
+		Ok(())
+	}
+
+	/// Rearrange the packet's sub-payloads so the packet fits into `MAX_PACKET_SIZE`. Reliable sub-payloads are tracked
+	/// using the sequence number of the packet they are sent in, so they always stay in the packet. Everything else is
+	/// fragmented and/or deferred to later packets as needed. Queued sub-payloads are only added to the packet while they
+	/// fit into `budget`, except for the first one so a small budget cannot stall the queue. Returns the amount of queued
+	/// bytes added to the packet.
+	pub(crate) fn fragment(&mut self, packet: &mut Packet, budget: usize, now: Instant)
+		-> Result<usize, NetworkStreamError>
+	{
+		let mut size = PACKET_HEADER_SIZE;
+		let mut deferred = Vec::new();
+		for sub_payload in packet.take_sub_payloads() {
+			if let SubPayload::Reliable(..) = sub_payload {
+				size += self.measure(&sub_payload)?;
+				packet.add_sub_payload(sub_payload);
+			} else {
+				deferred.push(sub_payload);
+			}
+		}
+
+		for sub_payload in deferred {
+			self.push(sub_payload, Priority::Normal, now)?;
+		}
+
+		// low priority sub-payloads that waited for too long are out of date
+		while let Some((_, _, queued_at)) = self.low_priority_queue.front() {
+			if now.saturating_duration_since(*queued_at) < LOW_PRIORITY_TIMEOUT {
+				break;
+			}
+
+			self.low_priority_queue.pop_front();
+		}
+
+		// fill the rest of the packet with queued sub-payloads, sending low priority ones last
+		let mut used = 0;
+		while let Some(length) = self.queue.front()
+			.map(|(_, length)| *length)
+			.or_else(|| self.low_priority_queue.front().map(|(_, length, _)| *length))
+		{
+			if size + length > MAX_UNENCRYPTED_PACKET_SIZE || (used > 0 && used + length > budget) {
+				break;
+			}
+
+			size += length;
+			used += length;
+			let sub_payload = match self.queue.pop_front() {
+				Some((sub_payload, _)) => sub_payload,
+				None => self.low_priority_queue.pop_front().unwrap().0,
+			};
+			packet.add_sub_payload(sub_payload);
+		}
+
+		Ok(used)
+	}
+
+	// encode a sub-payload to figure out its size. the encoded bytes are left in the stream
+	fn measure(&mut self, sub_payload: &SubPayload) -> Result<usize, NetworkStreamError> {
+		self.stream.export()?;
+		self.stream.encode(sub_payload)?;
+		Ok(self.stream.len())
+	}
+}
+
+/// Fragments received for a sub-payload that has not been fully received yet.
+#[derive(Debug)]
+struct FragmentGroup {
+	bytes: usize,
+	fragments: BTreeMap<u16, Vec<u8>>,
+	count: u16,
+	started_at: Instant,
+}
+
+/// Reassembles fragmented sub-payloads. Incomplete sub-payloads are discarded if they take too long to arrive, if they
+/// take up too much memory, or if there are too many of them.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+	/// The amount of fragment bytes we are buffering.
+	bytes: usize,
+	groups: BTreeMap<u32, FragmentGroup>,
+}
+
+impl Reassembler {
+	/// The amount of fragment bytes we are buffering.
+	pub fn buffered_bytes(&self) -> usize {
+		self.bytes
+	}
+
+	/// Add a fragment. Returns the reassembled sub-payload once all of its fragments have arrived.
+	pub(crate) fn handle_fragment(&mut self, fragment: Fragment, now: Instant)
+		-> Result<Option<SubPayload>, NetworkStreamError>
+	{
+		self.prune(now);
+
+		let group = self.groups.entry(fragment.group).or_insert_with(|| FragmentGroup {
+			bytes: 0,
+			fragments: BTreeMap::new(),
+			count: fragment.count,
+			started_at: now,
+		});
+
+		if group.count != fragment.count {
+			return Err(Box::new(FragmentError::InconsistentCount));
+		}
+
+		if group.fragments.contains_key(&fragment.index) {
+			return Ok(None);
+		}
+
+		group.bytes += fragment.data.len();
+		self.bytes += fragment.data.len();
+		group.fragments.insert(fragment.index, fragment.data);
+
+		if group.fragments.len() != group.count as usize {
+			self.enforce_cap(fragment.group);
+			return Ok(None);
+		}
+
+		// all fragments arrived, decode the sub-payload
+		let group = self.groups.remove(&fragment.group).unwrap();
+		self.bytes -= group.bytes;
+
+		let mut stream = NetworkReadStream::new();
+		stream.import(group.fragments.into_values().flatten().collect())?;
+
+		match stream.decode::<SubPayload>()?.0 {
+			SubPayload::Fragment(_) => Err(Box::new(FragmentError::NestedFragment)),
+			sub_payload => Ok(Some(sub_payload)),
+		}
+	}
+
+	// discard groups that took too long to arrive
+	fn prune(&mut self, now: Instant) {
+		let expired = self.groups.iter()
+			.filter(|(_, group)| now - group.started_at > FRAGMENT_TIMEOUT)
+			.map(|(id, _)| *id)
+			.collect::<Vec<u32>>();
+
+		for id in expired {
+			self.discard(id);
+		}
+	}
+
+	// discard the oldest groups until we are within the memory and group caps. the group that was just added to is
+	// discarded last
+	fn enforce_cap(&mut self, newest: u32) {
+		while self.bytes > MAX_REASSEMBLY_BYTES || self.groups.len() > MAX_FRAGMENT_GROUPS {
+			let oldest = self.groups.iter()
+				.filter(|(id, _)| **id != newest)
+				.min_by_key(|(_, group)| group.started_at)
+				.map(|(id, _)| *id)
+				.unwrap_or(newest);
+
+			self.discard(oldest);
+		}
+	}
+
+	fn discard(&mut self, id: u32) {
+		if let Some(group) = self.groups.remove(&id) {
+			self.bytes -= group.bytes;
+		}
+	}
+}
+
+/// Test fragmenting sub-payloads across packets.
+#[cfg(test)]
+mod tests {
+	use std::time::{ Duration, Instant, };
+	use streams::{ ReadStream, WriteStream, };
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+	use crate::reliable::ReliableMessage;
+	use crate::MAX_PACKET_SIZE;
+
+	use super::{
+		Fragment,
+		FragmentError,
+		Fragmenter,
+		Packet,
+		Priority,
+		Reassembler,
+		SubPayload,
+		FRAGMENT_TIMEOUT,
+		LOW_PRIORITY_TIMEOUT,
+		MAX_FRAGMENT_COUNT,
+		MAX_FRAGMENT_GROUPS,
+		MAX_FRAGMENT_SIZE,
+		MAX_REASSEMBLY_BYTES,
+	};
+
+	// send packets through the fragmenter until it runs out of sub-payloads, and return the encoded packets
+	fn send_packets(fragmenter: &mut Fragmenter, mut packet: Packet) -> Vec<Vec<u8>> {
+		let mut packets = Vec::new();
+		let mut stream = NetworkWriteStream::new();
+		loop {
+			fragmenter.fragment(&mut packet, usize::MAX, Instant::now()).expect("Could not fragment packet");
+			stream.encode(&packet).expect("Could not encode packet");
+			packets.push(stream.export().expect("Could not export packet"));
+
+			if !fragmenter.has_queued() {
+				return packets;
+			}
+
+			packet = Packet::new(0, 0);
+		}
+	}
+
+	// decode packets and reassemble their sub-payloads
+	fn receive_packets(reassembler: &mut Reassembler, packets: Vec<Vec<u8>>, now: Instant) -> Vec<SubPayload> {
+		let mut sub_payloads = Vec::new();
+		let mut stream = NetworkReadStream::new();
+		for bytes in packets {
+			stream.import(bytes).expect("Could not import packet");
+			let mut packet = stream.decode::<Packet>().expect("Could not decode packet").0;
+			for sub_payload in packet.take_sub_payloads() {
+				if let SubPayload::Fragment(fragment) = sub_payload {
+					if let Some(sub_payload) = reassembler.handle_fragment(fragment, now).expect("Could not reassemble") {
+						sub_payloads.push(sub_payload);
+					}
+				} else {
+					sub_payloads.push(sub_payload);
+				}
+			}
+		}
+
+		sub_payloads
+	}
+
+	/// Ensure that big sub-payloads are split across packets that fit into `MAX_PACKET_SIZE`, and are reassembled into
+	/// the original sub-payloads.
+	#[test]
+	fn fragment_and_reassemble() {
+		let world_state = (0..20_000u32).map(|number| (number % 241) as u8).collect::<Vec<u8>>();
+
+		let mut packet = Packet::new(0, 0);
+		packet.add_sub_payload(SubPayload::Ping(5));
+		packet.add_sub_payload(SubPayload::Unreliable(world_state.clone()));
+		packet.add_sub_payload(SubPayload::Reliable(0, ReliableMessage::Message(vec![1, 2, 3])));
+
+		let mut fragmenter = Fragmenter::default();
+		let packets = send_packets(&mut fragmenter, packet);
+		assert!(packets.len() > 1);
+		assert!(packets.iter().all(|packet| packet.len() <= MAX_PACKET_SIZE));
+
+		let mut reassembler = Reassembler::default();
+		let sub_payloads = receive_packets(&mut reassembler, packets, Instant::now());
+		assert!(matches!(sub_payloads[..], [
+			SubPayload::Reliable(0, ReliableMessage::Message(ref message)),
+			SubPayload::Ping(5),
+			SubPayload::Unreliable(ref state),
+		] if *message == vec![1, 2, 3] && *state == world_state));
+		assert_eq!(reassembler.buffered_bytes(), 0);
+	}
+
+	/// Ensure that packets with too many small sub-payloads are split into multiple packets.
+	#[test]
+	fn packet_size() {
+		let mut fragmenter = Fragmenter::default();
+		let mut packet = Packet::new(0, 0);
+		for _ in 0..500 {
+			packet.add_sub_payload(SubPayload::Ping(0));
+		}
+
+		let packets = send_packets(&mut fragmenter, packet);
+		assert!(packets.len() > 1);
+		for bytes in packets {
+			assert!(bytes.len() <= MAX_PACKET_SIZE);
+		}
+	}
+
+	/// Ensure that queued sub-payloads stay within the budget, and that low priority sub-payloads are sent last and are
+	/// discarded once they time out.
+	#[test]
+	fn budget_and_priority() {
+		let now = Instant::now();
+		let mut fragmenter = Fragmenter::default();
+		fragmenter.push(SubPayload::Unreliable(vec![0; 500]), Priority::Low, now).expect("Could not queue sub-payload");
+
+		let mut packet = Packet::new(0, 0);
+		packet.add_sub_payload(SubPayload::Unreliable(vec![1; 500]));
+		packet.add_sub_payload(SubPayload::Unreliable(vec![2; 500]));
+
+		// the first sub-payload is always sent, even if the budget is too small for it
+		let used = fragmenter.fragment(&mut packet, 100, now).expect("Could not fragment packet");
+		assert!(used > 500);
+		assert!(matches!(packet.get_sub_payloads()[..], [SubPayload::Unreliable(ref message)] if message[0] == 1));
+
+		let mut packet = Packet::new(0, 0);
+		fragmenter.fragment(&mut packet, 2000, now).expect("Could not fragment packet");
+		assert!(matches!(packet.get_sub_payloads()[..], [
+			SubPayload::Unreliable(ref normal),
+			SubPayload::Unreliable(ref low),
+		] if normal[0] == 2 && low[0] == 0));
+		assert!(!fragmenter.has_queued());
+
+		fragmenter.push(SubPayload::Unreliable(vec![0; 500]), Priority::Low, now).expect("Could not queue sub-payload");
+		let mut packet = Packet::new(0, 0);
+		fragmenter.fragment(&mut packet, 2000, now + LOW_PRIORITY_TIMEOUT).expect("Could not fragment packet");
+		assert!(packet.get_sub_payloads().is_empty());
+		assert!(!fragmenter.has_queued());
+	}
+
+	/// Ensure that incomplete sub-payloads are discarded once they time out.
+	#[test]
+	fn timeout() {
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+		let result = reassembler.handle_fragment(Fragment {
+			count: 2,
+			data: vec![0; 100],
+			group: 0,
+			index: 0,
+		}, now).expect("Could not handle fragment");
+
+		assert!(result.is_none());
+		assert_eq!(reassembler.buffered_bytes(), 100);
+
+		reassembler.handle_fragment(Fragment {
+			count: 2,
+			data: vec![0; 100],
+			group: 1,
+			index: 0,
+		}, now + FRAGMENT_TIMEOUT + Duration::from_secs(1)).expect("Could not handle fragment");
+
+		assert_eq!(reassembler.buffered_bytes(), 100);
+	}
+
+	/// Ensure that the reassembler does not buffer more than `MAX_REASSEMBLY_BYTES`.
+	#[test]
+	fn memory_cap() {
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+		for group in 0..(MAX_REASSEMBLY_BYTES / MAX_FRAGMENT_SIZE) as u32 * 2 {
+			reassembler.handle_fragment(Fragment {
+				count: 2,
+				data: vec![0; MAX_FRAGMENT_SIZE],
+				group,
+				index: 0,
+			}, now).expect("Could not handle fragment");
+
+			assert!(reassembler.buffered_bytes() <= MAX_REASSEMBLY_BYTES);
+		}
+	}
+
+	/// Ensure that fragments that disagree with their group are rejected.
+	#[test]
+	fn inconsistent_count() {
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+		reassembler.handle_fragment(Fragment {
+			count: 2,
+			data: vec![0; 10],
+			group: 0,
+			index: 0,
+		}, now).expect("Could not handle fragment");
+
+		let error = reassembler.handle_fragment(Fragment {
+			count: 3,
+			data: vec![0; 10],
+			group: 0,
+			index: 1,
+		}, now).unwrap_err();
+
+		assert_eq!(
+			error.as_any().downcast_ref::<FragmentError>().expect("Could not downcast `FragmentError`"),
+			&FragmentError::InconsistentCount
+		);
+	}
+
+	/// Ensure that empty fragments are rejected, and that a flood of tiny fragments cannot open unlimited groups.
+	#[test]
+	fn group_cap() {
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(&Fragment {
+			count: MAX_FRAGMENT_COUNT,
+			data: Vec::new(),
+			group: 0,
+			index: 0,
+		}).expect("Could not encode fragment");
+
+		let mut read_stream = NetworkReadStream::new();
+		read_stream.import(stream.export().expect("Could not export test stream")).expect("Could not import test stream");
+		assert!(read_stream.decode::<Fragment>().is_err());
+
+		// fragments that skipped decoding still cannot open more groups than the cap
+		let now = Instant::now();
+		let mut reassembler = Reassembler::default();
+		for group in 0..10_000 {
+			reassembler.handle_fragment(Fragment {
+				count: MAX_FRAGMENT_COUNT,
+				data: Vec::new(),
+				group,
+				index: 0,
+			}, now).expect("Could not handle fragment");
+
+			assert!(reassembler.groups.len() <= MAX_FRAGMENT_GROUPS);
+		}
+	}
+}
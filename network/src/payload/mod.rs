@@ -1,14 +1,27 @@
 pub mod acknowledge_mask;
 pub mod disconnect_reason;
+pub mod fragment;
 pub mod ntp_packet;
 pub mod packet;
 pub mod payload;
+pub mod snapshot;
 
 pub use acknowledge_mask::AcknowledgeMask;
 pub use disconnect_reason::DisconnectionReason;
+pub use disconnect_reason::MAX_KICK_MESSAGE_LENGTH;
+pub use fragment::Fragment;
+pub use fragment::Fragmenter;
+pub use fragment::Priority;
+pub use fragment::Reassembler;
 pub use ntp_packet::NtpPacketHeader;
 pub use ntp_packet::NtpRequestPacket;
 pub use ntp_packet::NtpResponsePacket;
 pub use packet::Packet;
+pub use packet::PacketExtension;
+pub use payload::MAX_SUB_PAYLOAD_SIZE;
+pub use payload::MAX_UNRELIABLE_MESSAGE_SIZE;
 pub use payload::Payload;
 pub use payload::SubPayload;
+pub use snapshot::EntityUpdate;
+pub use snapshot::MAX_SNAPSHOT_CHUNKS;
+pub use snapshot::SnapshotChunk;
@@ -1,8 +1,9 @@
+use std::time::{ SystemTime, UNIX_EPOCH, };
 use streams::{ Decode, Encode, Endable, ReadStream, StreamPosition, WriteStream, };
 use streams::u8_io::{ U8ReadStream, U8ReadStringSafeStream, U8WriteStream, };
 
 use crate::error::NetworkStreamError;
-use crate::ntp::NTP_MAGIC_NUMBER;
+use crate::ntp::{ MAX_NTP_PACKET_SIZE, NTP_MAGIC_NUMBER, };
 
 /// Header of a NTP packet.
 #[derive(Debug, Eq, PartialEq)]
@@ -122,3 +123,33 @@ where
 		}, position))
 	}
 }
+
+/// Encode a NTP response by hand, so the time it was sent at can be read as late as possible. The layout matches a
+/// `NtpPacketHeader` with packet type 1 followed by a `NtpResponsePacket`.
+pub(crate) fn encode_ntp_response(
+	host_id: u32,
+	index: u8,
+	recv_time: i128,
+	precision: u64
+) -> [u8; MAX_NTP_PACKET_SIZE] {
+	let mut buffer: [u8; MAX_NTP_PACKET_SIZE] = [0; MAX_NTP_PACKET_SIZE];
+
+	// write the NTP packet header. the magic number's length is encoded as a variable length quantity
+	buffer[0..2].copy_from_slice(&(NTP_MAGIC_NUMBER.len() as u16).to_le_bytes());
+	buffer[2..11].copy_from_slice(NTP_MAGIC_NUMBER.as_bytes());
+	buffer[11..15].copy_from_slice(&host_id.to_le_bytes());
+	buffer[15] = 1;
+
+	buffer[16] = index;
+
+	// send the receive time and our precision
+	buffer[17..33].copy_from_slice(&recv_time.to_le_bytes());
+	buffer[33..41].copy_from_slice(&precision.to_le_bytes());
+
+	// we need to send the times as quick as possible since the longer we take, the more inaccurate the `send_time` is
+	// going to be
+	let send_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as i128;
+	buffer[41..57].copy_from_slice(&send_time.to_le_bytes());
+
+	buffer
+}
@@ -6,8 +6,10 @@ use streams::{ Decode, Encode, Endable, ReadStream, StreamPosition, WriteStream,
 use streams::u8_io::{ U8ReadStream, U8ReadStringSafeStream, U8WriteStream, };
 
 use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::network_stream::NetworkReadStream;
 
 use super::{ AcknowledgeMask, Payload, SubPayload, };
+use super::acknowledge_mask::ACKNOWLEDGE_MASK_SIZE;
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum PacketError {
@@ -23,13 +25,19 @@ impl NetworkStreamErrorTrait for PacketError {
 #[derive(Debug, Eq, Hash, PartialEq)]
 pub enum PacketExtension {
 	Reserved = 0,
+	/// The packet's payload is encrypted, and is followed by an authentication tag.
+	Encrypted = 1,
 }
 
 /// The amount of extensions there are. There can be up to `ceil(EXTENSIONS_COUNT / 32)` extension `u32`s in a packet
 /// header.
-const EXTENSIONS_COUNT: usize = 1;
+const EXTENSIONS_COUNT: usize = 2;
 const EXTENSION_U32_COUNT: usize = (EXTENSIONS_COUNT + 32 - 1) / 32;
 
+/// The size of an encoded packet header: the sequence, the highest acknowledged sequence, the acknowledge mask, and the
+/// extension `u32`s.
+pub(crate) const PACKET_HEADER_SIZE: usize = 4 + 4 + ACKNOWLEDGE_MASK_SIZE * 8 + EXTENSION_U32_COUNT * 4;
+
 #[derive(Debug)]
 pub enum PacketExtensionError {
 	InvalidConversion,
@@ -41,6 +49,7 @@ impl TryInto<PacketExtension> for usize {
 	fn try_into(self) -> Result<PacketExtension, Self::Error> {
 		match self {
 			0 => Ok(PacketExtension::Reserved),
+			1 => Ok(PacketExtension::Encrypted),
 			_ => Err(PacketExtensionError::InvalidConversion),
 		}
 	}
@@ -66,6 +75,10 @@ pub struct Packet {
 pub(crate) struct HandledResult {
 	pub(crate) acknowledged_sequences: Vec<u32>,
 	pub(crate) dropped_sequences: Vec<u32>,
+	/// Whether or not the packet is older than a packet we already received.
+	pub(crate) out_of_order: bool,
+	/// The newest sequence received from the remote, which is not the packet's sequence if the packet arrived out of
+	/// order.
 	pub(crate) remote_sequence: u32,
 	pub(crate) new_highest_acknowledged_sequence: u32,
 	pub(crate) new_acknowledge_mask: AcknowledgeMask,
@@ -102,6 +115,38 @@ impl Packet {
 		self.payload.get_all()
 	}
 
+	/// Take the packet's sub-payloads, leaving the payload empty.
+	pub fn take_sub_payloads(&mut self) -> Vec<SubPayload> {
+		self.payload.take_all()
+	}
+
+	/// Enable an extension on the packet.
+	pub fn add_extension(&mut self, extension: PacketExtension) {
+		self.extensions.insert(extension);
+	}
+
+	/// Whether or not an extension is enabled on the packet.
+	pub fn has_extension(&self, extension: &PacketExtension) -> bool {
+		self.extensions.contains(extension)
+	}
+
+	/// Read the sequence number and extensions out of an encoded packet's header without decoding the payload. Returns
+	/// `None` if the bytes are too short to contain a header.
+	pub(crate) fn peek_header(bytes: &[u8]) -> Option<(u32, HashSet<PacketExtension>)> {
+		if bytes.len() < PACKET_HEADER_SIZE {
+			return None;
+		}
+
+		let mut stream = NetworkReadStream::new();
+		stream.import(bytes[0..PACKET_HEADER_SIZE].to_vec()).ok()?;
+
+		let (sequence, _) = stream.read_u32().ok()?;
+		stream.read_u32().ok()?;
+		stream.decode::<AcknowledgeMask>().ok()?;
+
+		Some((sequence, decode_extensions(&mut stream).ok()?))
+	}
+
 	/// Calculate what the local network stack should do next based on a packet received from the remote machine. The
 	/// `self` packet is assumed to be a packet from the remote machine, and this function is fed state from the local
 	/// machine in order to correctly calculate what the local machine needs to do next.
@@ -134,24 +179,39 @@ impl Packet {
 		// 	local_acknowledge_mask
 		// );
 
-		// update the acknowledge mask
-		if let Some(local_last_sequence_received) = local_last_sequence_received {
-			result.new_acknowledge_mask.shift(result.remote_sequence - local_last_sequence_received);
+		// update the acknowledge mask. packets that arrived out of order are marked in the mask without moving it.
+		// sequences wrap around, so they are compared using `is_newer_sequence`
+		match local_last_sequence_received {
+			Some(local_last_sequence_received) if !is_newer_sequence(self.sequence, local_last_sequence_received) => {
+				result.out_of_order = true;
+				result.remote_sequence = local_last_sequence_received;
+				result.new_acknowledge_mask.set(local_last_sequence_received.wrapping_sub(self.sequence));
+			},
+			Some(local_last_sequence_received) => {
+				result.new_acknowledge_mask.shift(self.sequence.wrapping_sub(local_last_sequence_received));
+				result.new_acknowledge_mask.set_first();
+			},
+			None => result.new_acknowledge_mask.set_first(),
 		}
 
-		result.new_acknowledge_mask.set_first();
-
 		// check remote acknowledge mask for packets that the remote may have not received
 		if let Some(local_highest_acknowledge_received) = local_highest_acknowledge_received {
-			for i in (local_highest_acknowledge_received + 1)..=result.new_highest_acknowledged_sequence {
-				let tested = remote_acknowledge_mask.test(result.new_highest_acknowledged_sequence - i);
-				if tested.is_some() && tested.unwrap() == true {
-					result.acknowledged_sequences.push(i);
-					// println!("client acknowledged: {}", i);
-				} else {
-					result.dropped_sequences.push(i);
-					// println!("client dropped: {}", i);
+			if is_newer_sequence(result.new_highest_acknowledged_sequence, local_highest_acknowledge_received) {
+				let count = result.new_highest_acknowledged_sequence.wrapping_sub(local_highest_acknowledge_received);
+				for offset in 1..=count {
+					let i = local_highest_acknowledge_received.wrapping_add(offset);
+					let tested = remote_acknowledge_mask.test(count - offset);
+					if tested.is_some() && tested.unwrap() == true {
+						result.acknowledged_sequences.push(i);
+						// println!("client acknowledged: {}", i);
+					} else {
+						result.dropped_sequences.push(i);
+						// println!("client dropped: {}", i);
+					}
 				}
+			} else {
+				// the packet arrived out of order, and does not know about acknowledgements we already handled
+				result.new_highest_acknowledged_sequence = local_highest_acknowledge_received;
 			}
 		}
 
@@ -159,19 +219,24 @@ impl Packet {
 	}
 }
 
+/// Whether or not `sequence` comes after `other`, accounting for wrap around.
+pub(crate) fn is_newer_sequence(sequence: u32, other: u32) -> bool {
+	sequence != other && sequence.wrapping_sub(other) < u32::MAX / 2
+}
+
 fn encode_extensions(extensions: &HashSet<PacketExtension>) -> [u32; EXTENSION_U32_COUNT] {
 	// encode extensions
 	let mut u32_array = [0; EXTENSION_U32_COUNT];
 	for i in 0..EXTENSIONS_COUNT {
 		// encode an extension flag into the current `u32` that we're on
 		if extensions.contains(&TryInto::try_into(i).unwrap()) {
-			u32_array[i / 31] = 1 << (i % 31);
+			u32_array[i / 31] |= 1 << (i % 31);
 		}
 
 		// if we're finished encoding 31 bits of extensions but there's more that we have to encode after this, then set
 		// the 32nd bit of the encoded `u32` to a 1 so we know that there's more extensions after this
 		if i % 31 == 30 && i != EXTENSIONS_COUNT - 1 {
-			u32_array[i / 31] = 1 << 31;
+			u32_array[i / 31] |= 1 << 31;
 		}
 	}
 
@@ -266,8 +331,10 @@ mod tests {
 
 	use crate::error::NetworkStreamError;
 	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream };
+	use crate::payload::AcknowledgeMask;
 
 	use super::{
+		Packet,
 		PacketError,
 		PacketExtension,
 		EXTENSION_U32_COUNT,
@@ -401,4 +468,47 @@ mod tests {
 		// check to see if the garbage is preserved
 		assert_eq!(read_stream.read_u64().expect("Could not read number").0, garbage);
 	}
+
+	/// Ensure that sequences are acknowledged across wrap around, and that packets that arrive out of order do not move
+	/// the acknowledge mask or the highest acknowledged sequence backwards.
+	#[test]
+	fn handle_sequences_wrapping() {
+		let mut acknowledge_mask = AcknowledgeMask::default();
+		let mut last_sequence_received = Some(u32::MAX - 1);
+		for sequence in [u32::MAX, 1, 0] {
+			let mut packet = Packet::new(0, 0);
+			packet.prepare(AcknowledgeMask::default(), sequence, 0);
+
+			let result = packet.handle_sequences(None, last_sequence_received, acknowledge_mask);
+			assert_eq!(result.out_of_order, sequence == 0);
+			acknowledge_mask = result.new_acknowledge_mask;
+			last_sequence_received = Some(result.remote_sequence);
+		}
+
+		assert_eq!(last_sequence_received, Some(1));
+		assert_eq!(acknowledge_mask.test(0), Some(true));
+		assert_eq!(acknowledge_mask.test(1), Some(true));
+		assert_eq!(acknowledge_mask.test(2), Some(true));
+
+		// the remote received `u32::MAX` and 1, but not 0
+		let mut remote_acknowledge_mask = AcknowledgeMask::default();
+		remote_acknowledge_mask.set(0);
+		remote_acknowledge_mask.set(2);
+
+		let mut packet = Packet::new(0, 0);
+		packet.prepare(remote_acknowledge_mask, 2, 1);
+
+		let result = packet.handle_sequences(Some(u32::MAX - 1), None, AcknowledgeMask::default());
+		assert_eq!(result.acknowledged_sequences, vec![u32::MAX, 1]);
+		assert_eq!(result.dropped_sequences, vec![0]);
+		assert_eq!(result.new_highest_acknowledged_sequence, 1);
+
+		// a packet sent before the remote acknowledged 1
+		packet.prepare(remote_acknowledge_mask, 1, u32::MAX);
+
+		let result = packet.handle_sequences(Some(1), None, AcknowledgeMask::default());
+		assert!(result.acknowledged_sequences.is_empty());
+		assert!(result.dropped_sequences.is_empty());
+		assert_eq!(result.new_highest_acknowledged_sequence, 1);
+	}
 }
@@ -4,11 +4,23 @@ use streams::{ Decode, Encode, Endable, ReadStream, StreamPosition, WriteStream,
 use streams::u8_io::{ U8ReadStream, U8ReadStringSafeStream, U8WriteStream, };
 
 use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::ntp::MAX_NTP_PACKET_SIZE;
+use crate::reliable::{ ReliableMessage, MAX_STREAM_NAME_LENGTH, };
+use crate::simulation::MAX_INPUT_SIZE;
 
-use super::DisconnectionReason;
+use super::{ DisconnectionReason, Fragment, SnapshotChunk, };
+use super::fragment::{ MAX_FRAGMENT_COUNT, MAX_FRAGMENT_SIZE, };
+
+/// The maximum size of an encoded sub-payload, which is the most data that can be split into fragments.
+pub const MAX_SUB_PAYLOAD_SIZE: usize = MAX_FRAGMENT_COUNT as usize * MAX_FRAGMENT_SIZE;
+
+/// The maximum size of an unreliable message. Leaves room for the sub-payload type and the message's length.
+pub const MAX_UNRELIABLE_MESSAGE_SIZE: usize = MAX_SUB_PAYLOAD_SIZE - 16;
 
 #[derive(Debug, Clone, Copy)]
 pub enum PayloadError {
+	InvalidBool,
+	InvalidLength,
 	InvalidSubPayloadType,
 }
 
@@ -36,6 +48,11 @@ impl Payload {
 	pub fn get_all(&self) -> &Vec<SubPayload> {
 		&self.sub_payloads
 	}
+
+	/// Take the sub-payload list, leaving the payload empty.
+	pub fn take_all(&mut self) -> Vec<SubPayload> {
+		std::mem::take(&mut self.sub_payloads)
+	}
 }
 
 impl<T> Encode<u8, T, NetworkStreamError> for Payload
@@ -72,8 +89,24 @@ where
 #[derive(Debug)]
 pub enum SubPayload {
 	Disconnect(DisconnectionReason),
+	/// A piece of a sub-payload that was too big to fit into a packet.
+	Fragment(Fragment),
+	/// An input the client wants the server to simulate on the specified tick.
+	Input(u64, Vec<u8>),
+	/// Tells the client the tick of the last input the server simulated.
+	InputAck(u64),
+	/// A NTP packet. Sent inside the session, so timing information is authenticated like the rest of the packet.
+	Ntp(Vec<u8>),
+	/// A ping sent at the specified time, in microseconds since the unix epoch.
 	Ping(u64),
+	/// A reply to a ping, echoing the ping's time so the sender can measure round-trip time.
 	Pong(u64),
+	/// A reliable message, identified by its ID in the connection's `ReliableChannel`.
+	Reliable(u64, ReliableMessage),
+	/// A piece of the entity state the server replicates to a client.
+	Snapshot(SnapshotChunk),
+	/// A message that is not resent if it is dropped.
+	Unreliable(Vec<u8>),
 }
 
 /// Used to identify sub-payloads in payload encode/decode.
@@ -83,6 +116,14 @@ pub enum SubPayloadType {
 	Ping						= 3,
 	Pong						= 4,
 	Disconnect			= 5,
+	Reliable				= 6,
+	Fragment				= 7,
+	ReliableFragment	= 8,
+	Unreliable			= 9,
+	Input						= 10,
+	InputAck				= 11,
+	Snapshot				= 12,
+	Ntp							= 13,
 }
 
 impl<T> Encode<u8, T, NetworkStreamError> for SubPayloadType
@@ -93,9 +134,17 @@ where
 		let value = match *self {
 			SubPayloadType::CreateStream => SubPayloadType::CreateStream as u8,
 			SubPayloadType::Disconnect => SubPayloadType::Disconnect as u8,
+			SubPayloadType::Fragment => SubPayloadType::Fragment as u8,
+			SubPayloadType::Input => SubPayloadType::Input as u8,
+			SubPayloadType::InputAck => SubPayloadType::InputAck as u8,
+			SubPayloadType::Ntp => SubPayloadType::Ntp as u8,
 			SubPayloadType::Ping => SubPayloadType::Ping as u8,
 			SubPayloadType::Pong => SubPayloadType::Pong as u8,
+			SubPayloadType::Reliable => SubPayloadType::Reliable as u8,
+			SubPayloadType::ReliableFragment => SubPayloadType::ReliableFragment as u8,
+			SubPayloadType::Snapshot => SubPayloadType::Snapshot as u8,
 			SubPayloadType::Stream => SubPayloadType::Stream as u8,
+			SubPayloadType::Unreliable => SubPayloadType::Unreliable as u8,
 		};
 
 		stream.write_u8(value)
@@ -114,6 +163,14 @@ where
 			3 => SubPayloadType::Ping,
 			4 => SubPayloadType::Pong,
 			5 => SubPayloadType::Disconnect,
+			6 => SubPayloadType::Reliable,
+			7 => SubPayloadType::Fragment,
+			8 => SubPayloadType::ReliableFragment,
+			9 => SubPayloadType::Unreliable,
+			10 => SubPayloadType::Input,
+			11 => SubPayloadType::InputAck,
+			12 => SubPayloadType::Snapshot,
+			13 => SubPayloadType::Ntp,
 			_ => return Err(Box::new(PayloadError::InvalidSubPayloadType)),
 		};
 
@@ -130,7 +187,26 @@ where
 			SubPayload::Disconnect(reason) => {
 				stream.encode(&SubPayloadType::Disconnect)?;
 				stream.encode(reason)?;
-			}
+			},
+			SubPayload::Fragment(fragment) => {
+				stream.encode(&SubPayloadType::Fragment)?;
+				stream.encode(fragment)?;
+			},
+			SubPayload::Input(tick, input) => {
+				stream.encode(&SubPayloadType::Input)?;
+				stream.write_u64(*tick)?;
+				stream.write_vlq(input.len() as u64)?;
+				stream.write_vector(input)?;
+			},
+			SubPayload::InputAck(tick) => {
+				stream.encode(&SubPayloadType::InputAck)?;
+				stream.write_u64(*tick)?;
+			},
+			SubPayload::Ntp(packet) => {
+				stream.encode(&SubPayloadType::Ntp)?;
+				stream.write_vlq(packet.len() as u64)?;
+				stream.write_vector(packet)?;
+			},
 			SubPayload::Ping(time) => {
 				stream.encode(&SubPayloadType::Ping)?;
 				stream.write_u64(*time)?;
@@ -139,6 +215,45 @@ where
 				stream.encode(&SubPayloadType::Pong)?;
 				stream.write_u64(*time)?;
 			},
+			SubPayload::Reliable(id, message) => {
+				match message {
+					ReliableMessage::CreateStream { name, stream_id, } => {
+						stream.encode(&SubPayloadType::CreateStream)?;
+						stream.write_vlq(*id)?;
+						stream.write_u32(*stream_id)?;
+						stream.write_string(name)?;
+					},
+					ReliableMessage::Message(message) => {
+						stream.encode(&SubPayloadType::Reliable)?;
+						stream.write_vlq(*id)?;
+						stream.write_vlq(message.len() as u64)?;
+						stream.write_vector(message)?;
+					},
+					ReliableMessage::MessageFragment(fragment) => {
+						stream.encode(&SubPayloadType::ReliableFragment)?;
+						stream.write_vlq(*id)?;
+						stream.write_vlq(fragment.len() as u64)?;
+						stream.write_vector(fragment)?;
+					},
+					ReliableMessage::Stream { data, finished, stream_id, } => {
+						stream.encode(&SubPayloadType::Stream)?;
+						stream.write_vlq(*id)?;
+						stream.write_u32(*stream_id)?;
+						stream.write_u8(*finished as u8)?;
+						stream.write_vlq(data.len() as u64)?;
+						stream.write_vector(data)?;
+					},
+				}
+			},
+			SubPayload::Snapshot(chunk) => {
+				stream.encode(&SubPayloadType::Snapshot)?;
+				stream.encode(chunk)?;
+			},
+			SubPayload::Unreliable(message) => {
+				stream.encode(&SubPayloadType::Unreliable)?;
+				stream.write_vlq(message.len() as u64)?;
+				stream.write_vector(message)?;
+			},
 		};
 		Ok(())
 	}
@@ -151,11 +266,46 @@ where
 	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
 		let (sub_payload_type, _) = stream.decode::<SubPayloadType>()?;
 		match sub_payload_type {
-			SubPayloadType::CreateStream => todo!(),
+			SubPayloadType::CreateStream => {
+				let (id, _) = stream.read_vlq()?;
+				let (stream_id, _) = stream.read_u32()?;
+				let (name, position) = stream.read_string_safe(1, MAX_STREAM_NAME_LENGTH)?;
+				Ok((SubPayload::Reliable(id, ReliableMessage::CreateStream {
+					name,
+					stream_id,
+				}), position))
+			},
 			SubPayloadType::Disconnect => {
 				let (reason, position) = stream.decode::<DisconnectionReason>()?;
 				Ok((SubPayload::Disconnect(reason), position))
 			},
+			SubPayloadType::Fragment => {
+				let (fragment, position) = stream.decode::<Fragment>()?;
+				Ok((SubPayload::Fragment(fragment), position))
+			},
+			SubPayloadType::Input => {
+				let (tick, _) = stream.read_u64()?;
+				let (length, _) = stream.read_vlq()?;
+				if length as usize > MAX_INPUT_SIZE {
+					return Err(Box::new(PayloadError::InvalidLength));
+				}
+
+				let (input, position) = stream.read_vector(length as usize)?;
+				Ok((SubPayload::Input(tick, input), position))
+			},
+			SubPayloadType::InputAck => {
+				let (tick, position) = stream.read_u64()?;
+				Ok((SubPayload::InputAck(tick), position))
+			},
+			SubPayloadType::Ntp => {
+				let (length, _) = stream.read_vlq()?;
+				if length as usize > MAX_NTP_PACKET_SIZE {
+					return Err(Box::new(PayloadError::InvalidLength));
+				}
+
+				let (packet, position) = stream.read_vector(length as usize)?;
+				Ok((SubPayload::Ntp(packet), position))
+			},
 			SubPayloadType::Ping => {
 				let (time, position) = stream.read_u64()?;
 				Ok((SubPayload::Ping(time), position))
@@ -164,7 +314,162 @@ where
 				let (time, position) = stream.read_u64()?;
 				Ok((SubPayload::Pong(time), position))
 			},
-			SubPayloadType::Stream => todo!(),
+			SubPayloadType::Reliable => {
+				let (id, _) = stream.read_vlq()?;
+				let (message, position) = read_payload_vector(stream)?;
+				Ok((SubPayload::Reliable(id, ReliableMessage::Message(message)), position))
+			},
+			SubPayloadType::ReliableFragment => {
+				let (id, _) = stream.read_vlq()?;
+				let (fragment, position) = read_payload_vector(stream)?;
+				Ok((SubPayload::Reliable(id, ReliableMessage::MessageFragment(fragment)), position))
+			},
+			SubPayloadType::Stream => {
+				let (id, _) = stream.read_vlq()?;
+				let (stream_id, _) = stream.read_u32()?;
+				let finished = match stream.read_u8()?.0 {
+					0 => false,
+					1 => true,
+					_ => return Err(Box::new(PayloadError::InvalidBool)),
+				};
+
+				let (data, position) = read_payload_vector(stream)?;
+				Ok((SubPayload::Reliable(id, ReliableMessage::Stream {
+					data,
+					finished,
+					stream_id,
+				}), position))
+			},
+			SubPayloadType::Snapshot => {
+				let (chunk, position) = stream.decode::<SnapshotChunk>()?;
+				Ok((SubPayload::Snapshot(chunk), position))
+			},
+			SubPayloadType::Unreliable => {
+				let (message, position) = read_payload_vector(stream)?;
+				Ok((SubPayload::Unreliable(message), position))
+			},
+		}
+	}
+}
+
+/// Read a length-prefixed byte vector out of a sub-payload. The length is checked before reading so a malicious length
+/// cannot make us allocate a huge vector.
+pub(crate) fn read_payload_vector<T>(stream: &mut T) -> Result<(Vec<u8>, StreamPosition), NetworkStreamError>
+where
+	T: U8ReadStream<NetworkStreamError>
+{
+	let (length, _) = stream.read_vlq()?;
+	if length as usize > MAX_SUB_PAYLOAD_SIZE {
+		return Err(Box::new(PayloadError::InvalidLength));
+	}
+
+	stream.read_vector(length as usize)
+}
+
+/// Test sub-payload encoding.
+#[cfg(test)]
+mod tests {
+	use streams::{ ReadStream, WriteStream, };
+	use streams::u8_io::U8WriteStream;
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+	use crate::reliable::ReliableMessage;
+	use crate::simulation::MAX_INPUT_SIZE;
+
+	use super::{ Payload, SubPayload, };
+
+	/// Ensure that reliable sub-payloads decode into the same messages they were encoded from.
+	#[test]
+	fn reliable_round_trip() {
+		let messages = [
+			ReliableMessage::CreateStream {
+				name: String::from("maps/egg.map"),
+				stream_id: 5,
+			},
+			ReliableMessage::Message(vec![1, 2, 3]),
+			ReliableMessage::Stream {
+				data: vec![4; 1024],
+				finished: false,
+				stream_id: 5,
+			},
+			ReliableMessage::Stream {
+				data: Vec::new(),
+				finished: true,
+				stream_id: 5,
+			},
+		];
+
+		let mut payload = Payload::default();
+		for (id, message) in messages.iter().enumerate() {
+			payload.add(SubPayload::Reliable(id as u64, message.clone()));
+		}
+
+		let mut write_stream = NetworkWriteStream::new();
+		write_stream.encode(&payload).expect("Could not encode payload");
+
+		let mut read_stream = NetworkReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		let payload = read_stream.decode::<Payload>().expect("Could not decode payload").0;
+		for (id, sub_payload) in payload.get_all().iter().enumerate() {
+			if let SubPayload::Reliable(decoded_id, message) = sub_payload {
+				assert_eq!(*decoded_id, id as u64);
+				assert_eq!(message, &messages[id]);
+			} else {
+				panic!("Decoded wrong sub-payload {:?}", sub_payload);
+			}
+		}
+	}
+
+	/// Ensure that inputs decode into the same inputs they were encoded from, and that oversized inputs are rejected.
+	#[test]
+	fn input_round_trip() {
+		for (input, valid) in [(vec![1, 2, 3], true), (vec![4; MAX_INPUT_SIZE], true), (vec![5; MAX_INPUT_SIZE + 1], false)] {
+			let mut payload = Payload::default();
+			payload.add(SubPayload::Input(1_000_000, input.clone()));
+			payload.add(SubPayload::InputAck(999_999));
+
+			let mut write_stream = NetworkWriteStream::new();
+			write_stream.encode(&payload).expect("Could not encode payload");
+
+			let mut read_stream = NetworkReadStream::new();
+			read_stream.import(write_stream.export().expect("Could not export test stream"))
+				.expect("Could not import test stream");
+
+			let result = read_stream.decode::<Payload>();
+			if !valid {
+				assert!(result.is_err());
+				continue;
+			}
+
+			let payload = result.expect("Could not decode payload").0;
+			match payload.get_all().as_slice() {
+				[SubPayload::Input(1_000_000, decoded), SubPayload::InputAck(999_999)] => assert_eq!(decoded, &input),
+				sub_payloads => panic!("Decoded wrong sub-payloads {:?}", sub_payloads),
+			}
+		}
+	}
+
+	/// Ensure that decoding truncated and garbage sub-payloads does not panic.
+	#[test]
+	fn truncated() {
+		for sub_payload_type in 0..=14 {
+			for garbage in [vec![], vec![0xFF], vec![0xFF; 3], vec![0xFF; 12], vec![0x01, 0x00, 0x00, 0x00, 0x00]] {
+				let mut write_stream = NetworkWriteStream::new();
+				write_stream.write_u8(sub_payload_type).expect("Could not write number");
+				write_stream.write_vector(&garbage).expect("Could not write garbage");
+
+				let mut read_stream = NetworkReadStream::new();
+				read_stream.import(write_stream.export().expect("Could not export test stream"))
+					.expect("Could not import test stream");
+
+				// some of the garbage happens to be valid, all we care about is that decoding does not panic
+				let result = read_stream.decode::<SubPayload>();
+				if garbage.len() < 3 {
+					assert!(result.is_err());
+				}
+			}
 		}
 	}
 }
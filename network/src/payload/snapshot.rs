@@ -0,0 +1,267 @@
+use std::fmt::Debug;
+
+use streams::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
+use streams::u8_io::{ U8ReadStream, U8WriteStream, };
+
+use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::replication::{ ComponentId, EntityId, };
+
+use super::payload::read_payload_vector;
+
+/// The maximum amount of chunks a snapshot can be split into.
+pub const MAX_SNAPSHOT_CHUNKS: u16 = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SnapshotError {
+	InvalidChunk,
+	InvalidEntity,
+	InvalidUpdateType,
+}
+
+impl NetworkStreamErrorTrait for SnapshotError {
+	fn as_any(&self) -> &dyn std::any::Any {
+		self
+	}
+}
+
+/// Describes how an entity changed since the baseline snapshot.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EntityUpdate {
+	/// The entity is no longer replicated to the client, either because it was despawned or because it is no longer
+	/// relevant to the client.
+	Despawn(EntityId),
+	/// The components of the entity that were added or changed since the baseline, and the components that were removed.
+	/// Entities that are not in the baseline have all of their components included.
+	Update {
+		changed: Vec<(ComponentId, Vec<u8>)>,
+		entity: EntityId,
+		removed: Vec<ComponentId>,
+	},
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for EntityUpdate
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		match self {
+			EntityUpdate::Despawn(entity) => {
+				stream.write_u8(1)?;
+				stream.write_vlq(entity.0 as u64)?;
+			},
+			EntityUpdate::Update { changed, entity, removed, } => {
+				stream.write_u8(2)?;
+				stream.write_vlq(entity.0 as u64)?;
+
+				stream.write_vlq(changed.len() as u64)?;
+				for (component_id, data) in changed {
+					stream.write_u16(*component_id)?;
+					stream.write_vlq(data.len() as u64)?;
+					stream.write_vector(data)?;
+				}
+
+				stream.write_vlq(removed.len() as u64)?;
+				for component_id in removed {
+					stream.write_u16(*component_id)?;
+				}
+			},
+		}
+
+		Ok(())
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for EntityUpdate
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		let (update_type, _) = stream.read_u8()?;
+		let (entity, mut position) = stream.read_vlq()?;
+		let Ok(entity) = u32::try_from(entity).map(EntityId) else {
+			return Err(Box::new(SnapshotError::InvalidEntity));
+		};
+
+		match update_type {
+			1 => Ok((EntityUpdate::Despawn(entity), position)),
+			2 => {
+				let (changed_count, _) = stream.read_vlq()?;
+				let mut changed = Vec::new();
+				for _ in 0..changed_count {
+					let (component_id, _) = stream.read_u16()?;
+					let (data, _) = read_payload_vector(stream)?;
+					changed.push((component_id, data));
+				}
+
+				let (removed_count, new_position) = stream.read_vlq()?;
+				position = new_position;
+
+				let mut removed = Vec::new();
+				for _ in 0..removed_count {
+					let (component_id, new_position) = stream.read_u16()?;
+					position = new_position;
+					removed.push(component_id);
+				}
+
+				Ok((
+					EntityUpdate::Update {
+						changed,
+						entity,
+						removed,
+					},
+					position
+				))
+			},
+			_ => Err(Box::new(SnapshotError::InvalidUpdateType)),
+		}
+	}
+}
+
+/// A piece of the entity state the server sent to a client for a tick. Snapshots are split into chunks that fit into
+/// a packet each, so the server can tell which chunks the client acknowledged.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SnapshotChunk {
+	/// The tick of the snapshot the updates are relative to. Entities that are not mentioned in any of the snapshot's
+	/// chunks did not change since the baseline. If there is no baseline, the snapshot contains every entity.
+	pub baseline: Option<u64>,
+	/// The amount of chunks the snapshot was split into.
+	pub count: u16,
+	/// The index of the chunk within the snapshot.
+	pub index: u16,
+	/// The simulation tick the snapshot was taken on.
+	pub tick: u64,
+	/// The entities in the chunk.
+	pub updates: Vec<EntityUpdate>,
+}
+
+impl<T> Encode<u8, T, NetworkStreamError> for SnapshotChunk
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		stream.write_u64(self.tick)?;
+
+		// the baseline is encoded relative to the tick, with 0 meaning that there is no baseline
+		stream.write_vlq(self.baseline.map_or(0, |baseline| self.tick - baseline))?;
+		stream.write_u16(self.index)?;
+		stream.write_u16(self.count)?;
+
+		stream.write_vlq(self.updates.len() as u64)?;
+		for update in &self.updates {
+			stream.encode(update)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for SnapshotChunk
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		let (tick, _) = stream.read_u64()?;
+		let (baseline_distance, _) = stream.read_vlq()?;
+		let (index, _) = stream.read_u16()?;
+		let (count, _) = stream.read_u16()?;
+		if baseline_distance > tick || index >= count || count > MAX_SNAPSHOT_CHUNKS {
+			return Err(Box::new(SnapshotError::InvalidChunk));
+		}
+
+		// every update takes up at least one byte, so a malicious count cannot make us loop for long
+		let (update_count, mut position) = stream.read_vlq()?;
+
+		let mut updates = Vec::new();
+		for _ in 0..update_count {
+			let (update, new_position) = stream.decode::<EntityUpdate>()?;
+			position = new_position;
+			updates.push(update);
+		}
+
+		Ok((
+			SnapshotChunk {
+				baseline: (baseline_distance != 0).then(|| tick - baseline_distance),
+				count,
+				index,
+				tick,
+				updates,
+			},
+			position
+		))
+	}
+}
+
+/// Test snapshot chunk encoding.
+#[cfg(test)]
+mod tests {
+	use streams::{ ReadStream, WriteStream, };
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+	use crate::replication::EntityId;
+
+	use super::{ EntityUpdate, SnapshotChunk, };
+
+	/// Ensure that snapshot chunks decode into the same chunks they were encoded from.
+	#[test]
+	fn round_trip() {
+		let chunks = [
+			SnapshotChunk {
+				baseline: None,
+				count: 2,
+				index: 0,
+				tick: 1_000,
+				updates: vec![
+					EntityUpdate::Update {
+						changed: vec![(1, vec![1, 2, 3]), (4, Vec::new())],
+						entity: EntityId(7),
+						removed: Vec::new(),
+					},
+				],
+			},
+			SnapshotChunk {
+				baseline: Some(990),
+				count: 2,
+				index: 1,
+				tick: 1_000,
+				updates: vec![
+					EntityUpdate::Despawn(EntityId(8)),
+					EntityUpdate::Update {
+						changed: Vec::new(),
+						entity: EntityId(70_000),
+						removed: vec![2, 3],
+					},
+				],
+			},
+		];
+
+		for chunk in chunks {
+			let mut write_stream = NetworkWriteStream::new();
+			write_stream.encode(&chunk).expect("Could not encode chunk");
+
+			let mut read_stream = NetworkReadStream::new();
+			read_stream.import(write_stream.export().expect("Could not export test stream"))
+				.expect("Could not import test stream");
+			assert_eq!(read_stream.decode::<SnapshotChunk>().expect("Could not decode chunk").0, chunk);
+		}
+	}
+
+	/// Ensure that chunks with indices outside of the snapshot are rejected.
+	#[test]
+	fn invalid_chunk() {
+		let chunk = SnapshotChunk {
+			baseline: None,
+			count: 1,
+			index: 1,
+			tick: 1_000,
+			updates: Vec::new(),
+		};
+
+		let mut write_stream = NetworkWriteStream::new();
+		write_stream.encode(&chunk).expect("Could not encode chunk");
+
+		let mut read_stream = NetworkReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+		assert!(read_stream.decode::<SnapshotChunk>().is_err());
+	}
+}
@@ -0,0 +1,687 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap, HashSet, VecDeque, };
+use std::time::{ Duration, Instant, };
+
+use crate::payload::{ Packet, SubPayload, };
+
+use super::{ IncomingStream, ReliableMessage, StreamError, StreamId, MAX_STREAM_NAME_LENGTH, };
+
+/// The maximum amount of reliable message bytes attached to a single packet. Leaves room in the packet for the header
+/// and other sub-payloads.
+const MAX_RELIABLE_BYTES_PER_PACKET: usize = 1024;
+
+/// The maximum size of the fragments that stream data is split into. Each fragment fits into a single packet.
+const MAX_STREAM_FRAGMENT_SIZE: usize = MAX_RELIABLE_BYTES_PER_PACKET;
+
+/// The maximum size of a message received with `ReliableChannel::send`. Bigger messages are discarded by the remote.
+pub const MAX_RELIABLE_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The maximum amount of streams the remote can have open at once. Streams stay open until they are finished and taken
+/// out of the channel.
+pub const MAX_INCOMING_STREAMS: usize = 32;
+
+/// The maximum amount of bytes a stream the remote opened can hold before the data is taken out of it.
+pub const MAX_INCOMING_STREAM_SIZE: usize = 16 * 1024 * 1024;
+
+/// The maximum amount of bytes all of the streams the remote opened can hold at once.
+pub const MAX_INCOMING_STREAMS_SIZE: usize = 64 * 1024 * 1024;
+
+/// The maximum amount of messages that can be sent ahead of the oldest message the remote has not acknowledged. Caps
+/// how many out of order messages the remote has to buffer.
+const MAX_RECEIVE_WINDOW: u64 = 4096;
+
+/// How long we wait for a packet carrying reliable messages to be acknowledged before we assume it was dropped. Covers
+/// the case where the remote never sends another packet that would tell us the packet was dropped.
+const RESEND_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Reliable messages that were attached to a packet, used to determine which messages need to be resent if the packet
+/// gets dropped.
+#[derive(Debug)]
+struct InFlightPacket {
+	message_ids: Vec<u64>,
+	sent_at: Instant,
+}
+
+/// Reliable, ordered message channel built on top of packet acknowledgements. Queued messages are attached to outgoing
+/// packets, and are tracked using the sequence number of the packet they were sent in. If the remote says the packet
+/// was dropped, the messages in it are queued for resending. Received messages are buffered until every message before
+/// them has been received, so messages are delivered in order and exactly once.
+///
+/// The channel also multiplexes streams. Either side can open a named stream and write arbitrarily large amounts of
+/// data into it, which is split into fragments that fit into a packet and reassembled on the remote.
+#[derive(Debug, Default)]
+pub struct ReliableChannel {
+	/// Whether or not we received reliable messages since the last packet we sent. The remote only learns that we got
+	/// its messages once we send it a packet, so we need to send one even if we have nothing else to say.
+	acknowledge_pending: bool,
+	/// Whether or not the big message we are reassembling went over `MAX_RELIABLE_MESSAGE_SIZE`, in which case the rest
+	/// of its pieces are discarded.
+	discard_partial_message: bool,
+	/// Maps packet sequence numbers to the reliable messages that were sent in the packet.
+	in_flight: HashMap<u32, InFlightPacket>,
+	/// Streams the remote opened.
+	incoming_streams: BTreeMap<StreamId, IncomingStream>,
+	/// Messages that were delivered in order, waiting to be received by the user.
+	messages: VecDeque<Vec<u8>>,
+	/// The ID we assign to the next message we queue.
+	next_send_id: u64,
+	/// The ID we assign to the next stream we open.
+	next_stream_id: StreamId,
+	/// The ID of the next message we will deliver.
+	next_receive_id: u64,
+	/// Streams we opened that have not been finished yet.
+	open_streams: HashSet<StreamId>,
+	/// Pieces of a big message we are reassembling.
+	partial_message: Vec<u8>,
+	/// IDs of messages that need to be attached to the next outgoing packet.
+	pending: BTreeSet<u64>,
+	/// Messages we received out of order, waiting for the messages before them.
+	received: BTreeMap<u64, ReliableMessage>,
+	/// Messages the remote has not acknowledged yet.
+	unacknowledged: BTreeMap<u64, ReliableMessage>,
+}
+
+impl ReliableChannel {
+	/// Queue a message to be sent reliably. Messages that do not fit into a packet are split into pieces, which are
+	/// reassembled into the original message on the remote.
+	pub fn send(&mut self, message: Vec<u8>) {
+		if message.len() <= MAX_RELIABLE_BYTES_PER_PACKET {
+			self.queue(ReliableMessage::Message(message));
+			return;
+		}
+
+		let mut pieces = message.chunks(MAX_RELIABLE_BYTES_PER_PACKET).peekable();
+		while let Some(piece) = pieces.next() {
+			if pieces.peek().is_some() {
+				self.queue(ReliableMessage::MessageFragment(piece.to_vec()));
+			} else {
+				self.queue(ReliableMessage::Message(piece.to_vec()));
+			}
+		}
+	}
+
+	/// Get the next message in order, if we have received it.
+	pub fn receive(&mut self) -> Option<Vec<u8>> {
+		self.messages.pop_front()
+	}
+
+	/// Open a stream on the remote.
+	pub fn open_stream(&mut self, name: &str) -> Result<StreamId, StreamError> {
+		if name.is_empty() || name.len() as u64 > MAX_STREAM_NAME_LENGTH {
+			return Err(StreamError::InvalidName);
+		}
+
+		let stream_id = self.next_stream_id;
+		self.next_stream_id += 1;
+		self.open_streams.insert(stream_id);
+
+		self.queue(ReliableMessage::CreateStream {
+			name: String::from(name),
+			stream_id,
+		});
+
+		Ok(stream_id)
+	}
+
+	/// Write data into a stream we opened. The data is split into fragments that each fit into a packet.
+	pub fn write_stream(&mut self, stream_id: StreamId, data: &[u8]) -> Result<(), StreamError> {
+		self.check_stream(stream_id)?;
+
+		for fragment in data.chunks(MAX_STREAM_FRAGMENT_SIZE) {
+			self.queue(ReliableMessage::Stream {
+				data: fragment.to_vec(),
+				finished: false,
+				stream_id,
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Tell the remote that we are done writing into a stream.
+	pub fn finish_stream(&mut self, stream_id: StreamId) -> Result<(), StreamError> {
+		self.check_stream(stream_id)?;
+		self.open_streams.remove(&stream_id);
+
+		self.queue(ReliableMessage::Stream {
+			data: Vec::new(),
+			finished: true,
+			stream_id,
+		});
+
+		Ok(())
+	}
+
+	/// Get a stream the remote opened.
+	pub fn get_incoming_stream(&mut self, stream_id: StreamId) -> Option<&mut IncomingStream> {
+		self.incoming_streams.get_mut(&stream_id)
+	}
+
+	/// Iterate through the streams the remote opened.
+	pub fn incoming_streams(&self) -> impl Iterator<Item = (&StreamId, &IncomingStream)> {
+		self.incoming_streams.iter()
+	}
+
+	/// Remove a stream the remote finished writing into, along with its reassembled data.
+	pub fn take_finished_stream(&mut self) -> Option<(StreamId, IncomingStream)> {
+		let stream_id = *self.incoming_streams.iter().find(|(_, stream)| stream.is_finished())?.0;
+		self.incoming_streams.remove(&stream_id).map(|stream| (stream_id, stream))
+	}
+
+	/// Whether or not the channel needs a packet to be sent this tick, either to send messages or to acknowledge messages
+	/// we received.
+	pub fn needs_packet(&self, now: Instant) -> bool {
+		self.acknowledge_pending
+			|| !self.pending.is_empty()
+			|| self.in_flight.values().any(|packet| now - packet.sent_at > RESEND_TIMEOUT)
+	}
+
+	/// The amount of messages the remote has not acknowledged yet.
+	pub fn unacknowledged_count(&self) -> usize {
+		self.unacknowledged.len()
+	}
+
+	/// Attach queued messages to an outgoing packet that will be sent with the specified sequence number.
+	pub(crate) fn attach(&mut self, packet: &mut Packet, sequence: u32, now: Instant) {
+		self.acknowledge_pending = false;
+
+		// assume packets that have not been acknowledged in a long time were dropped
+		let timed_out = self.in_flight.iter()
+			.filter(|(_, packet)| now - packet.sent_at > RESEND_TIMEOUT)
+			.map(|(sequence, _)| *sequence)
+			.collect::<Vec<u32>>();
+
+		for sequence in timed_out {
+			self.handle_dropped(sequence);
+		}
+
+		// attach as many messages as we can fit into the packet. a message that is too big for the budget is sent on its
+		// own so it cannot block the queue forever
+		let window_end = self.unacknowledged.keys().next().map_or(0, |id| id + MAX_RECEIVE_WINDOW);
+		let mut bytes = 0;
+		let mut message_ids = Vec::new();
+		for id in self.pending.range(..window_end) {
+			let message = &self.unacknowledged[id];
+			if !message_ids.is_empty() && bytes + message.len() > MAX_RELIABLE_BYTES_PER_PACKET {
+				break;
+			}
+
+			bytes += message.len();
+			message_ids.push(*id);
+			packet.add_sub_payload(SubPayload::Reliable(*id, message.clone()));
+		}
+
+		if message_ids.is_empty() {
+			return;
+		}
+
+		for id in message_ids.iter() {
+			self.pending.remove(id);
+		}
+
+		self.in_flight.insert(sequence, InFlightPacket {
+			message_ids,
+			sent_at: now,
+		});
+	}
+
+	/// Handle a reliable message sub-payload we received from the remote. Emits an error if the remote went over the
+	/// limits on incoming streams, in which case the offending stream is reset. Messages are still delivered afterwards.
+	pub(crate) fn handle_message(&mut self, id: u64, message: &ReliableMessage) -> Result<(), StreamError> {
+		self.acknowledge_pending = true;
+
+		// ignore messages we already delivered, already have buffered, or that are too far ahead of the messages we have
+		if id < self.next_receive_id || id >= self.next_receive_id + MAX_RECEIVE_WINDOW || self.received.contains_key(&id) {
+			return Ok(());
+		}
+
+		self.received.insert(id, message.clone());
+
+		// deliver all the messages we have in order
+		let mut result = Ok(());
+		while let Some(message) = self.received.remove(&self.next_receive_id) {
+			self.next_receive_id += 1;
+			result = result.and(self.deliver(message));
+		}
+
+		result
+	}
+
+	/// Handle the remote acknowledging one of our packets.
+	pub(crate) fn handle_acknowledged(&mut self, sequence: u32) {
+		if let Some(packet) = self.in_flight.remove(&sequence) {
+			for id in packet.message_ids {
+				self.unacknowledged.remove(&id);
+			}
+		}
+	}
+
+	/// Handle the remote dropping one of our packets by queueing the unacknowledged messages it carried for resending.
+	pub(crate) fn handle_dropped(&mut self, sequence: u32) {
+		if let Some(packet) = self.in_flight.remove(&sequence) {
+			for id in packet.message_ids {
+				if self.unacknowledged.contains_key(&id) {
+					self.pending.insert(id);
+				}
+			}
+		}
+	}
+
+	fn queue(&mut self, message: ReliableMessage) {
+		let id = self.next_send_id;
+		self.next_send_id += 1;
+
+		self.pending.insert(id);
+		self.unacknowledged.insert(id, message);
+	}
+
+	// make sure we can write into a stream
+	fn check_stream(&self, stream_id: StreamId) -> Result<(), StreamError> {
+		if self.open_streams.contains(&stream_id) {
+			Ok(())
+		} else if stream_id < self.next_stream_id {
+			Err(StreamError::Finished(stream_id))
+		} else {
+			Err(StreamError::UnknownStream(stream_id))
+		}
+	}
+
+	// process a message that was received in order
+	fn deliver(&mut self, message: ReliableMessage) -> Result<(), StreamError> {
+		match message {
+			ReliableMessage::CreateStream { name, stream_id, } => {
+				if self.incoming_streams.len() >= MAX_INCOMING_STREAMS && !self.incoming_streams.contains_key(&stream_id) {
+					return Err(StreamError::TooManyStreams(stream_id));
+				}
+
+				self.incoming_streams.insert(stream_id, IncomingStream::new(name));
+			},
+			ReliableMessage::Message(message) => {
+				let mut partial = std::mem::take(&mut self.partial_message);
+				if !std::mem::take(&mut self.discard_partial_message) {
+					if partial.is_empty() {
+						self.messages.push_back(message);
+					} else if partial.len() + message.len() <= MAX_RELIABLE_MESSAGE_SIZE {
+						partial.extend(message);
+						self.messages.push_back(partial);
+					}
+				}
+			},
+			ReliableMessage::MessageFragment(fragment) => {
+				if self.discard_partial_message {
+					return Ok(());
+				}
+
+				if self.partial_message.len() + fragment.len() > MAX_RELIABLE_MESSAGE_SIZE {
+					self.partial_message = Vec::new();
+					self.discard_partial_message = true;
+				} else {
+					self.partial_message.extend(fragment);
+				}
+			},
+			ReliableMessage::Stream { data, finished, stream_id, } => {
+				// ignore data for streams the remote never opened, or that we reset
+				let buffered = self.incoming_streams.values().map(|stream| stream.get_data().len()).sum::<usize>();
+				let Some(stream) = self.incoming_streams.get_mut(&stream_id) else {
+					return Ok(());
+				};
+
+				if stream.get_data().len() + data.len() > MAX_INCOMING_STREAM_SIZE {
+					self.incoming_streams.remove(&stream_id);
+					return Err(StreamError::StreamTooBig(stream_id));
+				}
+
+				if buffered + data.len() > MAX_INCOMING_STREAMS_SIZE {
+					self.incoming_streams.remove(&stream_id);
+					return Err(StreamError::StreamsTooBig(stream_id));
+				}
+
+				stream.push(&data, finished);
+			},
+		}
+
+		Ok(())
+	}
+}
+
+/// Test reliable delivery over a lossy connection.
+#[cfg(test)]
+mod tests {
+	use rand::{ Rng, SeedableRng, };
+	use rand::rngs::StdRng;
+	use std::collections::VecDeque;
+	use std::time::{ Duration, Instant, };
+	use streams::{ ReadStream, WriteStream, };
+
+	use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+	use crate::payload::{ AcknowledgeMask, Packet, SubPayload, };
+
+	use super::{ ReliableChannel, MAX_INCOMING_STREAMS, MAX_INCOMING_STREAM_SIZE, MAX_INCOMING_STREAMS_SIZE, };
+	use super::super::{ ReliableMessage, StreamError, StreamId, };
+
+	/// Stand-in for a UDP socket pair that drops a percentage of the datagrams sent over it.
+	struct LossyLink {
+		drop_chance: f64,
+		in_transit: VecDeque<Vec<u8>>,
+		rng: StdRng,
+	}
+
+	impl LossyLink {
+		fn new(drop_chance: f64, seed: u64) -> Self {
+			LossyLink {
+				drop_chance,
+				in_transit: VecDeque::new(),
+				rng: StdRng::seed_from_u64(seed),
+			}
+		}
+
+		fn send(&mut self, bytes: Vec<u8>) {
+			if !self.rng.gen_bool(self.drop_chance) {
+				self.in_transit.push_back(bytes);
+			}
+		}
+
+		fn recv(&mut self) -> Option<Vec<u8>> {
+			self.in_transit.pop_front()
+		}
+	}
+
+	/// Minimal version of the sequence bookkeeping the client and server do.
+	struct Endpoint {
+		acknowledge_mask: AcknowledgeMask,
+		channel: ReliableChannel,
+		delivered: Vec<Vec<u8>>,
+		finished_streams: Vec<(String, Vec<u8>)>,
+		highest_acknowledge_received: Option<u32>,
+		last_sequence_received: Option<u32>,
+		sequence: u32,
+	}
+
+	impl Endpoint {
+		fn new(sequence: u32, remote_sequence: u32) -> Self {
+			Endpoint {
+				acknowledge_mask: AcknowledgeMask::default(),
+				channel: ReliableChannel::default(),
+				delivered: Vec::new(),
+				finished_streams: Vec::new(),
+				highest_acknowledge_received: Some(sequence),
+				last_sequence_received: Some(remote_sequence),
+				sequence,
+			}
+		}
+
+		fn send(&mut self, link: &mut LossyLink, now: Instant) {
+			if !self.channel.needs_packet(now) {
+				return;
+			}
+
+			self.sequence = self.sequence.wrapping_add(1);
+
+			let mut packet = Packet::new(0, 0);
+			self.channel.attach(&mut packet, self.sequence, now);
+			packet.prepare(self.acknowledge_mask, self.sequence, self.last_sequence_received.unwrap_or(0));
+
+			let mut stream = NetworkWriteStream::new();
+			stream.encode(&packet).expect("Could not encode packet");
+			link.send(stream.export().expect("Could not export packet"));
+		}
+
+		fn recv(&mut self, link: &mut LossyLink) {
+			while let Some(bytes) = link.recv() {
+				let mut stream = NetworkReadStream::new();
+				stream.import(bytes).expect("Could not import packet");
+				let packet = stream.decode::<Packet>().expect("Could not decode packet").0;
+
+				let result = packet.handle_sequences(
+					self.highest_acknowledge_received,
+					self.last_sequence_received,
+					self.acknowledge_mask
+				);
+
+				self.acknowledge_mask = result.new_acknowledge_mask;
+				self.last_sequence_received = Some(result.remote_sequence);
+				self.highest_acknowledge_received = Some(result.new_highest_acknowledged_sequence);
+
+				for sequence in result.acknowledged_sequences {
+					self.channel.handle_acknowledged(sequence);
+				}
+
+				for sequence in result.dropped_sequences {
+					self.channel.handle_dropped(sequence);
+				}
+
+				for sub_payload in packet.get_sub_payloads() {
+					if let SubPayload::Reliable(id, message) = sub_payload {
+						self.channel.handle_message(*id, message).expect("Could not handle message");
+					}
+				}
+			}
+
+			while let Some(message) = self.channel.receive() {
+				self.delivered.push(message);
+			}
+
+			while let Some((_, stream)) = self.channel.take_finished_stream() {
+				self.finished_streams.push((String::from(stream.get_name()), stream.get_data().to_vec()));
+			}
+		}
+	}
+
+	/// Ensure that messages sent in both directions over a lossy link are delivered in order, exactly once.
+	#[test]
+	fn lossy_link() {
+		let mut client = Endpoint::new(1000, 500);
+		let mut server = Endpoint::new(500, 1000);
+
+		let mut client_to_server = LossyLink::new(0.3, 1);
+		let mut server_to_client = LossyLink::new(0.3, 2);
+
+		let messages = (0..200u32)
+			.map(|number| number.to_le_bytes().repeat((number % 50) as usize + 1))
+			.collect::<Vec<Vec<u8>>>();
+
+		let start = Instant::now();
+		for tick in 0..2000 {
+			let now = start + Duration::from_millis(tick * 33);
+
+			// queue a few messages every tick until we run out
+			if tick < 100 {
+				client.channel.send(messages[tick as usize * 2].clone());
+				client.channel.send(messages[tick as usize * 2 + 1].clone());
+				server.channel.send(messages[tick as usize].clone());
+			}
+
+			client.send(&mut client_to_server, now);
+			server.send(&mut server_to_client, now);
+
+			server.recv(&mut client_to_server);
+			client.recv(&mut server_to_client);
+		}
+
+		assert_eq!(server.delivered, messages);
+		assert_eq!(client.delivered, messages[0..100]);
+		assert_eq!(client.channel.unacknowledged_count(), 0);
+		assert_eq!(server.channel.unacknowledged_count(), 0);
+	}
+
+	/// Ensure that data written into streams is reassembled over a lossy link, even when the streams are interleaved.
+	#[test]
+	fn lossy_link_streams() {
+		let mut client = Endpoint::new(1000, 500);
+		let mut server = Endpoint::new(500, 1000);
+
+		let mut client_to_server = LossyLink::new(0.2, 3);
+		let mut server_to_client = LossyLink::new(0.2, 4);
+
+		let map = (0..100_000u32).map(|number| (number % 251) as u8).collect::<Vec<u8>>();
+		let patch = (0..20_000u32).map(|number| (number % 13) as u8).collect::<Vec<u8>>();
+
+		let map_stream = server.channel.open_stream("maps/egg.map").expect("Could not open stream");
+		let patch_stream = server.channel.open_stream("patch.carton").expect("Could not open stream");
+
+		// interleave the writes between both streams
+		for (map_chunk, patch_chunk) in map.chunks(10_000).zip(patch.chunks(2_000)) {
+			server.channel.write_stream(map_stream, map_chunk).expect("Could not write to stream");
+			server.channel.write_stream(patch_stream, patch_chunk).expect("Could not write to stream");
+		}
+
+		server.channel.finish_stream(patch_stream).expect("Could not finish stream");
+		server.channel.finish_stream(map_stream).expect("Could not finish stream");
+
+		assert_eq!(server.channel.write_stream(map_stream, &[0]), Err(StreamError::Finished(map_stream)));
+		assert_eq!(server.channel.write_stream(50, &[0]), Err(StreamError::UnknownStream(50)));
+
+		let start = Instant::now();
+		for tick in 0..5000 {
+			let now = start + Duration::from_millis(tick * 33);
+
+			client.send(&mut client_to_server, now);
+			server.send(&mut server_to_client, now);
+
+			server.recv(&mut client_to_server);
+			client.recv(&mut server_to_client);
+
+			if client.finished_streams.len() == 2 && server.channel.unacknowledged_count() == 0 {
+				break;
+			}
+		}
+
+		client.finished_streams.sort();
+		assert_eq!(client.finished_streams, vec![
+			(String::from("maps/egg.map"), map),
+			(String::from("patch.carton"), patch),
+		]);
+	}
+
+	/// Ensure that messages too big to fit into a packet are split up and reassembled over a lossy link.
+	#[test]
+	fn lossy_link_big_messages() {
+		let mut client = Endpoint::new(1000, 500);
+		let mut server = Endpoint::new(500, 1000);
+
+		let mut client_to_server = LossyLink::new(0.2, 5);
+		let mut server_to_client = LossyLink::new(0.2, 6);
+
+		let messages = vec![
+			vec![1, 2, 3],
+			(0..50_000u32).map(|number| (number % 199) as u8).collect::<Vec<u8>>(),
+			vec![4; 1025],
+			vec![5],
+		];
+
+		for message in messages.iter() {
+			server.channel.send(message.clone());
+		}
+
+		let start = Instant::now();
+		for tick in 0..5000 {
+			let now = start + Duration::from_millis(tick * 33);
+
+			client.send(&mut client_to_server, now);
+			server.send(&mut server_to_client, now);
+
+			server.recv(&mut client_to_server);
+			client.recv(&mut server_to_client);
+
+			if client.delivered.len() == messages.len() && server.channel.unacknowledged_count() == 0 {
+				break;
+			}
+		}
+
+		assert_eq!(client.delivered, messages);
+	}
+
+	/// Ensure that duplicated messages are only delivered once.
+	#[test]
+	fn duplicates() {
+		let mut channel = ReliableChannel::default();
+		channel.handle_message(1, &ReliableMessage::Message(vec![1])).expect("Could not handle message");
+		channel.handle_message(0, &ReliableMessage::Message(vec![0])).expect("Could not handle message");
+		channel.handle_message(1, &ReliableMessage::Message(vec![1])).expect("Could not handle message");
+
+		assert_eq!(channel.receive(), Some(vec![0]));
+		assert_eq!(channel.receive(), Some(vec![1]));
+
+		channel.handle_message(0, &ReliableMessage::Message(vec![0])).expect("Could not handle message");
+		assert_eq!(channel.receive(), None);
+	}
+
+	/// Ensure that remotes cannot open too many streams or write too much data into them.
+	#[test]
+	fn stream_limits() {
+		let mut channel = ReliableChannel::default();
+		let mut id = 0;
+		let mut handle = |channel: &mut ReliableChannel, message: ReliableMessage| {
+			id += 1;
+			channel.handle_message(id - 1, &message)
+		};
+
+		for stream_id in 0..MAX_INCOMING_STREAMS as StreamId {
+			let create = ReliableMessage::CreateStream {
+				name: String::from("stream"),
+				stream_id,
+			};
+
+			handle(&mut channel, create).expect("Could not open stream");
+		}
+
+		let create = ReliableMessage::CreateStream {
+			name: String::from("stream"),
+			stream_id: 100,
+		};
+
+		assert_eq!(handle(&mut channel, create), Err(StreamError::TooManyStreams(100)));
+		assert!(channel.get_incoming_stream(100).is_none());
+
+		// fill up a stream until it is reset
+		let fragment = vec![0; 1024 * 1024];
+		for _ in 0..MAX_INCOMING_STREAM_SIZE / fragment.len() {
+			let stream = ReliableMessage::Stream {
+				data: fragment.clone(),
+				finished: false,
+				stream_id: 0,
+			};
+
+			handle(&mut channel, stream).expect("Could not write to stream");
+		}
+
+		let stream = ReliableMessage::Stream {
+			data: vec![0],
+			finished: false,
+			stream_id: 0,
+		};
+
+		assert_eq!(handle(&mut channel, stream.clone()), Err(StreamError::StreamTooBig(0)));
+		assert!(channel.get_incoming_stream(0).is_none());
+
+		// data for the reset stream is ignored
+		handle(&mut channel, stream).expect("Could not ignore data for reset stream");
+
+		// taking data out of a stream makes room for more
+		let mut written = 0;
+		let result = loop {
+			let stream = ReliableMessage::Stream {
+				data: fragment.clone(),
+				finished: false,
+				stream_id: 1 + (written / MAX_INCOMING_STREAM_SIZE) as StreamId,
+			};
+
+			if let Err(error) = handle(&mut channel, stream) {
+				break error;
+			}
+
+			written += fragment.len();
+		};
+
+		assert_eq!(written, MAX_INCOMING_STREAMS_SIZE);
+		assert!(matches!(result, StreamError::StreamsTooBig(_)));
+
+		channel.get_incoming_stream(1).unwrap().take_data();
+		let stream = ReliableMessage::Stream {
+			data: fragment.clone(),
+			finished: false,
+			stream_id: 1,
+		};
+
+		handle(&mut channel, stream).expect("Could not write to stream");
+	}
+}
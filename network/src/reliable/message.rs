@@ -0,0 +1,119 @@
+use std::any::Any;
+
+use crate::error::NetworkStreamErrorTrait;
+
+/// Identifies a stream. Streams are one-directional, so the IDs of the streams we opened and the IDs of the streams the
+/// remote opened are independent of each other.
+pub type StreamId = u32;
+
+/// The maximum length of a stream's name.
+pub const MAX_STREAM_NAME_LENGTH: u64 = 255;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum StreamError {
+	/// Emitted if we tried to write to a stream that was already finished.
+	Finished(StreamId),
+	/// Emitted if the stream's name is empty or longer than `MAX_STREAM_NAME_LENGTH`.
+	InvalidName,
+	/// Emitted if the streams the remote opened hold more than `MAX_INCOMING_STREAMS_SIZE` bytes in total. The stream
+	/// that went over is reset.
+	StreamsTooBig(StreamId),
+	/// Emitted if a stream the remote opened holds more than `MAX_INCOMING_STREAM_SIZE` bytes. The stream is reset.
+	StreamTooBig(StreamId),
+	/// Emitted if the remote tried to open more than `MAX_INCOMING_STREAMS` streams at once. The stream is not opened.
+	TooManyStreams(StreamId),
+	/// Emitted if we tried to write to a stream that we did not open.
+	UnknownStream(StreamId),
+}
+
+impl NetworkStreamErrorTrait for StreamError {
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
+/// Message that is sent using a `ReliableChannel`. Each message is sent as its own sub-payload.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReliableMessage {
+	/// Opens a stream on the remote.
+	CreateStream {
+		name: String,
+		stream_id: StreamId,
+	},
+	/// Message queued using `ReliableChannel::send`. Also used for the last piece of a message that was too big to fit
+	/// into a packet.
+	Message(Vec<u8>),
+	/// Piece of a message that was too big to fit into a packet. The pieces are concatenated on the remote until the
+	/// `Message` that completes them arrives.
+	MessageFragment(Vec<u8>),
+	/// Fragment of the data written into a stream. `finished` is set on the stream's last fragment.
+	Stream {
+		data: Vec<u8>,
+		finished: bool,
+		stream_id: StreamId,
+	},
+}
+
+impl ReliableMessage {
+	/// Approximate encoded size of the message, used to figure out how many messages fit into a packet.
+	pub(crate) fn len(&self) -> usize {
+		match self {
+			ReliableMessage::CreateStream { name, .. } => name.len() + 4,
+			ReliableMessage::Message(message) => message.len(),
+			ReliableMessage::MessageFragment(fragment) => fragment.len(),
+			ReliableMessage::Stream { data, .. } => data.len() + 5,
+		}
+	}
+}
+
+/// A stream the remote opened. Data written into the stream is reassembled in order as it arrives.
+#[derive(Debug)]
+pub struct IncomingStream {
+	data: Vec<u8>,
+	finished: bool,
+	name: String,
+	/// The total amount of bytes received on the stream, including bytes that were already taken out of it.
+	received: u64,
+}
+
+impl IncomingStream {
+	pub(crate) fn new(name: String) -> Self {
+		IncomingStream {
+			data: Vec::new(),
+			finished: false,
+			name,
+			received: 0,
+		}
+	}
+
+	pub(crate) fn push(&mut self, data: &[u8], finished: bool) {
+		self.data.extend_from_slice(data);
+		self.received += data.len() as u64;
+		self.finished = self.finished || finished;
+	}
+
+	/// The name the remote opened the stream with.
+	pub fn get_name(&self) -> &str {
+		&self.name
+	}
+
+	/// Data received on the stream that has not been taken out of it yet.
+	pub fn get_data(&self) -> &[u8] {
+		&self.data
+	}
+
+	/// Take all of the data received on the stream so far.
+	pub fn take_data(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.data)
+	}
+
+	/// The total amount of bytes received on the stream.
+	pub fn get_received(&self) -> u64 {
+		self.received
+	}
+
+	/// Whether or not the remote finished writing into the stream.
+	pub fn is_finished(&self) -> bool {
+		self.finished
+	}
+}
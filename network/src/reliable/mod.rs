@@ -0,0 +1,13 @@
+pub mod channel;
+pub mod message;
+
+pub use channel::MAX_INCOMING_STREAMS;
+pub use channel::MAX_INCOMING_STREAM_SIZE;
+pub use channel::MAX_INCOMING_STREAMS_SIZE;
+pub use channel::MAX_RELIABLE_MESSAGE_SIZE;
+pub use channel::ReliableChannel;
+pub use message::IncomingStream;
+pub use message::MAX_STREAM_NAME_LENGTH;
+pub use message::ReliableMessage;
+pub use message::StreamError;
+pub use message::StreamId;
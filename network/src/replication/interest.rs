@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+
+use super::world::ReplicatedEntity;
+
+/// Identifies a group of entities that is only replicated to clients that can see the group, like the entities inside
+/// of a building or the members of a team.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct VisibilityGroup(pub u32);
+
+/// Decides which entities are relevant to a client. Entities are relevant if the client can see their visibility group
+/// and if they are within the client's view radius. Entities without a visibility group can be seen by everyone, and
+/// the distance check is skipped if either the client or the entity has no position.
+#[derive(Clone, Debug)]
+pub struct Interest {
+	/// The visibility groups the client can see.
+	pub groups: HashSet<VisibilityGroup>,
+	/// Where the client is viewing the world from.
+	pub position: Option<[f32; 3]>,
+	/// How far away from `position` the client can see entities.
+	pub radius: f32,
+}
+
+impl Default for Interest {
+	fn default() -> Self {
+		Interest {
+			groups: HashSet::new(),
+			position: None,
+			radius: f32::INFINITY,
+		}
+	}
+}
+
+impl Interest {
+	/// Whether or not the entity should be replicated to the client.
+	pub(crate) fn is_relevant(&self, entity: &ReplicatedEntity) -> bool {
+		if entity.group.is_some_and(|group| !self.groups.contains(&group)) {
+			return false;
+		}
+
+		match (self.position, entity.position) {
+			(Some(position), Some(entity_position)) => {
+				let distance_squared = position.iter()
+					.zip(entity_position.iter())
+					.map(|(a, b)| (a - b) * (a - b))
+					.sum::<f32>();
+
+				distance_squared <= self.radius * self.radius
+			},
+			_ => true,
+		}
+	}
+}
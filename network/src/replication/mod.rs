@@ -0,0 +1,69 @@
+pub mod interest;
+pub mod receiver;
+pub mod sender;
+pub mod world;
+
+pub use interest::Interest;
+pub use interest::VisibilityGroup;
+pub use world::ReplicatedWorld;
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use streams::{ Decode, Encode, };
+
+use crate::error::{ NetworkStreamError, NetworkStreamErrorTrait, };
+use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
+
+/// Identifies a type of component over the network.
+pub type ComponentId = u16;
+
+/// The encoded components of an entity.
+pub(crate) type Components = BTreeMap<ComponentId, Vec<u8>>;
+
+/// Identifies a replicated entity. IDs are assigned by the server, and are never reused while the server is running.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct EntityId(pub(crate) u32);
+
+impl std::fmt::Display for EntityId {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "entity {}", self.0)
+	}
+}
+
+/// A piece of entity state that is replicated from the server to its clients. Components are sent in the encoding
+/// produced by `Encode`, and are only sent again once their encoding changes.
+pub trait Component:
+	Encode<u8, NetworkWriteStream, NetworkStreamError> + Decode<u8, NetworkReadStream, NetworkStreamError>
+{
+	/// Identifies the component over the network. Has to be unique among the replicated components.
+	const ID: ComponentId;
+}
+
+/// A component that can be blended between two snapshots, so clients can display entities smoothly in between the
+/// snapshots they receive.
+pub trait Interpolate {
+	/// Blend between `self` and `other`. `t` goes from 0, which results in `self`, to 1, which results in `other`.
+	fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+#[derive(Debug)]
+pub enum ReplicationError {
+	/// Emitted if we tried to modify an entity that does not exist.
+	CouldNotFindEntity(EntityId),
+	/// Emitted if we could not encode a component.
+	NetworkStreamError(NetworkStreamError),
+	/// Emitted if the entities relevant to a client do not fit into `MAX_SNAPSHOT_CHUNKS` chunks.
+	SnapshotTooBig,
+}
+
+impl NetworkStreamErrorTrait for ReplicationError {
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+}
+
+impl From<NetworkStreamError> for ReplicationError {
+	fn from(error: NetworkStreamError) -> Self {
+		ReplicationError::NetworkStreamError(error)
+	}
+}
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use streams::ReadStream;
+
+use crate::network_stream::NetworkReadStream;
+use crate::payload::{ EntityUpdate, SnapshotChunk, };
+
+use super::{ Component, Components, EntityId, Interpolate, };
+use super::sender::MAX_SNAPSHOT_HISTORY;
+
+/// The amount of snapshots we wait for the rest of the chunks of at once. If more arrive, the oldest are discarded.
+const MAX_PARTIAL_SNAPSHOTS: usize = 8;
+
+/// The entities in a snapshot, along with their components.
+type Snapshot = BTreeMap<EntityId, Components>;
+
+/// Reassembles the snapshots the server sends us. Recent snapshots are kept around, since the server sends snapshots
+/// relative to earlier ones, and so entities can be interpolated in between snapshots.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotReceiver {
+	/// The chunks of snapshots we have not received in full yet, identified by tick.
+	partial_snapshots: BTreeMap<u64, BTreeMap<u16, SnapshotChunk>>,
+	/// The snapshots we received in full, identified by tick.
+	snapshots: BTreeMap<u64, Snapshot>,
+}
+
+impl SnapshotReceiver {
+	/// Handle a snapshot chunk. Once every chunk of a snapshot arrived, it is applied to the baseline it was built on.
+	pub(crate) fn handle_chunk(&mut self, chunk: SnapshotChunk) {
+		let tick = chunk.tick;
+		if self.snapshots.contains_key(&tick) {
+			return;
+		}
+
+		let chunks = self.partial_snapshots.entry(tick).or_default();
+		if chunks.values().next().is_some_and(|other| other.count != chunk.count || other.baseline != chunk.baseline) {
+			return;
+		}
+
+		let count = chunk.count as usize;
+		chunks.insert(chunk.index, chunk);
+		if chunks.len() < count {
+			if self.partial_snapshots.len() > MAX_PARTIAL_SNAPSHOTS {
+				self.partial_snapshots.pop_first();
+			}
+
+			return;
+		}
+
+		let chunks = self.partial_snapshots.remove(&tick).unwrap();
+		let baseline = chunks.values().next().unwrap().baseline;
+		let mut snapshot = match baseline {
+			Some(baseline) => match self.snapshots.get(&baseline) {
+				Some(snapshot) => snapshot.clone(),
+				None => return,
+			},
+			None => Snapshot::new(),
+		};
+
+		for update in chunks.into_values().flat_map(|chunk| chunk.updates) {
+			match update {
+				EntityUpdate::Despawn(entity) => {
+					snapshot.remove(&entity);
+				},
+				EntityUpdate::Update { changed, entity, removed, } => {
+					let components = snapshot.entry(entity).or_default();
+					for component_id in removed {
+						components.remove(&component_id);
+					}
+
+					components.extend(changed);
+				},
+			}
+		}
+
+		self.snapshots.insert(tick, snapshot);
+		if self.snapshots.len() > MAX_SNAPSHOT_HISTORY {
+			self.snapshots.pop_first();
+		}
+
+		// snapshots older than every snapshot we have are too old to be useful
+		let oldest_tick = *self.snapshots.keys().next().unwrap();
+		self.partial_snapshots.retain(|tick, _| *tick > oldest_tick);
+	}
+
+	/// The tick of the newest snapshot.
+	pub(crate) fn get_tick(&self) -> Option<u64> {
+		self.snapshots.keys().next_back().copied()
+	}
+
+	/// The entities in the newest snapshot.
+	pub(crate) fn get_entities(&self) -> Vec<EntityId> {
+		self.snapshots.values().next_back().map_or(Vec::new(), |snapshot| snapshot.keys().copied().collect())
+	}
+
+	/// Decode an entity's component from the newest snapshot.
+	pub(crate) fn get_component<C: Component>(&self, entity: EntityId) -> Option<C> {
+		decode_component(self.snapshots.values().next_back()?, entity)
+	}
+
+	/// Decode an entity's component, interpolated in between the two snapshots surrounding the specified tick. Ticks past
+	/// the newest snapshot the entity is in result in the component from that snapshot, since we do not extrapolate.
+	pub(crate) fn get_interpolated<C: Component + Interpolate>(&self, entity: EntityId, tick: f64) -> Option<C> {
+		let split = tick.max(0.0).floor() as u64;
+		let before = self.snapshots.range(..=split)
+			.rev()
+			.find_map(|(tick, snapshot)| Some((*tick, decode_component::<C>(snapshot, entity)?)));
+		let after = self.snapshots.range(split + 1..)
+			.find_map(|(tick, snapshot)| Some((*tick, decode_component::<C>(snapshot, entity)?)));
+
+		match (before, after) {
+			(Some((before_tick, before)), Some((after_tick, after))) => {
+				let t = (tick - before_tick as f64) / (after_tick - before_tick) as f64;
+				Some(before.interpolate(&after, t as f32))
+			},
+			(Some((_, component)), None) | (None, Some((_, component))) => Some(component),
+			(None, None) => None,
+		}
+	}
+}
+
+/// Decode an entity's component from a snapshot. Components that fail to decode are treated as missing.
+fn decode_component<C: Component>(snapshot: &Snapshot, entity: EntityId) -> Option<C> {
+	let data = snapshot.get(&entity)?.get(&C::ID)?;
+	let mut stream = NetworkReadStream::new();
+	stream.import(data.clone()).ok()?;
+	stream.decode::<C>().ok().map(|(component, _)| component)
+}
+
+/// Test snapshot reassembly and interpolation.
+#[cfg(test)]
+mod tests {
+	use streams::WriteStream;
+
+	use crate::network_stream::NetworkWriteStream;
+	use crate::payload::{ EntityUpdate, SnapshotChunk, };
+	use crate::replication::{ Component, EntityId, };
+	use crate::test_harness::TestPosition;
+
+	use super::SnapshotReceiver;
+
+	// a snapshot that moves an entity to the specified position
+	fn chunk(tick: u64, baseline: Option<u64>, position: f32) -> SnapshotChunk {
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(&TestPosition(position)).unwrap();
+
+		SnapshotChunk {
+			baseline,
+			count: 1,
+			index: 0,
+			tick,
+			updates: vec![EntityUpdate::Update {
+				changed: vec![(TestPosition::ID, stream.export().unwrap())],
+				entity: EntityId(0),
+				removed: Vec::new(),
+			}],
+		}
+	}
+
+	/// Ensure that components are interpolated in between the snapshots surrounding a tick.
+	#[test]
+	fn interpolation() {
+		let mut receiver = SnapshotReceiver::default();
+		receiver.handle_chunk(chunk(10, None, 0.0));
+		receiver.handle_chunk(chunk(20, Some(10), 10.0));
+
+		assert_eq!(receiver.get_interpolated::<TestPosition>(EntityId(0), 15.0), Some(TestPosition(5.0)));
+		assert_eq!(receiver.get_interpolated::<TestPosition>(EntityId(0), 12.5), Some(TestPosition(2.5)));
+		assert_eq!(receiver.get_interpolated::<TestPosition>(EntityId(0), 5.0), Some(TestPosition(0.0)));
+		assert_eq!(receiver.get_interpolated::<TestPosition>(EntityId(0), 25.0), Some(TestPosition(10.0)));
+		assert_eq!(receiver.get_interpolated::<TestPosition>(EntityId(1), 15.0), None);
+	}
+
+	/// Ensure that snapshots relative to a baseline we do not have are discarded.
+	#[test]
+	fn missing_baseline() {
+		let mut receiver = SnapshotReceiver::default();
+		receiver.handle_chunk(chunk(20, Some(10), 10.0));
+		assert_eq!(receiver.get_tick(), None);
+
+		receiver.handle_chunk(chunk(10, None, 0.0));
+		receiver.handle_chunk(chunk(20, Some(10), 10.0));
+		assert_eq!(receiver.get_tick(), Some(20));
+		assert_eq!(receiver.get_component::<TestPosition>(EntityId(0)), Some(TestPosition(10.0)));
+	}
+}
@@ -0,0 +1,327 @@
+use std::collections::{ BTreeMap, VecDeque, };
+use streams::WriteStream;
+
+use crate::network_stream::NetworkWriteStream;
+use crate::payload::{ EntityUpdate, SnapshotChunk, SubPayload, MAX_SNAPSHOT_CHUNKS, };
+use crate::payload::fragment::MAX_FRAGMENT_SIZE;
+
+use super::{ Components, EntityId, Interest, ReplicatedWorld, ReplicationError, };
+
+/// The amount of snapshots we remember sending to a client. Snapshots older than this cannot be used as a baseline.
+pub(crate) const MAX_SNAPSHOT_HISTORY: usize = 32;
+
+/// The amount of packets we remember the snapshot chunks of. Matches the amount of packets an acknowledge mask covers.
+const MAX_TRACKED_PACKETS: usize = 128;
+
+/// Entity updates are packed into chunks while they fit into this many bytes, which leaves room for the chunk's header
+/// so chunks are never fragmented. Fragmented chunks cannot be acknowledged, so entities whose components do not fit
+/// into a chunk on their own are always sent in full.
+const MAX_CHUNK_UPDATES_SIZE: usize = MAX_FRAGMENT_SIZE - 32;
+
+/// A snapshot we sent to a client.
+#[derive(Debug)]
+struct SentSnapshot {
+	/// The entities that were relevant to the client.
+	entities: BTreeMap<EntityId, Components>,
+	/// The amount of chunks of the snapshot the client has not acknowledged yet.
+	remaining_chunks: u16,
+	tick: u64,
+}
+
+/// Builds the snapshots the server sends to a client. Each snapshot only contains the changes since the newest snapshot
+/// the client acknowledged every chunk of, which is tracked using the acknowledgements of the packets the chunks were
+/// sent in.
+#[derive(Debug, Default)]
+pub(crate) struct SnapshotSender {
+	/// The tick of the newest snapshot the client received in full.
+	acknowledged_tick: Option<u64>,
+	/// Decides which entities are relevant to the client.
+	interest: Interest,
+	/// The ticks of the snapshot chunks sent in each packet, identified by the packet's sequence number.
+	packets: VecDeque<(u32, Vec<u64>)>,
+	/// The snapshots we sent recently, oldest first.
+	snapshots: VecDeque<SentSnapshot>,
+	/// Stream used to measure the size of entity updates.
+	stream: NetworkWriteStream,
+}
+
+impl SnapshotSender {
+	/// Change which entities are relevant to the client. Entities that are no longer relevant are despawned on the client
+	/// in the next snapshot.
+	pub(crate) fn set_interest(&mut self, interest: Interest) {
+		self.interest = interest;
+	}
+
+	/// Build the snapshot for the specified tick, split into chunks. Returns nothing if we already built a snapshot for
+	/// the tick or a later one.
+	pub(crate) fn build(&mut self, world: &ReplicatedWorld, tick: u64) -> Result<Vec<SnapshotChunk>, ReplicationError> {
+		if self.snapshots.back().is_some_and(|snapshot| snapshot.tick >= tick) {
+			return Ok(Vec::new());
+		}
+
+		let entities = world.iter()
+			.filter(|(_, entity)| self.interest.is_relevant(entity))
+			.map(|(entity_id, entity)| (*entity_id, entity.components.clone()))
+			.collect::<BTreeMap<EntityId, Components>>();
+
+		let baseline = self.acknowledged_tick
+			.and_then(|tick| self.snapshots.iter().find(|snapshot| snapshot.tick == tick));
+		let baseline_tick = baseline.map(|snapshot| snapshot.tick);
+
+		let empty = BTreeMap::new();
+		let baseline_entities = baseline.map_or(&empty, |snapshot| &snapshot.entities);
+
+		// figure out what changed since the baseline
+		let mut updates = baseline_entities.keys()
+			.filter(|entity| !entities.contains_key(entity))
+			.map(|entity| EntityUpdate::Despawn(*entity))
+			.collect::<Vec<EntityUpdate>>();
+
+		for (entity, components) in entities.iter() {
+			let baseline_components = baseline_entities.get(entity);
+			let changed = components.iter()
+				.filter(|(id, data)| baseline_components.and_then(|baseline| baseline.get(id)) != Some(data))
+				.map(|(id, data)| (*id, data.clone()))
+				.collect::<Vec<(u16, Vec<u8>)>>();
+
+			let removed = baseline_components.map_or(Vec::new(), |baseline| {
+				baseline.keys().filter(|id| !components.contains_key(id)).copied().collect()
+			});
+
+			if baseline_components.is_none() || !changed.is_empty() || !removed.is_empty() {
+				updates.push(EntityUpdate::Update {
+					changed,
+					entity: *entity,
+					removed,
+				});
+			}
+		}
+
+		// pack the updates into chunks. snapshots without any changes are still sent, so the client knows that nothing
+		// changed and can acknowledge the snapshot
+		let mut chunks = vec![Vec::new()];
+		let mut size = 0;
+		for update in updates {
+			self.stream.export()?;
+			self.stream.encode(&update)?;
+			let length = self.stream.len();
+
+			if size + length > MAX_CHUNK_UPDATES_SIZE && size > 0 {
+				chunks.push(Vec::new());
+				size = 0;
+			}
+
+			size += length;
+			chunks.last_mut().unwrap().push(update);
+		}
+
+		if chunks.len() > MAX_SNAPSHOT_CHUNKS as usize {
+			return Err(ReplicationError::SnapshotTooBig);
+		}
+
+		let count = chunks.len() as u16;
+		self.snapshots.push_back(SentSnapshot {
+			entities,
+			remaining_chunks: count,
+			tick,
+		});
+
+		if self.snapshots.len() > MAX_SNAPSHOT_HISTORY {
+			self.snapshots.pop_front();
+		}
+
+		Ok(chunks.into_iter()
+			.enumerate()
+			.map(|(index, updates)| SnapshotChunk {
+				baseline: baseline_tick,
+				count,
+				index: index as u16,
+				tick,
+				updates,
+			})
+			.collect())
+	}
+
+	/// Remember which snapshot chunks were sent in a packet.
+	pub(crate) fn record_sent(&mut self, sequence: u32, sub_payloads: &[SubPayload]) {
+		let ticks = sub_payloads.iter()
+			.filter_map(|sub_payload| match sub_payload {
+				SubPayload::Snapshot(chunk) => Some(chunk.tick),
+				_ => None,
+			})
+			.collect::<Vec<u64>>();
+
+		if ticks.is_empty() {
+			return;
+		}
+
+		self.packets.push_back((sequence, ticks));
+		if self.packets.len() > MAX_TRACKED_PACKETS {
+			self.packets.pop_front();
+		}
+	}
+
+	/// Handle the client acknowledging a packet. Once every chunk of a snapshot is acknowledged, the snapshot becomes the
+	/// baseline of the snapshots we send afterwards.
+	pub(crate) fn handle_acknowledged(&mut self, sequence: u32) {
+		let Some(index) = self.packets.iter().position(|(packet_sequence, _)| *packet_sequence == sequence) else {
+			return;
+		};
+
+		let (_, ticks) = self.packets.remove(index).unwrap();
+		for tick in ticks {
+			let Some(snapshot) = self.snapshots.iter_mut().find(|snapshot| snapshot.tick == tick) else {
+				continue;
+			};
+
+			snapshot.remaining_chunks = snapshot.remaining_chunks.saturating_sub(1);
+			if snapshot.remaining_chunks == 0 && self.acknowledged_tick.is_none_or(|acknowledged| tick > acknowledged) {
+				self.acknowledged_tick = Some(tick);
+			}
+		}
+
+		// snapshots older than the baseline are never used again
+		while self.snapshots.front().is_some_and(|snapshot| Some(snapshot.tick) < self.acknowledged_tick) {
+			self.snapshots.pop_front();
+		}
+	}
+
+	/// Forget the chunks sent in a dropped packet. The snapshot they belong to can no longer become a baseline.
+	pub(crate) fn handle_dropped(&mut self, sequence: u32) {
+		self.packets.retain(|(packet_sequence, _)| *packet_sequence != sequence);
+	}
+}
+
+/// Test snapshot building.
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+	use streams::WriteStream;
+
+	use crate::network_stream::NetworkWriteStream;
+	use crate::payload::{ EntityUpdate, SnapshotChunk, SubPayload, };
+	use crate::replication::{ Interest, ReplicatedWorld, VisibilityGroup, };
+	use crate::replication::receiver::SnapshotReceiver;
+	use crate::test_harness::TestPosition;
+
+	use super::{ SnapshotSender, MAX_FRAGMENT_SIZE, };
+
+	// pretend that the chunks were sent in a packet, and that the client acknowledged the packet
+	fn acknowledge(sender: &mut SnapshotSender, sequence: u32, chunks: &[SnapshotChunk]) {
+		let sub_payloads = chunks.iter().cloned().map(SubPayload::Snapshot).collect::<Vec<SubPayload>>();
+		sender.record_sent(sequence, &sub_payloads);
+		sender.handle_acknowledged(sequence);
+	}
+
+	/// Ensure that snapshots only contain the changes since the last snapshot the client acknowledged.
+	#[test]
+	fn delta() {
+		let mut world = ReplicatedWorld::default();
+		let first = world.spawn();
+		let second = world.spawn();
+		world.set_component(first, &TestPosition(1.0)).unwrap();
+		world.set_component(second, &TestPosition(2.0)).unwrap();
+
+		let mut sender = SnapshotSender::default();
+		let mut receiver = SnapshotReceiver::default();
+		let chunks = sender.build(&world, 1).unwrap();
+		assert_eq!(chunks.len(), 1);
+		assert_eq!(chunks[0].baseline, None);
+		assert_eq!(chunks[0].updates.len(), 2);
+		acknowledge(&mut sender, 1, &chunks);
+		chunks.into_iter().for_each(|chunk| receiver.handle_chunk(chunk));
+
+		// the second snapshot is never acknowledged, so the third snapshot is relative to the first
+		world.set_component(first, &TestPosition(3.0)).unwrap();
+		let chunks = sender.build(&world, 2).unwrap();
+		assert_eq!(chunks[0].baseline, Some(1));
+		assert!(matches!(chunks[0].updates[..], [EntityUpdate::Update { entity, .. }] if entity == first));
+		assert!(sender.build(&world, 2).unwrap().is_empty());
+
+		world.despawn(second);
+		let chunks = sender.build(&world, 3).unwrap();
+		assert_eq!(chunks[0].baseline, Some(1));
+		assert_eq!(chunks[0].updates.len(), 2);
+		assert!(chunks[0].updates.contains(&EntityUpdate::Despawn(second)));
+		chunks.into_iter().for_each(|chunk| receiver.handle_chunk(chunk));
+
+		assert_eq!(receiver.get_tick(), Some(3));
+		assert_eq!(receiver.get_entities(), vec![first]);
+		assert_eq!(receiver.get_component::<TestPosition>(first), Some(TestPosition(3.0)));
+
+		// nothing changed since the snapshot was acknowledged
+		let chunks = sender.build(&world, 4).unwrap();
+		acknowledge(&mut sender, 2, &chunks);
+		let chunks = sender.build(&world, 5).unwrap();
+		assert_eq!(chunks[0].baseline, Some(4));
+		assert!(chunks[0].updates.is_empty());
+	}
+
+	/// Ensure that clients are only sent the entities they can see.
+	#[test]
+	fn relevance() {
+		let mut world = ReplicatedWorld::default();
+		let near = world.spawn();
+		world.set_position(near, Some([1.0, 0.0, 0.0])).unwrap();
+		let far = world.spawn();
+		world.set_position(far, Some([100.0, 0.0, 0.0])).unwrap();
+		let hidden = world.spawn();
+		world.set_group(hidden, Some(VisibilityGroup(1))).unwrap();
+		let global = world.spawn();
+
+		let mut sender = SnapshotSender::default();
+		sender.set_interest(Interest {
+			groups: HashSet::new(),
+			position: Some([0.0, 0.0, 0.0]),
+			radius: 10.0,
+		});
+
+		let mut receiver = SnapshotReceiver::default();
+		sender.build(&world, 1).unwrap().into_iter().for_each(|chunk| receiver.handle_chunk(chunk));
+		assert_eq!(receiver.get_entities(), vec![near, global]);
+
+		sender.set_interest(Interest {
+			groups: HashSet::from([VisibilityGroup(1)]),
+			..Interest::default()
+		});
+
+		sender.build(&world, 2).unwrap().into_iter().for_each(|chunk| receiver.handle_chunk(chunk));
+		assert_eq!(receiver.get_entities(), vec![near, far, hidden, global]);
+	}
+
+	/// Ensure that big snapshots are split into chunks, and only become the baseline once every chunk is acknowledged.
+	#[test]
+	fn chunks() {
+		let mut world = ReplicatedWorld::default();
+		for index in 0..500 {
+			let entity = world.spawn();
+			world.set_component(entity, &TestPosition(index as f32)).unwrap();
+		}
+
+		let mut sender = SnapshotSender::default();
+		let chunks = sender.build(&world, 1).unwrap();
+		assert!(chunks.len() > 1);
+
+		// chunks have to fit into a packet without being fragmented
+		let mut stream = NetworkWriteStream::new();
+		for chunk in chunks.iter() {
+			stream.encode(chunk).unwrap();
+			assert!(stream.len() <= MAX_FRAGMENT_SIZE);
+			stream.export().unwrap();
+		}
+
+		// chunks can arrive in any order
+		let mut receiver = SnapshotReceiver::default();
+		for chunk in chunks.iter().rev() {
+			assert_eq!(receiver.get_tick(), None);
+			receiver.handle_chunk(chunk.clone());
+		}
+		assert_eq!(receiver.get_entities().len(), 500);
+
+		acknowledge(&mut sender, 1, &chunks[1..]);
+		assert_eq!(sender.build(&world, 2).unwrap()[0].baseline, None);
+
+		acknowledge(&mut sender, 2, &chunks[..1]);
+		assert_eq!(sender.build(&world, 3).unwrap()[0].baseline, Some(1));
+	}
+}
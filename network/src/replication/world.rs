@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use streams::WriteStream;
+
+use crate::network_stream::NetworkWriteStream;
+
+use super::{ Component, Components, EntityId, ReplicationError, VisibilityGroup, };
+
+/// The state of an entity on the server.
+#[derive(Debug, Default)]
+pub(crate) struct ReplicatedEntity {
+	pub(crate) components: Components,
+	/// Clients only receive the entity if they can see this group. Everyone can see entities without a group.
+	pub(crate) group: Option<VisibilityGroup>,
+	/// Where the entity is, used to skip entities that are too far away from a client.
+	pub(crate) position: Option<[f32; 3]>,
+}
+
+/// The entities a server replicates to its clients. Game code updates the world every simulation tick, and the server
+/// sends the parts of it relevant to each client in snapshots.
+#[derive(Debug, Default)]
+pub struct ReplicatedWorld {
+	entities: BTreeMap<EntityId, ReplicatedEntity>,
+	/// The ID we give to the next entity that is spawned.
+	next_entity_id: u32,
+	/// The stream we encode components with.
+	stream: NetworkWriteStream,
+}
+
+impl ReplicatedWorld {
+	/// Create an entity without any components.
+	pub fn spawn(&mut self) -> EntityId {
+		let entity = EntityId(self.next_entity_id);
+		self.next_entity_id = self.next_entity_id.wrapping_add(1);
+		self.entities.insert(entity, ReplicatedEntity::default());
+		entity
+	}
+
+	/// Remove an entity. Returns false if the entity did not exist.
+	pub fn despawn(&mut self, entity: EntityId) -> bool {
+		self.entities.remove(&entity).is_some()
+	}
+
+	/// Whether or not the entity exists.
+	pub fn contains(&self, entity: EntityId) -> bool {
+		self.entities.contains_key(&entity)
+	}
+
+	/// Add a component to an entity, or replace the component the entity already has.
+	pub fn set_component<C: Component>(&mut self, entity: EntityId, component: &C) -> Result<(), ReplicationError> {
+		let entity = self.entities.get_mut(&entity).ok_or(ReplicationError::CouldNotFindEntity(entity))?;
+
+		// discard anything a failed encode left behind
+		self.stream.export()?;
+		self.stream.encode(component)?;
+		entity.components.insert(C::ID, self.stream.export()?);
+		Ok(())
+	}
+
+	/// Remove a component from an entity. Does nothing if the entity does not have the component.
+	pub fn remove_component<C: Component>(&mut self, entity: EntityId) -> Result<(), ReplicationError> {
+		self.get_entity_mut(entity)?.components.remove(&C::ID);
+		Ok(())
+	}
+
+	/// Set the visibility group of an entity. Entities without a group are replicated to every client.
+	pub fn set_group(&mut self, entity: EntityId, group: Option<VisibilityGroup>) -> Result<(), ReplicationError> {
+		self.get_entity_mut(entity)?.group = group;
+		Ok(())
+	}
+
+	/// Set the position of an entity, which decides which clients are close enough to receive it. Entities without a
+	/// position are replicated regardless of distance.
+	pub fn set_position(&mut self, entity: EntityId, position: Option<[f32; 3]>) -> Result<(), ReplicationError> {
+		self.get_entity_mut(entity)?.position = position;
+		Ok(())
+	}
+
+	/// Iterate over every entity.
+	pub(crate) fn iter(&self) -> impl Iterator<Item = (&EntityId, &ReplicatedEntity)> {
+		self.entities.iter()
+	}
+
+	fn get_entity_mut(&mut self, entity: EntityId) -> Result<&mut ReplicatedEntity, ReplicationError> {
+		self.entities.get_mut(&entity).ok_or(ReplicationError::CouldNotFindEntity(entity))
+	}
+}
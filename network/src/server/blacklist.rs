@@ -0,0 +1,502 @@
+use std::collections::{ BTreeSet, HashMap, HashSet, };
+use std::net::{ IpAddr, Ipv6Addr, };
+use std::path::{ Path, PathBuf, };
+use std::str::FromStr;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH, };
+
+use crate::address::to_ipv6;
+
+use super::ServerConfig;
+
+/// How long a strike counts towards a ban.
+const STRIKE_LIFETIME: Duration = Duration::from_secs(60);
+
+/// How long we remember an expired ban for. If the range is banned again before then, the ban is escalated.
+const OFFENSE_LIFETIME: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// The prefix length of the range we automatically ban for native IPv6 addresses. Clients usually get a whole /64, so
+/// banning a single address is easy to get around.
+const AUTOMATIC_BAN_PREFIX_LENGTH: u8 = 64;
+
+/// A range of IPv6 addresses that share their first `prefix_length` bits. IPv4 ranges are stored as ranges of
+/// IPv4-mapped addresses.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct AddressRange {
+	/// The first address in the range.
+	address: Ipv6Addr,
+	prefix_length: u8,
+}
+
+impl AddressRange {
+	/// Create the range containing `address` with the specified prefix length. Prefix lengths over 128 are clamped.
+	pub fn new(address: Ipv6Addr, prefix_length: u8) -> Self {
+		let prefix_length = prefix_length.min(128);
+		let mask = if prefix_length == 0 { 0 } else { u128::MAX << (128 - prefix_length as u32) };
+		AddressRange {
+			address: Ipv6Addr::from(u128::from(address) & mask),
+			prefix_length,
+		}
+	}
+
+	/// Create the range that only contains `address`.
+	pub fn single(address: Ipv6Addr) -> Self {
+		AddressRange::new(address, 128)
+	}
+
+	pub fn get_address(&self) -> Ipv6Addr {
+		self.address
+	}
+
+	pub fn get_prefix_length(&self) -> u8 {
+		self.prefix_length
+	}
+
+	/// Whether or not the address is in the range.
+	pub fn contains(&self, address: &Ipv6Addr) -> bool {
+		AddressRange::new(*address, self.prefix_length) == *self
+	}
+}
+
+impl std::fmt::Display for AddressRange {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self.address.to_ipv4_mapped() {
+			Some(address) if self.prefix_length >= 96 => write!(f, "{}/{}", address, self.prefix_length - 96),
+			_ => write!(f, "{}/{}", self.address, self.prefix_length),
+		}
+	}
+}
+
+/// Emitted if a string is not an address range.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidAddressRange;
+
+impl FromStr for AddressRange {
+	type Err = InvalidAddressRange;
+
+	/// Parse a range written as `address/prefix_length`, or a single address. IPv4 prefix lengths count IPv4 bits.
+	fn from_str(string: &str) -> Result<Self, Self::Err> {
+		let (address, prefix_length) = match string.split_once('/') {
+			Some((address, prefix_length)) => (address, Some(prefix_length)),
+			None => (string, None),
+		};
+
+		let address = address.parse::<IpAddr>().map_err(|_| InvalidAddressRange)?;
+		let (maximum_length, offset) = if address.is_ipv4() { (32, 96) } else { (128, 0) };
+		let prefix_length = match prefix_length.map(|prefix_length| prefix_length.parse::<u8>()) {
+			Some(Ok(prefix_length)) if prefix_length <= maximum_length => prefix_length,
+			Some(_) => return Err(InvalidAddressRange),
+			None => maximum_length,
+		};
+
+		Ok(AddressRange::new(to_ipv6(address), prefix_length + offset))
+	}
+}
+
+/// A ban on a range of addresses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ban {
+	/// When the ban is lifted. Permanent bans never expire.
+	pub expires: Option<SystemTime>,
+	/// The amount of times the range has been banned. Used to escalate the duration of automatic bans.
+	pub offenses: u32,
+}
+
+impl Ban {
+	/// Whether or not the ban is still in effect.
+	pub fn is_active(&self, now: SystemTime) -> bool {
+		self.expires.is_none_or(|expires| expires > now)
+	}
+}
+
+/// The strikes an address has collected.
+#[derive(Debug)]
+struct Strikes {
+	count: u32,
+	last_strike: Instant,
+}
+
+/// Keeps track of which addresses the server refuses to talk to. Addresses collect strikes when they misbehave, and are
+/// banned for a while once they collect enough of them. Each time a range is banned again, the ban lasts twice as long.
+/// Addresses on the allow list are never banned.
+#[derive(Debug)]
+pub(crate) struct Blacklist {
+	/// Ranges that are never banned, and never collect strikes.
+	allow_list: HashSet<AddressRange>,
+	/// How long the first automatic ban of a range lasts.
+	ban_duration: Duration,
+	/// Where the ban list is persisted.
+	ban_list_path: Option<PathBuf>,
+	/// Bans that are in effect, or expired recently enough that we still remember them for escalation.
+	bans: HashMap<AddressRange, Ban>,
+	/// The longest an automatic ban can last.
+	max_ban_duration: Duration,
+	/// The prefix lengths used by `allow_list` and `bans`, so we only have to do one lookup per prefix length.
+	prefix_lengths: BTreeSet<u8>,
+	/// The amount of strikes an address can collect before it is banned.
+	strikes_before_ban: u32,
+	strikes: HashMap<Ipv6Addr, Strikes>,
+}
+
+impl Blacklist {
+	/// Create a blacklist using the server's ban settings. If the server has a ban list, it is loaded from disk.
+	pub(crate) fn new(config: &ServerConfig) -> Result<Self, std::io::Error> {
+		let mut blacklist = Blacklist {
+			allow_list: HashSet::new(),
+			ban_duration: config.ban_duration,
+			ban_list_path: config.ban_list_path.clone(),
+			bans: HashMap::new(),
+			max_ban_duration: config.max_ban_duration,
+			prefix_lengths: BTreeSet::new(),
+			strikes_before_ban: config.strikes_before_ban,
+			strikes: HashMap::new(),
+		};
+
+		if let Some(path) = config.ban_list_path.as_ref() {
+			if path.exists() {
+				blacklist.load(path)?;
+			}
+		}
+
+		Ok(blacklist)
+	}
+
+	/// Whether or not we should drop data from the address.
+	pub(crate) fn is_banned(&self, address: &Ipv6Addr, now: SystemTime) -> bool {
+		if self.is_allowed(address) {
+			return false;
+		}
+
+		self.prefix_lengths.iter()
+			.filter_map(|prefix_length| self.bans.get(&AddressRange::new(*address, *prefix_length)))
+			.any(|ban| ban.is_active(now))
+	}
+
+	/// Whether or not the address is on the allow list.
+	pub(crate) fn is_allowed(&self, address: &Ipv6Addr) -> bool {
+		self.prefix_lengths.iter()
+			.any(|prefix_length| self.allow_list.contains(&AddressRange::new(*address, *prefix_length)))
+	}
+
+	/// Give the address a strike. Returns the range that was banned if the address collected enough strikes.
+	pub(crate) fn strike(&mut self, address: Ipv6Addr, now: Instant) -> Option<AddressRange> {
+		if self.is_allowed(&address) {
+			return None;
+		}
+
+		let strikes = self.strikes.entry(address).or_insert(Strikes {
+			count: 0,
+			last_strike: now,
+		});
+
+		if now - strikes.last_strike > STRIKE_LIFETIME {
+			strikes.count = 0;
+		}
+
+		strikes.count += 1;
+		strikes.last_strike = now;
+
+		if strikes.count < self.strikes_before_ban {
+			return None;
+		}
+
+		self.strikes.remove(&address);
+
+		// IPv4 clients share a single address, so we cannot ban anything bigger than it
+		let range = if address.to_ipv4_mapped().is_some() {
+			AddressRange::single(address)
+		} else {
+			AddressRange::new(address, AUTOMATIC_BAN_PREFIX_LENGTH)
+		};
+
+		let offenses = self.bans.get(&range).map_or(0, |ban| ban.offenses);
+		let duration = self.ban_duration
+			.checked_mul(1 << offenses.min(31))
+			.unwrap_or(self.max_ban_duration)
+			.min(self.max_ban_duration);
+
+		self.ban(range, Some(duration));
+		Some(range)
+	}
+
+	/// Ban a range. Bans without a duration, or that last too long for their expiry to be represented, are permanent.
+	pub(crate) fn ban(&mut self, range: AddressRange, duration: Option<Duration>) {
+		let offenses = self.bans.get(&range).map_or(0, |ban| ban.offenses);
+		self.bans.insert(range, Ban {
+			expires: duration.and_then(|duration| SystemTime::now().checked_add(duration)),
+			offenses: offenses + 1,
+		});
+		self.prefix_lengths.insert(range.prefix_length);
+	}
+
+	/// Lift a ban. Returns whether or not the range was banned.
+	pub(crate) fn unban(&mut self, range: &AddressRange) -> bool {
+		self.bans.remove(range).is_some_and(|ban| ban.is_active(SystemTime::now()))
+	}
+
+	/// Get the bans that are in effect.
+	pub(crate) fn get_bans(&self) -> Vec<(AddressRange, Ban)> {
+		let now = SystemTime::now();
+		let mut bans = self.bans.iter()
+			.filter(|(_, ban)| ban.is_active(now))
+			.map(|(range, ban)| (*range, *ban))
+			.collect::<Vec<(AddressRange, Ban)>>();
+
+		bans.sort_by_key(|(range, _)| *range);
+		bans
+	}
+
+	pub(crate) fn allow(&mut self, range: AddressRange) {
+		self.allow_list.insert(range);
+		self.prefix_lengths.insert(range.prefix_length);
+	}
+
+	/// Remove a range from the allow list. Returns whether or not the range was on the allow list.
+	pub(crate) fn disallow(&mut self, range: &AddressRange) -> bool {
+		self.allow_list.remove(range)
+	}
+
+	pub(crate) fn get_allow_list(&self) -> Vec<AddressRange> {
+		let mut allow_list = self.allow_list.iter().copied().collect::<Vec<AddressRange>>();
+		allow_list.sort();
+		allow_list
+	}
+
+	/// Forget old strikes, and bans that expired long enough ago that they no longer count towards escalation.
+	pub(crate) fn prune(&mut self, now: Instant) {
+		self.strikes.retain(|_, strikes| now - strikes.last_strike <= STRIKE_LIFETIME);
+
+		let system_now = SystemTime::now();
+		self.bans.retain(|_, ban| {
+			ban.expires
+				.and_then(|expires| expires.checked_add(OFFENSE_LIFETIME))
+				.is_none_or(|forgotten| forgotten > system_now)
+		});
+	}
+
+	/// Write the ban list to disk, if the server has one.
+	pub(crate) fn save(&self) -> Result<(), std::io::Error> {
+		let Some(path) = self.ban_list_path.as_ref() else {
+			return Ok(());
+		};
+
+		let mut contents = String::new();
+		for range in self.get_allow_list() {
+			contents.push_str(&format!("allow {}\n", range));
+		}
+
+		let mut bans = self.bans.iter().collect::<Vec<(&AddressRange, &Ban)>>();
+		bans.sort_by_key(|(range, _)| **range);
+		for (range, ban) in bans {
+			let expires = ban.expires.map_or(String::from("never"), |expires| {
+				expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().to_string()
+			});
+
+			contents.push_str(&format!("ban {} {} {}\n", range, expires, ban.offenses));
+		}
+
+		// write to a temporary file first so a crash cannot leave us with half a ban list
+		let temporary_path = path.with_extension("tmp");
+		std::fs::write(&temporary_path, contents)?;
+		std::fs::rename(temporary_path, path)
+	}
+
+	/// Read a ban list written by `save`.
+	fn load(&mut self, path: &Path) -> Result<(), std::io::Error> {
+		let invalid = |number: usize| {
+			std::io::Error::new(std::io::ErrorKind::InvalidData, format!("invalid ban list entry on line {}", number + 1))
+		};
+
+		for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+			let words = line.split_whitespace().collect::<Vec<&str>>();
+			match words[..] {
+				[] => {},
+				["allow", range] => self.allow(range.parse().map_err(|_| invalid(number))?),
+				["ban", range, expires, offenses] => {
+					let range = range.parse::<AddressRange>().map_err(|_| invalid(number))?;
+					let expires = match expires {
+						"never" => None,
+						expires => {
+							let expires = Duration::from_secs(expires.parse().map_err(|_| invalid(number))?);
+							Some(UNIX_EPOCH.checked_add(expires).ok_or_else(|| invalid(number))?)
+						},
+					};
+
+					self.bans.insert(range, Ban {
+						expires,
+						offenses: offenses.parse().map_err(|_| invalid(number))?,
+					});
+					self.prefix_lengths.insert(range.prefix_length);
+				},
+				_ => return Err(invalid(number)),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Test bans, strikes, and ban list persistence.
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv6Addr;
+	use std::time::{ Duration, Instant, SystemTime, };
+
+	use crate::server::ServerConfig;
+
+	use super::{ AddressRange, Blacklist, STRIKE_LIFETIME, };
+
+	fn config() -> ServerConfig {
+		ServerConfig {
+			ban_duration: Duration::from_secs(60),
+			max_ban_duration: Duration::from_secs(200),
+			strikes_before_ban: 3,
+			..Default::default()
+		}
+	}
+
+	/// Ensure that ranges contain the addresses that share their prefix.
+	#[test]
+	fn address_range() {
+		let range = "2001:db8:0:1::5/64".parse::<AddressRange>().expect("Could not parse range");
+		assert_eq!(range.to_string(), "2001:db8:0:1::/64");
+		assert!(range.contains(&"2001:db8:0:1:ffff::1".parse().unwrap()));
+		assert!(!range.contains(&"2001:db8:0:2::1".parse().unwrap()));
+
+		let single = "::1".parse::<AddressRange>().expect("Could not parse range");
+		assert_eq!(single, AddressRange::single(Ipv6Addr::LOCALHOST));
+		assert!("::1/abc".parse::<AddressRange>().is_err());
+		assert!("::1/129".parse::<AddressRange>().is_err());
+
+		let ipv4 = "10.1.2.3/8".parse::<AddressRange>().expect("Could not parse range");
+		assert_eq!(ipv4.to_string(), "10.0.0.0/8");
+		assert!(ipv4.contains(&"::ffff:10.200.0.1".parse().unwrap()));
+		assert!(!ipv4.contains(&"::ffff:11.0.0.1".parse().unwrap()));
+		assert_eq!("10.1.2.3".parse::<AddressRange>(), Ok(AddressRange::single("::ffff:10.1.2.3".parse().unwrap())));
+		assert!("10.1.2.3/33".parse::<AddressRange>().is_err());
+	}
+
+	/// Ensure that addresses are only banned after collecting enough strikes, that their whole /64 is banned, and that
+	/// repeated bans last longer.
+	#[test]
+	fn strikes() {
+		let mut blacklist = Blacklist::new(&config()).expect("Could not create blacklist");
+		let address: Ipv6Addr = "2001:db8::5".parse().unwrap();
+		let neighbor: Ipv6Addr = "2001:db8::6".parse().unwrap();
+		let now = Instant::now();
+
+		assert_eq!(blacklist.strike(address, now), None);
+		assert_eq!(blacklist.strike(address, now), None);
+
+		// strikes wear off
+		assert_eq!(blacklist.strike(address, now + STRIKE_LIFETIME + Duration::from_secs(1)), None);
+		assert_eq!(blacklist.strike(address, now + STRIKE_LIFETIME + Duration::from_secs(1)), None);
+		assert!(!blacklist.is_banned(&address, SystemTime::now()));
+
+		let range = blacklist.strike(address, now + STRIKE_LIFETIME + Duration::from_secs(1));
+		assert_eq!(range, Some(AddressRange::new(address, 64)));
+		assert!(blacklist.is_banned(&neighbor, SystemTime::now()));
+		assert!(!blacklist.is_banned(&neighbor, SystemTime::now() + Duration::from_secs(61)));
+
+		// the second ban lasts twice as long, the third is capped
+		for _ in 0..3 {
+			blacklist.strike(address, now);
+		}
+		assert!(blacklist.is_banned(&neighbor, SystemTime::now() + Duration::from_secs(61)));
+		assert!(!blacklist.is_banned(&neighbor, SystemTime::now() + Duration::from_secs(121)));
+
+		for _ in 0..3 {
+			blacklist.strike(address, now);
+		}
+		assert!(blacklist.is_banned(&neighbor, SystemTime::now() + Duration::from_secs(199)));
+		assert!(!blacklist.is_banned(&neighbor, SystemTime::now() + Duration::from_secs(201)));
+
+		// IPv4 clients only get their own address banned
+		let ipv4 = "::ffff:10.0.0.1".parse::<Ipv6Addr>().unwrap();
+		for _ in 0..3 {
+			blacklist.strike(ipv4, now);
+		}
+		assert!(blacklist.is_banned(&ipv4, SystemTime::now()));
+		assert!(!blacklist.is_banned(&"::ffff:10.0.0.2".parse().unwrap(), SystemTime::now()));
+	}
+
+	/// Ensure that the allow list overrides bans and strikes.
+	#[test]
+	fn allow_list() {
+		let mut blacklist = Blacklist::new(&config()).expect("Could not create blacklist");
+		let address: Ipv6Addr = "2001:db8::5".parse().unwrap();
+
+		blacklist.ban(AddressRange::new(address, 48), None);
+		blacklist.allow(AddressRange::single(address));
+		assert!(!blacklist.is_banned(&address, SystemTime::now()));
+		assert!(blacklist.is_banned(&"2001:db8::6".parse().unwrap(), SystemTime::now()));
+
+		for _ in 0..10 {
+			assert_eq!(blacklist.strike(address, Instant::now()), None);
+		}
+
+		assert!(blacklist.disallow(&AddressRange::single(address)));
+		assert!(blacklist.is_banned(&address, SystemTime::now()));
+		assert!(blacklist.unban(&AddressRange::new(address, 48)));
+		assert!(!blacklist.is_banned(&address, SystemTime::now()));
+	}
+
+	/// Ensure that the ban list is read back the way it was written.
+	#[test]
+	fn persistence() {
+		let path = std::env::temp_dir().join(format!("eggine-ban-list-{}", std::process::id()));
+		let config = ServerConfig {
+			ban_list_path: Some(path.clone()),
+			..config()
+		};
+
+		let mut blacklist = Blacklist::new(&config).expect("Could not create blacklist");
+		blacklist.ban("2001:db8::/64".parse().unwrap(), None);
+		blacklist.ban("2001:db8:1::5".parse().unwrap(), Some(Duration::from_secs(100)));
+		blacklist.allow("2001:db8::5".parse().unwrap());
+		blacklist.save().expect("Could not save ban list");
+
+		let loaded = Blacklist::new(&config).expect("Could not load ban list");
+		std::fs::remove_file(&path).expect("Could not remove ban list");
+
+		assert_eq!(loaded.get_allow_list(), blacklist.get_allow_list());
+		assert_eq!(loaded.get_bans().len(), 2);
+		for ((loaded_range, loaded_ban), (range, ban)) in loaded.get_bans().iter().zip(blacklist.get_bans().iter()) {
+			assert_eq!(loaded_range, range);
+			assert_eq!(loaded_ban.offenses, ban.offenses);
+			assert_eq!(loaded_ban.expires.is_some(), ban.expires.is_some());
+		}
+	}
+
+	/// Ensure that bans whose expiry cannot be represented do not panic.
+	#[test]
+	fn overflow() {
+		let path = std::env::temp_dir().join(format!("eggine-ban-list-overflow-{}", std::process::id()));
+		let config = ServerConfig {
+			ban_duration: Duration::MAX,
+			ban_list_path: Some(path.clone()),
+			max_ban_duration: Duration::MAX,
+			..config()
+		};
+
+		// expiry times that can be represented, but not once the offense lifetime is added, are kept by pruning
+		std::fs::write(&path, format!("ban ::1 {} 1\n", i64::MAX)).expect("Could not write ban list");
+		let mut blacklist = Blacklist::new(&config).expect("Could not load ban list");
+		blacklist.prune(Instant::now());
+		assert!(blacklist.is_banned(&Ipv6Addr::LOCALHOST, SystemTime::now()));
+
+		// expiry times that cannot be represented are rejected
+		std::fs::write(&path, format!("ban ::1 {} 1\n", u64::MAX)).expect("Could not write ban list");
+		let error = Blacklist::new(&config).expect_err("Loaded an invalid ban list");
+		assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+		std::fs::remove_file(&path).expect("Could not remove ban list");
+
+		// bans that last too long are permanent
+		let address: Ipv6Addr = "2001:db8::5".parse().unwrap();
+		for _ in 0..3 {
+			blacklist.strike(address, Instant::now());
+		}
+
+		let range = AddressRange::new(address, 64);
+		assert_eq!(blacklist.get_bans().iter().find(|(banned, _)| *banned == range).unwrap().1.expires, None);
+	}
+}
@@ -1,22 +1,42 @@
 use std::net::SocketAddr;
 use std::time::Instant;
 
-use crate::payload::{ AcknowledgeMask, Packet, };
+use crate::congestion::CongestionController;
+use crate::connection_statistics::ConnectionStatistics;
+use crate::encryption::Session;
+use crate::event::ClientId;
+use crate::payload::{ AcknowledgeMask, Fragmenter, Packet, Reassembler, };
+use crate::reliable::ReliableChannel;
+use crate::replication::sender::SnapshotSender;
+use crate::simulation::InputBuffer;
 
 /// Server representation of a connected client.
 #[derive(Debug)]
 pub struct ClientConnection {
 	/// Server-side acknowledge mask for this client.
 	pub(crate) acknowledge_mask: AcknowledgeMask,
+	/// Whether or not the client sent us data that we have not acknowledged yet.
+	pub(crate) acknowledge_pending: bool,
 	/// Address of the client.
 	pub(crate) address: SocketAddr,
+	/// Decides how many bytes of unreliable data we send to the client each tick.
+	pub(crate) congestion: CongestionController,
+	/// Keeps packets sent to the client within `MAX_PACKET_SIZE`.
+	pub(crate) fragmenter: Fragmenter,
+	/// The handshake we replied to the client with. Kept until the client sends us its first packet, so we can send the
+	/// reply again if the client did not receive it.
+	pub(crate) handshake_reply: Option<Vec<u8>>,
 	/// The highest sequence number that the client said it had acknowledged. This is initialized as `None`, since the
 	/// client starts off having acknowledged nothing.
 	pub(crate) highest_acknowledge_received: Option<u32>,
+	/// The ID game code refers to the client with.
+	pub(crate) id: ClientId,
+	/// The inputs the client sent for upcoming simulation ticks.
+	pub(crate) input_buffer: InputBuffer,
 	/// The last time we received information from the client.
 	pub(crate) last_activity: Instant,
-	/// The last time we went a ping to the client.
-	pub(crate) last_ping_time: Instant,
+	/// The last time we sent a packet to the client.
+	pub(crate) last_send_time: Instant,
 	/// The last sequence we received from the client.
 	pub(crate) last_sequence_received: Option<u32>,
 	/// The NTP id that the client uses to talk to us.
@@ -24,6 +44,16 @@ pub struct ClientConnection {
 	/// Each client has a new packet assigned to them every server tick. All information that needs to be sent that tick
 	/// should be encoded into the client's outgoing packet. Clients can only be sent one packet per tick.
 	pub(crate) outgoing_packet: Packet,
+	/// Reassembles fragmented sub-payloads sent by the client.
+	pub(crate) reassembler: Reassembler,
+	/// Reliable messages exchanged with the client.
+	pub(crate) reliable_channel: ReliableChannel,
 	/// The server-side sequence number. Server -> client packets will be identified using this sequence number.
 	pub(crate) sequence: u32,
+	/// Encrypts and authenticates the packets exchanged with the client.
+	pub(crate) session: Session,
+	/// Builds the snapshots of the entities replicated to the client.
+	pub(crate) snapshot_sender: SnapshotSender,
+	/// Round-trip time, packet loss and bandwidth of the connection.
+	pub(crate) statistics: ConnectionStatistics,
 }
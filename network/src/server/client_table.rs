@@ -1,6 +1,8 @@
-use std::collections::{ HashMap, HashSet, };
-use std::collections::hash_map::Iter;
-use std::net::{ Ipv6Addr, SocketAddr, };
+use std::collections::HashMap;
+use std::collections::hash_map::{ Iter, IterMut, };
+use std::net::SocketAddr;
+
+use crate::event::ClientId;
 
 use super::ClientConnection;
 use super::server::ServerError;
@@ -10,19 +12,18 @@ use super::server::ServerError;
 pub(crate) struct ClientTable {
 	/// Maps IP address & port to a client.
 	address_to_client: HashMap<SocketAddr, ClientConnection>,
-	/// If we get too many invalid packets from an IP address, add them to the blacklist so we immediately discard any
-	/// additional packets from them. The blacklist blocks any communication from IP addresses, regardless of port.
-	blacklist: HashSet<Ipv6Addr>,
+	/// Maps client ID to IP address & port.
+	id_to_address: HashMap<ClientId, SocketAddr>,
 }
 
 impl ClientTable {
 	/// Get the client using the specified address.
-	/*pub(crate) fn get_client(&self, source: &SocketAddr) -> Result<&ClientConnection, ServerError> {
-		match self.address_to_client.get(&source) {
+	pub(crate) fn get_client(&self, source: &SocketAddr) -> Result<&ClientConnection, ServerError> {
+		match self.address_to_client.get(source) {
     	Some(client) => Ok(client),
     	None => Err(ServerError::CouldNotFindClient),
 		}
-	}*/
+	}
 
 	/// Get the client using the specified address.
 	pub(crate) fn get_client_mut(&mut self, source: &SocketAddr) -> Result<&mut ClientConnection, ServerError> {
@@ -32,6 +33,11 @@ impl ClientTable {
 		}
 	}
 
+	/// Get the address of the client using the specified ID.
+	pub(crate) fn get_address(&self, client_id: &ClientId) -> Result<SocketAddr, ServerError> {
+		self.id_to_address.get(client_id).copied().ok_or(ServerError::CouldNotFindClient)
+	}
+
 	/// Determine if a client exists in the table.
 	pub(crate) fn has_client(&self, source: &SocketAddr) -> bool {
 		self.address_to_client.contains_key(source)
@@ -39,12 +45,15 @@ impl ClientTable {
 
 	/// Add a client to the table.
 	pub(crate) fn add_client(&mut self, source: SocketAddr, client_connection: ClientConnection) {
+		self.id_to_address.insert(client_connection.id, source);
 		self.address_to_client.insert(source, client_connection);
 	}
 
 	/// Remove a client from the table.
 	pub(crate) fn remove_client(&mut self, source: &SocketAddr) {
-		self.address_to_client.remove(source);
+		if let Some(client_connection) = self.address_to_client.remove(source) {
+			self.id_to_address.remove(&client_connection.id);
+		}
 	}
 
 	/// Return an iterator over the `SocketAddr` -> `ClientConnection` mapping.
@@ -53,22 +62,7 @@ impl ClientTable {
 	}
 
 	/// Return a mutable iterator over the `SocketAddr` -> `ClientConnection` mapping.
-	/*pub(crate) fn client_iter_mut(&mut self) -> IterMut<'_, std::net::SocketAddr, ClientConnection> {
+	pub(crate) fn client_iter_mut(&mut self) -> IterMut<'_, std::net::SocketAddr, ClientConnection> {
 		self.address_to_client.iter_mut()
-	}*/
-
-	/// Add an IP address to the blacklist.
-	pub(crate) fn add_to_blacklist(&mut self, address: Ipv6Addr) {
-		self.blacklist.insert(address);
-	}
-
-	/// Remove an IP address from the blacklist.
-	/*pub(crate) fn remove_from_blacklist(&mut self, address: &Ipv6Addr) {
-		self.blacklist.remove(address);
-	}*/
-
-	/// Check if an IP address is in the blacklist.
-	pub(crate) fn is_in_blacklist(&self, address: &Ipv6Addr) -> bool {
-		self.blacklist.contains(address)
 	}
 }
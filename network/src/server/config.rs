@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::link_conditioner::LinkConditions;
+use crate::log::{ LogLevel, DEFAULT_LOG_CAPACITY, };
+
+/// Limits and timings used by a server.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+	/// How long the first automatic ban of an address lasts. Each time the address is banned again, the ban lasts twice
+	/// as long.
+	pub ban_duration: Duration,
+	/// Where bans and the allow list are persisted. If there is no path, bans are forgotten when the server stops.
+	pub ban_list_path: Option<PathBuf>,
+	/// If we have not sent a client anything for this long, we send it an empty packet so it knows we are still here.
+	pub keepalive_interval: Duration,
+	/// The amount of log records the server keeps around.
+	pub log_capacity: usize,
+	/// Log records below this level are discarded.
+	pub log_level: LogLevel,
+	/// The longest an automatic ban can last.
+	pub max_ban_duration: Duration,
+	/// The amount of clients that can be connected at once. Clients that try to connect afterwards are rejected with
+	/// `DisconnectionReason::ServerFull`.
+	pub max_clients: usize,
+	/// The amount of clients that can be connected from the same IP address at once.
+	pub max_clients_per_ip: usize,
+	/// How often we send NTP requests to each client.
+	pub ntp_sync_interval: Duration,
+	/// Simulated network conditions applied to packets we receive. Only meant for testing.
+	pub receive_conditions: Option<LinkConditions>,
+	/// Simulated network conditions applied to packets we send. Only meant for testing.
+	pub send_conditions: Option<LinkConditions>,
+	/// The amount of simulation ticks per second. Has to match the clients' simulation tick rate.
+	pub simulation_tick_rate: u32,
+	/// The amount of times an address can misbehave within a minute before it is banned.
+	pub strikes_before_ban: u32,
+	/// The amount of times `Server::tick` is expected to be called per second.
+	pub tick_rate: u32,
+	/// How long a client can go without sending us anything before it is disconnected.
+	pub timeout: Duration,
+}
+
+impl Default for ServerConfig {
+	fn default() -> Self {
+		ServerConfig {
+			ban_duration: Duration::from_secs(60),
+			ban_list_path: None,
+			keepalive_interval: Duration::from_secs(1),
+			log_capacity: DEFAULT_LOG_CAPACITY,
+			log_level: LogLevel::Info,
+			max_ban_duration: Duration::from_secs(60 * 60 * 24),
+			max_clients: 64,
+			max_clients_per_ip: 4,
+			ntp_sync_interval: Duration::from_secs(1),
+			receive_conditions: None,
+			send_conditions: None,
+			simulation_tick_rate: 60,
+			strikes_before_ban: 3,
+			tick_rate: 30,
+			timeout: Duration::from_secs(30),
+		}
+	}
+}
+
+impl ServerConfig {
+	/// The amount of time between ticks.
+	pub fn get_tick_duration(&self) -> Duration {
+		Duration::from_secs(1) / self.tick_rate.max(1)
+	}
+
+	/// The amount of time between simulation ticks.
+	pub fn get_simulation_tick_duration(&self) -> Duration {
+		Duration::from_secs(1) / self.simulation_tick_rate.max(1)
+	}
+}
@@ -0,0 +1,101 @@
+use hmac::{ Hmac, Mac, };
+use rand::RngCore;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::encryption::PUBLIC_KEY_SIZE;
+use crate::handshake::Cookie;
+
+/// How long a client has to echo a cookie back to us.
+const COOKIE_LIFETIME: Duration = Duration::from_secs(10);
+
+/// Issues and verifies the cookies used to challenge new clients. Cookies are authenticated using a secret that only
+/// the server knows, so the server does not have to remember which cookies it handed out. This keeps clients that spoof
+/// their address from making the server allocate state.
+pub(crate) struct CookieJar {
+	secret: [u8; 32],
+}
+
+impl std::fmt::Debug for CookieJar {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("CookieJar").finish_non_exhaustive()
+	}
+}
+
+impl Default for CookieJar {
+	fn default() -> Self {
+		let mut secret = [0; 32];
+		rand::thread_rng().fill_bytes(&mut secret);
+		CookieJar {
+			secret,
+		}
+	}
+}
+
+impl CookieJar {
+	/// Create a cookie for a client. `now` is the current unix time in seconds.
+	pub(crate) fn issue(&self, source: SocketAddr, public_key: &[u8; PUBLIC_KEY_SIZE], now: u64) -> Cookie {
+		let expires = now + COOKIE_LIFETIME.as_secs();
+		Cookie {
+			expires,
+			mac: self.mac(source, public_key, expires).finalize().into_bytes().into(),
+		}
+	}
+
+	/// Whether or not a cookie echoed by a client was issued by us for the client's address and public key, and has not
+	/// expired yet.
+	pub(crate) fn verify(&self, cookie: &Cookie, source: SocketAddr, public_key: &[u8; PUBLIC_KEY_SIZE], now: u64)
+		-> bool
+	{
+		if cookie.expires < now || cookie.expires > now + COOKIE_LIFETIME.as_secs() {
+			return false;
+		}
+
+		self.mac(source, public_key, cookie.expires).verify_slice(&cookie.mac).is_ok()
+	}
+
+	fn mac(&self, source: SocketAddr, public_key: &[u8; PUBLIC_KEY_SIZE], expires: u64) -> Hmac<Sha256> {
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+		match source {
+			SocketAddr::V4(address) => mac.update(&address.ip().octets()),
+			SocketAddr::V6(address) => mac.update(&address.ip().octets()),
+		}
+
+		mac.update(&source.port().to_le_bytes());
+		mac.update(public_key);
+		mac.update(&expires.to_le_bytes());
+		mac
+	}
+}
+
+/// Test cookie verification.
+#[cfg(test)]
+mod tests {
+	use std::net::SocketAddr;
+
+	use super::{ CookieJar, COOKIE_LIFETIME, };
+
+	/// Ensure that cookies are only accepted from the address and public key they were issued to, before they expire.
+	#[test]
+	fn verify() {
+		let jar = CookieJar::default();
+		let source: SocketAddr = "[::1]:5000".parse().unwrap();
+		let other: SocketAddr = "[::1]:5001".parse().unwrap();
+		let public_key = [5; 32];
+
+		let cookie = jar.issue(source, &public_key, 1000);
+		assert!(jar.verify(&cookie, source, &public_key, 1000));
+		assert!(jar.verify(&cookie, source, &public_key, 1000 + COOKIE_LIFETIME.as_secs()));
+
+		assert!(!jar.verify(&cookie, other, &public_key, 1000));
+		assert!(!jar.verify(&cookie, source, &[6; 32], 1000));
+		assert!(!jar.verify(&cookie, source, &public_key, 1001 + COOKIE_LIFETIME.as_secs()));
+		assert!(!CookieJar::default().verify(&cookie, source, &public_key, 1000));
+
+		// extending the expiry time invalidates the cookie
+		let mut extended = cookie.clone();
+		extended.expires += 1;
+		assert!(!jar.verify(&extended, source, &public_key, 1001));
+	}
+}
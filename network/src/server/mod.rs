@@ -1,6 +1,13 @@
+pub mod blacklist;
 pub mod client_connection;
+pub mod config;
+pub(crate) mod cookie_jar;
 pub(crate) mod client_table;
 pub mod server;
 
+pub use blacklist::{ AddressRange, Ban, InvalidAddressRange, };
+pub(crate) use blacklist::Blacklist;
 pub use client_connection::ClientConnection;
+pub use config::ServerConfig;
+pub(crate) use cookie_jar::CookieJar;
 pub(crate) use client_table::ClientTable;
\ No newline at end of file
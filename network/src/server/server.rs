@@ -1,60 +1,84 @@
 use rand::Rng;
-use std::net::{ Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket, };
+use std::collections::VecDeque;
+use std::net::{ SocketAddr, ToSocketAddrs, };
 use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH, };
 use streams::{ ReadStream, WriteStream, };
 
+use crate::address::to_ipv6;
+use crate::congestion::{ CongestionController, MAX_PACKETS_PER_TICK, };
+use crate::connection_statistics::ConnectionStatistics;
+use crate::encryption::{ KeyExchange, Role, Session, };
 use crate::error::NetworkStreamError;
-use crate::handshake::{ Handshake, Version, };
-use crate::log::{ Log, LogLevel, };
+use crate::event::{ Channel, ClientId, ServerEvent, };
+use crate::handshake::{ Challenge, Handshake, Rejection, Version, HANDSHAKE_PADDED_SIZE, PROTOCOL_CHECKSUM, };
+use crate::link_conditioner::LinkConditioner;
+use crate::log::{ Log, LogFields, LogLevel, };
 use crate::network_stream::{ NetworkReadStream, NetworkWriteStream, };
-use crate::payload::{ AcknowledgeMask, DisconnectionReason, Packet, SubPayload, };
+use crate::payload::{
+	AcknowledgeMask,
+	DisconnectionReason,
+	Fragmenter,
+	Packet,
+	Priority,
+	Reassembler,
+	SubPayload,
+	MAX_KICK_MESSAGE_LENGTH,
+	MAX_UNRELIABLE_MESSAGE_SIZE,
+};
+use crate::reliable::{ IncomingStream, ReliableChannel, StreamError, StreamId, };
+use crate::replication::{ Interest, ReplicatedWorld, ReplicationError, };
+use crate::replication::sender::SnapshotSender;
+use crate::simulation::{ tick_at, InputBuffer, MAX_CATCH_UP_TICKS, };
+use crate::transport::{ Transport, UdpTransport, };
 use crate::MAX_PACKET_SIZE;
 
 use crate::ntp::{ NtpServer, NtpServerError, };
-use super::{ ClientConnection, ClientTable, };
+use super::{ AddressRange, Ban, Blacklist, ClientConnection, ClientTable, CookieJar, ServerConfig, };
+
+/// The amount of copies of a disconnect packet we send to a client.
+const DISCONNECT_COPIES: usize = 3;
 
 #[derive(Debug)]
 pub enum ServerError {
+	/// Emitted if we could not read or write the ban list. Non-fatal.
+	BanList(std::io::ErrorKind),
 	/// Emitted if we receive data from a blacklisted IP. Non-fatal.
 	Blacklisted(SocketAddr),
 	/// Emitted if we encountered a problem during client connection creation. Non-fatal.
 	ClientCreation,
 	/// Emitted if we could not find a `ClientConnection` associated with a `SocketAddr`
 	CouldNotFindClient,
-	/// Emitted if we could not convert a `SourceAddr` into an `Ipv6Addr` during a receive call. Non-fatal.
-	InvalidIP,
+	/// Emitted if an unreliable message is bigger than `MAX_UNRELIABLE_MESSAGE_SIZE`. Non-fatal.
+	MessageTooBig,
 	/// Emitted if we encountered a problem with network streams.
 	NetworkStreamError(NetworkStreamError),
 	/// Wrapper for an error from the server NTP implementation
 	NtpError(NtpServerError),
 	/// Emitted if a received packet is too big to be an eggine packet. Non-fatal.
 	PacketTooBig(SocketAddr),
-	/// Emitted if we encountered an OS error during a socket operation.
+	/// Emitted if we could not replicate entities to a client. Non-fatal.
+	Replication(ReplicationError),
+	/// Emitted if we encountered an OS error during a transport operation.
 	Socket(std::io::ErrorKind),
+	/// Emitted if we misused a stream, or if a client went over the limits on the streams it can open. Non-fatal.
+	Stream(StreamError),
 }
 
 impl ServerError {
 	/// Identifies whether or not the server needs a restart upon the emission of an error.
 	pub fn is_fatal(&self) -> bool {
 		match self {
+			ServerError::BanList(_) => false,
 			ServerError::Blacklisted(_) => false,
 			ServerError::ClientCreation => false,
 			ServerError::CouldNotFindClient => false,
-			ServerError::InvalidIP => false,
+			ServerError::MessageTooBig => false,
 			ServerError::NetworkStreamError(_) => false,
-			ServerError::NtpError(error) => {
-				if let NtpServerError::Socket(error) = error {
-					match error {
-						tokio::io::ErrorKind::AddrInUse => true,
-						tokio::io::ErrorKind::AddrNotAvailable => true,
-						_ => false,
-					}
-				} else {
-					false
-				}
-			},
+			ServerError::NtpError(error) => error.is_fatal(),
 			ServerError::PacketTooBig(_) => false,
+			ServerError::Replication(_) => false,
 			ServerError::Socket(_) => true,
+			ServerError::Stream(_) => false,
 		}
 	}
 }
@@ -71,6 +95,18 @@ impl From<NtpServerError> for ServerError {
 	}
 }
 
+impl From<ReplicationError> for ServerError {
+	fn from(error: ReplicationError) -> Self {
+		ServerError::Replication(error)
+	}
+}
+
+impl From<StreamError> for ServerError {
+	fn from(error: StreamError) -> Self {
+		ServerError::Stream(error)
+	}
+}
+
 impl From<std::io::Error> for ServerError {
 	fn from(error: std::io::Error) -> Self {
 		ServerError::Socket(error.kind())
@@ -81,54 +117,95 @@ impl From<std::io::Error> for ServerError {
 /// information. The two communicate using a packet format built upon the streams library.
 #[derive(Debug)]
 pub struct Server {
+	/// The address the transport is bound to.
+	address: SocketAddr,
+	/// The addresses we refuse to talk to.
+	blacklist: Blacklist,
 	client_table: ClientTable,
+	/// Limits and timings used by the server.
+	config: ServerConfig,
+	/// Issues the cookies we challenge new clients with.
+	cookie_jar: CookieJar,
+	/// Events game code has not handled yet.
+	events: VecDeque<ServerEvent>,
 	/// Handshake we compare client handshakes against.
 	handshake: Handshake,
+	/// The last time we sent NTP requests to clients.
+	last_ntp_sync: Instant,
+	/// The last simulation tick handed out by `next_simulation_tick`.
+	last_simulation_tick: Option<u64>,
 	log: Log,
+	/// The ID we give to the next client that connects.
+	next_client_id: u64,
 	ntp_server: NtpServer,
 	/// The buffer we write into when we receive data.
 	receive_buffer: [u8; MAX_PACKET_SIZE + 1],
+	/// Holds back received data to simulate network conditions.
+	receive_conditioner: Option<LinkConditioner<(SocketAddr, Vec<u8>)>>,
 	/// The stream we import data into when we receive data.
 	receive_stream: NetworkReadStream,
+	/// Holds back sent data to simulate network conditions.
+	send_conditioner: Option<LinkConditioner<(SocketAddr, Vec<u8>)>>,
 	/// The stream we use to export data so we can sent it to a client.
 	send_stream: NetworkWriteStream,
-	/// The socket the server is being hosted on.
-	socket: UdpSocket,
+	/// Set once `shutdown` is called, after which new clients are rejected.
+	shutting_down: bool,
+	/// The transport the server is being hosted on.
+	transport: Box<dyn Transport>,
+	/// The entities we replicate to clients.
+	world: ReplicatedWorld,
 }
 
 impl Server {
 	/// Initialize the server and listen on the specified address.
 	pub async fn new<T: ToSocketAddrs>(address: T) -> Result<Self, ServerError> {
-		let socket = UdpSocket::bind(address)?;
+		Server::with_config(address, ServerConfig::default()).await
+	}
 
-		socket.set_nonblocking(true)?;
+	/// Initialize the server with the specified limits and timings, and listen on the specified address.
+	pub async fn with_config<T: ToSocketAddrs>(address: T, config: ServerConfig) -> Result<Self, ServerError> {
+		Server::with_transport(Box::new(UdpTransport::bind(address)?), config)
+	}
 
-		let mut ntp_address = socket.local_addr()?;
-		ntp_address.set_port(ntp_address.port() + 1);
+	/// Initialize the server with the specified limits and timings, and host it on the specified transport.
+	pub fn with_transport(transport: Box<dyn Transport>, config: ServerConfig) -> Result<Self, ServerError> {
+		let blacklist = Blacklist::new(&config).map_err(|error| ServerError::BanList(error.kind()))?;
+		let receive_conditioner = config.receive_conditions.clone().map(LinkConditioner::new);
+		let send_conditioner = config.send_conditions.clone().map(LinkConditioner::new);
+		let log = Log::new(config.log_level, config.log_capacity);
 
 		Ok(Server {
+			address: transport.local_addr()?,
+			blacklist,
 			client_table: ClientTable::default(),
+			config,
+			cookie_jar: CookieJar::default(),
+			events: VecDeque::new(),
 			handshake: Handshake {
-				checksum: [0; 16],
+				checksum: PROTOCOL_CHECKSUM,
+				cookie: None,
 				ntp_id: 0,
+				public_key: [0; 32],
 				sequences: (0, 0),
-				version: Version {
-					branch: String::from("master"),
-					major: 0,
-					minor: 0,
-					revision: 0,
-				},
+				version: Version::current(),
 			},
-			log: Log::default(),
-			ntp_server: NtpServer::new(ntp_address, None).await?,
+			last_ntp_sync: Instant::now(),
+			last_simulation_tick: None,
+			log,
+			next_client_id: 0,
+			ntp_server: NtpServer::new(None),
 			// create the receive buffer. if we ever receive a packet that is greater than `MAX_PACKET_SIZE`, then the recv
 			// function call will say that we have read `MAX_PACKET_SIZE + 1` bytes. the extra read byte allows us to check
 			// if a packet is too big to decode, while also allowing us to use all the packet bytes within the range
 			// `0..MAX_PACKET_SIZE`.
 			receive_buffer: [0; MAX_PACKET_SIZE + 1],
+			receive_conditioner,
 			receive_stream: NetworkReadStream::new(),
+			send_conditioner,
 			send_stream: NetworkWriteStream::new(),
-			socket,
+			shutting_down: false,
+			transport,
+			world: ReplicatedWorld::default(),
 		})
 	}
 
@@ -139,30 +216,59 @@ impl Server {
 			// send client outgoing packets
 			// TODO make this a little more efficient, right now this sucks b/c i can't use a &self ref in the for loop source
 			let sources = self.client_table.client_iter().map(|(source, _)| *source).collect::<Vec<SocketAddr>>();
-			let mut reset_sources = Vec::new();
+			let now = Instant::now();
 			for source in sources {
-				let client = self.client_table.get_client_mut(&source)?;
-				if client.outgoing_packet.get_sub_payloads().len() > 0 { // only send packets if we have information to send
-					client.sequence += 1;
+				let mut budget = self.client_table.get_client_mut(&source)?.congestion.get_budget();
+				for packet_index in 0..MAX_PACKETS_PER_TICK {
+					let client = self.client_table.get_client_mut(&source)?;
+
+					// only send packets if we have information to send, or if the client has not heard from us in a while.
+					// queued unreliable data that does not fit into the first packet is sent while the budget allows it
+					let needs_packet = if packet_index == 0 {
+						!client.outgoing_packet.get_sub_payloads().is_empty()
+							|| client.reliable_channel.needs_packet(now)
+							|| client.fragmenter.has_queued()
+							|| client.acknowledge_pending
+							|| now - client.last_send_time >= self.config.keepalive_interval
+					} else {
+						budget > 0 && client.fragmenter.has_queued()
+					};
+
+					if !needs_packet {
+						break;
+					}
+
+					client.sequence = client.sequence.wrapping_add(1);
+					client.reliable_channel.attach(&mut client.outgoing_packet, client.sequence, now);
+					let used = client.fragmenter.fragment(&mut client.outgoing_packet, budget, now)?;
+					budget = budget.saturating_sub(used);
+					client.snapshot_sender.record_sent(client.sequence, client.outgoing_packet.get_sub_payloads());
 					client.outgoing_packet.prepare(
 						client.acknowledge_mask,
 						client.sequence,
 						client.last_sequence_received.unwrap_or(0)
 					);
+					Session::prepare(&mut client.outgoing_packet);
 
 					self.send_stream.encode(&client.outgoing_packet)?;
 
-					let bytes = self.send_stream.export()?;
+					// reset the client's outgoing packet so we can write new information into it
+					client.outgoing_packet.next();
+
+					let mut bytes = self.send_stream.export()?;
+					client.session.seal(&mut bytes)?;
+					client.acknowledge_pending = false;
+					client.last_send_time = now;
+					client.statistics.record_sent(client.sequence, bytes.len(), now);
 					self.send_bytes_to(source, &bytes)?;
-					reset_sources.push(source);
 				}
-			}
 
-			// reset client outgoing packets so we can write new information into them
-			for source in reset_sources {
 				let client = self.client_table.get_client_mut(&source)?;
-				client.outgoing_packet.next();
+				let limited = client.fragmenter.has_queued();
+				client.congestion.update(&client.statistics, limited, now);
 			}
+
+			self.flush_send_conditioner()?;
 		}
 
 		// receive packets
@@ -179,12 +285,15 @@ impl Server {
 			}
 		}
 
+		// forget old strikes and bans
+		self.blacklist.prune(Instant::now());
+
 		// find clients that have lived for too long
 		{
 			let now = Instant::now();
 			let time_out_clients = self.client_table.client_iter()
 				.filter_map(|(_, client)| {
-					if now - client.last_activity > Duration::from_secs(30) {
+					if now - client.last_activity > self.config.timeout {
 						Some(client.address)
 					} else {
 						None
@@ -194,50 +303,52 @@ impl Server {
 
 			// force disconnects on timed out clients
 			for source in time_out_clients {
-				self.log.print(LogLevel::Error, format!("{:?} timed out", source), 0);
-				self.disconnect_client(source, DisconnectionReason::Timeout)?;
+				self.log.print_with(LogLevel::Warning, String::from("timed out"), 0, LogFields::default().peer(source));
+				self.disconnect(source, DisconnectionReason::Timeout)?;
 			}
 		}
 
 		// send NTP packets to all clients
-		for (address, _) in self.client_table.client_iter() {
-			let mut ntp_address = address.clone();
-			ntp_address.set_port(ntp_address.port() + 1);
-
-			if let Err(error) = self.ntp_server.sync_time(Some(ntp_address)).await {
-				if error.is_fatal() {
-					return Err(error.into());
+		let now = Instant::now();
+		if now - self.last_ntp_sync >= self.config.ntp_sync_interval {
+			self.last_ntp_sync = now;
+			for (address, _) in self.client_table.client_iter() {
+				if let Err(error) = self.ntp_server.sync_time(Some(*address)) {
+					if error.is_fatal() {
+						return Err(error.into());
+					}
 				}
 			}
-		}
 
-		// process NTP packets
-		self.ntp_server.process_all().await?;
+			self.send_ntp_packets()?;
+		}
 
 		Ok(())
 	}
 
-	/// Attempt to receive data from the socket.
+	/// Attempt to receive data from the transport.
 	fn recv(&mut self) -> Result<(), ServerError> {
-		let (read_bytes, source) = self.socket.recv_from(&mut self.receive_buffer)?;
+		let (read_bytes, source) = self.receive_from_transport()?;
 
 		// convert the `SocketAddr` into a `Ipv6Addr`. `Ipv6Addr`s do not contain the port the client connected from, the
-		// lack of which is required for the blacklist implementation
-		let address = if let SocketAddr::V6(address) = source {
-			address.ip().clone()
-		} else {
-			return Err(ServerError::InvalidIP);
-		};
+		// lack of which is required for the blacklist implementation. IPv4 addresses are mapped into IPv6 addresses
+		let address = to_ipv6(source.ip());
 
 		// stop blacklisted data from continuing
-		if self.client_table.is_in_blacklist(&address) {
+		if self.blacklist.is_banned(&address, SystemTime::now()) {
 			return Err(ServerError::Blacklisted(source));
 		}
 
-		// make sure what we just read is not too big to be an eggine packet
+		// make sure what we just read is not too big to be an eggine packet. addresses that have not proven they can
+		// receive data are not blacklisted, since the packet may have been sent from a spoofed address
 		if read_bytes > MAX_PACKET_SIZE {
-			self.log.print(LogLevel::Blacklist, format!("received too big of a packet from {:?}", source), 0);
-			self.client_table.add_to_blacklist(address.clone());
+			if self.client_table.has_client(&source) {
+				self.log.print_with(
+					LogLevel::Blacklist, String::from("received too big of a packet"), 0, LogFields::default().peer(source)
+				);
+				self.strike(source)?;
+			}
+
 			return Err(ServerError::PacketTooBig(source));
 		}
 
@@ -248,36 +359,393 @@ impl Server {
 		// now we're done error checking, figure out what to do with the data we just got
 		if self.client_table.has_client(&source) {
 			self.decode_packet(source, buffer)?;
+		} else if read_bytes < HANDSHAKE_PADDED_SIZE {
+			// we only reply to handshakes that are at least as big as our replies, so we cannot be used for amplification
+			return Err(ServerError::ClientCreation);
 		} else {
-			self.initialize_client(source, &address, buffer)?;
+			self.initialize_client(source, buffer)?;
+		}
+
+		Ok(())
+	}
+
+	/// Get the address the server is listening on.
+	pub fn get_local_address(&self) -> SocketAddr {
+		self.address
+	}
+
+	/// Get the limits and timings used by the server.
+	pub fn get_config(&self) -> &ServerConfig {
+		&self.config
+	}
+
+	/// Get the server's log.
+	pub fn get_log(&self) -> &Log {
+		&self.log
+	}
+
+	/// Get the server's log, so its level, capacity and sinks can be changed.
+	pub fn get_log_mut(&mut self) -> &mut Log {
+		&mut self.log
+	}
+
+	/// Get the next event game code has not handled yet. Should be drained after every tick.
+	pub fn poll_event(&mut self) -> Option<ServerEvent> {
+		self.events.pop_front()
+	}
+
+	/// Get the address the specified client is connected from.
+	pub fn get_client_address(&self, client_id: ClientId) -> Option<SocketAddr> {
+		self.client_table.get_address(&client_id).ok()
+	}
+
+	/// Get the amount of unreliable bytes sent to the specified client each tick. Adapts to the packet loss and round-trip
+	/// time of the connection.
+	pub fn get_send_budget(&self, client_id: ClientId) -> Option<usize> {
+		let source = self.client_table.get_address(&client_id).ok()?;
+		self.client_table.get_client(&source).ok().map(|client| client.congestion.get_budget())
+	}
+
+	/// Get the round-trip time, packet loss and bandwidth of the connection to the specified client.
+	pub fn get_statistics(&self, client_id: ClientId) -> Option<&ConnectionStatistics> {
+		let source = self.client_table.get_address(&client_id).ok()?;
+		self.client_table.get_client(&source).ok().map(|client| &client.statistics)
+	}
+
+	/// Get the simulation tick the server's time currently falls into.
+	pub fn get_simulation_tick(&self) -> u64 {
+		tick_at(NtpServer::get_micros(), self.config.get_simulation_tick_duration())
+	}
+
+	/// Get the next simulation tick game code should simulate, or `None` if it is not time for the next tick yet. Should
+	/// be called until it returns `None`. If the server falls too far behind, the ticks it missed are skipped.
+	pub fn next_simulation_tick(&mut self) -> Option<u64> {
+		let current_tick = self.get_simulation_tick();
+		let tick = match self.last_simulation_tick {
+			Some(last_tick) if last_tick >= current_tick => return None,
+			Some(last_tick) => (last_tick + 1).max(current_tick.saturating_sub(MAX_CATCH_UP_TICKS)),
+			None => current_tick,
+		};
+
+		self.last_simulation_tick = Some(tick);
+		Some(tick)
+	}
+
+	/// Take the inputs clients sent for the specified simulation tick, ordered by client ID. Inputs for earlier ticks are
+	/// dropped, and clients are told which of their inputs were simulated.
+	pub fn take_inputs(&mut self, tick: u64) -> Vec<(ClientId, Vec<u8>)> {
+		let mut inputs = Vec::new();
+		for (_, client) in self.client_table.client_iter_mut() {
+			if let Some(input) = client.input_buffer.take(tick) {
+				client.outgoing_packet.add_sub_payload(SubPayload::InputAck(tick));
+				inputs.push((client.id, input));
+			}
+		}
+
+		inputs.sort_by_key(|(client_id, _)| *client_id);
+		inputs
+	}
+
+	/// Get the tick of the last input of the specified client that was simulated.
+	pub fn get_last_processed_input_tick(&self, client_id: ClientId) -> Option<u64> {
+		let source = self.client_table.get_address(&client_id).ok()?;
+		self.client_table.get_client(&source).ok()?.input_buffer.get_last_processed_tick()
+	}
+
+	/// Get the entities we replicate to clients.
+	pub fn get_world(&self) -> &ReplicatedWorld {
+		&self.world
+	}
+
+	/// Get the entities we replicate to clients, so they can be modified.
+	pub fn get_world_mut(&mut self) -> &mut ReplicatedWorld {
+		&mut self.world
+	}
+
+	/// Change which entities are relevant to the specified client. Clients start off seeing every entity that is not in a
+	/// visibility group.
+	pub fn set_interest(&mut self, client_id: ClientId, interest: Interest) -> Result<(), ServerError> {
+		self.get_client_mut(client_id)?.snapshot_sender.set_interest(interest);
+		Ok(())
+	}
+
+	/// Queue a snapshot of the world for every client, containing the changes to the entities relevant to the client
+	/// since the last snapshot the client acknowledged. Should be called after the world is updated for a simulation tick.
+	/// Snapshots that wait for too long to be sent are discarded, since newer snapshots replace them.
+	pub fn replicate(&mut self, tick: u64) -> Result<(), ServerError> {
+		let now = Instant::now();
+		for (_, client) in self.client_table.client_iter_mut() {
+			for chunk in client.snapshot_sender.build(&self.world, tick)? {
+				client.fragmenter.push(SubPayload::Snapshot(chunk), Priority::Low, now)?;
+			}
 		}
 
 		Ok(())
 	}
 
-	// Ping a client at the specified address.
-	pub fn ping(&mut self, source: SocketAddr) -> Result<(), ServerError> {
+	/// Ping the specified client.
+	pub fn ping(&mut self, client_id: ClientId) -> Result<(), ServerError> {
+		let source = self.client_table.get_address(&client_id)?;
 		let client = self.client_table.get_client_mut(&source)?;
 		client.outgoing_packet.add_sub_payload(SubPayload::Ping(
-			SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+			SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
 		));
 
 		Ok(())
 	}
 
+	/// Queue a message to be reliably sent to the specified client. Reliable messages are delivered in order, exactly
+	/// once.
+	pub fn send_reliable(&mut self, client_id: ClientId, message: Vec<u8>) -> Result<(), ServerError> {
+		self.get_client_mut(client_id)?.reliable_channel.send(message);
+		Ok(())
+	}
+
+	/// Queue a message to be sent to the specified client. Unreliable messages are not resent if they are dropped, and
+	/// may arrive out of order. Messages too big to fit into a packet are fragmented, and are lost if any of their
+	/// fragments are dropped.
+	pub fn send_unreliable(&mut self, client_id: ClientId, message: Vec<u8>) -> Result<(), ServerError> {
+		if message.len() > MAX_UNRELIABLE_MESSAGE_SIZE {
+			return Err(ServerError::MessageTooBig);
+		}
+
+		self.get_client_mut(client_id)?.outgoing_packet.add_sub_payload(SubPayload::Unreliable(message));
+		Ok(())
+	}
+
+	/// Queue a message to be sent to the specified client with the specified priority. Low priority messages are sent
+	/// after everything else once the client's send budget runs out, and are discarded if they wait for too long.
+	pub fn send_unreliable_with_priority(&mut self, client_id: ClientId, message: Vec<u8>, priority: Priority)
+		-> Result<(), ServerError>
+	{
+		if message.len() > MAX_UNRELIABLE_MESSAGE_SIZE {
+			return Err(ServerError::MessageTooBig);
+		}
+
+		self.get_client_mut(client_id)?.fragmenter.push(SubPayload::Unreliable(message), priority, Instant::now())?;
+		Ok(())
+	}
+
+	/// Open a named stream on the specified client.
+	pub fn open_stream(&mut self, client_id: ClientId, name: &str) -> Result<StreamId, ServerError> {
+		Ok(self.get_client_mut(client_id)?.reliable_channel.open_stream(name)?)
+	}
+
+	/// Write data into a stream we opened on the specified client. The data is fragmented across as many packets as it
+	/// needs.
+	pub fn write_stream(&mut self, client_id: ClientId, stream_id: StreamId, data: &[u8]) -> Result<(), ServerError> {
+		Ok(self.get_client_mut(client_id)?.reliable_channel.write_stream(stream_id, data)?)
+	}
+
+	/// Tell the specified client that we are done writing into a stream.
+	pub fn finish_stream(&mut self, client_id: ClientId, stream_id: StreamId) -> Result<(), ServerError> {
+		Ok(self.get_client_mut(client_id)?.reliable_channel.finish_stream(stream_id)?)
+	}
+
+	/// Remove a stream the specified client finished writing into, along with its reassembled data.
+	pub fn take_finished_stream(&mut self, client_id: ClientId)
+		-> Result<Option<(StreamId, IncomingStream)>, ServerError>
+	{
+		Ok(self.get_client_mut(client_id)?.reliable_channel.take_finished_stream())
+	}
+
+	/// Kick the specified client. The message is shown to the client, and is truncated to `MAX_KICK_MESSAGE_LENGTH`
+	/// bytes.
+	pub fn kick(&mut self, client_id: ClientId, message: &str) -> Result<(), ServerError> {
+		let mut length = message.len().min(MAX_KICK_MESSAGE_LENGTH);
+		while !message.is_char_boundary(length) {
+			length -= 1;
+		}
+
+		self.disconnect_client(client_id, DisconnectionReason::Kicked(String::from(&message[0..length])))
+	}
+
+	/// Ban the IP address of the specified client, and disconnect the client. Bans without a duration are permanent.
+	pub fn ban(&mut self, client_id: ClientId, duration: Option<Duration>) -> Result<(), ServerError> {
+		let source = self.client_table.get_address(&client_id)?;
+		self.add_ban(AddressRange::single(to_ipv6(source.ip())), duration)
+	}
+
+	/// Ban a range of addresses, and disconnect the clients connected from it. Bans without a duration are permanent.
+	pub fn add_ban(&mut self, range: AddressRange, duration: Option<Duration>) -> Result<(), ServerError> {
+		self.log.print(LogLevel::Blacklist, format!("banned {} for {:?}", range, duration), 0);
+		self.blacklist.ban(range, duration);
+		self.disconnect_range(range, DisconnectionReason::Banned)?;
+		self.save_blacklist()
+	}
+
+	/// Lift a ban. Returns whether or not the range was banned.
+	pub fn remove_ban(&mut self, range: AddressRange) -> Result<bool, ServerError> {
+		let removed = self.blacklist.unban(&range);
+		self.save_blacklist()?;
+		Ok(removed)
+	}
+
+	/// Get the bans that are in effect.
+	pub fn get_bans(&self) -> Vec<(AddressRange, Ban)> {
+		self.blacklist.get_bans()
+	}
+
+	/// Add a range to the allow list. Addresses on the allow list are never banned, even if they are in a banned range.
+	pub fn add_to_allow_list(&mut self, range: AddressRange) -> Result<(), ServerError> {
+		self.blacklist.allow(range);
+		self.save_blacklist()
+	}
+
+	/// Remove a range from the allow list. Returns whether or not the range was on the allow list.
+	pub fn remove_from_allow_list(&mut self, range: AddressRange) -> Result<bool, ServerError> {
+		let removed = self.blacklist.disallow(&range);
+		self.save_blacklist()?;
+		Ok(removed)
+	}
+
+	/// Get the ranges on the allow list.
+	pub fn get_allow_list(&self) -> Vec<AddressRange> {
+		self.blacklist.get_allow_list()
+	}
+
+	/// Disconnect every client with `DisconnectionReason::ServerShutdown`. Clients that try to connect afterwards are
+	/// rejected with the same reason.
+	pub fn shutdown(&mut self) -> Result<(), ServerError> {
+		self.shutting_down = true;
+
+		let sources = self.client_table.client_iter().map(|(source, _)| *source).collect::<Vec<SocketAddr>>();
+		for source in sources {
+			if let Err(error) = self.disconnect(source, DisconnectionReason::ServerShutdown) {
+				if error.is_fatal() {
+					return Err(error);
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Disconnect a client from the server, and tell the client that their connection has been closed.
+	pub fn disconnect_client(&mut self, client_id: ClientId, reason: DisconnectionReason) -> Result<(), ServerError> {
+		let source = self.client_table.get_address(&client_id)?;
+		self.disconnect(source, reason)
+	}
+
+	/// Give the address a strike for misbehaving. If it collected enough strikes, its range is banned and every client
+	/// connected from the range is disconnected.
+	fn strike(&mut self, source: SocketAddr) -> Result<(), ServerError> {
+		if let Some(range) = self.blacklist.strike(to_ipv6(source.ip()), Instant::now()) {
+			self.log.print_with(
+				LogLevel::Blacklist, format!("banned {} after too many strikes", range), 0, LogFields::default().peer(source)
+			);
+			self.disconnect_range(range, DisconnectionReason::Banned)?;
+			self.save_blacklist()?;
+		}
+
+		Ok(())
+	}
+
+	/// Disconnect every client connected from an address in the range.
+	fn disconnect_range(&mut self, range: AddressRange, reason: DisconnectionReason) -> Result<(), ServerError> {
+		let sources = self.client_table.client_iter()
+			.map(|(source, _)| *source)
+			.filter(|source| range.contains(&to_ipv6(source.ip())))
+			.collect::<Vec<SocketAddr>>();
+
+		for source in sources {
+			self.disconnect(source, reason.clone())?;
+		}
+
+		Ok(())
+	}
+
+	/// Write the ban list to disk.
+	fn save_blacklist(&mut self) -> Result<(), ServerError> {
+		if let Err(error) = self.blacklist.save() {
+			self.log.print(LogLevel::Error, format!("could not save ban list for {:?}", error), 0);
+			return Err(ServerError::BanList(error.kind()));
+		}
+
+		Ok(())
+	}
+
+	/// Get the specified client's connection.
+	fn get_client_mut(&mut self, client_id: ClientId) -> Result<&mut ClientConnection, ServerError> {
+		let source = self.client_table.get_address(&client_id)?;
+		self.client_table.get_client_mut(&source)
+	}
+
+	/// Disconnect a client from the server. Removes the `ClientConnection` associated with the source address from the
+	/// server connection state. Tell the client that their connection has been closed.
+	fn disconnect(&mut self, source: SocketAddr, reason: DisconnectionReason) -> Result<(), ServerError> {
+		let client = self.client_table.get_client_mut(&source)?;
+
+		// the disconnect is sent in its own packet, so it cannot be deferred behind anything else we wanted to send
+		client.sequence = client.sequence.wrapping_add(1);
+		let mut packet = Packet::new(client.sequence, 0);
+		packet.add_sub_payload(SubPayload::Disconnect(reason.clone()));
+		packet.prepare(client.acknowledge_mask, client.sequence, client.last_sequence_received.unwrap_or(0));
+		Session::prepare(&mut packet);
+
+		self.send_stream.encode(&packet)?;
+
+		let mut bytes = self.send_stream.export()?;
+		client.session.seal(&mut bytes)?;
+
+		let fields = LogFields::default().client(client.id).peer(source);
+		self.log.print_with(LogLevel::Info, format!("disconnected with reason {:?}", reason), 0, fields);
+		self.events.push_back(ServerEvent::Disconnected(client.id, reason));
+
+		// remove the client to the NTP server whitelist
+		self.ntp_server.disconnect_id(source, client.ntp_id_client);
+
+		// remove the client before we have a chance of erroring out during the send
+		self.client_table.remove_client(&source);
+
+		// the disconnect is not resent, so send a few copies in case some of them are dropped. the client ignores the
+		// copies after the first one it receives
+		for _ in 0..DISCONNECT_COPIES {
+			self.send_bytes_to(source, &bytes)?;
+		}
+
+		Ok(())
+	}
+
 	/// Decode a packet from an already connected IP address.
-	fn decode_packet(&mut self, source: SocketAddr, buffer: Vec<u8>) -> Result<(), ServerError> {
+	fn decode_packet(&mut self, source: SocketAddr, mut buffer: Vec<u8>) -> Result<(), ServerError> {
 		let now = Instant::now();
+		let recv_time = NtpServer::get_micros(); // taken before decoding so NTP timing information stays accurate
 		let client = self.client_table.get_client_mut(&source)?;
-		client.last_activity = now;
+		let read_bytes = buffer.len();
+
+		// drop packets that were not sent by the client
+		if let Err(error) = client.session.open(&mut buffer) {
+			// the client resends its handshake until it receives our reply, so send the reply again in case it was dropped
+			if let Some(bytes) = client.handshake_reply.clone().filter(|_| read_bytes >= HANDSHAKE_PADDED_SIZE) {
+				return self.send_bytes_to(source, &bytes);
+			}
 
-		let last_ping_time = client.last_ping_time;
+			self.log.print_with(
+				LogLevel::Error,
+				format!("could not authenticate packet for {:?}", error),
+				0,
+				LogFields::default().client(client.id).peer(source)
+			);
+			return Err(error.into());
+		}
+
+		client.last_activity = now;
+		client.handshake_reply = None;
 
 		self.receive_stream.import(buffer)?;
-		let packet = match self.receive_stream.decode::<Packet>() {
+		let mut packet = match self.receive_stream.decode::<Packet>() {
 			Ok((packet, _)) => packet,
 			Err(error) => {
-				self.log.print(LogLevel::Error, format!("could not decode packet from {:?} for {:?}", source, error), 0);
+				// the packet was authenticated, so the client itself sent us invalid data
+				self.log.print_with(
+					LogLevel::Error,
+					format!("could not decode packet for {:?}", error),
+					0,
+					LogFields::default().client(client.id).peer(source)
+				);
+				self.disconnect(source, DisconnectionReason::ProtocolError)?;
+				self.strike(source)?;
 				return Err(error.into());
 			},
 		};
@@ -291,132 +759,313 @@ impl Server {
 		client.acknowledge_mask = result.new_acknowledge_mask;
 		client.last_sequence_received = Some(result.remote_sequence);
 		client.highest_acknowledge_received = Some(result.new_highest_acknowledged_sequence);
+		client.statistics.record_received(read_bytes, result.out_of_order, now);
 
-		// handle sub-payloads
-		for sub_payload in packet.get_sub_payloads() {
+		for sequence in result.acknowledged_sequences {
+			client.reliable_channel.handle_acknowledged(sequence);
+			client.snapshot_sender.handle_acknowledged(sequence);
+			client.statistics.record_acknowledged(sequence, now);
+		}
+
+		let fields = LogFields::default().client(client.id).peer(source);
+		for sequence in result.dropped_sequences {
+			self.log.print_with(LogLevel::Trace, String::from("packet dropped"), 0, fields.sequence(sequence));
+			client.reliable_channel.handle_dropped(sequence);
+			client.snapshot_sender.handle_dropped(sequence);
+			client.statistics.record_dropped(sequence);
+			client.congestion.handle_dropped(sequence, client.sequence, now);
+		}
+
+		// handle sub-payloads. reassembled sub-payloads are handled along with the rest of the packet
+		let current_tick = tick_at(NtpServer::get_micros(), self.config.get_simulation_tick_duration());
+		let mut sub_payloads = VecDeque::from(packet.take_sub_payloads());
+
+		// acknowledge packets that carry data quickly, so the client can measure round-trip time and packet loss
+		client.acknowledge_pending |= !sub_payloads.is_empty();
+		while let Some(sub_payload) = sub_payloads.pop_front() {
 			match sub_payload {
 				SubPayload::Disconnect(reason) => {
-					self.log.print(LogLevel::Info, format!("got disconnect from {:?} for reason {:?}", source, reason), 0);
-					self.disconnect_client(source, DisconnectionReason::Requested)?;
+					self.log.print_with(LogLevel::Info, format!("got disconnect for reason {:?}", reason), 0, fields);
+					self.disconnect(source, DisconnectionReason::Requested)?;
 					return Ok(()); // stop processing sub payloads now, the connection is now closed
 				},
+				SubPayload::Fragment(fragment) => match client.reassembler.handle_fragment(fragment, now) {
+					Ok(Some(sub_payload)) => sub_payloads.push_back(sub_payload),
+					Ok(None) => {},
+					Err(error) => {
+						self.log.print_with(
+							LogLevel::Error,
+							format!("could not reassemble for {:?}", error),
+							0,
+							fields.sequence(result.remote_sequence)
+						);
+						self.disconnect(source, DisconnectionReason::ProtocolError)?;
+						self.strike(source)?;
+						return Err(error.into());
+					},
+				},
+				SubPayload::Input(tick, input) => {
+					// clients resend inputs until we acknowledge them, so copies are expected
+					client.input_buffer.insert(tick, input, current_tick);
+				},
+				SubPayload::InputAck(_) => {},
+				SubPayload::Ntp(bytes) => {
+					if let Err(error) = self.ntp_server.process(source, &bytes, recv_time) {
+						self.log.print_with(LogLevel::Warning, format!("could not process NTP packet for {:?}", error), 0, fields);
+					}
+				},
 				SubPayload::Ping(time) => {
-					self.log.print(LogLevel::Info, format!("got ping with time {}", time), 0);
+					self.log.print_with(LogLevel::Trace, format!("got ping with time {}", time), 0, fields);
 
-					// send a pong to the client
-					client.outgoing_packet.add_sub_payload(SubPayload::Pong(
-						SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
-					));
+					// send a pong to the client, echoing the ping's time so it can measure round-trip time
+					client.outgoing_packet.add_sub_payload(SubPayload::Pong(time));
 				},
 				SubPayload::Pong(time) => {
-					self.log.print(
-						LogLevel::Info,
-						format!(
-							"got pong with time {} from {:?}. round-trip duration is {}us",
-							time,
-							source,
-							(now - last_ping_time).as_micros()
-						),
-						0
+					client.statistics.record_pong(time);
+					self.log.print_with(
+						LogLevel::Trace, format!("got pong. round-trip duration is {:?}", client.statistics.get_rtt()), 0, fields
 					);
-				}
+				},
+				SubPayload::Reliable(id, message) => {
+					let result = client.reliable_channel.handle_message(id, &message);
+					while let Some(message) = client.reliable_channel.receive() {
+						self.events.push_back(ServerEvent::Message(client.id, Channel::Reliable, message));
+					}
+
+					// the client tried to make us buffer more stream data than we allow
+					if let Err(error) = result {
+						self.log.print_with(LogLevel::Error, format!("could not handle stream for {:?}", error), 0, fields);
+						self.disconnect(source, DisconnectionReason::ProtocolError)?;
+						self.strike(source)?;
+						return Err(error.into());
+					}
+				},
+				SubPayload::Snapshot(_) => {},
+				SubPayload::Unreliable(message) => {
+					self.events.push_back(ServerEvent::Message(client.id, Channel::Unreliable, message));
+				},
 			}
 		}
 
-		Ok(())
+		// reply to NTP requests right away, since the replies contain the time they were sent at
+		self.send_ntp_packets()
+	}
+
+	/// Tell a client that we will not accept its handshake.
+	fn reject_client(&mut self, source: SocketAddr, reason: DisconnectionReason) -> Result<(), ServerError> {
+		self.send_stream.encode(&Rejection {
+			reason,
+			version: self.handshake.version.clone(),
+		})?;
+
+		let bytes = self.send_stream.export()?;
+		self.send_bytes_to(source, &bytes)?;
+		Err(ServerError::ClientCreation)
 	}
 
 	/// Send a byte vector to the specified client address.
-	fn send_bytes_to(&mut self, source: SocketAddr, bytes: &Vec<u8>) -> Result<(), ServerError> {
-		// TODO check if the amount of bytes sent in the socket matches the size of the exported vector
-		self.socket.send_to(&bytes, source)?;
+	fn send_bytes_to(&mut self, source: SocketAddr, bytes: &[u8]) -> Result<(), ServerError> {
+		// TODO check if the amount of bytes sent in the transport matches the size of the exported vector
+		if let Some(conditioner) = self.send_conditioner.as_mut() {
+			conditioner.push((source, bytes.to_vec()), Instant::now());
+			return Ok(());
+		}
+
+		self.transport.send_to(bytes, source)?;
 		Ok(())
 	}
 
+	/// Send the packets the NTP server queued up. Each NTP packet is sealed in its own packet and sent right away, so
+	/// the time it was sent at is not thrown off by waiting for the next tick.
+	fn send_ntp_packets(&mut self) -> Result<(), ServerError> {
+		while let Some((address, bytes)) = self.ntp_server.poll_packet() {
+			// the client may have disconnected since the packet was queued
+			let Ok(client) = self.client_table.get_client_mut(&address) else {
+				continue;
+			};
+
+			client.sequence = client.sequence.wrapping_add(1);
+			let mut packet = Packet::new(client.sequence, 0);
+			packet.add_sub_payload(SubPayload::Ntp(bytes));
+			packet.prepare(client.acknowledge_mask, client.sequence, client.last_sequence_received.unwrap_or(0));
+			Session::prepare(&mut packet);
+
+			self.send_stream.encode(&packet)?;
+
+			let mut bytes = self.send_stream.export()?;
+			client.session.seal(&mut bytes)?;
+
+			let now = Instant::now();
+			client.last_send_time = now;
+			client.statistics.record_sent(client.sequence, bytes.len(), now);
+			self.send_bytes_to(address, &bytes)?;
+		}
+
+		Ok(())
+	}
+
+	/// Send the data held back by simulated network conditions that is ready to be delivered.
+	fn flush_send_conditioner(&mut self) -> Result<(), ServerError> {
+		if let Some(conditioner) = self.send_conditioner.as_mut() {
+			let now = Instant::now();
+			while let Some((address, bytes)) = conditioner.pop(now) {
+				self.transport.send_to(&bytes, address)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Receive data from the transport into the receive buffer. If we are simulating network conditions, received data is
+	/// held back until it is ready to be delivered.
+	fn receive_from_transport(&mut self) -> Result<(usize, SocketAddr), ServerError> {
+		let Some(conditioner) = self.receive_conditioner.as_mut() else {
+			return Ok(self.transport.recv_from(&mut self.receive_buffer)?);
+		};
+
+		let now = Instant::now();
+		loop {
+			match self.transport.recv_from(&mut self.receive_buffer) {
+				Ok((read_bytes, source)) => conditioner.push((source, self.receive_buffer[0..read_bytes].to_vec()), now),
+				Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => break,
+				Err(error) => return Err(error.into()),
+			}
+		}
+
+		let Some((source, bytes)) = conditioner.pop(now) else {
+			return Err(ServerError::Socket(std::io::ErrorKind::WouldBlock));
+		};
+
+		self.receive_buffer[0..bytes.len()].copy_from_slice(&bytes);
+		Ok((bytes.len(), source))
+	}
+
 	/// Attempt to initialize a connection with a new IP address who just talked to us. Test for a handshake, and make
 	/// sure the handshake is compatible with the server's handshake.
-	fn initialize_client(&mut self, source: SocketAddr, address: &Ipv6Addr, handshake_buffer: Vec<u8>)
-		-> Result<(), ServerError>
-	{
+	fn initialize_client(&mut self, source: SocketAddr, handshake_buffer: Vec<u8>) -> Result<(), ServerError> {
 		self.receive_stream.import(handshake_buffer)?;
 
-		self.log.print(LogLevel::Info, format!("client talking from {:?}", source), 0);
+		let fields = LogFields::default().peer(source);
+		self.log.print_with(LogLevel::Debug, String::from("client talking"), 0, fields);
 
 		// decode handshake
 		let handshake = match self.receive_stream.decode::<Handshake>() {
 			Ok((handshake, _)) => handshake,
 			Err(error) => {
-				self.log.print(LogLevel::Error, format!("could not decode handshake from {:?} for {:?}", source, error), 0);
+				self.log.print_with(LogLevel::Error, format!("could not decode handshake for {:?}", error), 0, fields);
 				return Err(error.into());
 			},
 		};
 
-		// check handshake
+		// make the client prove that it can receive data at the address it is sending from before we allocate anything
+		// for it. we do not blacklist anyone until then, since the address may be spoofed
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+		let has_valid_cookie = handshake.cookie.as_ref()
+			.is_some_and(|cookie| self.cookie_jar.verify(cookie, source, &handshake.public_key, now));
+
+		if !has_valid_cookie {
+			let challenge = Challenge {
+				cookie: self.cookie_jar.issue(source, &handshake.public_key, now),
+			};
+
+			self.send_stream.encode(&challenge)?;
+
+			let bytes = self.send_stream.export()?;
+			self.send_bytes_to(source, &bytes)?;
+			return Ok(());
+		}
+
+		// check handshake. the client proved its address with the cookie, so an incompatible handshake is most likely an
+		// out of date client rather than an attacker
 		if !self.handshake.is_compatible(&handshake) {
-			self.log.print(
-				LogLevel::Blacklist, format!("invalid handshake, theirs: {:?}, ours: {:?}", handshake, self.handshake), 1
+			self.log.print_with(
+				LogLevel::Error, format!("invalid handshake, theirs: {:?}, ours: {:?}", handshake, self.handshake), 1, fields
 			);
-			self.client_table.add_to_blacklist(address.clone());
-			return Err(ServerError::ClientCreation);
+			return self.reject_client(source, DisconnectionReason::VersionMismatch);
+		}
+
+		if self.shutting_down {
+			return self.reject_client(source, DisconnectionReason::ServerShutdown);
 		}
 
 		// figure out if they already joined on this ip/port
 		if self.client_table.has_client(&source) {
-			self.log.print(LogLevel::Error, format!("already connected"), 1);
+			self.log.print_with(LogLevel::Error, String::from("already connected"), 1, fields);
 			return Err(ServerError::ClientCreation);
 		}
 
-		// we're home free, add the client to the client list
-		let sequence = 500;
-		let their_sequence = 1000;
+		if self.client_table.client_iter().len() >= self.config.max_clients {
+			return self.reject_client(source, DisconnectionReason::ServerFull);
+		}
+
+		let clients_from_ip = self.client_table.client_iter()
+			.filter(|(address, _)| address.ip() == source.ip())
+			.count();
+
+		if clients_from_ip >= self.config.max_clients_per_ip {
+			return self.reject_client(source, DisconnectionReason::TooManyConnections);
+		}
+
+		// we're home free, add the client to the client list. sequences start at random numbers so they are harder to
+		// guess
+		let sequence = rand::thread_rng().gen::<u32>();
+		let their_sequence = rand::thread_rng().gen::<u32>();
 
 		let their_ntp_id = rand::thread_rng().gen::<u32>();
 
-		self.log.print(LogLevel::Info, format!("established client connection successfully"), 1);
+		// derive the keys used to encrypt the connection
+		let key_exchange = KeyExchange::new();
+		let public_key = key_exchange.get_public_key();
+		let session = match key_exchange.finish(handshake.public_key, Role::Server) {
+			Ok(session) => session,
+			Err(error) => {
+				self.log.print_with(LogLevel::Error, format!("could not exchange keys for {:?}", error), 1, fields);
+				return Err(ServerError::ClientCreation);
+			},
+		};
+
+		let id = ClientId(self.next_client_id);
+		self.next_client_id += 1;
+
+		self.log.print_with(LogLevel::Info, String::from("established connection successfully"), 1, fields.client(id));
 		self.client_table.add_client(source, ClientConnection {
 			acknowledge_mask: AcknowledgeMask::default(),
+			acknowledge_pending: false,
 			address: source,
+			congestion: CongestionController::default(),
+			fragmenter: Fragmenter::default(),
+			handshake_reply: None,
 			ntp_id_client: their_ntp_id,
 			highest_acknowledge_received: Some(sequence),
+			id,
+			input_buffer: InputBuffer::default(),
 			last_activity: Instant::now(),
-			last_ping_time: Instant::now(),
-			last_sequence_received: None,
+			last_send_time: Instant::now(),
+			last_sequence_received: Some(their_sequence),
 			outgoing_packet: Packet::new(sequence, 0),
+			reassembler: Reassembler::default(),
+			reliable_channel: ReliableChannel::default(),
 			sequence,
+			session,
+			snapshot_sender: SnapshotSender::default(),
+			statistics: ConnectionStatistics::default(),
 		});
 
+		self.events.push_back(ServerEvent::Connected(id));
+
 		// add the client to the NTP server whitelist so they can get accurate times
-		self.ntp_server.associate_host_id(their_ntp_id, handshake.ntp_id);
+		self.ntp_server.associate_host_id(source, their_ntp_id, handshake.ntp_id);
 
 		self.handshake.sequences = (sequence, their_sequence);
 		self.handshake.ntp_id = their_ntp_id; // tell the client to use this ID
+		self.handshake.public_key = public_key;
 
 		// send our handshake to the client
 		self.send_stream.encode(&self.handshake)?;
 
 		let bytes = self.send_stream.export()?;
 		self.send_bytes_to(source, &bytes)?;
-
-		Ok(())
-	}
-
-	/// Disconnect a client from the server. Removes the `ClientConnection` associated with the source address from the
-	/// server connection state. Tell the client that their connection has been closed.
-	fn disconnect_client(&mut self, source: SocketAddr, reason: DisconnectionReason) -> Result<(), ServerError> {
-		let client = self.client_table.get_client_mut(&source)?;
-		client.outgoing_packet.add_sub_payload(SubPayload::Disconnect(reason));
-		self.send_stream.encode(&client.outgoing_packet)?;
-
-		self.log.print(LogLevel::Info, format!("disconnected client with reason {:?}", reason), 0);
-
-		// remove the client to the NTP server whitelist
-		self.ntp_server.disconnect_id(source, client.ntp_id_client);
-
-		// remove the client before we have a chance of erroring out during the send
-		self.client_table.remove_client(&source);
-
-		let bytes = self.send_stream.export()?;
-		self.send_bytes_to(source, &bytes)?;
+		self.client_table.get_client_mut(&source)?.handshake_reply = Some(bytes);
 
 		Ok(())
 	}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The maximum size of an input.
+pub const MAX_INPUT_SIZE: usize = 128;
+
+/// How many ticks ahead of the server a client can send inputs for. Inputs further ahead than this are dropped.
+pub(crate) const MAX_INPUT_LEAD: u64 = 64;
+
+/// The maximum amount of inputs buffered for a single client, on either end of the connection.
+const MAX_BUFFERED_INPUTS: usize = 64;
+
+/// The amount of inputs the client sends in each packet. The newest inputs that were not acknowledged yet are resent
+/// along with each new input, so an input survives a dropped packet as long as one of the next few packets arrives.
+/// Older inputs are not resent, since they are the most likely to arrive too late to be simulated.
+pub(crate) const MAX_INPUTS_PER_PACKET: usize = 4;
+
+/// The maximum amount of ticks the server simulates to catch up after a stall. Ticks past that are skipped.
+pub(crate) const MAX_CATCH_UP_TICKS: u64 = 8;
+
+/// Get the simulation tick that a time falls into. Ticks are counted from the UNIX epoch, so the server and the clients
+/// agree on the current tick as long as their clocks are synchronized.
+pub fn tick_at(time: i128, tick_duration: Duration) -> u64 {
+	(time.max(0) / tick_duration.as_micros().max(1) as i128) as u64
+}
+
+/// Server-side buffer of the inputs a client sent for upcoming ticks.
+#[derive(Debug, Default)]
+pub(crate) struct InputBuffer {
+	inputs: BTreeMap<u64, Vec<u8>>,
+	/// The tick of the last input we took out of the buffer.
+	last_processed_tick: Option<u64>,
+	/// The earliest tick we still accept inputs for.
+	next_tick: u64,
+}
+
+impl InputBuffer {
+	/// Buffer an input for the specified tick. Inputs for ticks that were already simulated, inputs too far ahead of
+	/// `current_tick`, and copies of inputs that are already buffered are dropped. Returns whether the input was buffered.
+	pub(crate) fn insert(&mut self, tick: u64, input: Vec<u8>, current_tick: u64) -> bool {
+		if tick < self.next_tick
+			|| tick > current_tick.saturating_add(MAX_INPUT_LEAD)
+			|| self.inputs.len() >= MAX_BUFFERED_INPUTS
+			|| self.inputs.contains_key(&tick)
+		{
+			return false;
+		}
+
+		self.inputs.insert(tick, input);
+		true
+	}
+
+	/// Take the input for the specified tick. Inputs for earlier ticks arrived too late to be simulated, and are dropped.
+	pub(crate) fn take(&mut self, tick: u64) -> Option<Vec<u8>> {
+		self.inputs = self.inputs.split_off(&tick);
+		self.next_tick = self.next_tick.max(tick.saturating_add(1));
+
+		let input = self.inputs.remove(&tick)?;
+		self.last_processed_tick = Some(tick);
+		Some(input)
+	}
+
+	/// The tick of the last input that was simulated.
+	pub(crate) fn get_last_processed_tick(&self) -> Option<u64> {
+		self.last_processed_tick
+	}
+}
+
+/// Client-side record of the inputs the server has not acknowledged yet.
+#[derive(Debug, Default)]
+pub(crate) struct PendingInputs {
+	inputs: BTreeMap<u64, Vec<u8>>,
+	/// The tick of the last input the server said it simulated.
+	last_processed_tick: Option<u64>,
+}
+
+impl PendingInputs {
+	/// Record an input for the specified tick. Returns false if there already is an input for the tick, or if the server
+	/// already simulated the tick.
+	pub(crate) fn push(&mut self, tick: u64, input: Vec<u8>) -> bool {
+		if self.last_processed_tick.is_some_and(|last| tick <= last) || self.inputs.contains_key(&tick) {
+			return false;
+		}
+
+		self.inputs.insert(tick, input);
+		if self.inputs.len() > MAX_BUFFERED_INPUTS {
+			self.inputs.pop_first();
+		}
+
+		true
+	}
+
+	/// Forget inputs the server simulated, or that are too old for the server to accept.
+	pub(crate) fn acknowledge(&mut self, tick: u64) {
+		if self.last_processed_tick.is_some_and(|last| tick <= last) {
+			return;
+		}
+
+		self.last_processed_tick = Some(tick);
+		self.discard_before(tick.saturating_add(1));
+	}
+
+	/// Forget inputs for ticks before the specified tick. Used to drop inputs that arrived too late on the server.
+	pub(crate) fn discard_before(&mut self, tick: u64) {
+		self.inputs = self.inputs.split_off(&tick);
+	}
+
+	/// The newest inputs, which are sent in the next packet. Inputs older than these are not resent, even if they were
+	/// not acknowledged yet.
+	pub(crate) fn get_newest(&self) -> impl Iterator<Item = (&u64, &Vec<u8>)> {
+		self.inputs.iter().rev().take(MAX_INPUTS_PER_PACKET)
+	}
+
+	/// The tick of the last input the server said it simulated.
+	pub(crate) fn get_last_processed_tick(&self) -> Option<u64> {
+		self.last_processed_tick
+	}
+}
+
+/// Test input buffering.
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+
+	use super::{ tick_at, InputBuffer, PendingInputs, MAX_INPUTS_PER_PACKET, MAX_INPUT_LEAD, };
+
+	/// Ensure that times are converted into the ticks they fall into.
+	#[test]
+	fn ticks() {
+		let tick_duration = Duration::from_micros(16_666);
+		assert_eq!(tick_at(0, tick_duration), 0);
+		assert_eq!(tick_at(16_665, tick_duration), 0);
+		assert_eq!(tick_at(16_666, tick_duration), 1);
+		assert_eq!(tick_at(-1, tick_duration), 0);
+	}
+
+	/// Ensure that the server only accepts inputs for upcoming ticks, and drops inputs that arrive too late.
+	#[test]
+	fn input_buffer() {
+		let mut buffer = InputBuffer::default();
+		assert!(buffer.insert(10, vec![1], 10));
+		assert!(buffer.insert(12, vec![2], 10));
+		assert!(!buffer.insert(12, vec![3], 10));
+		assert!(!buffer.insert(11 + MAX_INPUT_LEAD, vec![4], 10));
+		assert!(buffer.insert(8, vec![5], 10));
+
+		assert_eq!(buffer.take(9), None);
+		assert_eq!(buffer.get_last_processed_tick(), None);
+		assert_eq!(buffer.take(10), Some(vec![1]));
+		assert_eq!(buffer.get_last_processed_tick(), Some(10));
+		assert!(!buffer.insert(10, vec![6], 11));
+		assert!(!buffer.insert(8, vec![7], 11));
+
+		assert_eq!(buffer.take(11), None);
+		assert_eq!(buffer.take(12), Some(vec![2]));
+		assert_eq!(buffer.get_last_processed_tick(), Some(12));
+	}
+
+	/// Ensure that the client resends its newest inputs until the server acknowledges them, and nothing older.
+	#[test]
+	fn pending_inputs() {
+		let mut pending = PendingInputs::default();
+		for tick in 0..10 {
+			assert!(pending.push(tick, vec![tick as u8]));
+		}
+		assert!(!pending.push(5, vec![0]));
+
+		let newest = pending.get_newest().map(|(tick, _)| *tick).collect::<Vec<u64>>();
+		assert_eq!(newest.len(), MAX_INPUTS_PER_PACKET);
+		assert_eq!(newest[0], 9);
+		assert_eq!(newest[MAX_INPUTS_PER_PACKET - 1], 10 - MAX_INPUTS_PER_PACKET as u64);
+
+		pending.acknowledge(8);
+		pending.acknowledge(3);
+		assert_eq!(pending.get_last_processed_tick(), Some(8));
+		assert_eq!(pending.get_newest().map(|(tick, _)| *tick).collect::<Vec<u64>>(), vec![9]);
+		assert!(!pending.push(7, vec![0]));
+	}
+}
@@ -0,0 +1,508 @@
+use std::time::{ Duration, Instant, };
+use streams::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
+use streams::u8_io::{ U8ReadStream, U8WriteStream, };
+
+use crate::client::client::ClientError;
+use crate::error::NetworkStreamError;
+use crate::replication::{ Component, ComponentId, Interpolate, };
+use crate::transport::MemoryNetwork;
+use crate::{ Channel, Client, ClientConfig, ClientEvent, ClientId, Server, ServerConfig, ServerEvent, };
+
+/// How long the harness sleeps between ticks.
+const TICK_DURATION: Duration = Duration::from_millis(5);
+
+/// A replicated component used in tests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct TestPosition(pub(crate) f32);
+
+impl<T> Encode<u8, T, NetworkStreamError> for TestPosition
+where
+	T: WriteStream<u8, NetworkStreamError> + U8WriteStream<NetworkStreamError>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), NetworkStreamError> {
+		stream.write_u32(self.0.to_bits())
+	}
+}
+
+impl<T> Decode<u8, T, NetworkStreamError> for TestPosition
+where
+	T: ReadStream<u8, NetworkStreamError> + U8ReadStream<NetworkStreamError>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), NetworkStreamError> {
+		let (bits, position) = stream.read_u32()?;
+		Ok((TestPosition(f32::from_bits(bits)), position))
+	}
+}
+
+impl Component for TestPosition {
+	const ID: ComponentId = 1;
+}
+
+impl Interpolate for TestPosition {
+	fn interpolate(&self, other: &Self, t: f32) -> Self {
+		TestPosition(self.0 + (other.0 - self.0) * t)
+	}
+}
+
+/// Runs a server and clients that talk to each other over loopback within the same process, so they can be tested
+/// together. Events are collected so tests can inspect them after the fact.
+pub(crate) struct TestHarness {
+	/// The events each client emitted, in the same order as `clients`.
+	pub(crate) client_events: Vec<Vec<ClientEvent>>,
+	pub(crate) clients: Vec<Client>,
+	pub(crate) server: Server,
+	/// The events the server emitted.
+	pub(crate) server_events: Vec<ServerEvent>,
+}
+
+impl TestHarness {
+	/// Start a server, and start connecting `count` clients to it.
+	pub(crate) async fn new(count: usize, server_config: ServerConfig, client_config: ClientConfig) -> Self {
+		let server = Server::with_config("127.0.0.1:0", server_config).await.expect("Could not start server");
+
+		let mut clients = Vec::new();
+		for _ in 0..count {
+			let mut client = Client::with_config("127.0.0.1:0", client_config.clone()).expect("Could not start client");
+			client.initialize_connection(server.get_local_address()).await.expect("Could not connect to server");
+			clients.push(client);
+		}
+
+		TestHarness::from_parts(server, clients)
+	}
+
+	/// Start a server and `count` clients that talk over an in-memory network instead of sockets.
+	pub(crate) async fn in_memory(count: usize, server_config: ServerConfig, client_config: ClientConfig) -> Self {
+		let network = MemoryNetwork::default();
+		let bind = |address: &str| Box::new(network.bind(address.parse().unwrap()).expect("Could not bind transport"));
+		let server = Server::with_transport(bind("10.0.0.1:1000"), server_config).expect("Could not start server");
+
+		let mut clients = Vec::new();
+		for _ in 0..count {
+			let client = Client::with_transport(bind("10.0.1.1:0"), client_config.clone());
+			let mut client = client.expect("Could not start client");
+			client.initialize_connection(server.get_local_address()).await.expect("Could not connect to server");
+			clients.push(client);
+		}
+
+		TestHarness::from_parts(server, clients)
+	}
+
+	fn from_parts(server: Server, clients: Vec<Client>) -> Self {
+		let count = clients.len();
+		TestHarness {
+			client_events: (0..count).map(|_| Vec::new()).collect(),
+			clients,
+			server,
+			server_events: Vec::new(),
+		}
+	}
+
+	/// Tick the server and every client once, and collect their events.
+	pub(crate) async fn tick(&mut self) {
+		if let Err(error) = self.server.tick().await {
+			assert!(!error.is_fatal(), "{:?}", error);
+		}
+
+		while let Some(event) = self.server.poll_event() {
+			self.server_events.push(event);
+		}
+
+		for (client, events) in self.clients.iter_mut().zip(self.client_events.iter_mut()) {
+			match client.tick().await {
+				Ok(()) | Err(ClientError::Disconnected(_)) => {},
+				Err(error) => assert!(!error.is_fatal(), "{:?}", error),
+			}
+
+			while let Some(event) = client.poll_event() {
+				events.push(event);
+			}
+		}
+	}
+
+	/// Tick until `condition` is met. Panics if it is not met within `timeout`.
+	pub(crate) async fn run_until(&mut self, timeout: Duration, condition: impl Fn(&TestHarness) -> bool) {
+		let start = Instant::now();
+		while !condition(self) {
+			assert!(Instant::now() - start < timeout, "Condition was not met within {:?}", timeout);
+			self.tick().await;
+			tokio::time::sleep(TICK_DURATION).await;
+		}
+	}
+
+	/// Tick for the specified amount of time.
+	pub(crate) async fn run_for(&mut self, duration: Duration) {
+		let start = Instant::now();
+		while Instant::now() - start < duration {
+			self.tick().await;
+			tokio::time::sleep(TICK_DURATION).await;
+		}
+	}
+
+	/// Wait for every client to connect, and return their IDs in the same order as `clients`.
+	pub(crate) async fn connect_all(&mut self, timeout: Duration) -> Vec<ClientId> {
+		self.run_until(timeout, |harness| {
+			harness.client_events.iter().all(|events| events.contains(&ClientEvent::Connected))
+		}).await;
+
+		self.clients.iter()
+			.map(|client| {
+				let address = client.get_local_address().expect("Could not get client address");
+				self.get_client_ids().into_iter()
+					.find(|id| self.server.get_client_address(*id) == Some(address))
+					.expect("Could not find client on server")
+			})
+			.collect()
+	}
+
+	/// The IDs of the clients the server saw connect.
+	pub(crate) fn get_client_ids(&self) -> Vec<ClientId> {
+		self.server_events.iter()
+			.filter_map(|event| match event {
+				ServerEvent::Connected(id) => Some(*id),
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// The messages the server received from the specified client over the specified channel.
+	pub(crate) fn get_server_messages(&self, client_id: ClientId, channel: Channel) -> Vec<&Vec<u8>> {
+		self.server_events.iter()
+			.filter_map(|event| match event {
+				ServerEvent::Message(id, message_channel, message) if *id == client_id && *message_channel == channel => {
+					Some(message)
+				},
+				_ => None,
+			})
+			.collect()
+	}
+
+	/// The messages the specified client received over the specified channel.
+	pub(crate) fn get_client_messages(&self, index: usize, channel: Channel) -> Vec<&Vec<u8>> {
+		self.client_events[index].iter()
+			.filter_map(|event| match event {
+				ClientEvent::Message(message_channel, message) if *message_channel == channel => Some(message),
+				_ => None,
+			})
+			.collect()
+	}
+}
+
+/// Test the server and clients together.
+#[cfg(test)]
+mod tests {
+	use std::collections::HashSet;
+	use std::time::{ Duration, Instant, };
+
+	use streams::WriteStream;
+
+	use crate::client::client::ClientError;
+	use crate::handshake::{ Rejection, Version, };
+	use crate::network_stream::NetworkWriteStream;
+	use crate::payload::DisconnectionReason;
+	use crate::transport::{ MemoryNetwork, Transport, };
+	use crate::{
+		Channel,
+		Client,
+		ClientConfig,
+		ClientEvent,
+		ClientId,
+		Interest,
+		LinkConditions,
+		Server,
+		ServerConfig,
+		VisibilityGroup,
+	};
+	use crate::MAX_PACKET_SIZE;
+
+	use super::{ TestHarness, TestPosition, };
+
+	// conditions of a bad connection
+	fn bad_conditions(seed: u64) -> LinkConditions {
+		LinkConditions {
+			duplication: 0.1,
+			jitter: Duration::from_millis(10),
+			latency: Duration::from_millis(20),
+			loss: 0.1,
+			reordering: 0.1,
+			seed,
+		}
+	}
+
+	/// Ensure that many clients can connect to the same server.
+	#[tokio::test]
+	async fn connect() {
+		let mut harness = TestHarness::new(4, ServerConfig::default(), ClientConfig::default()).await;
+		let client_ids = harness.connect_all(Duration::from_secs(5)).await;
+
+		assert_eq!(harness.get_client_ids().len(), 4);
+		assert_eq!(client_ids.iter().collect::<HashSet<&ClientId>>().len(), 4);
+	}
+
+	/// Ensure that the server and clients can talk over an in-memory network.
+	#[tokio::test]
+	async fn in_memory() {
+		let mut harness = TestHarness::in_memory(2, ServerConfig::default(), ClientConfig::default()).await;
+		let client_ids = harness.connect_all(Duration::from_secs(5)).await;
+
+		for (index, client_id) in client_ids.iter().enumerate() {
+			harness.clients[index].send_reliable(vec![1, 2, 3]);
+			harness.server.send_reliable(*client_id, vec![4, 5, 6]).expect("Could not send message");
+		}
+
+		harness.run_until(Duration::from_secs(5), |harness| {
+			client_ids.iter().enumerate().all(|(index, client_id)| {
+				harness.get_server_messages(*client_id, Channel::Reliable) == vec![&vec![1, 2, 3]]
+					&& harness.get_client_messages(index, Channel::Reliable) == vec![&vec![4, 5, 6]]
+			})
+		}).await;
+	}
+
+	/// Ensure that reliable messages are delivered in order, exactly once, over bad connections.
+	#[tokio::test]
+	async fn reliable_delivery() {
+		let server_config = ServerConfig {
+			receive_conditions: Some(bad_conditions(1)),
+			send_conditions: Some(bad_conditions(2)),
+			..ServerConfig::default()
+		};
+
+		let client_config = ClientConfig {
+			send_conditions: Some(bad_conditions(3)),
+			..ClientConfig::default()
+		};
+
+		let mut harness = TestHarness::new(3, server_config, client_config).await;
+		let client_ids = harness.connect_all(Duration::from_secs(10)).await;
+
+		let expected = (0..30u8).map(|number| vec![number; number as usize * 50]).collect::<Vec<Vec<u8>>>();
+		for (index, client_id) in client_ids.iter().enumerate() {
+			for message in expected.iter() {
+				harness.clients[index].send_reliable(message.clone());
+				harness.server.send_reliable(*client_id, message.clone()).expect("Could not send message");
+			}
+		}
+
+		harness.run_until(Duration::from_secs(20), |harness| {
+			client_ids.iter().enumerate().all(|(index, client_id)| {
+				harness.get_server_messages(*client_id, Channel::Reliable).len() >= expected.len()
+					&& harness.get_client_messages(index, Channel::Reliable).len() >= expected.len()
+			})
+		}).await;
+
+		// give duplicated packets time to arrive
+		harness.run_for(Duration::from_millis(200)).await;
+
+		let expected = expected.iter().collect::<Vec<&Vec<u8>>>();
+		for (index, client_id) in client_ids.iter().enumerate() {
+			assert_eq!(harness.get_server_messages(*client_id, Channel::Reliable), expected);
+			assert_eq!(harness.get_client_messages(index, Channel::Reliable), expected);
+		}
+	}
+
+	/// Ensure that acknowledgements account for dropped and reordered packets.
+	#[tokio::test]
+	async fn acknowledgements() {
+		let server_config = ServerConfig {
+			send_conditions: Some(bad_conditions(4)),
+			..ServerConfig::default()
+		};
+
+		let mut harness = TestHarness::new(1, server_config, ClientConfig::default()).await;
+		let client_id = harness.connect_all(Duration::from_secs(10)).await[0];
+
+		for _ in 0..200 {
+			harness.server.send_unreliable(client_id, vec![0; 100]).expect("Could not send message");
+			harness.tick().await;
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+
+		harness.run_for(Duration::from_millis(200)).await;
+
+		let received = harness.get_client_messages(0, Channel::Unreliable).len();
+		assert!(received > 150 && received < 200);
+
+		// packets that are reordered may be acknowledged after they are assumed to be dropped
+		let packet_loss = harness.server.get_statistics(client_id).expect("Could not get statistics").get_packet_loss();
+		assert!(packet_loss > 0.05 && packet_loss < 0.3, "{}", packet_loss);
+		assert!(harness.clients[0].get_statistics().get_out_of_order_count() > 0);
+	}
+
+	/// Ensure that inputs are simulated on the tick they were sent for, even if some of the packets carrying them are
+	/// dropped.
+	#[tokio::test]
+	async fn inputs() {
+		let client_config = ClientConfig {
+			ntp_sync_interval: Duration::from_millis(50),
+			send_conditions: Some(bad_conditions(5)),
+			..ClientConfig::default()
+		};
+
+		let mut harness = TestHarness::new(1, ServerConfig::default(), client_config).await;
+		let client_id = harness.connect_all(Duration::from_secs(10)).await[0];
+		harness.run_until(Duration::from_secs(5), |harness| harness.clients[0].get_simulation_tick().is_some()).await;
+
+		let mut sent = HashSet::new();
+		let mut simulated = Vec::new();
+		for _ in 0..200 {
+			let tick = harness.clients[0].get_input_tick().expect("Could not get input tick");
+			if sent.insert(tick) {
+				harness.clients[0].send_input(tick, tick.to_le_bytes().to_vec()).expect("Could not send input");
+			}
+
+			while let Some(tick) = harness.server.next_simulation_tick() {
+				for (id, input) in harness.server.take_inputs(tick) {
+					assert_eq!(id, client_id);
+					assert_eq!(input, tick.to_le_bytes());
+					simulated.push(tick);
+				}
+			}
+
+			harness.tick().await;
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+
+		// the clock may still be settling at first, so some inputs arrive late
+		assert!(simulated.len() > sent.len() / 2, "{} of {}", simulated.len(), sent.len());
+		assert!(simulated.windows(2).all(|ticks| ticks[0] < ticks[1]));
+		assert_eq!(harness.server.get_last_processed_input_tick(client_id), simulated.last().copied());
+		assert!(harness.clients[0].get_last_processed_input_tick().is_some());
+	}
+
+	/// Ensure that clients receive the entities relevant to them, and that updates keep arriving over bad connections.
+	#[tokio::test]
+	async fn replication() {
+		let server_config = ServerConfig {
+			send_conditions: Some(bad_conditions(6)),
+			..ServerConfig::default()
+		};
+
+		let mut harness = TestHarness::new(2, server_config, ClientConfig::default()).await;
+		let client_ids = harness.connect_all(Duration::from_secs(10)).await;
+		harness.server.set_interest(client_ids[1], Interest {
+			groups: HashSet::from([VisibilityGroup(1)]),
+			..Interest::default()
+		}).expect("Could not set interest");
+
+		let world = harness.server.get_world_mut();
+		let moving = world.spawn();
+		let hidden = world.spawn();
+		world.set_group(hidden, Some(VisibilityGroup(1))).expect("Could not set group");
+		world.set_component(hidden, &TestPosition(-1.0)).expect("Could not set component");
+
+		// the entity stops moving halfway through, so the last position has time to arrive
+		for tick in 0..200 {
+			let world = harness.server.get_world_mut();
+			world.set_component(moving, &TestPosition(tick.min(99) as f32)).expect("Could not set component");
+			harness.server.replicate(tick).expect("Could not replicate");
+
+			harness.tick().await;
+			tokio::time::sleep(Duration::from_millis(5)).await;
+		}
+
+		for client in harness.clients.iter() {
+			assert_eq!(client.get_component(moving), Some(TestPosition(99.0)));
+		}
+
+		assert_eq!(harness.clients[0].get_entities(), vec![moving]);
+		assert_eq!(harness.clients[1].get_entities(), vec![moving, hidden]);
+		assert_eq!(harness.clients[1].get_component(hidden), Some(TestPosition(-1.0)));
+	}
+
+	/// Ensure that oversized datagrams from addresses that have not proven themselves do not get the address banned,
+	/// since the address may be spoofed.
+	#[tokio::test]
+	async fn oversized_datagrams() {
+		let network = MemoryNetwork::default();
+		let transport = network.bind("10.0.0.1:1000".parse().unwrap()).expect("Could not bind transport");
+		let server = Server::with_transport(Box::new(transport), ServerConfig::default());
+		let mut server = server.expect("Could not start server");
+
+		let attacker = network.bind("10.0.2.1:1000".parse().unwrap()).expect("Could not bind transport");
+		for _ in 0..10 {
+			attacker.send_to(&[0; MAX_PACKET_SIZE + 1], server.get_local_address()).expect("Could not send datagram");
+		}
+
+		server.tick().await.expect("Could not tick server");
+		assert!(server.get_bans().is_empty());
+	}
+
+	/// Ensure that connecting to an address that never answers times out.
+	#[tokio::test]
+	async fn handshake_timeout() {
+		let network = MemoryNetwork::default();
+		let transport = network.bind("10.0.1.1:1000".parse().unwrap()).expect("Could not bind transport");
+		let config = ClientConfig {
+			timeout: Duration::from_millis(500),
+			..Default::default()
+		};
+
+		let mut client = Client::with_transport(Box::new(transport), config).expect("Could not start client");
+		client.initialize_connection("10.0.0.1:1000").await.expect("Could not connect to server");
+
+		let start = Instant::now();
+		let error = loop {
+			assert!(start.elapsed() < Duration::from_secs(5), "Handshake did not time out");
+			if let Err(error) = client.tick().await {
+				break error;
+			}
+
+			tokio::time::sleep(Duration::from_millis(20)).await;
+		};
+
+		assert!(start.elapsed() >= Duration::from_millis(500));
+		assert!(matches!(error, ClientError::Disconnected(DisconnectionReason::Timeout)));
+		assert_eq!(client.poll_event(), Some(ClientEvent::Disconnected(DisconnectionReason::Timeout)));
+		assert!(!client.is_connection_valid());
+	}
+
+	/// Ensure that rejections sent before the client answered a challenge are ignored, since the server only rejects
+	/// handshakes that carry its cookie.
+	#[tokio::test]
+	async fn spoofed_rejection() {
+		let network = MemoryNetwork::default();
+		let transport = network.bind("10.0.1.1:1000".parse().unwrap()).expect("Could not bind transport");
+		let attacker = network.bind("10.0.0.1:1000".parse().unwrap()).expect("Could not bind transport");
+		let config = ClientConfig {
+			timeout: Duration::from_millis(500),
+			..Default::default()
+		};
+
+		let mut client = Client::with_transport(Box::new(transport), config).expect("Could not start client");
+		client.initialize_connection("10.0.0.1:1000").await.expect("Could not connect to server");
+
+		let mut stream = NetworkWriteStream::new();
+		stream.encode(&Rejection {
+			reason: DisconnectionReason::ServerFull,
+			version: Version::current(),
+		}).expect("Could not encode rejection");
+
+		let bytes = stream.export().expect("Could not export stream");
+		attacker.send_to(&bytes, "10.0.1.1:1000".parse().unwrap()).expect("Could not send rejection");
+		client.tick().await.expect("Rejection was not ignored");
+		assert_eq!(client.poll_event(), None);
+		assert!(client.get_server_version().is_none());
+	}
+
+	/// Ensure that the client keeps trying to connect after the server rejects it, and reports why once its handshake
+	/// times out.
+	#[tokio::test]
+	async fn rejection() {
+		let server_config = ServerConfig {
+			max_clients: 0,
+			..Default::default()
+		};
+
+		let client_config = ClientConfig {
+			timeout: Duration::from_millis(500),
+			..Default::default()
+		};
+
+		let mut harness = TestHarness::in_memory(1, server_config, client_config).await;
+		harness.run_until(Duration::from_secs(5), |harness| harness.clients[0].get_server_version().is_some()).await;
+		assert!(harness.client_events[0].is_empty());
+
+		let disconnected = ClientEvent::Disconnected(DisconnectionReason::ServerFull);
+		harness.run_until(Duration::from_secs(5), |harness| harness.client_events[0].contains(&disconnected)).await;
+		assert_eq!(harness.client_events[0], vec![disconnected]);
+	}
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{ Arc, Mutex, };
+use std::sync::mpsc::{ self, Receiver, Sender, TryRecvError, };
+
+use super::Transport;
+
+/// The first port handed out to transports bound to port 0, the start of the dynamic port range.
+const FIRST_DYNAMIC_PORT: u16 = 49152;
+
+/// A datagram along with the address it was sent from.
+type Datagram = (SocketAddr, Vec<u8>);
+
+/// Connects `MemoryTransport`s within the same process. Datagrams are delivered instantly and in order, and datagrams
+/// sent to addresses that no transport is bound to are dropped, like they would be over UDP. Cloning the network gives
+/// another handle to the same network.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryNetwork {
+	/// Where datagrams sent to each bound address go.
+	hosts: Arc<Mutex<HashMap<SocketAddr, Sender<Datagram>>>>,
+}
+
+impl MemoryNetwork {
+	/// Bind a transport to the specified address. If the port is 0, the transport is bound to the lowest unused port in
+	/// the dynamic port range instead.
+	pub fn bind(&self, mut address: SocketAddr) -> Result<MemoryTransport, std::io::Error> {
+		let mut hosts = self.hosts.lock().unwrap();
+		if address.port() == 0 {
+			let port = (FIRST_DYNAMIC_PORT..=u16::MAX)
+				.find(|port| !hosts.contains_key(&SocketAddr::new(address.ip(), *port)))
+				.ok_or(std::io::Error::from(std::io::ErrorKind::AddrInUse))?;
+
+			address.set_port(port);
+		} else if hosts.contains_key(&address) {
+			return Err(std::io::ErrorKind::AddrInUse.into());
+		}
+
+		let (sender, receiver) = mpsc::channel();
+		hosts.insert(address, sender);
+
+		Ok(MemoryTransport {
+			local_address: address,
+			network: self.clone(),
+			receiver,
+		})
+	}
+}
+
+/// Transport over a `MemoryNetwork`. Unbinds its address when dropped.
+#[derive(Debug)]
+pub struct MemoryTransport {
+	/// The address the transport is bound to.
+	local_address: SocketAddr,
+	network: MemoryNetwork,
+	/// Receives the datagrams sent to our address.
+	receiver: Receiver<Datagram>,
+}
+
+impl Transport for MemoryTransport {
+	fn send_to(&self, bytes: &[u8], address: SocketAddr) -> Result<usize, std::io::Error> {
+		let hosts = self.network.hosts.lock().unwrap();
+		if let Some(sender) = hosts.get(&address) {
+			// the receiving transport may be in the middle of being dropped, in which case the datagram is lost
+			let _ = sender.send((self.local_address, bytes.to_vec()));
+		}
+
+		Ok(bytes.len())
+	}
+
+	fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), std::io::Error> {
+		let (source, bytes) = match self.receiver.try_recv() {
+			Ok(datagram) => datagram,
+			Err(TryRecvError::Empty) => return Err(std::io::ErrorKind::WouldBlock.into()),
+			Err(TryRecvError::Disconnected) => return Err(std::io::ErrorKind::NotConnected.into()),
+		};
+
+		// datagrams that do not fit into the buffer are truncated, like they are over UDP
+		let read_bytes = bytes.len().min(buffer.len());
+		buffer[0..read_bytes].copy_from_slice(&bytes[0..read_bytes]);
+		Ok((read_bytes, source))
+	}
+
+	fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+		Ok(self.local_address)
+	}
+}
+
+impl Drop for MemoryTransport {
+	fn drop(&mut self) {
+		self.network.hosts.lock().unwrap().remove(&self.local_address);
+	}
+}
+
+/// Test the in-memory transport.
+#[cfg(test)]
+mod tests {
+	use std::net::SocketAddr;
+
+	use crate::transport::Transport;
+
+	use super::MemoryNetwork;
+
+	/// Ensure that datagrams are delivered between transports, along with the address they were sent from.
+	#[test]
+	fn delivery() {
+		let network = MemoryNetwork::default();
+		let first = network.bind("10.0.0.1:1000".parse().unwrap()).expect("Could not bind transport");
+		let second = network.bind("10.0.0.2:0".parse().unwrap()).expect("Could not bind transport");
+		let second_address = second.local_addr().unwrap();
+		assert_ne!(second_address.port(), 0);
+
+		let mut buffer = [0; 3];
+		assert_eq!(first.recv_from(&mut buffer).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+
+		first.send_to(&[1, 2, 3], second_address).expect("Could not send datagram");
+		first.send_to(&[4, 5, 6, 7], second_address).expect("Could not send datagram");
+		first.send_to(&[8], "10.0.0.3:1000".parse().unwrap()).expect("Could not send datagram");
+
+		assert_eq!(second.recv_from(&mut buffer).unwrap(), (3, first.local_addr().unwrap()));
+		assert_eq!(buffer, [1, 2, 3]);
+		assert_eq!(second.recv_from(&mut buffer).unwrap(), (3, first.local_addr().unwrap()));
+		assert_eq!(buffer, [4, 5, 6]);
+		assert_eq!(second.recv_from(&mut buffer).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+	}
+
+	/// Ensure that addresses cannot be bound twice, and are unbound once their transport is dropped.
+	#[test]
+	fn binding() {
+		let network = MemoryNetwork::default();
+		let address: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+		let transport = network.bind(address).expect("Could not bind transport");
+		assert_eq!(network.bind(address).unwrap_err().kind(), std::io::ErrorKind::AddrInUse);
+
+		drop(transport);
+		network.bind(address).expect("Could not bind transport");
+	}
+}
@@ -0,0 +1,25 @@
+pub mod memory;
+pub mod udp;
+
+pub use memory::MemoryNetwork;
+pub use memory::MemoryTransport;
+pub use udp::UdpTransport;
+
+use std::net::SocketAddr;
+
+/// Moves datagrams between the server and its clients. Implemented over UDP sockets for real connections, and over
+/// in-memory channels for listen servers and tests that run the server and clients in the same process.
+///
+/// Addresses passed to and returned from a transport are normalized, see `address::normalize_address`.
+pub trait Transport: std::fmt::Debug + Send {
+	/// Send a datagram to the specified address. Returns the amount of bytes sent.
+	fn send_to(&self, bytes: &[u8], address: SocketAddr) -> Result<usize, std::io::Error>;
+
+	/// Receive the next datagram into the buffer, truncating it if it does not fit. Returns the amount of bytes read and
+	/// the address the datagram was sent from. Does not block, emits `std::io::ErrorKind::WouldBlock` if there is nothing
+	/// to receive.
+	fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), std::io::Error>;
+
+	/// Get the address the transport is bound to.
+	fn local_addr(&self) -> Result<SocketAddr, std::io::Error>;
+}
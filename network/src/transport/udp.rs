@@ -0,0 +1,41 @@
+use std::net::{ SocketAddr, ToSocketAddrs, UdpSocket, };
+
+use crate::address::{ bind_udp, normalize_address, to_socket_family, };
+
+use super::Transport;
+
+/// Transport over a non-blocking UDP socket.
+#[derive(Debug)]
+pub struct UdpTransport {
+	/// The address the socket is bound to.
+	local_address: SocketAddr,
+	socket: UdpSocket,
+}
+
+impl UdpTransport {
+	/// Bind a UDP socket to the first address that works. IPv6 addresses are bound as dual-stack sockets.
+	pub fn bind<T: ToSocketAddrs>(address: T) -> Result<Self, std::io::Error> {
+		let socket = bind_udp(address)?;
+		socket.set_nonblocking(true)?;
+
+		Ok(UdpTransport {
+			local_address: socket.local_addr()?,
+			socket,
+		})
+	}
+}
+
+impl Transport for UdpTransport {
+	fn send_to(&self, bytes: &[u8], address: SocketAddr) -> Result<usize, std::io::Error> {
+		self.socket.send_to(bytes, to_socket_family(address, &self.local_address))
+	}
+
+	fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddr), std::io::Error> {
+		let (read_bytes, source) = self.socket.recv_from(buffer)?;
+		Ok((read_bytes, normalize_address(source)))
+	}
+
+	fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+		Ok(self.local_address)
+	}
+}
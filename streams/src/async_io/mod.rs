@@ -0,0 +1,254 @@
+// the async traits are only ever used with concrete streams, so the futures they return do not need `Send` bounds
+#![allow(async_fn_in_trait)]
+
+pub mod reading;
+pub mod writing;
+
+pub use reading::AsyncU8ReadStream;
+pub use reading::AsyncU8ReadStringStream;
+pub use reading::TokioReadStream;
+pub use writing::AsyncU8WriteStream;
+pub use writing::TokioWriteStream;
+
+use crate::StreamPosition;
+
+/// Asynchronously decode an object. Returns the deserialized object along with a pointer describing how many encoded
+/// primitives were read. For more information on how `Error` is meant to be used, see `ReadStream`.
+pub trait AsyncDecode<Encoding, Stream, Error>: Sized {
+	async fn decode(stream: &mut Stream) -> Result<(Self, StreamPosition), Error>;
+}
+
+/// Asynchronously encode an object. Object can mutate itself. For more information on how `Error` is meant to be used,
+/// see `ReadStream`.
+pub trait AsyncEncodeMut<Encoding, Stream, Error> {
+	async fn encode_mut(&mut self, stream: &mut Stream) -> Result<(), Error>;
+}
+
+/// Asynchronously encode an object. For more information on how `Error` is meant to be used, see `ReadStream`.
+pub trait AsyncEncode<Encoding, Stream, Error> {
+	async fn encode(&self, stream: &mut Stream) -> Result<(), Error>;
+}
+
+/// Asynchronous version of `ReadStream`. Instead of importing data in discrete chunks, the stream reads from a source
+/// that produces data over time, and decoding waits on the source whenever it runs out of data.
+pub trait AsyncReadStream<Encoding, Error>: Sized {
+	/// Use the `AsyncDecode` trait to decode an object out of the stream.
+	async fn decode<T: AsyncDecode<Encoding, Self, Error>>(&mut self) -> Result<(T, StreamPosition), Error>;
+}
+
+/// Asynchronous version of `WriteStream`. Instead of exporting data in discrete chunks, encoded data is written into a
+/// sink as it is encoded.
+pub trait AsyncWriteStream<Encoding, Error>: Sized {
+	/// Use the `AsyncEncodeMut` trait to encode an object into the stream.
+	async fn encode_mut<T: AsyncEncodeMut<Encoding, Self, Error>>(&mut self, object: &mut T) -> Result<(), Error>;
+
+	/// Use the `AsyncEncode` trait to encode an object into the stream.
+	async fn encode<T: AsyncEncode<Encoding, Self, Error>>(&mut self, object: &T) -> Result<(), Error>;
+
+	/// Wait until all encoded data has been written into the sink.
+	async fn flush(&mut self) -> Result<(), Error>;
+}
+
+/// Test decoding objects out of an asynchronous source that produces data over time.
+#[cfg(test)]
+mod tests {
+	use crate::{ ReadStream, StreamPosition, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStringStream, U8WriteStream, };
+
+	use super::{
+		AsyncDecode,
+		AsyncEncode,
+		AsyncReadStream,
+		AsyncU8ReadStream,
+		AsyncU8ReadStringStream,
+		AsyncU8WriteStream,
+		AsyncWriteStream,
+		TokioReadStream,
+		TokioWriteStream,
+	};
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io(std::io::ErrorKind),
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(error: std::io::Error) -> Self {
+			TestStreamError::Io(error.kind())
+		}
+	}
+
+	#[derive(Debug, Eq, PartialEq)]
+	struct NestedTestObject {
+		signed_long: i64,
+		variable_length: u64,
+	}
+
+	impl<T> AsyncEncode<u8, T, TestStreamError> for NestedTestObject
+	where
+		T: AsyncWriteStream<u8, TestStreamError> + AsyncU8WriteStream<TestStreamError>
+	{
+		async fn encode(&self, stream: &mut T) -> Result<(), TestStreamError> {
+			stream.write_u64(self.signed_long as u64).await?;
+			stream.write_vlq(self.variable_length).await
+		}
+	}
+
+	impl<T> AsyncDecode<u8, T, TestStreamError> for NestedTestObject
+	where
+		T: AsyncReadStream<u8, TestStreamError> + AsyncU8ReadStream<TestStreamError>
+	{
+		async fn decode(stream: &mut T) -> Result<(Self, StreamPosition), TestStreamError> {
+			let (signed_long, _) = stream.read_u64().await?;
+			let (variable_length, position) = stream.read_vlq().await?;
+
+			Ok((
+				NestedTestObject {
+					signed_long: signed_long as i64,
+					variable_length,
+				},
+				position
+			))
+		}
+	}
+
+	#[derive(Debug, Eq, PartialEq)]
+	struct TestObject {
+		nested_object: NestedTestObject,
+		numbers: Vec<u32>,
+		string: String,
+	}
+
+	impl<T> AsyncEncode<u8, T, TestStreamError> for TestObject
+	where
+		T: AsyncWriteStream<u8, TestStreamError> + AsyncU8WriteStream<TestStreamError>
+	{
+		async fn encode(&self, stream: &mut T) -> Result<(), TestStreamError> {
+			stream.encode(&self.nested_object).await?;
+			stream.write_string(&self.string).await?;
+
+			stream.write_vlq(self.numbers.len() as u64).await?;
+			for number in self.numbers.iter() {
+				stream.write_u32(*number).await?;
+			}
+
+			Ok(())
+		}
+	}
+
+	impl<T> AsyncDecode<u8, T, TestStreamError> for TestObject
+	where
+		T: AsyncReadStream<u8, TestStreamError> + AsyncU8ReadStream<TestStreamError>
+			+ AsyncU8ReadStringStream<TestStreamError>
+	{
+		async fn decode(stream: &mut T) -> Result<(Self, StreamPosition), TestStreamError> {
+			let (nested_object, _) = stream.decode::<NestedTestObject>().await?;
+			let (string, mut position) = stream.read_string().await?;
+
+			let (length, _) = stream.read_vlq().await?;
+			let mut numbers = Vec::new();
+			for _ in 0..length {
+				let (number, new_position) = stream.read_u32().await?;
+				position = new_position;
+				numbers.push(number);
+			}
+
+			Ok((
+				TestObject {
+					nested_object,
+					numbers,
+					string,
+				},
+				position
+			))
+		}
+	}
+
+	fn test_object() -> TestObject {
+		TestObject {
+			nested_object: NestedTestObject {
+				signed_long: -906_543_840_289,
+				variable_length: 1_930_283_129,
+			},
+			numbers: (0..2000).map(|number| number * 7).collect(),
+			string: String::from("anyone else want to go to [funny location goes here]"),
+		}
+	}
+
+	/// Ensure that an object much larger than the pipe between the writer and reader is decoded incrementally.
+	#[tokio::test]
+	async fn decode_incrementally() {
+		let (writer, reader) = tokio::io::duplex(64);
+		let mut write_stream = TokioWriteStream::<_, TestStreamError>::new(writer);
+		let mut read_stream = TokioReadStream::<_, TestStreamError>::new(reader);
+
+		let object = test_object();
+		let (encoded, decoded) = tokio::join!(
+			async {
+				write_stream.encode(&object).await?;
+				write_stream.flush().await
+			},
+			read_stream.decode::<TestObject>()
+		);
+
+		encoded.expect("Could not encode TestObject");
+		let (decoded, position) = decoded.expect("Could not decode TestObject");
+		assert_eq!(decoded, object);
+		assert_eq!(position, read_stream.get_position());
+	}
+
+	/// Ensure that huge vector lengths sent by a remote emit an error once the source runs out, instead of allocating
+	/// the entire vector up front.
+	#[tokio::test]
+	async fn huge_vector() {
+		let (writer, reader) = tokio::io::duplex(64);
+		let mut write_stream = TokioWriteStream::<_, TestStreamError>::new(writer);
+		write_stream.write_vlq((1 << 60) - 1).await.expect("Could not write length");
+		write_stream.write_vector(&[1, 2, 3]).await.expect("Could not write vector");
+		drop(write_stream);
+
+		let mut read_stream = TokioReadStream::<_, TestStreamError>::new(reader);
+		let (length, _) = read_stream.read_vlq().await.expect("Could not read length");
+		let result = read_stream.read_vector(length as usize).await;
+		assert!(matches!(result, Err(TestStreamError::Io(std::io::ErrorKind::UnexpectedEof))));
+	}
+
+	/// Ensure that strings written by synchronous streams can be read by asynchronous streams, and the other way around.
+	#[tokio::test]
+	async fn sync_strings() {
+		let string = "héllo wörld 🥚";
+
+		let mut write_stream = U8BufferWriteStream::<TestStreamError>::new();
+		write_stream.write_string(string).expect("Could not write string");
+		let bytes = write_stream.export().expect("Could not export stream");
+
+		let mut read_stream = TokioReadStream::<_, TestStreamError>::new(&bytes[..]);
+		assert_eq!(read_stream.read_string().await.expect("Could not read string").0, string);
+
+		let mut bytes = Vec::new();
+		{
+			let mut write_stream = TokioWriteStream::<_, TestStreamError>::new(&mut bytes);
+			write_stream.write_string(string).await.expect("Could not write string");
+			write_stream.flush().await.expect("Could not flush stream");
+		}
+
+		let mut read_stream = U8BufferReadStream::<TestStreamError>::new();
+		read_stream.import(bytes).expect("Could not import stream");
+		assert_eq!(read_stream.read_string().expect("Could not read string").0, string);
+	}
+
+	/// Ensure that reading from a closed source emits an error instead of waiting forever.
+	#[tokio::test]
+	async fn unexpected_eof() {
+		let (writer, reader) = tokio::io::duplex(64);
+		let mut write_stream = TokioWriteStream::<_, TestStreamError>::new(writer);
+		write_stream.write_u16(5).await.expect("Could not write number");
+		drop(write_stream);
+
+		let mut read_stream = TokioReadStream::<_, TestStreamError>::new(reader);
+		assert_eq!(read_stream.read_u16().await.expect("Could not read number").0, 5);
+
+		let result = read_stream.read_u64().await;
+		assert!(matches!(result, Err(TestStreamError::Io(std::io::ErrorKind::UnexpectedEof))));
+	}
+}
@@ -0,0 +1,177 @@
+use std::marker::PhantomData;
+use tokio::io::{ AsyncRead, AsyncReadExt, };
+
+use crate::StreamPosition;
+
+use super::{ AsyncDecode, AsyncReadStream, };
+
+/// The most bytes we allocate for a vector before its data arrives. Lengths usually come from the remote, so vectors are
+/// read in chunks of this size to keep a remote from making us allocate more memory than it actually sends.
+const MAX_READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Trait for an asynchronous stream that implements `u8` reading. Reads wait until enough bytes have arrived to complete
+/// the read, so objects can be decoded as their data is received instead of after the entire object is buffered.
+pub trait AsyncU8ReadStream<Error> {
+	/// Reads one byte.
+	async fn read_u8(&mut self) -> Result<(u8, StreamPosition), Error>;
+
+	/// Reads a char as a `u8`.
+	async fn read_char(&mut self) -> Result<(char, StreamPosition), Error>;
+
+	/// Reads two bytes in little-endian format.
+	async fn read_u16(&mut self) -> Result<(u16, StreamPosition), Error>;
+
+	/// Reads four bytes in little-endian format.
+	async fn read_u32(&mut self) -> Result<(u32, StreamPosition), Error>;
+
+	/// Reads eight bytes in little-endian format.
+	async fn read_u64(&mut self) -> Result<(u64, StreamPosition), Error>;
+
+	/// Reads a variable length quantity integer. The 16th bit in a 2 byte pair represents if the number has another two
+	/// bits. 1 if there are, 0 if there aren't. Integers within the range of `0..2**60` are supported.
+	async fn read_vlq(&mut self) -> Result<(u64, StreamPosition), Error>;
+
+	/// Reads a specified amount of bytes into a vector.
+	async fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), Error>;
+}
+
+pub trait AsyncU8ReadStringStream<Error> {
+	/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings
+	/// can be up to 2**60 bytes long.
+	async fn read_string(&mut self) -> Result<(String, StreamPosition), Error>;
+}
+
+/// Reads `u8` encoded data from a tokio `AsyncRead`. Every primitive read waits on the reader, so readers that make
+/// system calls (like sockets) should be wrapped in a `tokio::io::BufReader`. IO errors are converted into `Error`.
+#[derive(Debug)]
+pub struct TokioReadStream<R, Error> {
+	error: PhantomData<fn() -> Error>,
+	position: StreamPosition,
+	reader: R,
+}
+
+impl<R, Error> TokioReadStream<R, Error>
+where
+	R: AsyncRead + Unpin
+{
+	pub fn new(reader: R) -> Self {
+		TokioReadStream {
+			error: PhantomData,
+			position: 0,
+			reader,
+		}
+	}
+
+	/// Get a reference to the underlying reader.
+	pub fn get_reader(&self) -> &R {
+		&self.reader
+	}
+
+	/// Get the amount of bytes read from the reader.
+	pub fn get_position(&self) -> StreamPosition {
+		self.position
+	}
+
+	/// Consume the stream and return the underlying reader.
+	pub fn into_reader(self) -> R {
+		self.reader
+	}
+}
+
+impl<R, Error> TokioReadStream<R, Error>
+where
+	R: AsyncRead + Unpin,
+	Error: From<std::io::Error>
+{
+	/// Wait until the buffer is filled by the reader.
+	async fn read_exact<const BYTES: usize>(&mut self) -> Result<[u8; BYTES], Error> {
+		let mut buffer = [0; BYTES];
+		self.reader.read_exact(&mut buffer).await?;
+		self.position += BYTES as StreamPosition;
+		Ok(buffer)
+	}
+}
+
+impl<R, Error> AsyncU8ReadStream<Error> for TokioReadStream<R, Error>
+where
+	R: AsyncRead + Unpin,
+	Error: From<std::io::Error>
+{
+	async fn read_u8(&mut self) -> Result<(u8, StreamPosition), Error> {
+		let buffer = self.read_exact::<1>().await?;
+		Ok((buffer[0], self.position))
+	}
+
+	async fn read_char(&mut self) -> Result<(char, StreamPosition), Error> {
+		let buffer = self.read_exact::<1>().await?;
+		Ok((buffer[0] as char, self.position))
+	}
+
+	async fn read_u16(&mut self) -> Result<(u16, StreamPosition), Error> {
+		let buffer = self.read_exact::<2>().await?;
+		Ok((u16::from_le_bytes(buffer), self.position))
+	}
+
+	async fn read_u32(&mut self) -> Result<(u32, StreamPosition), Error> {
+		let buffer = self.read_exact::<4>().await?;
+		Ok((u32::from_le_bytes(buffer), self.position))
+	}
+
+	async fn read_u64(&mut self) -> Result<(u64, StreamPosition), Error> {
+		let buffer = self.read_exact::<8>().await?;
+		Ok((u64::from_le_bytes(buffer), self.position))
+	}
+
+	async fn read_vlq(&mut self) -> Result<(u64, StreamPosition), Error> {
+		let mut number = 0;
+		let mut read = 0;
+		loop {
+			let (bytes, _) = self.read_u16().await?;
+			number |= (bytes as u64 & 0x7FFF) << (read / 2 * 15);
+			read += 2;
+
+			if bytes & 0x8000 == 0 || read >= 8 {
+				break;
+			}
+		}
+
+		Ok((number, self.position))
+	}
+
+	async fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), Error> {
+		let mut buffer = Vec::new();
+		while buffer.len() < length {
+			let start = buffer.len();
+			buffer.resize(length.min(start + MAX_READ_CHUNK_SIZE), 0);
+			self.reader.read_exact(&mut buffer[start..]).await?;
+		}
+
+		self.position += length as StreamPosition;
+		Ok((buffer, self.position))
+	}
+}
+
+impl<R, Error> AsyncU8ReadStringStream<Error> for TokioReadStream<R, Error>
+where
+	R: AsyncRead + Unpin,
+	Error: From<std::io::Error>
+{
+	async fn read_string(&mut self) -> Result<(String, StreamPosition), Error> {
+		let (length, _) = self.read_vlq().await?;
+		let (buffer, position) = self.read_vector(length as usize).await?;
+
+		match String::from_utf8(buffer) {
+			Ok(string) => Ok((string, position)),
+			Err(error) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, error).into()),
+		}
+	}
+}
+
+impl<R, Error> AsyncReadStream<u8, Error> for TokioReadStream<R, Error>
+where
+	R: AsyncRead + Unpin
+{
+	async fn decode<T: AsyncDecode<u8, Self, Error>>(&mut self) -> Result<(T, StreamPosition), Error> {
+		T::decode(self).await
+	}
+}
@@ -0,0 +1,145 @@
+use std::marker::PhantomData;
+use tokio::io::{ AsyncWrite, AsyncWriteExt, };
+
+use super::{ AsyncEncode, AsyncEncodeMut, AsyncWriteStream, };
+
+/// Trait for an asynchronous stream that implements `u8` writing.
+pub trait AsyncU8WriteStream<Error> {
+	/// Writes one byte.
+	async fn write_u8(&mut self, byte: u8) -> Result<(), Error>;
+
+	/// Writes a char as a `u8`.
+	async fn write_char(&mut self, character: char) -> Result<(), Error>;
+
+	/// Writes two bytes in little-endian format.
+	async fn write_u16(&mut self, number: u16) -> Result<(), Error>;
+
+	/// Writes four bytes in little-endian format.
+	async fn write_u32(&mut self, number: u32) -> Result<(), Error>;
+
+	/// Writes eight bytes in little-endian format.
+	async fn write_u64(&mut self, number: u64) -> Result<(), Error>;
+
+	/// Writes a variable length quantity integer. The 16th bit in a 2 byte pair represents if the number has another two
+	/// bits. 1 if there are, 0 if there aren't. Integers within the range of `0..2**60` are supported.
+	async fn write_vlq(&mut self, number: u64) -> Result<(), Error>;
+
+	/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings
+	/// can be up to 2**60 bytes long.
+	async fn write_string(&mut self, string: &str) -> Result<(), Error>;
+
+	/// Writes a vector to the stream.
+	async fn write_vector(&mut self, vector: &[u8]) -> Result<(), Error>;
+}
+
+/// Writes `u8` encoded data into a tokio `AsyncWrite`. Every primitive write waits on the writer, so writers that make
+/// system calls (like sockets) should be wrapped in a `tokio::io::BufWriter`. IO errors are converted into `Error`.
+#[derive(Debug)]
+pub struct TokioWriteStream<W, Error> {
+	error: PhantomData<fn() -> Error>,
+	writer: W,
+}
+
+impl<W, Error> TokioWriteStream<W, Error>
+where
+	W: AsyncWrite + Unpin
+{
+	pub fn new(writer: W) -> Self {
+		TokioWriteStream {
+			error: PhantomData,
+			writer,
+		}
+	}
+
+	/// Get a reference to the underlying writer.
+	pub fn get_writer(&self) -> &W {
+		&self.writer
+	}
+
+	/// Consume the stream and return the underlying writer.
+	pub fn into_writer(self) -> W {
+		self.writer
+	}
+}
+
+impl<W, Error> AsyncU8WriteStream<Error> for TokioWriteStream<W, Error>
+where
+	W: AsyncWrite + Unpin,
+	Error: From<std::io::Error>
+{
+	async fn write_u8(&mut self, byte: u8) -> Result<(), Error> {
+		self.writer.write_all(&[byte]).await?;
+		Ok(())
+	}
+
+	async fn write_char(&mut self, character: char) -> Result<(), Error> {
+		self.writer.write_all(&[character as u8]).await?;
+		Ok(())
+	}
+
+	async fn write_u16(&mut self, number: u16) -> Result<(), Error> {
+		self.writer.write_all(&number.to_le_bytes()).await?;
+		Ok(())
+	}
+
+	async fn write_u32(&mut self, number: u32) -> Result<(), Error> {
+		self.writer.write_all(&number.to_le_bytes()).await?;
+		Ok(())
+	}
+
+	async fn write_u64(&mut self, number: u64) -> Result<(), Error> {
+		self.writer.write_all(&number.to_le_bytes()).await?;
+		Ok(())
+	}
+
+	async fn write_vlq(&mut self, number: u64) -> Result<(), Error> {
+		let mut shift = number;
+		for _ in 0..4 {
+			let number = if shift >> 15 != 0 {
+				(shift as u16 & 0x7FFF) | 0x8000
+			} else {
+				shift as u16 & 0x7FFF
+			};
+
+			self.write_u16(number).await?;
+
+			shift >>= 15;
+
+			if shift == 0 {
+				break;
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn write_string(&mut self, string: &str) -> Result<(), Error> {
+		self.write_vlq(string.len() as u64).await?;
+		self.writer.write_all(string.as_bytes()).await?;
+		Ok(())
+	}
+
+	async fn write_vector(&mut self, vector: &[u8]) -> Result<(), Error> {
+		self.writer.write_all(vector).await?;
+		Ok(())
+	}
+}
+
+impl<W, Error> AsyncWriteStream<u8, Error> for TokioWriteStream<W, Error>
+where
+	W: AsyncWrite + Unpin,
+	Error: From<std::io::Error>
+{
+	async fn encode_mut<T: AsyncEncodeMut<u8, Self, Error>>(&mut self, object: &mut T) -> Result<(), Error> {
+		object.encode_mut(self).await
+	}
+
+	async fn encode<T: AsyncEncode<u8, Self, Error>>(&mut self, object: &T) -> Result<(), Error> {
+		object.encode(self).await
+	}
+
+	async fn flush(&mut self) -> Result<(), Error> {
+		self.writer.flush().await?;
+		Ok(())
+	}
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::StreamPosition;
+use crate::u8_io::{ U8ReadStream, U8WriteStream, };
+
+use super::{ DecodeDelta, EncodeDelta, };
+
+/// The amount of elements past the end of the baseline we reserve space for up front when decoding a vector. Lengths
+/// come straight from the stream, so reserving space for all of them would let corrupt data allocate arbitrary amounts
+/// of memory. Vectors longer than this grow as their elements are decoded.
+const MAX_PREALLOCATED_ELEMENTS: usize = 64;
+
+/// Vectors are encoded as their new length, followed by a bitmask of elements that differ from the baseline element at
+/// the same index, followed by the delta of each changed element. Only elements that have a baseline element are
+/// included in the bitmask. Elements past the end of the baseline are always encoded, using their default value as a
+/// baseline.
+impl<T, S, Error> EncodeDelta<u8, S, Error> for Vec<T>
+where
+	T: Default + EncodeDelta<u8, S, Error> + PartialEq,
+	S: U8WriteStream<Error>
+{
+	fn encode_delta(&self, baseline: &Self, stream: &mut S) -> Result<(), Error> {
+		stream.write_vlq(self.len() as u64)?;
+
+		let shared = usize::min(self.len(), baseline.len());
+		let mut mask = vec![0; shared.div_ceil(8)];
+		for i in 0..shared {
+			if self[i] != baseline[i] {
+				mask[i / 8] |= 1 << (i % 8);
+			}
+		}
+
+		for byte in mask.iter() {
+			stream.write_u8(*byte)?;
+		}
+
+		for i in 0..shared {
+			if (mask[i / 8] & (1 << (i % 8))) != 0 {
+				self[i].encode_delta(&baseline[i], stream)?;
+			}
+		}
+
+		let default = T::default();
+		for element in self.iter().skip(shared) {
+			element.encode_delta(&default, stream)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl<T, S, Error> DecodeDelta<u8, S, Error> for Vec<T>
+where
+	T: Clone + Default + DecodeDelta<u8, S, Error>,
+	S: U8ReadStream<Error>
+{
+	fn decode_delta(baseline: &Self, stream: &mut S) -> Result<(Self, StreamPosition), Error> {
+		let (length, mut position) = stream.read_vlq()?;
+		let length = length as usize;
+
+		let shared = usize::min(length, baseline.len());
+		let mut mask = Vec::new();
+		for _ in 0..shared.div_ceil(8) {
+			let (byte, new_position) = stream.read_u8()?;
+			position = new_position;
+			mask.push(byte);
+		}
+
+		let mut vector = Vec::with_capacity(length.min(baseline.len() + MAX_PREALLOCATED_ELEMENTS));
+		for i in 0..shared {
+			if (mask[i / 8] & (1 << (i % 8))) != 0 {
+				let (element, new_position) = T::decode_delta(&baseline[i], stream)?;
+				position = new_position;
+				vector.push(element);
+			} else {
+				vector.push(baseline[i].clone());
+			}
+		}
+
+		let default = T::default();
+		for _ in shared..length {
+			let (element, new_position) = T::decode_delta(&default, stream)?;
+			position = new_position;
+			vector.push(element);
+		}
+
+		Ok((vector, position))
+	}
+}
+
+/// Maps are encoded as a list of keys removed from the baseline, followed by a list of entries that were added or
+/// changed. Keys are always encoded in full by delta encoding them against their default value. Changed values are
+/// delta encoded against their baseline value, and added values are delta encoded against their default value.
+impl<K, V, S, Error> EncodeDelta<u8, S, Error> for HashMap<K, V>
+where
+	K: Default + EncodeDelta<u8, S, Error> + Eq + Hash,
+	V: Default + EncodeDelta<u8, S, Error> + PartialEq,
+	S: U8WriteStream<Error>
+{
+	fn encode_delta(&self, baseline: &Self, stream: &mut S) -> Result<(), Error> {
+		let default_key = K::default();
+		let default_value = V::default();
+
+		// write removed keys
+		let removed = baseline.keys().filter(|key| !self.contains_key(key)).collect::<Vec<&K>>();
+		stream.write_vlq(removed.len() as u64)?;
+		for key in removed {
+			key.encode_delta(&default_key, stream)?;
+		}
+
+		// write added and changed entries
+		let changed = self.iter()
+			.filter(|(key, value)| baseline.get(key) != Some(value))
+			.collect::<Vec<(&K, &V)>>();
+
+		stream.write_vlq(changed.len() as u64)?;
+		for (key, value) in changed {
+			key.encode_delta(&default_key, stream)?;
+			value.encode_delta(baseline.get(key).unwrap_or(&default_value), stream)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl<K, V, S, Error> DecodeDelta<u8, S, Error> for HashMap<K, V>
+where
+	K: Default + DecodeDelta<u8, S, Error> + Eq + Hash,
+	V: Clone + Default + DecodeDelta<u8, S, Error>,
+	S: U8ReadStream<Error>,
+	HashMap<K, V>: Clone
+{
+	fn decode_delta(baseline: &Self, stream: &mut S) -> Result<(Self, StreamPosition), Error> {
+		let default_key = K::default();
+		let default_value = V::default();
+
+		let mut map = baseline.clone();
+
+		let (removed_count, _) = stream.read_vlq()?;
+		for _ in 0..removed_count {
+			let (key, _) = K::decode_delta(&default_key, stream)?;
+			map.remove(&key);
+		}
+
+		let (changed_count, mut position) = stream.read_vlq()?;
+		for _ in 0..changed_count {
+			let (key, _) = K::decode_delta(&default_key, stream)?;
+			let (value, new_position) = V::decode_delta(baseline.get(&key).unwrap_or(&default_value), stream)?;
+			position = new_position;
+			map.insert(key, value);
+		}
+
+		Ok((map, position))
+	}
+}
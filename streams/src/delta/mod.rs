@@ -0,0 +1,290 @@
+pub mod collections;
+pub mod primitives;
+pub mod vectors;
+
+use crate::{ Decode, Encode, ReadStream, StreamPosition, WriteStream, };
+use crate::u8_io::{ U8ReadStream, U8WriteStream, };
+
+/// Encode the differences between an object and a baseline version of the object. Used to avoid re-sending data that
+/// the receiver already has. For more information on how `Error` is meant to be used, see `ReadStream`.
+///
+/// Objects with fields are expected to write a `ChangedFields` bitmask describing which fields differ from the
+/// baseline, followed by the delta encoding of each changed field. Primitives have no fields to compare, so their delta
+/// encoding is their full value. Whether or not a primitive changed is tracked by the bitmask of the object that
+/// contains it.
+pub trait EncodeDelta<Encoding, Stream, Error> {
+	fn encode_delta(&self, baseline: &Self, stream: &mut Stream) -> Result<(), Error>;
+}
+
+/// Decode an object using the differences written by `EncodeDelta`. The decoder must be given the same baseline that
+/// the encoder used. For more information on how `Error` is meant to be used, see `ReadStream`.
+pub trait DecodeDelta<Encoding, Stream, Error>: Sized {
+	fn decode_delta(baseline: &Self, stream: &mut Stream) -> Result<(Self, StreamPosition), Error>;
+}
+
+/// Bitmask describing which fields of an object differ from the baseline. Fields are identified by their index, and
+/// there can be up to `ChangedFields::MAX_FIELDS` fields in an object. The mask is encoded as a variable length
+/// integer, so objects that only change their first few fields have small masks.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ChangedFields {
+	mask: u64,
+}
+
+impl ChangedFields {
+	/// The maximum amount of fields a mask can describe. Limited by the range of variable length integers.
+	pub const MAX_FIELDS: u32 = 60;
+
+	/// Compare a field against its baseline and mark it as changed if they differ. Returns whether or not the field
+	/// changed.
+	pub fn compare<T: PartialEq>(&mut self, field: u32, value: &T, baseline: &T) -> bool {
+		let changed = value != baseline;
+		self.set(field, changed);
+		changed
+	}
+
+	/// Mark a field as changed or unchanged.
+	pub fn set(&mut self, field: u32, changed: bool) {
+		assert!(field < ChangedFields::MAX_FIELDS, "Field index {} is out of range", field);
+
+		if changed {
+			self.mask |= 1 << field;
+		} else {
+			self.mask &= !(1 << field);
+		}
+	}
+
+	/// Test if a field changed.
+	pub fn is_changed(&self, field: u32) -> bool {
+		field < ChangedFields::MAX_FIELDS && (self.mask & (1 << field)) != 0
+	}
+
+	/// Whether or not no fields changed.
+	pub fn is_empty(&self) -> bool {
+		self.mask == 0
+	}
+}
+
+impl<T, Error> Encode<u8, T, Error> for ChangedFields
+where
+	T: WriteStream<u8, Error> + U8WriteStream<Error>
+{
+	fn encode(&self, stream: &mut T) -> Result<(), Error> {
+		stream.write_vlq(self.mask)
+	}
+}
+
+impl<T, Error> Decode<u8, T, Error> for ChangedFields
+where
+	T: ReadStream<u8, Error> + U8ReadStream<Error>
+{
+	fn decode(stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+		let (mask, position) = stream.read_vlq()?;
+		Ok((ChangedFields {
+			mask,
+		}, position))
+	}
+}
+
+/// Test delta encoding against baselines.
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use crate::{ ReadStream, StreamPosition, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+	use super::{ ChangedFields, DecodeDelta, EncodeDelta, };
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io,
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(_: std::io::Error) -> Self {
+			TestStreamError::Io
+		}
+	}
+
+	type TestWriteStream = U8BufferWriteStream<TestStreamError>;
+	type TestReadStream = U8BufferReadStream<TestStreamError>;
+
+	#[derive(Clone, Debug, Default, PartialEq)]
+	struct Entity {
+		health: u16,
+		inventory: Vec<u32>,
+		name: String,
+		position: glam::Vec3,
+		tags: HashMap<String, i32>,
+	}
+
+	impl<T, Error> EncodeDelta<u8, T, Error> for Entity
+	where
+		T: WriteStream<u8, Error> + U8WriteStream<Error>
+	{
+		fn encode_delta(&self, baseline: &Self, stream: &mut T) -> Result<(), Error> {
+			let mut changed = ChangedFields::default();
+			changed.compare(0, &self.health, &baseline.health);
+			changed.compare(1, &self.inventory, &baseline.inventory);
+			changed.compare(2, &self.name, &baseline.name);
+			changed.compare(3, &self.position, &baseline.position);
+			changed.compare(4, &self.tags, &baseline.tags);
+			stream.encode(&changed)?;
+
+			if changed.is_changed(0) {
+				self.health.encode_delta(&baseline.health, stream)?;
+			}
+
+			if changed.is_changed(1) {
+				self.inventory.encode_delta(&baseline.inventory, stream)?;
+			}
+
+			if changed.is_changed(2) {
+				self.name.encode_delta(&baseline.name, stream)?;
+			}
+
+			if changed.is_changed(3) {
+				self.position.encode_delta(&baseline.position, stream)?;
+			}
+
+			if changed.is_changed(4) {
+				self.tags.encode_delta(&baseline.tags, stream)?;
+			}
+
+			Ok(())
+		}
+	}
+
+	impl<T, Error> DecodeDelta<u8, T, Error> for Entity
+	where
+		T: ReadStream<u8, Error> + U8ReadStream<Error> + U8ReadStringStream<Error>
+	{
+		fn decode_delta(baseline: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+			let (changed, mut position) = stream.decode::<ChangedFields>()?;
+			let mut entity = baseline.clone();
+
+			if changed.is_changed(0) {
+				(entity.health, position) = u16::decode_delta(&baseline.health, stream)?;
+			}
+
+			if changed.is_changed(1) {
+				(entity.inventory, position) = Vec::decode_delta(&baseline.inventory, stream)?;
+			}
+
+			if changed.is_changed(2) {
+				(entity.name, position) = String::decode_delta(&baseline.name, stream)?;
+			}
+
+			if changed.is_changed(3) {
+				(entity.position, position) = glam::Vec3::decode_delta(&baseline.position, stream)?;
+			}
+
+			if changed.is_changed(4) {
+				(entity.tags, position) = HashMap::decode_delta(&baseline.tags, stream)?;
+			}
+
+			Ok((entity, position))
+		}
+	}
+
+	fn baseline() -> Entity {
+		Entity {
+			health: 100,
+			inventory: vec![1, 2, 3, 4],
+			name: String::from("egg"),
+			position: glam::Vec3::new(1.0, 2.0, 3.0),
+			tags: HashMap::from([
+				(String::from("team"), 1),
+				(String::from("score"), 10),
+				(String::from("kills"), 0),
+			]),
+		}
+	}
+
+	// delta encode an object, and return the decoded object along with the amount of bytes the delta took up
+	fn round_trip<T>(object: &T, baseline: &T) -> (T, usize)
+	where
+		T: EncodeDelta<u8, TestWriteStream, TestStreamError> + DecodeDelta<u8, TestReadStream, TestStreamError>
+	{
+		let mut write_stream = TestWriteStream::new();
+		object.encode_delta(baseline, &mut write_stream).expect("Could not encode delta");
+		let bytes = write_stream.export().expect("Could not export test stream");
+		let length = bytes.len();
+
+		let mut read_stream = TestReadStream::new();
+		read_stream.import(bytes).expect("Could not import test stream");
+		let (decoded, _) = T::decode_delta(baseline, &mut read_stream).expect("Could not decode delta");
+		assert!(read_stream.remaining() == 0, "Delta decode did not consume the entire delta");
+
+		(decoded, length)
+	}
+
+	/// Ensure that an object equal to its baseline encodes to only the changed fields mask.
+	#[test]
+	fn unchanged() {
+		let (decoded, length) = round_trip(&baseline(), &baseline());
+		assert_eq!(decoded, baseline());
+		assert_eq!(length, 2);
+	}
+
+	/// Ensure that changed fields survive a round trip, and that unchanged fields are not encoded.
+	#[test]
+	fn changed_fields() {
+		let mut entity = baseline();
+		entity.health = 75;
+		entity.position.y = -4.5;
+
+		let (decoded, length) = round_trip(&entity, &baseline());
+		assert_eq!(decoded, entity);
+
+		// mask + u16 health + vector component mask + one f32 component
+		assert_eq!(length, 2 + 2 + 1 + 4);
+	}
+
+	/// Ensure that vectors can grow, shrink, and change elements.
+	#[test]
+	fn vectors() {
+		let base = vec![1_u32, 2, 3, 4];
+		for vector in [vec![1, 2, 3, 4, 5, 6], vec![1, 9], vec![], vec![4, 3, 2, 1]] {
+			let (decoded, _) = round_trip(&vector, &base);
+			assert_eq!(decoded, vector);
+		}
+	}
+
+	/// Ensure that corrupt vector lengths result in an error instead of a huge allocation.
+	#[test]
+	fn corrupt_vector_length() {
+		let mut write_stream = TestWriteStream::new();
+		write_stream.write_vlq((1 << 60) - 1).expect("Could not write length");
+
+		let mut read_stream = TestReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		let result = Vec::<u32>::decode_delta(&vec![1, 2, 3], &mut read_stream);
+		assert!(matches!(result, Err(TestStreamError::Io)));
+	}
+
+	/// Ensure that maps can add, remove, and change entries.
+	#[test]
+	fn maps() {
+		let mut entity = baseline();
+		entity.tags.remove("kills");
+		entity.tags.insert(String::from("score"), 11);
+		entity.tags.insert(String::from("deaths"), 2);
+
+		let (decoded, _) = round_trip(&entity, &baseline());
+		assert_eq!(decoded, entity);
+	}
+
+	/// Ensure that glam vectors only encode the components that changed.
+	#[test]
+	fn glam_vectors() {
+		let (decoded, length) = round_trip(&glam::Vec4::new(1.0, 5.0, 3.0, 4.0), &glam::Vec4::new(1.0, 2.0, 3.0, 4.0));
+		assert_eq!(decoded, glam::Vec4::new(1.0, 5.0, 3.0, 4.0));
+		assert_eq!(length, 1 + 4);
+
+		let (decoded, _) = round_trip(&glam::IVec2::new(-7, 8), &glam::IVec2::ZERO);
+		assert_eq!(decoded, glam::IVec2::new(-7, 8));
+	}
+}
@@ -0,0 +1,113 @@
+use crate::StreamPosition;
+use crate::u8_io::{ U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+use super::{ DecodeDelta, EncodeDelta, };
+
+/// Implements delta encoding for an integer by writing the full integer using the specified read/write functions. The
+/// integer is casted into the type that the read/write functions expect.
+macro_rules! integer_delta {
+	($integer:ty, $encoded:ty, $write:ident, $read:ident) => {
+		impl<T, Error> EncodeDelta<u8, T, Error> for $integer
+		where
+			T: U8WriteStream<Error>
+		{
+			fn encode_delta(&self, _: &Self, stream: &mut T) -> Result<(), Error> {
+				stream.$write(*self as $encoded)
+			}
+		}
+
+		impl<T, Error> DecodeDelta<u8, T, Error> for $integer
+		where
+			T: U8ReadStream<Error>
+		{
+			fn decode_delta(_: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+				let (number, position) = stream.$read()?;
+				Ok((number as $integer, position))
+			}
+		}
+	};
+}
+
+integer_delta!(u8, u8, write_u8, read_u8);
+integer_delta!(u16, u16, write_u16, read_u16);
+integer_delta!(u32, u32, write_u32, read_u32);
+integer_delta!(u64, u64, write_u64, read_u64);
+integer_delta!(i8, u8, write_u8, read_u8);
+integer_delta!(i16, u16, write_u16, read_u16);
+integer_delta!(i32, u32, write_u32, read_u32);
+integer_delta!(i64, u64, write_u64, read_u64);
+
+impl<T, Error> EncodeDelta<u8, T, Error> for bool
+where
+	T: U8WriteStream<Error>
+{
+	fn encode_delta(&self, _: &Self, stream: &mut T) -> Result<(), Error> {
+		stream.write_u8(*self as u8)
+	}
+}
+
+impl<T, Error> DecodeDelta<u8, T, Error> for bool
+where
+	T: U8ReadStream<Error>
+{
+	fn decode_delta(_: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+		let (byte, position) = stream.read_u8()?;
+		Ok((byte != 0, position))
+	}
+}
+
+impl<T, Error> EncodeDelta<u8, T, Error> for f32
+where
+	T: U8WriteStream<Error>
+{
+	fn encode_delta(&self, _: &Self, stream: &mut T) -> Result<(), Error> {
+		stream.write_u32(self.to_bits())
+	}
+}
+
+impl<T, Error> DecodeDelta<u8, T, Error> for f32
+where
+	T: U8ReadStream<Error>
+{
+	fn decode_delta(_: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+		let (bits, position) = stream.read_u32()?;
+		Ok((f32::from_bits(bits), position))
+	}
+}
+
+impl<T, Error> EncodeDelta<u8, T, Error> for f64
+where
+	T: U8WriteStream<Error>
+{
+	fn encode_delta(&self, _: &Self, stream: &mut T) -> Result<(), Error> {
+		stream.write_u64(self.to_bits())
+	}
+}
+
+impl<T, Error> DecodeDelta<u8, T, Error> for f64
+where
+	T: U8ReadStream<Error>
+{
+	fn decode_delta(_: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+		let (bits, position) = stream.read_u64()?;
+		Ok((f64::from_bits(bits), position))
+	}
+}
+
+impl<T, Error> EncodeDelta<u8, T, Error> for String
+where
+	T: U8WriteStream<Error>
+{
+	fn encode_delta(&self, _: &Self, stream: &mut T) -> Result<(), Error> {
+		stream.write_string(self)
+	}
+}
+
+impl<T, Error> DecodeDelta<u8, T, Error> for String
+where
+	T: U8ReadStringStream<Error>
+{
+	fn decode_delta(_: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+		stream.read_string()
+	}
+}
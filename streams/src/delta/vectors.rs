@@ -0,0 +1,72 @@
+use crate::StreamPosition;
+use crate::u8_io::{ U8ReadStream, U8WriteStream, };
+
+use super::{ DecodeDelta, EncodeDelta, };
+
+/// Implements delta encoding for a glam vector. Vectors are encoded as a `u8` bitmask of components that differ from
+/// the baseline, followed by each changed component.
+macro_rules! vector_delta {
+	($vector:ty, $component:ty) => {
+		impl<T, Error> EncodeDelta<u8, T, Error> for $vector
+		where
+			T: U8WriteStream<Error>
+		{
+			fn encode_delta(&self, baseline: &Self, stream: &mut T) -> Result<(), Error> {
+				let components = self.to_array();
+				let baseline_components = baseline.to_array();
+
+				let mut mask: u8 = 0;
+				for (i, (component, baseline_component)) in components.iter().zip(baseline_components.iter()).enumerate() {
+					if component != baseline_component {
+						mask |= 1 << i;
+					}
+				}
+
+				stream.write_u8(mask)?;
+
+				for (i, (component, baseline_component)) in components.iter().zip(baseline_components.iter()).enumerate() {
+					if (mask & (1 << i)) != 0 {
+						component.encode_delta(baseline_component, stream)?;
+					}
+				}
+
+				Ok(())
+			}
+		}
+
+		impl<T, Error> DecodeDelta<u8, T, Error> for $vector
+		where
+			T: U8ReadStream<Error>
+		{
+			fn decode_delta(baseline: &Self, stream: &mut T) -> Result<(Self, StreamPosition), Error> {
+				let (mask, mut position) = stream.read_u8()?;
+
+				let mut components = baseline.to_array();
+				for (i, component) in components.iter_mut().enumerate() {
+					if (mask & (1 << i)) != 0 {
+						let (new_component, new_position) = <$component>::decode_delta(component, stream)?;
+						*component = new_component;
+						position = new_position;
+					}
+				}
+
+				Ok((<$vector>::from_array(components), position))
+			}
+		}
+	};
+}
+
+vector_delta!(glam::Vec2, f32);
+vector_delta!(glam::Vec3, f32);
+vector_delta!(glam::Vec3A, f32);
+vector_delta!(glam::Vec4, f32);
+vector_delta!(glam::DVec2, f64);
+vector_delta!(glam::DVec3, f64);
+vector_delta!(glam::DVec4, f64);
+vector_delta!(glam::IVec2, i32);
+vector_delta!(glam::IVec3, i32);
+vector_delta!(glam::IVec4, i32);
+vector_delta!(glam::UVec2, u32);
+vector_delta!(glam::UVec3, u32);
+vector_delta!(glam::UVec4, u32);
+vector_delta!(glam::Quat, f32);
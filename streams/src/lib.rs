@@ -1,13 +1,25 @@
+pub mod async_io;
 pub mod decode;
+pub mod delta;
 pub mod encode;
 pub mod endable;
 pub mod peek;
 pub mod read_stream;
 pub mod seek;
+pub mod serde_bridge;
 pub mod u8_io;
+pub mod versioning;
 pub mod write_stream;
 
+pub use async_io::AsyncDecode;
+pub use async_io::AsyncEncode;
+pub use async_io::AsyncEncodeMut;
+pub use async_io::AsyncReadStream;
+pub use async_io::AsyncWriteStream;
 pub use decode::Decode;
+pub use delta::ChangedFields;
+pub use delta::DecodeDelta;
+pub use delta::EncodeDelta;
 pub use encode::Encode;
 pub use encode::EncodeMut;
 pub use endable::Endable;
@@ -16,6 +28,12 @@ pub use read_stream::ReadStream;
 pub use read_stream::StreamPosition;
 pub use read_stream::StreamPositionDelta;
 pub use seek::Seekable;
+pub use serde_bridge::SerdeError;
+pub use serde_bridge::SerdeStringTable;
+pub use versioning::DecodeContext;
+pub use versioning::DecodeVersioned;
+pub use versioning::EncodeVersioned;
+pub use versioning::Versioned;
 pub use write_stream::WriteStream;
 
 /// Test the `u8` encoding reference implementation of read/write streams.
@@ -166,7 +184,7 @@ mod tests {
 
 	impl U8ReadStringStream<TestStreamError> for TestReadStream {
 		fn read_string(&mut self) -> Result<(String, StreamPosition), TestStreamError> {
-			let (string, read_bytes) = read_string(&self.buffer[self.position as usize..]);
+			let (string, read_bytes) = read_string(&self.buffer[self.position as usize..]).expect("Invalid string");
 			self.position += read_bytes;
 			return Ok((string, self.position));
 		}
@@ -0,0 +1,338 @@
+use serde::de::{
+	DeserializeSeed,
+	EnumAccess,
+	IntoDeserializer,
+	MapAccess,
+	SeqAccess,
+	VariantAccess,
+	Visitor,
+};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use crate::u8_io::U8ReadStream;
+
+use super::{ SerdeError, SerdeStringTable, };
+
+/// Serde `Deserializer` that decodes values written by a `StreamSerializer` out of a `U8ReadStream`. Since the encoding
+/// is not self-describing, `deserialize_any` is not supported. If the serializer was given a string table, then the
+/// deserializer must be given the same string table.
+pub struct StreamDeserializer<'a, S, Error> {
+	error: PhantomData<fn() -> Error>,
+	stream: &'a mut S,
+	string_table: Option<&'a dyn SerdeStringTable>,
+}
+
+impl<'a, S, Error> StreamDeserializer<'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	/// Create a deserializer that reads inline strings.
+	pub fn new(stream: &'a mut S) -> Self {
+		StreamDeserializer {
+			error: PhantomData,
+			stream,
+			string_table: None,
+		}
+	}
+
+	/// Create a deserializer that looks up strings in the supplied string table.
+	pub fn with_string_table(stream: &'a mut S, string_table: &'a dyn SerdeStringTable) -> Self {
+		StreamDeserializer {
+			error: PhantomData,
+			stream,
+			string_table: Some(string_table),
+		}
+	}
+
+	fn read_u8(&mut self) -> Result<u8, SerdeError<Error>> {
+		Ok(self.stream.read_u8().map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_u16(&mut self) -> Result<u16, SerdeError<Error>> {
+		Ok(self.stream.read_u16().map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_u32(&mut self) -> Result<u32, SerdeError<Error>> {
+		Ok(self.stream.read_u32().map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_u64(&mut self) -> Result<u64, SerdeError<Error>> {
+		Ok(self.stream.read_u64().map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_vlq(&mut self) -> Result<u64, SerdeError<Error>> {
+		Ok(self.stream.read_vlq().map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_bytes(&mut self) -> Result<Vec<u8>, SerdeError<Error>> {
+		let length = self.read_vlq()?;
+		Ok(self.stream.read_vector(length as usize).map_err(SerdeError::Stream)?.0)
+	}
+
+	fn read_string(&mut self) -> Result<String, SerdeError<Error>> {
+		if let Some(string_table) = self.string_table {
+			let id = self.read_vlq()?;
+			match string_table.get_string(id) {
+				Some(string) => Ok(String::from(string)),
+				None => Err(SerdeError::NotInStringTable(id)),
+			}
+		} else {
+			String::from_utf8(self.read_bytes()?).map_err(|_| SerdeError::InvalidUtf8)
+		}
+	}
+}
+
+impl<'de, 'a, 'b, S, Error> serde::Deserializer<'de> for &'b mut StreamDeserializer<'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	type Error = SerdeError<Error>;
+
+	fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+		Err(SerdeError::NotSelfDescribing)
+	}
+
+	fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		match self.read_u8()? {
+			0 => visitor.visit_bool(false),
+			1 => visitor.visit_bool(true),
+			byte => Err(SerdeError::InvalidBool(byte)),
+		}
+	}
+
+	fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_i8(self.read_u8()? as i8)
+	}
+
+	fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_i16(self.read_u16()? as i16)
+	}
+
+	fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_i32(self.read_u32()? as i32)
+	}
+
+	fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_i64(self.read_u64()? as i64)
+	}
+
+	fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_u8(self.read_u8()?)
+	}
+
+	fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_u16(self.read_u16()?)
+	}
+
+	fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_u32(self.read_u32()?)
+	}
+
+	fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_u64(self.read_u64()?)
+	}
+
+	fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_f32(f32::from_bits(self.read_u32()?))
+	}
+
+	fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_f64(f64::from_bits(self.read_u64()?))
+	}
+
+	fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		let number = self.read_u32()?;
+		match char::from_u32(number) {
+			Some(character) => visitor.visit_char(character),
+			None => Err(SerdeError::InvalidChar(number)),
+		}
+	}
+
+	fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_string(self.read_string()?)
+	}
+
+	fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_string(self.read_string()?)
+	}
+
+	fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_byte_buf(self.read_bytes()?)
+	}
+
+	fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_byte_buf(self.read_bytes()?)
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		match self.read_u8()? {
+			0 => visitor.visit_none(),
+			1 => visitor.visit_some(self),
+			byte => Err(SerdeError::InvalidOption(byte)),
+		}
+	}
+
+	fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_unit_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_unit()
+	}
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(self, _: &'static str, visitor: V)
+		-> Result<V::Value, Self::Error>
+	{
+		visitor.visit_newtype_struct(self)
+	}
+
+	fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		let length = self.read_vlq()? as usize;
+		visitor.visit_seq(CountedAccess {
+			deserializer: self,
+			remaining: length,
+		})
+	}
+
+	fn deserialize_tuple<V: Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value, Self::Error> {
+		visitor.visit_seq(CountedAccess {
+			deserializer: self,
+			remaining: length,
+		})
+	}
+
+	fn deserialize_tuple_struct<V: Visitor<'de>>(self, _: &'static str, length: usize, visitor: V)
+		-> Result<V::Value, Self::Error>
+	{
+		self.deserialize_tuple(length, visitor)
+	}
+
+	fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+		let length = self.read_vlq()? as usize;
+		visitor.visit_map(CountedAccess {
+			deserializer: self,
+			remaining: length,
+		})
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(self, _: &'static str, fields: &'static [&'static str], visitor: V)
+		-> Result<V::Value, Self::Error>
+	{
+		self.deserialize_tuple(fields.len(), visitor)
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(
+		self, _: &'static str, _: &'static [&'static str], visitor: V
+	) -> Result<V::Value, Self::Error> {
+		visitor.visit_enum(self)
+	}
+
+	fn deserialize_identifier<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+		Err(SerdeError::NotSelfDescribing)
+	}
+
+	fn deserialize_ignored_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+		Err(SerdeError::NotSelfDescribing)
+	}
+
+	fn is_human_readable(&self) -> bool {
+		false
+	}
+}
+
+/// Gives serde access to a sequence or map with a known amount of elements.
+struct CountedAccess<'b, 'a, S, Error> {
+	deserializer: &'b mut StreamDeserializer<'a, S, Error>,
+	remaining: usize,
+}
+
+impl<'de, 'a, 'b, S, Error> SeqAccess<'de> for CountedAccess<'b, 'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	type Error = SerdeError<Error>;
+
+	fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+		if self.remaining == 0 {
+			return Ok(None);
+		}
+
+		self.remaining -= 1;
+		seed.deserialize(&mut *self.deserializer).map(Some)
+	}
+
+	fn size_hint(&self) -> Option<usize> {
+		Some(self.remaining)
+	}
+}
+
+impl<'de, 'a, 'b, S, Error> MapAccess<'de> for CountedAccess<'b, 'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	type Error = SerdeError<Error>;
+
+	fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+		if self.remaining == 0 {
+			return Ok(None);
+		}
+
+		self.remaining -= 1;
+		seed.deserialize(&mut *self.deserializer).map(Some)
+	}
+
+	fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+		seed.deserialize(&mut *self.deserializer)
+	}
+
+	fn size_hint(&self) -> Option<usize> {
+		Some(self.remaining)
+	}
+}
+
+impl<'de, 'a, 'b, S, Error> EnumAccess<'de> for &'b mut StreamDeserializer<'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	type Error = SerdeError<Error>;
+	type Variant = Self;
+
+	fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Self::Error> {
+		let variant_index = self.read_vlq()?;
+		let variant_index = u32::try_from(variant_index).map_err(|_| SerdeError::InvalidVariant(variant_index))?;
+		let value = seed.deserialize(variant_index.into_deserializer())?;
+		Ok((value, self))
+	}
+}
+
+impl<'de, 'a, 'b, S, Error> VariantAccess<'de> for &'b mut StreamDeserializer<'a, S, Error>
+where
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	type Error = SerdeError<Error>;
+
+	fn unit_variant(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Self::Error> {
+		seed.deserialize(self)
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(self, length: usize, visitor: V) -> Result<V::Value, Self::Error> {
+		serde::Deserializer::deserialize_tuple(self, length, visitor)
+	}
+
+	fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V)
+		-> Result<V::Value, Self::Error>
+	{
+		serde::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+	}
+}
@@ -0,0 +1,287 @@
+pub mod de;
+pub mod ser;
+
+pub use de::StreamDeserializer;
+pub use ser::StreamSerializer;
+
+use serde::{ Deserialize, Serialize, };
+use serde::de::DeserializeOwned;
+use std::fmt::{ Debug, Display, };
+
+use crate::u8_io::{ U8ReadStream, U8WriteStream, };
+
+/// Emitted when serde serialization/deserialization fails. Errors emitted by the underlying stream are wrapped in
+/// `SerdeError::Stream`.
+#[derive(Debug)]
+pub enum SerdeError<Error> {
+	/// Error message from a `Serialize`/`Deserialize` implementation.
+	Custom(String),
+	/// Decoded a `bool` that was not a 0 or a 1.
+	InvalidBool(u8),
+	/// Decoded a `char` that was not a valid unicode scalar value.
+	InvalidChar(u32),
+	/// Decoded an `Option` tag that was not a 0 or a 1.
+	InvalidOption(u8),
+	/// Decoded a string that was not valid UTF-8.
+	InvalidUtf8,
+	/// Decoded an enum variant index that does not fit into a `u32`.
+	InvalidVariant(u64),
+	/// The encoding does not describe its own types, so it cannot be deserialized without knowing the type ahead of time.
+	NotSelfDescribing,
+	/// Decoded a string table ID that was not in the string table.
+	NotInStringTable(u64),
+	/// Error emitted by the underlying stream.
+	Stream(Error),
+	/// Sequences and maps need to know their length before they are serialized.
+	UnknownLength,
+}
+
+impl<Error: Debug> Display for SerdeError<Error> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SerdeError::Custom(message) => write!(f, "{}", message),
+			error => write!(f, "{:?}", error),
+		}
+	}
+}
+
+impl<Error: Debug> std::error::Error for SerdeError<Error> {}
+
+impl<Error: Debug> serde::ser::Error for SerdeError<Error> {
+	fn custom<T: Display>(message: T) -> Self {
+		SerdeError::Custom(message.to_string())
+	}
+}
+
+impl<Error: Debug> serde::de::Error for SerdeError<Error> {
+	fn custom<T: Display>(message: T) -> Self {
+		SerdeError::Custom(message.to_string())
+	}
+}
+
+/// Describes a string table that serde strings can be stored in. String tables let repeated strings be encoded once,
+/// with every use of the string encoded as the string's ID.
+pub trait SerdeStringTable {
+	/// Get the ID of a string, inserting the string into the table if it is not already in it.
+	fn get_or_insert(&mut self, string: &str) -> u64;
+
+	/// Get a string by ID.
+	fn get_string(&self, id: u64) -> Option<&str>;
+}
+
+/// Serialize a value into a stream. Strings are written inline.
+pub fn serialize_into<T, S, Error>(stream: &mut S, value: &T) -> Result<(), SerdeError<Error>>
+where
+	T: ?Sized + Serialize,
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	value.serialize(&mut StreamSerializer::new(stream))
+}
+
+/// Serialize a value into a stream. Strings are written into the string table, and only their IDs are written into the
+/// stream.
+pub fn serialize_into_with_string_table<T, S, Error>(
+	stream: &mut S, value: &T, string_table: &mut dyn SerdeStringTable
+) -> Result<(), SerdeError<Error>>
+where
+	T: ?Sized + Serialize,
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	value.serialize(&mut StreamSerializer::with_string_table(stream, string_table))
+}
+
+/// Deserialize a value out of a stream that was written using `serialize_into`.
+pub fn deserialize_from<T, S, Error>(stream: &mut S) -> Result<T, SerdeError<Error>>
+where
+	T: DeserializeOwned,
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	T::deserialize(&mut StreamDeserializer::new(stream))
+}
+
+/// Deserialize a value out of a stream that was written using `serialize_into_with_string_table`.
+pub fn deserialize_from_with_string_table<'de, T, S, Error>(stream: &mut S, string_table: &dyn SerdeStringTable)
+	-> Result<T, SerdeError<Error>>
+where
+	T: Deserialize<'de>,
+	S: U8ReadStream<Error>,
+	Error: Debug
+{
+	T::deserialize(&mut StreamDeserializer::with_string_table(stream, string_table))
+}
+
+/// Test serializing derived types into streams.
+#[cfg(test)]
+mod tests {
+	use serde::{ Deserialize, Serialize, };
+	use std::collections::{ BTreeMap, HashMap, };
+
+	use crate::{ ReadStream, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8WriteStream, };
+
+	use super::{
+		SerdeError,
+		SerdeStringTable,
+		deserialize_from,
+		deserialize_from_with_string_table,
+		serialize_into,
+		serialize_into_with_string_table,
+	};
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io,
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(_: std::io::Error) -> Self {
+			TestStreamError::Io
+		}
+	}
+
+	#[derive(Debug, Default)]
+	struct TestStringTable {
+		mapping: HashMap<String, u64>,
+		strings: Vec<String>,
+	}
+
+	impl SerdeStringTable for TestStringTable {
+		fn get_or_insert(&mut self, string: &str) -> u64 {
+			if let Some(id) = self.mapping.get(string) {
+				return *id;
+			}
+
+			let id = self.strings.len() as u64;
+			self.mapping.insert(String::from(string), id);
+			self.strings.push(String::from(string));
+			id
+		}
+
+		fn get_string(&self, id: u64) -> Option<&str> {
+			self.strings.get(id as usize).map(|string| string.as_str())
+		}
+	}
+
+	#[derive(Debug, Deserialize, PartialEq, Serialize)]
+	enum Shape {
+		Empty,
+		Circle(f32),
+		Rectangle(f32, f32),
+		Polygon { name: String, points: Vec<(i32, i32)> },
+	}
+
+	#[derive(Debug, Deserialize, PartialEq, Serialize)]
+	struct Unit;
+
+	#[derive(Debug, Deserialize, PartialEq, Serialize)]
+	struct Meters(f64);
+
+	#[derive(Debug, Deserialize, PartialEq, Serialize)]
+	struct Config {
+		bytes: Vec<u8>,
+		character: char,
+		enabled: bool,
+		height: Meters,
+		name: String,
+		nickname: Option<String>,
+		shapes: Vec<Shape>,
+		signed: (i8, i16, i32, i64),
+		tags: BTreeMap<String, String>,
+		title: Option<String>,
+		unit: Unit,
+		unsigned: (u8, u16, u32, u64),
+	}
+
+	fn test_config() -> Config {
+		Config {
+			bytes: vec![0, 1, 2, 255],
+			character: 'ü',
+			enabled: true,
+			height: Meters(1.85),
+			name: String::from("héllo wörld"),
+			nickname: None,
+			shapes: vec![
+				Shape::Empty,
+				Shape::Circle(2.5),
+				Shape::Rectangle(1.0, -3.0),
+				Shape::Polygon {
+					name: String::from("triangle"),
+					points: vec![(0, 0), (1, 0), (0, -1)],
+				},
+			],
+			signed: (-7, -1589, -96892, -906_543_840_289),
+			tags: BTreeMap::from([
+				(String::from("team"), String::from("triangle")),
+				(String::from("shape"), String::from("triangle")),
+			]),
+			title: Some(String::from("egg")),
+			unit: Unit,
+			unsigned: (1, 5814, 100019, u64::MAX),
+		}
+	}
+
+	fn import(write_stream: &mut U8BufferWriteStream<TestStreamError>) -> U8BufferReadStream<TestStreamError> {
+		let mut read_stream = U8BufferReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+		read_stream
+	}
+
+	/// Ensure that derived types round trip through a stream with strings written inline.
+	#[test]
+	fn round_trip() {
+		let mut write_stream = U8BufferWriteStream::new();
+		serialize_into(&mut write_stream, &test_config()).expect("Could not serialize Config");
+		write_stream.write_u32(0xBEEF_BEEF).expect("Could not write sentinel");
+
+		let mut read_stream = import(&mut write_stream);
+		let config = deserialize_from::<Config, _, _>(&mut read_stream).expect("Could not deserialize Config");
+		assert_eq!(config, test_config());
+		assert_eq!(read_stream.read_u32().expect("Could not read sentinel").0, 0xBEEF_BEEF);
+	}
+
+	/// Ensure that derived types round trip through a stream with strings written into a string table, and that repeated
+	/// strings are only stored once.
+	#[test]
+	fn round_trip_with_string_table() {
+		let mut string_table = TestStringTable::default();
+		let mut write_stream = U8BufferWriteStream::new();
+		serialize_into_with_string_table(&mut write_stream, &test_config(), &mut string_table)
+			.expect("Could not serialize Config");
+
+		assert_eq!(string_table.strings.len(), 5);
+
+		let mut read_stream = import(&mut write_stream);
+		let config = deserialize_from_with_string_table::<Config, _, _>(&mut read_stream, &string_table)
+			.expect("Could not deserialize Config");
+		assert_eq!(config, test_config());
+	}
+
+	/// Ensure that invalid data emits errors instead of panicking.
+	#[test]
+	fn invalid_data() {
+		let mut write_stream = U8BufferWriteStream::new();
+		write_stream.write_u8(2).expect("Could not write number");
+
+		let mut read_stream = import(&mut write_stream);
+		let result = deserialize_from::<bool, _, _>(&mut read_stream);
+		assert!(matches!(result, Err(SerdeError::InvalidBool(2))));
+
+		let mut write_stream = U8BufferWriteStream::new();
+		write_stream.write_vlq(0).expect("Could not write number");
+
+		let mut read_stream = import(&mut write_stream);
+		let result = deserialize_from_with_string_table::<String, _, _>(&mut read_stream, &TestStringTable::default());
+		assert!(matches!(result, Err(SerdeError::NotInStringTable(0))));
+
+		let mut write_stream = U8BufferWriteStream::new();
+		write_stream.write_vlq(u32::MAX as u64 + 1).expect("Could not write number");
+
+		let mut read_stream = import(&mut write_stream);
+		let result = deserialize_from::<Shape, _, _>(&mut read_stream);
+		assert!(matches!(result, Err(SerdeError::InvalidVariant(index)) if index == u32::MAX as u64 + 1));
+	}
+}
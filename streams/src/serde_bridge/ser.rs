@@ -0,0 +1,336 @@
+use serde::ser::{
+	Serialize,
+	SerializeMap,
+	SerializeSeq,
+	SerializeStruct,
+	SerializeStructVariant,
+	SerializeTuple,
+	SerializeTupleStruct,
+	SerializeTupleVariant,
+};
+use std::fmt::Debug;
+use std::marker::PhantomData;
+
+use crate::u8_io::U8WriteStream;
+
+use super::{ SerdeError, SerdeStringTable, };
+
+/// Serde `Serializer` that encodes values into a `U8WriteStream`. The encoding is compact and is not self-describing,
+/// so values have to be deserialized into the same type they were serialized from. Lengths and enum variant indices
+/// are written as variable length integers. If a string table is supplied, strings are written as string table IDs.
+pub struct StreamSerializer<'a, S, Error> {
+	error: PhantomData<fn() -> Error>,
+	stream: &'a mut S,
+	string_table: Option<&'a mut dyn SerdeStringTable>,
+}
+
+impl<'a, S, Error> StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	/// Create a serializer that writes strings inline.
+	pub fn new(stream: &'a mut S) -> Self {
+		StreamSerializer {
+			error: PhantomData,
+			stream,
+			string_table: None,
+		}
+	}
+
+	/// Create a serializer that writes strings into the supplied string table.
+	pub fn with_string_table(stream: &'a mut S, string_table: &'a mut dyn SerdeStringTable) -> Self {
+		StreamSerializer {
+			error: PhantomData,
+			stream,
+			string_table: Some(string_table),
+		}
+	}
+
+	fn write_vlq(&mut self, number: u64) -> Result<(), SerdeError<Error>> {
+		self.stream.write_vlq(number).map_err(SerdeError::Stream)
+	}
+
+	fn write_length(&mut self, length: Option<usize>) -> Result<(), SerdeError<Error>> {
+		match length {
+			Some(length) => self.write_vlq(length as u64),
+			None => Err(SerdeError::UnknownLength),
+		}
+	}
+}
+
+impl<'a, 'b, S, Error> serde::Serializer for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	type SerializeSeq = Self;
+	type SerializeTuple = Self;
+	type SerializeTupleStruct = Self;
+	type SerializeTupleVariant = Self;
+	type SerializeMap = Self;
+	type SerializeStruct = Self;
+	type SerializeStructVariant = Self;
+
+	fn serialize_bool(self, value: bool) -> Result<(), Self::Error> {
+		self.stream.write_u8(value as u8).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_i8(self, value: i8) -> Result<(), Self::Error> {
+		self.stream.write_u8(value as u8).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_i16(self, value: i16) -> Result<(), Self::Error> {
+		self.stream.write_u16(value as u16).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_i32(self, value: i32) -> Result<(), Self::Error> {
+		self.stream.write_u32(value as u32).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_i64(self, value: i64) -> Result<(), Self::Error> {
+		self.stream.write_u64(value as u64).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_u8(self, value: u8) -> Result<(), Self::Error> {
+		self.stream.write_u8(value).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_u16(self, value: u16) -> Result<(), Self::Error> {
+		self.stream.write_u16(value).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_u32(self, value: u32) -> Result<(), Self::Error> {
+		self.stream.write_u32(value).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_u64(self, value: u64) -> Result<(), Self::Error> {
+		self.stream.write_u64(value).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_f32(self, value: f32) -> Result<(), Self::Error> {
+		self.stream.write_u32(value.to_bits()).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_f64(self, value: f64) -> Result<(), Self::Error> {
+		self.stream.write_u64(value.to_bits()).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_char(self, value: char) -> Result<(), Self::Error> {
+		self.stream.write_u32(value as u32).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_str(self, value: &str) -> Result<(), Self::Error> {
+		if let Some(string_table) = self.string_table.as_mut() {
+			let id = string_table.get_or_insert(value);
+			self.write_vlq(id)
+		} else {
+			self.serialize_bytes(value.as_bytes())
+		}
+	}
+
+	fn serialize_bytes(self, value: &[u8]) -> Result<(), Self::Error> {
+		self.write_vlq(value.len() as u64)?;
+		self.stream.write_vector(&value.to_vec()).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_none(self) -> Result<(), Self::Error> {
+		self.stream.write_u8(0).map_err(SerdeError::Stream)
+	}
+
+	fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Self::Error> {
+		self.stream.write_u8(1).map_err(SerdeError::Stream)?;
+		value.serialize(self)
+	}
+
+	fn serialize_unit(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn serialize_unit_struct(self, _: &'static str) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	fn serialize_unit_variant(self, _: &'static str, variant_index: u32, _: &'static str)
+		-> Result<(), Self::Error>
+	{
+		self.write_vlq(variant_index as u64)
+	}
+
+	fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, value: &T) -> Result<(), Self::Error> {
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: ?Sized + Serialize>(
+		self, _: &'static str, variant_index: u32, _: &'static str, value: &T
+	) -> Result<(), Self::Error> {
+		self.write_vlq(variant_index as u64)?;
+		value.serialize(self)
+	}
+
+	fn serialize_seq(self, length: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+		self.write_length(length)?;
+		Ok(self)
+	}
+
+	fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Self::Error> {
+		Ok(self)
+	}
+
+	fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+		Ok(self)
+	}
+
+	fn serialize_tuple_variant(self, _: &'static str, variant_index: u32, _: &'static str, _: usize)
+		-> Result<Self::SerializeTupleVariant, Self::Error>
+	{
+		self.write_vlq(variant_index as u64)?;
+		Ok(self)
+	}
+
+	fn serialize_map(self, length: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+		self.write_length(length)?;
+		Ok(self)
+	}
+
+	fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Self::Error> {
+		Ok(self)
+	}
+
+	fn serialize_struct_variant(self, _: &'static str, variant_index: u32, _: &'static str, _: usize)
+		-> Result<Self::SerializeStructVariant, Self::Error>
+	{
+		self.write_vlq(variant_index as u64)?;
+		Ok(self)
+	}
+
+	fn is_human_readable(&self) -> bool {
+		false
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeSeq for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeTuple for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeTupleStruct for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeTupleVariant for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeMap for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Self::Error> {
+		key.serialize(&mut **self)
+	}
+
+	fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeStruct for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_field<T: ?Sized + Serialize>(&mut self, _: &'static str, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
+
+impl<'a, 'b, S, Error> SerializeStructVariant for &'b mut StreamSerializer<'a, S, Error>
+where
+	S: U8WriteStream<Error>,
+	Error: Debug
+{
+	type Ok = ();
+	type Error = SerdeError<Error>;
+
+	fn serialize_field<T: ?Sized + Serialize>(&mut self, _: &'static str, value: &T) -> Result<(), Self::Error> {
+		value.serialize(&mut **self)
+	}
+
+	fn end(self) -> Result<(), Self::Error> {
+		Ok(())
+	}
+}
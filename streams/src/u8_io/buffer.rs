@@ -0,0 +1,278 @@
+use std::marker::PhantomData;
+
+use crate::{ Decode, Encode, EncodeMut, Endable, Peekable, ReadStream, Seekable, StreamPosition, WriteStream, };
+
+use super::reading::{ read_char, read_string, read_u8, read_u16, read_u32, read_u64, read_vlq, };
+use super::writing::{ write_char, write_string, write_u8, write_u16, write_u32, write_u64, write_vlq, };
+use super::{ U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+/// In-memory stream that encodes objects into a byte vector. Used when an object has to be fully encoded before it is
+/// written into another stream, like when the encoded object needs to be prefixed with its length. The stream never
+/// emits errors, so it can be used with any `Error` type.
+#[derive(Debug)]
+pub struct U8BufferWriteStream<Error> {
+	buffer: Vec<u8>,
+	error: PhantomData<fn() -> Error>,
+}
+
+impl<Error> U8BufferWriteStream<Error> {
+	pub fn new() -> Self {
+		U8BufferWriteStream {
+			buffer: Vec::new(),
+			error: PhantomData,
+		}
+	}
+
+	/// The amount of bytes written into the stream since the last export.
+	pub fn len(&self) -> usize {
+		self.buffer.len()
+	}
+
+	/// Whether or not anything was written into the stream since the last export.
+	pub fn is_empty(&self) -> bool {
+		self.buffer.is_empty()
+	}
+}
+
+impl<Error> Default for U8BufferWriteStream<Error> {
+	fn default() -> Self {
+		U8BufferWriteStream::new()
+	}
+}
+
+impl<Error> U8WriteStream<Error> for U8BufferWriteStream<Error> {
+	fn write_u8(&mut self, byte: u8) -> Result<(), Error> {
+		write_u8(byte, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_char(&mut self, character: char) -> Result<(), Error> {
+		write_char(character, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_u16(&mut self, number: u16) -> Result<(), Error> {
+		write_u16(number, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_u32(&mut self, number: u32) -> Result<(), Error> {
+		write_u32(number, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_u64(&mut self, number: u64) -> Result<(), Error> {
+		write_u64(number, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_vlq(&mut self, number: u64) -> Result<(), Error> {
+		write_vlq(number, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_string(&mut self, string: &str) -> Result<(), Error> {
+		write_string(string, &mut self.buffer);
+		Ok(())
+	}
+
+	fn write_vector(&mut self, vector: &Vec<u8>) -> Result<(), Error> {
+		self.buffer.extend(vector);
+		Ok(())
+	}
+}
+
+impl<Error> WriteStream<u8, Error> for U8BufferWriteStream<Error> {
+	type Export = Vec<u8>;
+
+	fn encode_mut<T: EncodeMut<u8, Self, Error>>(&mut self, object: &mut T) -> Result<(), Error> {
+		object.encode_mut(self)
+	}
+
+	fn encode<T: Encode<u8, Self, Error>>(&mut self, object: &T) -> Result<(), Error> {
+		object.encode(self)
+	}
+
+	fn export(&mut self) -> Result<Self::Export, Error> {
+		Ok(std::mem::take(&mut self.buffer))
+	}
+
+	fn can_export(&self) -> bool {
+		!self.buffer.is_empty()
+	}
+}
+
+/// In-memory stream that decodes objects out of a byte vector. Reading past the end of the imported vector emits an
+/// `UnexpectedEof` error, since the vector may have been truncated or corrupted. Decoders that expect to run out of data
+/// should check `Endable::is_at_end` first.
+#[derive(Debug)]
+pub struct U8BufferReadStream<Error> {
+	buffer: Vec<u8>,
+	error: PhantomData<fn() -> Error>,
+	position: StreamPosition,
+}
+
+impl<Error> U8BufferReadStream<Error> {
+	pub fn new() -> Self {
+		U8BufferReadStream {
+			buffer: Vec::new(),
+			error: PhantomData,
+			position: 0,
+		}
+	}
+
+	/// The amount of bytes left to read in the stream.
+	pub fn remaining(&self) -> usize {
+		self.buffer.len().saturating_sub(self.position as usize)
+	}
+}
+
+impl<Error> U8BufferReadStream<Error>
+where
+	Error: From<std::io::Error>
+{
+	/// Make sure there are enough bytes left in the buffer to read the specified amount of bytes.
+	fn check_remaining(&self, length: usize) -> Result<(), Error> {
+		if length > self.remaining() {
+			Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into())
+		} else {
+			Ok(())
+		}
+	}
+}
+
+impl<Error> Default for U8BufferReadStream<Error> {
+	fn default() -> Self {
+		U8BufferReadStream::new()
+	}
+}
+
+impl<Error> U8ReadStream<Error> for U8BufferReadStream<Error>
+where
+	Error: From<std::io::Error>
+{
+	fn read_u8(&mut self) -> Result<(u8, StreamPosition), Error> {
+		self.check_remaining(1)?;
+		let (byte, delta) = read_u8(&self.buffer[self.position as usize..]);
+		self.position += delta;
+		Ok((byte, self.position))
+	}
+
+	fn read_char(&mut self) -> Result<(char, StreamPosition), Error> {
+		self.check_remaining(1)?;
+		let (character, delta) = read_char(&self.buffer[self.position as usize..]);
+		self.position += delta;
+		Ok((character, self.position))
+	}
+
+	fn read_u16(&mut self) -> Result<(u16, StreamPosition), Error> {
+		self.check_remaining(2)?;
+		let (number, delta) = read_u16(&self.buffer[self.position as usize..]);
+		self.position += delta;
+		Ok((number, self.position))
+	}
+
+	fn read_u32(&mut self) -> Result<(u32, StreamPosition), Error> {
+		self.check_remaining(4)?;
+		let (number, delta) = read_u32(&self.buffer[self.position as usize..]);
+		self.position += delta;
+		Ok((number, self.position))
+	}
+
+	fn read_u64(&mut self) -> Result<(u64, StreamPosition), Error> {
+		self.check_remaining(8)?;
+		let (number, delta) = read_u64(&self.buffer[self.position as usize..]);
+		self.position += delta;
+		Ok((number, self.position))
+	}
+
+	fn read_vlq(&mut self) -> Result<(u64, StreamPosition), Error> {
+		// the integer is between 2 and 8 bytes long, so only check for the bytes we are about to read
+		let remaining = self.remaining();
+		let (number, delta) = if remaining >= 8 {
+			read_vlq(&self.buffer[self.position as usize..])
+		} else {
+			let mut padded = self.buffer[self.buffer.len() - remaining..].to_vec();
+			padded.resize(8, 0);
+
+			let (number, delta) = read_vlq(&padded);
+			self.check_remaining(delta as usize)?;
+			(number, delta)
+		};
+
+		self.position += delta;
+		Ok((number, self.position))
+	}
+
+	fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), Error> {
+		self.check_remaining(length)?;
+		let start = self.position as usize;
+		let output = self.buffer[start..start + length].to_vec();
+		self.position += length as StreamPosition;
+		Ok((output, self.position))
+	}
+}
+
+impl<Error> U8ReadStringStream<Error> for U8BufferReadStream<Error>
+where
+	Error: From<std::io::Error>
+{
+	fn read_string(&mut self) -> Result<(String, StreamPosition), Error> {
+		// make sure the entire string is in the buffer before we read it
+		let start = self.position;
+		let (length, _) = self.read_vlq()?;
+		let length = usize::try_from(length).unwrap_or(usize::MAX);
+		self.check_remaining(length)?;
+		self.position = start;
+
+		let (string, delta) = read_string(&self.buffer[self.position as usize..])
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+		self.position += delta;
+		Ok((string, self.position))
+	}
+}
+
+impl<Error> ReadStream<u8, Error> for U8BufferReadStream<Error> {
+	type Import = Vec<u8>;
+
+	fn decode<T: Decode<u8, Self, Error>>(&mut self) -> Result<(T, StreamPosition), Error> {
+		T::decode(self)
+	}
+
+	fn can_decode(&self) -> bool {
+		self.remaining() > 0
+	}
+
+	fn import(&mut self, buffer: Self::Import) -> Result<(), Error> {
+		self.buffer = buffer;
+		self.position = 0;
+		Ok(())
+	}
+}
+
+impl<Error> Endable<Error> for U8BufferReadStream<Error> {
+	fn is_at_end(&mut self) -> Result<bool, Error> {
+		Ok(self.position >= self.buffer.len() as StreamPosition)
+	}
+}
+
+impl<Error> Peekable<u8, Error> for U8BufferReadStream<Error>
+where
+	Error: From<std::io::Error>
+{
+	fn peek(&mut self) -> Result<u8, Error> {
+		self.check_remaining(1)?;
+		Ok(self.buffer[self.position as usize])
+	}
+}
+
+impl<Error> Seekable<Error> for U8BufferReadStream<Error> {
+	fn seek(&mut self, position: StreamPosition) -> Result<(), Error> {
+		self.position = position;
+		Ok(())
+	}
+
+	fn get_position(&mut self) -> Result<StreamPosition, Error> {
+		Ok(self.position)
+	}
+}
@@ -0,0 +1,408 @@
+use std::marker::PhantomData;
+
+use crate::{ Decode, Encode, EncodeMut, Endable, ReadStream, StreamPosition, WriteStream, };
+
+use super::writing::{ write_char, write_string, write_u16, write_u32, write_u64, write_vlq, };
+use super::{ U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+/// Hash function that checksum streams accumulate written/read bytes into.
+pub trait Checksum: Default {
+	/// Accumulate bytes into the checksum.
+	fn update(&mut self, bytes: &[u8]);
+
+	/// The checksum of all bytes accumulated since the last reset.
+	fn finish(&self) -> u64;
+
+	/// Reset the checksum to its initial state.
+	fn reset(&mut self);
+}
+
+/// CRC-32 checksum. Cheap to compute and catches corruption, but is only 32 bits wide.
+#[derive(Debug, Default)]
+pub struct Crc32 {
+	hasher: crc32fast::Hasher,
+}
+
+impl Checksum for Crc32 {
+	fn update(&mut self, bytes: &[u8]) {
+		self.hasher.update(bytes);
+	}
+
+	fn finish(&self) -> u64 {
+		self.hasher.clone().finalize() as u64
+	}
+
+	fn reset(&mut self) {
+		self.hasher.reset();
+	}
+}
+
+/// 64-bit xxHash checksum.
+pub struct XxHash64 {
+	hasher: xxhash_rust::xxh64::Xxh64,
+}
+
+impl Default for XxHash64 {
+	fn default() -> Self {
+		XxHash64 {
+			hasher: xxhash_rust::xxh64::Xxh64::new(0),
+		}
+	}
+}
+
+impl Checksum for XxHash64 {
+	fn update(&mut self, bytes: &[u8]) {
+		self.hasher.update(bytes);
+	}
+
+	fn finish(&self) -> u64 {
+		self.hasher.digest()
+	}
+
+	fn reset(&mut self) {
+		self.hasher.reset(0);
+	}
+}
+
+/// Write stream wrapper that accumulates a checksum of everything written into the wrapped stream. The checksum is
+/// appended to the wrapped stream with `write_checksum`, and verified on the other end using `ChecksumReadStream`.
+#[derive(Debug)]
+pub struct ChecksumWriteStream<S, C, Error> {
+	checksum: C,
+	error: PhantomData<fn() -> Error>,
+	scratch: Vec<u8>,
+	stream: S,
+}
+
+impl<S, C, Error> ChecksumWriteStream<S, C, Error>
+where
+	S: U8WriteStream<Error>,
+	C: Checksum
+{
+	pub fn new(stream: S) -> Self {
+		ChecksumWriteStream {
+			checksum: C::default(),
+			error: PhantomData,
+			scratch: Vec::new(),
+			stream,
+		}
+	}
+
+	/// The checksum of everything written since the last call to `write_checksum`.
+	pub fn checksum(&self) -> u64 {
+		self.checksum.finish()
+	}
+
+	/// Write the checksum into the wrapped stream as a little-endian `u64`, and reset it. The checksum itself is not
+	/// accumulated into the next checksum.
+	pub fn write_checksum(&mut self) -> Result<(), Error> {
+		let checksum = self.checksum.finish();
+		self.checksum.reset();
+		self.stream.write_u64(checksum)
+	}
+
+	pub fn get_stream(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	pub fn into_stream(self) -> S {
+		self.stream
+	}
+
+	// write the scratch buffer into the wrapped stream, and accumulate it into the checksum if the write succeeded
+	fn flush_scratch(&mut self) -> Result<(), Error> {
+		let result = self.stream.write_vector(&self.scratch);
+		if result.is_ok() {
+			self.checksum.update(&self.scratch);
+		}
+
+		self.scratch.clear();
+		result
+	}
+}
+
+impl<S, C, Error> U8WriteStream<Error> for ChecksumWriteStream<S, C, Error>
+where
+	S: U8WriteStream<Error>,
+	C: Checksum
+{
+	fn write_u8(&mut self, byte: u8) -> Result<(), Error> {
+		self.stream.write_u8(byte)?;
+		self.checksum.update(&[byte]);
+		Ok(())
+	}
+
+	fn write_char(&mut self, character: char) -> Result<(), Error> {
+		write_char(character, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_u16(&mut self, number: u16) -> Result<(), Error> {
+		write_u16(number, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_u32(&mut self, number: u32) -> Result<(), Error> {
+		write_u32(number, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_u64(&mut self, number: u64) -> Result<(), Error> {
+		write_u64(number, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_vlq(&mut self, number: u64) -> Result<(), Error> {
+		write_vlq(number, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_string(&mut self, string: &str) -> Result<(), Error> {
+		write_string(string, &mut self.scratch);
+		self.flush_scratch()
+	}
+
+	fn write_vector(&mut self, vector: &Vec<u8>) -> Result<(), Error> {
+		self.stream.write_vector(vector)?;
+		self.checksum.update(vector);
+		Ok(())
+	}
+}
+
+impl<S, C, Error> WriteStream<u8, Error> for ChecksumWriteStream<S, C, Error>
+where
+	S: U8WriteStream<Error> + WriteStream<u8, Error>,
+	C: Checksum
+{
+	type Export = S::Export;
+
+	fn encode_mut<T: EncodeMut<u8, Self, Error>>(&mut self, object: &mut T) -> Result<(), Error> {
+		object.encode_mut(self)
+	}
+
+	fn encode<T: Encode<u8, Self, Error>>(&mut self, object: &T) -> Result<(), Error> {
+		object.encode(self)
+	}
+
+	fn export(&mut self) -> Result<Self::Export, Error> {
+		self.stream.export()
+	}
+
+	fn can_export(&self) -> bool {
+		self.stream.can_export()
+	}
+}
+
+/// Read stream wrapper that accumulates a checksum of everything read out of the wrapped stream. Once the data covered
+/// by a checksum has been read, `verify_checksum` reads the checksum written by `ChecksumWriteStream::write_checksum`
+/// and compares it against the accumulated one.
+#[derive(Debug)]
+pub struct ChecksumReadStream<S, C, Error> {
+	checksum: C,
+	error: PhantomData<fn() -> Error>,
+	stream: S,
+}
+
+impl<S, C, Error> ChecksumReadStream<S, C, Error>
+where
+	S: U8ReadStream<Error>,
+	C: Checksum
+{
+	pub fn new(stream: S) -> Self {
+		ChecksumReadStream {
+			checksum: C::default(),
+			error: PhantomData,
+			stream,
+		}
+	}
+
+	/// The checksum of everything read since the last call to `verify_checksum`.
+	pub fn checksum(&self) -> u64 {
+		self.checksum.finish()
+	}
+
+	/// Read the checksum out of the wrapped stream and compare it against the accumulated checksum, then reset the
+	/// accumulated checksum. Returns false if the data was corrupted.
+	pub fn verify_checksum(&mut self) -> Result<(bool, StreamPosition), Error> {
+		let expected = self.checksum.finish();
+		self.checksum.reset();
+		let (checksum, position) = self.stream.read_u64()?;
+		Ok((checksum == expected, position))
+	}
+
+	pub fn get_stream(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	pub fn into_stream(self) -> S {
+		self.stream
+	}
+}
+
+impl<S, C, Error> U8ReadStream<Error> for ChecksumReadStream<S, C, Error>
+where
+	S: U8ReadStream<Error>,
+	C: Checksum
+{
+	fn read_u8(&mut self) -> Result<(u8, StreamPosition), Error> {
+		let (byte, position) = self.stream.read_u8()?;
+		self.checksum.update(&[byte]);
+		Ok((byte, position))
+	}
+
+	fn read_char(&mut self) -> Result<(char, StreamPosition), Error> {
+		let (byte, position) = self.read_u8()?;
+		Ok((byte as char, position))
+	}
+
+	fn read_u16(&mut self) -> Result<(u16, StreamPosition), Error> {
+		let (number, position) = self.stream.read_u16()?;
+		self.checksum.update(&number.to_le_bytes());
+		Ok((number, position))
+	}
+
+	fn read_u32(&mut self) -> Result<(u32, StreamPosition), Error> {
+		let (number, position) = self.stream.read_u32()?;
+		self.checksum.update(&number.to_le_bytes());
+		Ok((number, position))
+	}
+
+	fn read_u64(&mut self) -> Result<(u64, StreamPosition), Error> {
+		let (number, position) = self.stream.read_u64()?;
+		self.checksum.update(&number.to_le_bytes());
+		Ok((number, position))
+	}
+
+	fn read_vlq(&mut self) -> Result<(u64, StreamPosition), Error> {
+		// read the integer two bytes at a time so the checksum sees the exact bytes that were written
+		let mut number = 0;
+		let mut read = 0;
+		loop {
+			let (bytes, position) = self.read_u16()?;
+			number |= (bytes as u64 & 0x7FFF) << (read * 15);
+			read += 1;
+
+			if bytes & 0x8000 == 0 || read >= 4 {
+				return Ok((number, position));
+			}
+		}
+	}
+
+	fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), Error> {
+		let (vector, position) = self.stream.read_vector(length)?;
+		self.checksum.update(&vector);
+		Ok((vector, position))
+	}
+}
+
+impl<S, C, Error> U8ReadStringStream<Error> for ChecksumReadStream<S, C, Error>
+where
+	S: U8ReadStream<Error>,
+	C: Checksum,
+	Error: From<std::io::Error>
+{
+	fn read_string(&mut self) -> Result<(String, StreamPosition), Error> {
+		let (length, _) = self.read_vlq()?;
+		let (bytes, position) = self.read_vector(length as usize)?;
+		let string = String::from_utf8(bytes)
+			.map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+		Ok((string, position))
+	}
+}
+
+impl<S, C, Error> ReadStream<u8, Error> for ChecksumReadStream<S, C, Error>
+where
+	S: U8ReadStream<Error> + ReadStream<u8, Error>,
+	C: Checksum
+{
+	type Import = S::Import;
+
+	fn decode<T: Decode<u8, Self, Error>>(&mut self) -> Result<(T, StreamPosition), Error> {
+		T::decode(self)
+	}
+
+	fn can_decode(&self) -> bool {
+		self.stream.can_decode()
+	}
+
+	fn import(&mut self, import: Self::Import) -> Result<(), Error> {
+		self.checksum.reset();
+		self.stream.import(import)
+	}
+}
+
+impl<S, C, Error> Endable<Error> for ChecksumReadStream<S, C, Error>
+where
+	S: Endable<Error>
+{
+	fn is_at_end(&mut self) -> Result<bool, Error> {
+		self.stream.is_at_end()
+	}
+}
+
+/// Test detecting corrupted data.
+#[cfg(test)]
+mod tests {
+	use crate::{ ReadStream, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+	use super::{ Checksum, ChecksumReadStream, ChecksumWriteStream, Crc32, XxHash64, };
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io,
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(_: std::io::Error) -> Self {
+			TestStreamError::Io
+		}
+	}
+
+	fn write_test_data<C: Checksum>() -> Vec<u8> {
+		let mut write_stream = ChecksumWriteStream::<_, C, TestStreamError>::new(U8BufferWriteStream::new());
+		write_stream.write_u8(5).expect("Could not write number");
+		write_stream.write_u16(5814).expect("Could not write number");
+		write_stream.write_u32(100019).expect("Could not write number");
+		write_stream.write_u64(u64::MAX - 5).expect("Could not write number");
+		write_stream.write_vlq(1_930_283_129).expect("Could not write number");
+		write_stream.write_string("eggine").expect("Could not write string");
+		write_stream.write_string("héllo wörld").expect("Could not write string");
+		write_stream.write_vector(&vec![1, 2, 3]).expect("Could not write vector");
+		write_stream.write_checksum().expect("Could not write checksum");
+		write_stream.export().expect("Could not export test stream")
+	}
+
+	fn verify_test_data<C: Checksum>(bytes: Vec<u8>) -> bool {
+		let mut read_stream = ChecksumReadStream::<_, C, TestStreamError>::new(U8BufferReadStream::new());
+		read_stream.import(bytes).expect("Could not import test stream");
+		read_stream.read_u8().expect("Could not read number");
+		read_stream.read_u16().expect("Could not read number");
+		read_stream.read_u32().expect("Could not read number");
+		read_stream.read_u64().expect("Could not read number");
+		read_stream.read_vlq().expect("Could not read number");
+		assert_eq!(read_stream.read_string().expect("Could not read string").0, "eggine");
+		assert_eq!(read_stream.read_string().expect("Could not read string").0, "héllo wörld");
+		read_stream.read_vector(3).expect("Could not read vector");
+		read_stream.verify_checksum().expect("Could not read checksum").0
+	}
+
+	/// Ensure that the read stream accumulates the same checksum as the write stream.
+	#[test]
+	fn verify() {
+		assert!(verify_test_data::<Crc32>(write_test_data::<Crc32>()));
+		assert!(verify_test_data::<XxHash64>(write_test_data::<XxHash64>()));
+	}
+
+	/// Ensure that a corrupted byte fails verification.
+	#[test]
+	fn corrupted() {
+		let mut bytes = write_test_data::<Crc32>();
+		bytes[3] ^= 0x10;
+		assert!(!verify_test_data::<Crc32>(bytes));
+
+		let mut bytes = write_test_data::<XxHash64>();
+		bytes[20] ^= 0x01;
+		assert!(!verify_test_data::<XxHash64>(bytes));
+	}
+}
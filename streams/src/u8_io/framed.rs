@@ -0,0 +1,378 @@
+use std::marker::PhantomData;
+
+use crate::{ Decode, Encode, EncodeMut, Endable, ReadStream, StreamPosition, WriteStream, };
+
+use super::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+/// Write stream wrapper that length-prefixes each encoded object. Objects encoded with `WriteStream::encode` are
+/// buffered until they are fully encoded, and then written into the wrapped stream as a variable length integer followed
+/// by the object's bytes. Objects encoded by another object's encode implementation are part of the outer object's
+/// frame.
+#[derive(Debug)]
+pub struct FramedWriteStream<S, Error> {
+	depth: usize,
+	frame: U8BufferWriteStream<Error>,
+	stream: S,
+}
+
+impl<S, Error> FramedWriteStream<S, Error>
+where
+	S: U8WriteStream<Error>
+{
+	pub fn new(stream: S) -> Self {
+		FramedWriteStream {
+			depth: 0,
+			frame: U8BufferWriteStream::new(),
+			stream,
+		}
+	}
+
+	pub fn get_stream(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	pub fn into_stream(self) -> S {
+		self.stream
+	}
+
+	// write the frame into the wrapped stream once the outermost object is done encoding
+	fn finish_frame(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+		self.depth -= 1;
+		if self.depth != 0 {
+			return result;
+		}
+
+		let bytes = self.frame.export()?;
+		result?;
+
+		self.stream.write_vlq(bytes.len() as u64)?;
+		self.stream.write_vector(&bytes)
+	}
+}
+
+impl<S, Error> U8WriteStream<Error> for FramedWriteStream<S, Error> {
+	fn write_u8(&mut self, byte: u8) -> Result<(), Error> {
+		self.frame.write_u8(byte)
+	}
+
+	fn write_char(&mut self, character: char) -> Result<(), Error> {
+		self.frame.write_char(character)
+	}
+
+	fn write_u16(&mut self, number: u16) -> Result<(), Error> {
+		self.frame.write_u16(number)
+	}
+
+	fn write_u32(&mut self, number: u32) -> Result<(), Error> {
+		self.frame.write_u32(number)
+	}
+
+	fn write_u64(&mut self, number: u64) -> Result<(), Error> {
+		self.frame.write_u64(number)
+	}
+
+	fn write_vlq(&mut self, number: u64) -> Result<(), Error> {
+		self.frame.write_vlq(number)
+	}
+
+	fn write_string(&mut self, string: &str) -> Result<(), Error> {
+		self.frame.write_string(string)
+	}
+
+	fn write_vector(&mut self, vector: &Vec<u8>) -> Result<(), Error> {
+		self.frame.write_vector(vector)
+	}
+}
+
+impl<S, Error> WriteStream<u8, Error> for FramedWriteStream<S, Error>
+where
+	S: U8WriteStream<Error> + WriteStream<u8, Error>
+{
+	type Export = S::Export;
+
+	fn encode_mut<T: EncodeMut<u8, Self, Error>>(&mut self, object: &mut T) -> Result<(), Error> {
+		self.depth += 1;
+		let result = object.encode_mut(self);
+		self.finish_frame(result)
+	}
+
+	fn encode<T: Encode<u8, Self, Error>>(&mut self, object: &T) -> Result<(), Error> {
+		self.depth += 1;
+		let result = object.encode(self);
+		self.finish_frame(result)
+	}
+
+	fn export(&mut self) -> Result<Self::Export, Error> {
+		self.stream.export()
+	}
+
+	fn can_export(&self) -> bool {
+		self.stream.can_export()
+	}
+}
+
+/// Read stream wrapper that decodes objects written by `FramedWriteStream`. `ReadStream::decode` reads an entire frame
+/// out of the wrapped stream before decoding the object out of it, and skips whatever the decoder did not read. Decoders
+/// can test `Endable::is_at_end` to determine whether or not they have reached the end of the frame.
+#[derive(Debug)]
+pub struct FramedReadStream<S, Error> {
+	depth: usize,
+	error: PhantomData<fn() -> Error>,
+	frame: U8BufferReadStream<Error>,
+	stream: S,
+}
+
+impl<S, Error> FramedReadStream<S, Error>
+where
+	S: U8ReadStream<Error>
+{
+	pub fn new(stream: S) -> Self {
+		FramedReadStream {
+			depth: 0,
+			error: PhantomData,
+			frame: U8BufferReadStream::new(),
+			stream,
+		}
+	}
+
+	pub fn get_stream(&mut self) -> &mut S {
+		&mut self.stream
+	}
+
+	pub fn into_stream(self) -> S {
+		self.stream
+	}
+}
+
+impl<S, Error> U8ReadStream<Error> for FramedReadStream<S, Error>
+where
+	Error: From<std::io::Error>
+{
+	fn read_u8(&mut self) -> Result<(u8, StreamPosition), Error> {
+		self.frame.read_u8()
+	}
+
+	fn read_char(&mut self) -> Result<(char, StreamPosition), Error> {
+		self.frame.read_char()
+	}
+
+	fn read_u16(&mut self) -> Result<(u16, StreamPosition), Error> {
+		self.frame.read_u16()
+	}
+
+	fn read_u32(&mut self) -> Result<(u32, StreamPosition), Error> {
+		self.frame.read_u32()
+	}
+
+	fn read_u64(&mut self) -> Result<(u64, StreamPosition), Error> {
+		self.frame.read_u64()
+	}
+
+	fn read_vlq(&mut self) -> Result<(u64, StreamPosition), Error> {
+		self.frame.read_vlq()
+	}
+
+	fn read_vector(&mut self, length: usize) -> Result<(Vec<u8>, StreamPosition), Error> {
+		self.frame.read_vector(length)
+	}
+}
+
+impl<S, Error> U8ReadStringStream<Error> for FramedReadStream<S, Error>
+where
+	Error: From<std::io::Error>
+{
+	fn read_string(&mut self) -> Result<(String, StreamPosition), Error> {
+		self.frame.read_string()
+	}
+}
+
+impl<S, Error> ReadStream<u8, Error> for FramedReadStream<S, Error>
+where
+	S: U8ReadStream<Error> + ReadStream<u8, Error>
+{
+	type Import = S::Import;
+
+	fn decode<T: Decode<u8, Self, Error>>(&mut self) -> Result<(T, StreamPosition), Error> {
+		if self.depth != 0 {
+			return T::decode(self);
+		}
+
+		let (length, _) = self.stream.read_vlq()?;
+		let (bytes, position) = self.stream.read_vector(length as usize)?;
+		self.frame.import(bytes)?;
+
+		self.depth += 1;
+		let result = T::decode(self);
+		self.depth -= 1;
+
+		Ok((result?.0, position))
+	}
+
+	fn can_decode(&self) -> bool {
+		self.stream.can_decode()
+	}
+
+	fn import(&mut self, import: Self::Import) -> Result<(), Error> {
+		self.stream.import(import)
+	}
+}
+
+impl<S, Error> Endable<Error> for FramedReadStream<S, Error> {
+	fn is_at_end(&mut self) -> Result<bool, Error> {
+		self.frame.is_at_end()
+	}
+}
+
+/// Test framing objects.
+#[cfg(test)]
+mod tests {
+	use crate::{ Decode, Encode, Endable, ReadStream, StreamPosition, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+	use super::{ FramedReadStream, FramedWriteStream, };
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io,
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(_: std::io::Error) -> Self {
+			TestStreamError::Io
+		}
+	}
+
+	#[derive(Debug, Eq, PartialEq)]
+	struct Position {
+		x: u32,
+		y: u32,
+	}
+
+	impl<T> Encode<u8, T, TestStreamError> for Position
+	where
+		T: WriteStream<u8, TestStreamError> + U8WriteStream<TestStreamError>
+	{
+		fn encode(&self, stream: &mut T) -> Result<(), TestStreamError> {
+			stream.write_u32(self.x)?;
+			stream.write_u32(self.y)
+		}
+	}
+
+	impl<T> Decode<u8, T, TestStreamError> for Position
+	where
+		T: ReadStream<u8, TestStreamError> + U8ReadStream<TestStreamError>
+	{
+		fn decode(stream: &mut T) -> Result<(Self, StreamPosition), TestStreamError> {
+			let (x, _) = stream.read_u32()?;
+			let (y, position) = stream.read_u32()?;
+			Ok((Position { x, y, }, position))
+		}
+	}
+
+	#[derive(Debug, Eq, PartialEq)]
+	struct Player {
+		name: String,
+		position: Position,
+		score: Option<u64>,
+	}
+
+	impl<T> Encode<u8, T, TestStreamError> for Player
+	where
+		T: WriteStream<u8, TestStreamError> + U8WriteStream<TestStreamError>
+	{
+		fn encode(&self, stream: &mut T) -> Result<(), TestStreamError> {
+			stream.write_string(&self.name)?;
+			stream.encode(&self.position)?;
+			if let Some(score) = self.score {
+				stream.write_u64(score)?;
+			}
+			Ok(())
+		}
+	}
+
+	impl<T> Decode<u8, T, TestStreamError> for Player
+	where
+		T: ReadStream<u8, TestStreamError> + U8ReadStream<TestStreamError> + U8ReadStringStream<TestStreamError>
+			+ Endable<TestStreamError>
+	{
+		fn decode(stream: &mut T) -> Result<(Self, StreamPosition), TestStreamError> {
+			let (name, _) = stream.read_string()?;
+			let (position, mut stream_position) = stream.decode::<Position>()?;
+
+			// the score is optional, and is only present if there is data left in the frame
+			let score = if stream.is_at_end()? {
+				None
+			} else {
+				let (score, new_position) = stream.read_u64()?;
+				stream_position = new_position;
+				Some(score)
+			};
+
+			Ok((Player { name, position, score, }, stream_position))
+		}
+	}
+
+	fn players() -> Vec<Player> {
+		vec![
+			Player {
+				name: String::from("egg"),
+				position: Position { x: 5, y: 10, },
+				score: Some(100),
+			},
+			Player {
+				name: String::from("bacon"),
+				position: Position { x: 20, y: 1, },
+				score: None,
+			},
+		]
+	}
+
+	/// Ensure that nested objects are written into the same frame, and that frames are decoded back into the same objects.
+	#[test]
+	fn round_trip() {
+		let mut write_stream = FramedWriteStream::<_, TestStreamError>::new(U8BufferWriteStream::new());
+		for player in players().iter() {
+			write_stream.encode(player).expect("Could not encode Player");
+		}
+
+		let mut read_stream = FramedReadStream::new(U8BufferReadStream::new());
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		let mut decoded = Vec::new();
+		while read_stream.can_decode() {
+			decoded.push(read_stream.decode::<Player>().expect("Could not decode Player").0);
+		}
+
+		assert_eq!(decoded, players());
+	}
+
+	/// Ensure that the part of a frame a decoder did not read is skipped.
+	#[test]
+	fn skip_unread() {
+		let mut write_stream = FramedWriteStream::<_, TestStreamError>::new(U8BufferWriteStream::new());
+		for player in players().iter() {
+			write_stream.encode(player).expect("Could not encode Player");
+		}
+
+		let mut read_stream = FramedReadStream::new(U8BufferReadStream::new());
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		// only decode the name out of the first frame
+		struct Name(String);
+
+		impl Decode<u8, FramedReadStream<U8BufferReadStream<TestStreamError>, TestStreamError>, TestStreamError> for Name {
+			fn decode(stream: &mut FramedReadStream<U8BufferReadStream<TestStreamError>, TestStreamError>)
+				-> Result<(Self, StreamPosition), TestStreamError>
+			{
+				let (name, position) = stream.read_string()?;
+				Ok((Name(name), position))
+			}
+		}
+
+		assert_eq!(read_stream.decode::<Name>().expect("Could not decode Name").0.0, "egg");
+		assert_eq!(read_stream.decode::<Player>().expect("Could not decode Player").0, players().remove(1));
+		assert!(!read_stream.can_decode());
+	}
+}
@@ -1,6 +1,18 @@
+pub mod buffer;
+pub mod checksum;
+pub mod framed;
 pub mod reading;
 pub mod writing;
 
+pub use buffer::U8BufferReadStream;
+pub use buffer::U8BufferWriteStream;
+pub use checksum::Checksum;
+pub use checksum::ChecksumReadStream;
+pub use checksum::ChecksumWriteStream;
+pub use checksum::Crc32;
+pub use checksum::XxHash64;
+pub use framed::FramedReadStream;
+pub use framed::FramedWriteStream;
 pub use reading::U8ReadStream;
 pub use reading::U8ReadStringStream;
 pub use reading::U8ReadStringSafeStream;
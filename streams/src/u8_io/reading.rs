@@ -57,26 +57,26 @@ pub fn read_vlq(vector: &[u8]) -> (u64, StreamPositionDelta) {
 	return (number, read as StreamPositionDelta);
 }
 
-/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-/// characters.
-pub fn read_string(vector: &[u8]) -> (String, StreamPositionDelta) {
+/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings can
+/// be up to 2**60 bytes long. Fails if the string is not valid UTF-8.
+pub fn read_string(vector: &[u8]) -> Result<(String, StreamPositionDelta), std::string::FromUtf8Error> {
 	let (length, read_bytes) = read_vlq(vector);
 
-	let mut output = String::new();
-	for i in 0..length as usize {
-		output.push(vector[read_bytes as usize + i] as char);
-	}
-	return (output, read_bytes + length);
+	let start = read_bytes as usize;
+	let output = String::from_utf8(vector[start..start + length as usize].to_vec())?;
+	Ok((output, read_bytes + length))
 }
 
 #[derive(Debug)]
 pub enum ReadStringSafeError {
+	InvalidUtf8,
 	TooLong(u64),
 	TooShort(u64),
 }
 
-/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-/// characters. If the length is below the minimum length or above the maximum length, the read will fail.
+/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings can
+/// be up to 2**60 bytes long. If the length is below the minimum length or above the maximum length, or if the string
+/// is not valid UTF-8, the read will fail.
 pub fn read_string_safe(vector: &[u8], minimum_length: u64, maximum_length: u64)
 	-> Result<(String, StreamPosition), ReadStringSafeError>
 {
@@ -88,11 +88,12 @@ pub fn read_string_safe(vector: &[u8], minimum_length: u64, maximum_length: u64)
 		return Err(ReadStringSafeError::TooLong(length));
 	}
 
-	let mut output = String::new();
-	for i in 0..length as usize {
-		output.push(vector[read_bytes as usize + i] as char);
-	}
-	return Ok((output, read_bytes + length));
+	let start = read_bytes as usize;
+	let Ok(output) = String::from_utf8(vector[start..start + length as usize].to_vec()) else {
+		return Err(ReadStringSafeError::InvalidUtf8);
+	};
+
+	Ok((output, read_bytes + length))
 }
 
 /// Trait for a stream that implements `u8` reading.
@@ -121,15 +122,16 @@ pub trait U8ReadStream<Error> {
 }
 
 pub trait U8ReadStringStream<Error> {
-	/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-	/// characters.
+	/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings
+	/// can be up to 2**60 bytes long.
 	fn read_string(&mut self) -> Result<(String, StreamPosition), Error>;
 }
 
 /// Tests the length of the string before reading its contents.
 pub trait U8ReadStringSafeStream<Error> {
-	/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-	/// characters. If the length is below the minimum length or above the maximum length, the read will fail.
+	/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings
+	/// can be up to 2**60 bytes long. If the length is below the minimum length or above the maximum length, the read
+	/// will fail.
 	fn read_string_safe(&mut self, minimum_length: u64, maximum_length: u64)
 		-> Result<(String, StreamPosition), Error>;
 }
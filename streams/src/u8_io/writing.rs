@@ -56,14 +56,11 @@ pub fn write_vlq(number: u64, vector: &mut Vec<u8>) {
 	}
 }
 
-/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-/// characters.
+/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings can
+/// be up to 2**60 bytes long.
 pub fn write_string(string: &str, vector: &mut Vec<u8>) {
 	write_vlq(string.len() as u64, vector);
-
-	for character in string.chars() {
-		write_char(character, vector);
-	}
+	vector.extend_from_slice(string.as_bytes());
 }
 
 /// Trait for a stream that implements `u8` writing.
@@ -87,8 +84,8 @@ pub trait U8WriteStream<Error> {
 	/// bits. 1 if there are, 0 if there aren't. Integers within the range of `0..2**60` are supported.
 	fn write_vlq(&mut self, number: u64) -> Result<(), Error>;
 
-	/// Strings are length encoded, with a variable length integer representing the length. Strings can have up to 2**60
-	/// characters.
+	/// Strings are encoded as UTF-8, prefixed with a variable length integer representing the length in bytes. Strings
+	/// can be up to 2**60 bytes long.
 	fn write_string(&mut self, string: &str) -> Result<(), Error>;
 
 	/// Writes a vector to file.
@@ -0,0 +1,284 @@
+use crate::{ ReadStream, StreamPosition, WriteStream, };
+use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8WriteStream, };
+
+/// Identifies the layout of an encoded object. Incremented whenever fields are added to an object's encoding.
+pub type SchemaVersion = u64;
+
+/// Describes the encoded data to a `DecodeVersioned` implementation, so the decoder can figure out which fields it can
+/// expect to find in the section it is decoding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecodeContext {
+	/// The schema version of the decoder.
+	pub reader_version: SchemaVersion,
+	/// The schema version the object was encoded with.
+	pub writer_version: SchemaVersion,
+}
+
+impl DecodeContext {
+	/// Whether or not the writer knew about fields introduced in the specified schema version.
+	pub fn has_version(&self, version: SchemaVersion) -> bool {
+		self.writer_version >= version
+	}
+
+	/// Whether or not the writer is newer than the reader. If it is, the section may contain trailing fields that the
+	/// reader does not understand, which are skipped once the decoder is finished.
+	pub fn is_writer_newer(&self) -> bool {
+		self.writer_version > self.reader_version
+	}
+}
+
+/// Describes the schema version of an object's encoding.
+pub trait Versioned {
+	const VERSION: SchemaVersion;
+}
+
+/// Encode an object into a length-prefixed section. The section is prefixed with `Versioned::VERSION`, which is handed
+/// to the decoder via a `DecodeContext`. New fields should only ever be appended to the end of the section so older
+/// decoders can ignore them.
+pub trait EncodeVersioned<Error>: Versioned {
+	fn encode_versioned(&self, stream: &mut U8BufferWriteStream<Error>) -> Result<(), Error>;
+}
+
+/// Decode an object out of a length-prefixed section. The section stream only contains the object's data, so decoders
+/// can test `Endable::is_at_end` to determine if an optional field was written by an older encoder. Any data left in the
+/// section after decoding is skipped, which allows older decoders to read data written by newer encoders.
+pub trait DecodeVersioned<Error>: Versioned + Sized {
+	fn decode_versioned(stream: &mut U8BufferReadStream<Error>, context: &DecodeContext) -> Result<Self, Error>;
+}
+
+/// Encode a versioned object into a stream. The encoding starts with the writer's schema version and the length of the
+/// section, both written as variable length integers, followed by the section itself.
+pub fn encode_versioned<T, S, Error>(stream: &mut S, object: &T) -> Result<(), Error>
+where
+	T: EncodeVersioned<Error>,
+	S: U8WriteStream<Error>
+{
+	let mut section = U8BufferWriteStream::new();
+	object.encode_versioned(&mut section)?;
+	let bytes = section.export()?;
+
+	stream.write_vlq(T::VERSION)?;
+	stream.write_vlq(bytes.len() as u64)?;
+	stream.write_vector(&bytes)
+}
+
+/// Decode a versioned object out of a stream. The entire section is consumed from the stream regardless of how much of
+/// it the decoder read, so the stream is always left at the start of whatever was encoded after the object.
+pub fn decode_versioned<T, S, Error>(stream: &mut S) -> Result<(T, StreamPosition), Error>
+where
+	T: DecodeVersioned<Error>,
+	S: U8ReadStream<Error>
+{
+	let (writer_version, _) = stream.read_vlq()?;
+	let (length, _) = stream.read_vlq()?;
+	let (bytes, position) = stream.read_vector(length as usize)?;
+
+	let mut section = U8BufferReadStream::new();
+	section.import(bytes)?;
+
+	let context = DecodeContext {
+		reader_version: T::VERSION,
+		writer_version,
+	};
+
+	Ok((T::decode_versioned(&mut section, &context)?, position))
+}
+
+/// Test decoding data written by older and newer encoders.
+#[cfg(test)]
+mod tests {
+	use crate::{ Endable, ReadStream, WriteStream, };
+	use crate::u8_io::{ U8BufferReadStream, U8BufferWriteStream, U8ReadStream, U8ReadStringStream, U8WriteStream, };
+
+	use super::{ DecodeContext, DecodeVersioned, EncodeVersioned, Versioned, decode_versioned, encode_versioned, };
+
+	#[derive(Debug)]
+	enum TestStreamError {
+		Io(std::io::ErrorKind),
+	}
+
+	impl From<std::io::Error> for TestStreamError {
+		fn from(error: std::io::Error) -> Self {
+			TestStreamError::Io(error.kind())
+		}
+	}
+
+	/// The first version of the test object.
+	#[derive(Debug, Eq, PartialEq)]
+	struct PlayerV1 {
+		health: u16,
+		name: String,
+	}
+
+	impl Versioned for PlayerV1 {
+		const VERSION: u64 = 1;
+	}
+
+	impl EncodeVersioned<TestStreamError> for PlayerV1 {
+		fn encode_versioned(&self, stream: &mut U8BufferWriteStream<TestStreamError>) -> Result<(), TestStreamError> {
+			stream.write_string(&self.name)?;
+			stream.write_u16(self.health)
+		}
+	}
+
+	impl DecodeVersioned<TestStreamError> for PlayerV1 {
+		fn decode_versioned(stream: &mut U8BufferReadStream<TestStreamError>, _: &DecodeContext)
+			-> Result<Self, TestStreamError>
+		{
+			let (name, _) = stream.read_string()?;
+			let (health, _) = stream.read_u16()?;
+
+			Ok(PlayerV1 {
+				health,
+				name,
+			})
+		}
+	}
+
+	/// The second version of the test object, which appends the `armor` field.
+	#[derive(Debug, Eq, PartialEq)]
+	struct PlayerV2 {
+		armor: u16,
+		health: u16,
+		name: String,
+		writer_version: u64,
+	}
+
+	impl Versioned for PlayerV2 {
+		const VERSION: u64 = 2;
+	}
+
+	impl EncodeVersioned<TestStreamError> for PlayerV2 {
+		fn encode_versioned(&self, stream: &mut U8BufferWriteStream<TestStreamError>) -> Result<(), TestStreamError> {
+			stream.write_string(&self.name)?;
+			stream.write_u16(self.health)?;
+			stream.write_u16(self.armor)
+		}
+	}
+
+	impl DecodeVersioned<TestStreamError> for PlayerV2 {
+		fn decode_versioned(stream: &mut U8BufferReadStream<TestStreamError>, context: &DecodeContext)
+			-> Result<Self, TestStreamError>
+		{
+			let (name, _) = stream.read_string()?;
+			let (health, _) = stream.read_u16()?;
+
+			// armor was introduced in version 2, older writers do not know about it
+			let armor = if context.has_version(2) && !stream.is_at_end()? {
+				stream.read_u16()?.0
+			} else {
+				0
+			};
+
+			Ok(PlayerV2 {
+				armor,
+				health,
+				name,
+				writer_version: context.writer_version,
+			})
+		}
+	}
+
+	const SENTINEL: u32 = 0xBEEF_BEEF;
+
+	// encode an object followed by a sentinel, and import the result into a read stream
+	fn encode_with_sentinel<T: EncodeVersioned<TestStreamError>>(object: &T) -> U8BufferReadStream<TestStreamError> {
+		let mut write_stream = U8BufferWriteStream::new();
+		encode_versioned(&mut write_stream, object).expect("Could not encode versioned object");
+		write_stream.write_u32(SENTINEL).expect("Could not write sentinel");
+
+		let mut read_stream = U8BufferReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		read_stream
+	}
+
+	/// Ensure that a newer decoder fills in defaults for fields an older encoder did not write.
+	#[test]
+	fn decode_old_with_new() {
+		let mut read_stream = encode_with_sentinel(&PlayerV1 {
+			health: 100,
+			name: String::from("egg"),
+		});
+
+		let (player, _) = decode_versioned::<PlayerV2, _, _>(&mut read_stream).expect("Could not decode PlayerV2");
+		assert_eq!(player, PlayerV2 {
+			armor: 0,
+			health: 100,
+			name: String::from("egg"),
+			writer_version: 1,
+		});
+
+		assert_eq!(read_stream.read_u32().expect("Could not read sentinel").0, SENTINEL);
+	}
+
+	/// Ensure that an older decoder skips fields a newer encoder wrote.
+	#[test]
+	fn decode_new_with_old() {
+		let mut read_stream = encode_with_sentinel(&PlayerV2 {
+			armor: 50,
+			health: 100,
+			name: String::from("egg"),
+			writer_version: 2,
+		});
+
+		let (player, _) = decode_versioned::<PlayerV1, _, _>(&mut read_stream).expect("Could not decode PlayerV1");
+		assert_eq!(player, PlayerV1 {
+			health: 100,
+			name: String::from("egg"),
+		});
+
+		// the unknown armor field must have been skipped
+		assert_eq!(read_stream.read_u32().expect("Could not read sentinel").0, SENTINEL);
+	}
+
+	/// Ensure that objects round-trip when the encoder and decoder agree on the version.
+	#[test]
+	fn decode_same_version() {
+		let player = PlayerV2 {
+			armor: 50,
+			health: 100,
+			name: String::from("egg"),
+			writer_version: 2,
+		};
+
+		let mut read_stream = encode_with_sentinel(&player);
+		let (decoded, _) = decode_versioned::<PlayerV2, _, _>(&mut read_stream).expect("Could not decode PlayerV2");
+		assert_eq!(decoded, player);
+		assert_eq!(read_stream.read_u32().expect("Could not read sentinel").0, SENTINEL);
+	}
+
+	/// Ensure that truncated sections result in an error instead of a panic.
+	#[test]
+	fn decode_truncated() {
+		let mut write_stream = U8BufferWriteStream::<TestStreamError>::new();
+		encode_versioned(&mut write_stream, &PlayerV2 {
+			armor: 50,
+			health: 100,
+			name: String::from("egg"),
+			writer_version: 2,
+		}).expect("Could not encode versioned object");
+
+		let bytes = write_stream.export().expect("Could not export test stream");
+		for length in 0..bytes.len() {
+			let mut read_stream = U8BufferReadStream::new();
+			read_stream.import(bytes[0..length].to_vec()).expect("Could not import test stream");
+
+			let result = decode_versioned::<PlayerV2, _, _>(&mut read_stream);
+			assert!(matches!(result, Err(TestStreamError::Io(std::io::ErrorKind::UnexpectedEof))));
+		}
+
+		// a section that claims to be longer than it is
+		let mut write_stream = U8BufferWriteStream::<TestStreamError>::new();
+		write_stream.write_vlq(2).expect("Could not write version");
+		write_stream.write_vlq(1 << 40).expect("Could not write length");
+
+		let mut read_stream = U8BufferReadStream::new();
+		read_stream.import(write_stream.export().expect("Could not export test stream"))
+			.expect("Could not import test stream");
+
+		let result = decode_versioned::<PlayerV2, _, _>(&mut read_stream);
+		assert!(matches!(result, Err(TestStreamError::Io(std::io::ErrorKind::UnexpectedEof))));
+	}
+}